@@ -0,0 +1,137 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Merging fragments as they arrive, e.g. one at a time from a
+// service, instead of all at once.
+//
+// Each fragment is checked as it's added: one which isn't well
+// formed, or names another schema, is turned away, and the
+// names it defines are checked against those of the fragments
+// before it. The merge itself waits for `finalize`, which
+// merges what was added as `merge_fragments` would.
+
+use std::collections::HashMap;
+
+use error::*;
+use lint;
+use scan;
+use tokenizer;
+use {find_fragment_duplicates, merge_fragments, Fragment, MergeOptions, Merged, Names, Warning};
+
+/// Fragments added so far, to be merged into one schema.
+///
+/// It owns everything it holds, so it can be kept between
+/// requests, or shared between threads behind a lock.
+#[derive(Debug, Clone, Default)]
+pub struct SchemaAccumulator {
+    options: MergeOptions,
+    fragments: Vec<String>,
+    /// The schema named so far, and the fragment naming it first
+    schema_name: Option<(String, usize)>,
+    /// Names defined so far, with where each was first defined
+    /// and its definition
+    names: Names<String>,
+}
+
+impl SchemaAccumulator {
+    pub fn new(options: MergeOptions) -> SchemaAccumulator {
+        SchemaAccumulator {
+            options,
+            fragments: Vec::new(),
+            schema_name: None,
+            names: HashMap::new(),
+        }
+    }
+
+    /// Check `fragment` and add it, returning what the lint
+    /// rules found about the names it defines (those at level
+    /// `deny` included). A fragment which isn't well formed, or
+    /// names another schema than those before it (unless
+    /// `MergeOptions::combine`), is an error and isn't added.
+    pub fn add_fragment(&mut self, fragment: String) -> Result<Vec<Warning>> {
+        let index = self.fragments.len();
+        let options = &self.options;
+        if let Err(err) = tokenizer::check_well_formed(&fragment, options.scan.ignore_case) {
+            let (line, column) = tokenizer::line_col(&fragment, err.pos);
+            return Err(ErrorKind::MalformedFragment(index, line, column, err.message).into());
+        }
+        let input = scan::strip_namespace(&fragment, &options.scan);
+        let mut processed = Fragment::process_fragments(&input, &options.scan)
+            .chain_err(|| format!("Could not process fragment {}", index))?;
+        if let Some(ref only) = options.only_schema {
+            processed.retain(|frag| frag.schema_name.as_ref().is_none_or(|name| name == only));
+        }
+
+        let mut schema_name = self.schema_name.clone();
+        for name in processed.iter().filter_map(|frag| frag.schema_name.as_ref()) {
+            match schema_name {
+                Some((ref first, first_index)) if first != name && !options.combine => {
+                    return Err(ErrorKind::SchemaNameMismatch(first.clone(), first_index, name.to_string(), index).into());
+                },
+                Some(_) => {},
+                None => schema_name = Some((name.to_string(), index)),
+            }
+        }
+
+        // nothing turns the fragment away from here on, so its
+        // names are added as they're checked
+        let mut warnings = Vec::new();
+        for frag in &mut processed {
+            find_fragment_duplicates(index, &input, frag, &mut self.names, options, &mut warnings);
+        }
+        warnings.retain(|warning| options.level(warning.rule) != lint::Level::Allow);
+
+        self.schema_name = schema_name;
+        self.fragments.push(fragment);
+        Ok(warnings)
+    }
+
+    /// The fragments added so far, in the order added
+    pub fn fragments(&self) -> &[String] {
+        &self.fragments
+    }
+
+    /// Merge the fragments added into one schema, as
+    /// `merge_fragments` does, findings and all.
+    pub fn finalize(self) -> Result<Merged> {
+        merge_fragments(&self.fragments, &self.options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_accumulator() {
+        let mut accumulator = SchemaAccumulator::new(MergeOptions::default());
+        let warnings = accumulator.add_fragment("<Schema name=\"Sales\"><Cube name=\"Sales\"/></Schema>".to_owned()).unwrap();
+        assert!(warnings.is_empty());
+
+        // a virtual cube shares names with cubes
+        let warnings = accumulator.add_fragment("<Schema name=\"Sales\">\n<VirtualCube name=\"Sales\"/>\n</Schema>".to_owned()).unwrap();
+        let found: Vec<_> = warnings.iter().map(|w| (w.fragment, w.line, w.rule, w.first)).collect();
+        assert_eq!(found, vec![(1, 2, "duplicate-name", Some((0, 1))), (1, 2, "duplicate-conflict", None)]);
+
+        // turned away, so the next fragment is still the third
+        let err = accumulator.add_fragment("<Schema name=\"Ops\"/>".to_owned()).unwrap_err();
+        assert!(matches!(*err.kind(), ErrorKind::SchemaNameMismatch(ref first, 0, ref second, 2) if first == "Sales" && second == "Ops"));
+        let err = accumulator.add_fragment("<Cube name=\"Stock\">".to_owned()).unwrap_err();
+        assert!(matches!(*err.kind(), ErrorKind::MalformedFragment(2, ..)));
+        assert_eq!(accumulator.fragments().len(), 2);
+
+        let warnings = accumulator.add_fragment("<Cube name=\"Stock\"/>".to_owned()).unwrap();
+        assert!(warnings.is_empty());
+
+        let merged = accumulator.finalize().unwrap();
+        assert_eq!(merged.schema_name, "Sales");
+        assert_eq!(merged.elements.len(), 3);
+        // found again, along with the rest
+        let rules: Vec<_> = merged.report().findings.iter().filter(|finding| finding.fragment == 1).map(|finding| finding.rule).collect();
+        assert_eq!(rules, vec!["duplicate-conflict", "duplicate-name"]);
+    }
+}
@@ -0,0 +1,255 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Merging the `<Annotations>` of the Schema blocks of several
+// fragments into the one block a schema can have, and likewise
+// those of the versions of a cube overlaid on each other.
+//
+// Each `<Annotation>` is kept as written, in the order its name
+// was first seen. One set again to the same value is dropped,
+// while one set to another value is an `annotation-conflict`.
+//
+// Annotations can also be injected at merge time, into the
+// schema or into every cube, e.g. to tag a build with where it
+// runs; those replace any with the same name.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+
+use escape;
+use scan::{self, ScanOptions};
+use tokenizer;
+use tree;
+use validate;
+use Warning;
+
+const ANNOTATION_TAG: &str = "Annotation";
+pub const ANNOTATIONS_TAG: &str = "Annotations";
+
+/// One annotation of the merged block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation<'a> {
+    /// The annotation's name, unescaped; empty if it has none
+    pub name: String,
+    /// The annotation's value, unescaped
+    pub value: String,
+    /// The `<Annotation>` element, as written in its fragment
+    pub text: Cow<'a, str>,
+}
+
+/// Merge `blocks`, each an `<Annotations>` element as
+/// (fragment, input, text), with `text` a slice of `input`, of
+/// the schema or, if named, of `cube`. Conflicting values keep
+/// the first, and are warned about.
+pub fn merge_annotations<'a>(
+    blocks: &[(usize, &'a str, &'a str)],
+    cube: Option<&str>,
+    options: &ScanOptions,
+) -> (Vec<Annotation<'a>>, Vec<Warning>) {
+    let mut res: Vec<Annotation> = Vec::new();
+    let mut warnings = Vec::new();
+    // name to its place in `res` and where it was first set
+    let mut seen: HashMap<String, (usize, usize, usize)> = HashMap::new();
+    for &(fragment, input, text) in blocks {
+        let block = match tree::parse(text).ok().and_then(|roots| roots.into_iter().next()) {
+            Some(block) => block,
+            None => continue,
+        };
+        let offset = scan::offset_in(input, text);
+        for element in block.children.iter().filter(|child| scan::names_match(child.name, ANNOTATION_TAG, options)) {
+            let annotation = Annotation {
+                name: element.name_attr().map(|name| name.into_owned()).unwrap_or_default(),
+                value: validate::text_content(element.body),
+                text: Cow::Borrowed(&text[element.start..element.start + element.text.len()]),
+            };
+            let start = offset + element.start;
+            let (line, column) = tokenizer::line_col(input, start);
+            match seen.get(&annotation.name) {
+                Some(&(i, first_fragment, first_line)) if !annotation.name.is_empty() => {
+                    if res[i].value != annotation.value {
+                        let label = format!("{} \"{}\"", element.name, annotation.name);
+                        let (message, owner, path) = match cube {
+                            Some(cube) => (
+                                format!(
+                                    "Annotation \"{}\" of cube \"{}\" is \"{}\" here, but was \"{}\" first",
+                                    annotation.name, cube, annotation.value, res[i].value
+                                ),
+                                format!("Cube \"{}\"", cube),
+                                vec![format!("Cube \"{}\"", cube), block.name.to_owned(), label],
+                            ),
+                            None => (
+                                format!(
+                                    "Schema annotation \"{}\" is \"{}\" here, but was \"{}\" first",
+                                    annotation.name, annotation.value, res[i].value
+                                ),
+                                format!("Annotation \"{}\"", annotation.name),
+                                vec![block.name.to_owned(), label],
+                            ),
+                        };
+                        warnings.push(Warning {
+                            fragment,
+                            line,
+                            column,
+                            rule: "annotation-conflict",
+                            element: Some(owner),
+                            path,
+                            span: start..start + element.text.len(),
+                            message,
+                            first: Some((first_fragment, first_line)),
+                        });
+                    }
+                },
+                _ => {
+                    seen.insert(annotation.name.clone(), (res.len(), fragment, line));
+                    res.push(annotation);
+                },
+            }
+        }
+    }
+    (res, warnings)
+}
+
+/// The `<Annotations>` block holding `annotations`, one to a
+/// line and indented two spaces
+pub fn annotations_block(annotations: &[Annotation]) -> String {
+    let mut res = String::from("<Annotations>\n");
+    for annotation in annotations {
+        res.push_str("  ");
+        res.push_str(&annotation.text);
+        res.push('\n');
+    }
+    res.push_str("</Annotations>");
+    res
+}
+
+/// Set each of `injected` (name to value) in `annotations`,
+/// replacing any with the same name, and adding the others last
+pub fn inject(annotations: &mut Vec<Annotation>, injected: &BTreeMap<String, String>) {
+    annotations.retain(|annotation| !injected.contains_key(&annotation.name));
+    annotations.extend(injected.iter().map(|(name, value)| Annotation {
+        name: name.clone(),
+        value: value.clone(),
+        text: Cow::Owned(annotation(name, value)),
+    }));
+}
+
+/// Set each of `injected` in the annotations of `element`, a
+/// cube or the like: annotations with those names are replaced,
+/// and the new ones go at the end of its `<Annotations>`, or in
+/// a new one as its first child.
+pub fn inject_into<'a>(element: &'a str, injected: &BTreeMap<String, String>, options: &ScanOptions) -> Cow<'a, str> {
+    if injected.is_empty() {
+        return Cow::Borrowed(element);
+    }
+    let root = match tree::parse(element).ok().and_then(|roots| roots.into_iter().next()) {
+        Some(root) => root,
+        None => return Cow::Borrowed(element),
+    };
+    let added: String = injected.iter().map(|(name, value)| annotation(name, value)).collect();
+    let block = root.children.iter().find(|child| scan::names_match(child.name, ANNOTATIONS_TAG, options));
+
+    let mut res = String::with_capacity(element.len() + added.len());
+    match block {
+        // those replaced are cut out, the rest kept as written
+        Some(block) if !block.text.ends_with("/>") => {
+            let mut pos = 0;
+            let replaced = block.children.iter()
+                .filter(|child| child.name_attr().is_some_and(|name| injected.contains_key(name.as_ref())));
+            for child in replaced {
+                res.push_str(element[pos..child.start].trim_end());
+                pos = child.start + child.text.len();
+            }
+            let end_tag = block.start + block.text.rfind("</").unwrap_or(block.text.len());
+            res.push_str(&element[pos..end_tag]);
+            res.push_str(&added);
+            res.push_str(&element[end_tag..]);
+        },
+        Some(block) => {
+            res.push_str(&element[..block.start]);
+            res.push_str(&annotations_tag(&added));
+            res.push_str(&element[block.start + block.text.len()..]);
+        },
+        None if root.text.ends_with("/>") => {
+            res.push_str(root.text[..root.text.len() - 2].trim_end());
+            res.push('>');
+            res.push_str(&annotations_tag(&added));
+            res.push_str(&format!("</{}>", root.name));
+        },
+        None => {
+            let body_start = scan::offset_in(element, root.body);
+            res.push_str(&element[..body_start]);
+            res.push_str(&annotations_tag(&added));
+            res.push_str(&element[body_start..]);
+        },
+    }
+    Cow::Owned(res)
+}
+
+/// An `<Annotation>` element setting `name` to `value`
+fn annotation(name: &str, value: &str) -> String {
+    format!("<{0} name=\"{1}\">{2}</{0}>", ANNOTATION_TAG, escape::escape(name), escape::escape(value))
+}
+
+fn annotations_tag(annotations: &str) -> String {
+    format!("<{0}>{1}</{0}>", ANNOTATIONS_TAG, annotations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_annotations() {
+        let f0 = "<Schema name=\"s\">\n<Annotations>\n  <Annotation name=\"owner\">sales</Annotation>\n  <Annotation name=\"tier\">1</Annotation>\n</Annotations>\n</Schema>";
+        let f1 = "<Annotations><Annotation name=\"tier\">2</Annotation><Annotation name=\"owner\">sales</Annotation><Annotation name=\"team\">bi &amp; ops</Annotation></Annotations>";
+        let blocks = vec![(0, f0, &f0[18..133]), (1, f1, f1)];
+        let (annotations, warnings) = merge_annotations(&blocks, None, &ScanOptions::default());
+        let found: Vec<_> = annotations.iter().map(|a| (a.name.as_str(), a.value.as_str())).collect();
+        assert_eq!(found, vec![("owner", "sales"), ("tier", "1"), ("team", "bi & ops")]);
+        assert_eq!(
+            annotations_block(&annotations),
+            "<Annotations>\n  <Annotation name=\"owner\">sales</Annotation>\n  <Annotation name=\"tier\">1</Annotation>\n  \
+             <Annotation name=\"team\">bi &amp; ops</Annotation>\n</Annotations>"
+        );
+
+        let found: Vec<_> = warnings.iter().map(|w| (w.fragment, w.line, w.column, w.rule, w.message.as_str(), w.first)).collect();
+        assert_eq!(
+            found,
+            vec![(1, 1, 14, "annotation-conflict", "Schema annotation \"tier\" is \"2\" here, but was \"1\" first", Some((0, 4)))]
+        );
+        assert_eq!(warnings[0].path, vec!["Annotations".to_owned(), "Annotation \"tier\"".to_owned()]);
+
+        let (_, warnings) = merge_annotations(&blocks, Some("Sales"), &ScanOptions::default());
+        assert_eq!(warnings[0].message, "Annotation \"tier\" of cube \"Sales\" is \"2\" here, but was \"1\" first");
+        assert_eq!(warnings[0].element.as_deref(), Some("Cube \"Sales\""));
+    }
+
+    #[test]
+    fn test_inject_into() {
+        let mut injected = BTreeMap::new();
+        injected.insert("env".to_owned(), "prod".to_owned());
+        injected.insert("version".to_owned(), "a<b".to_owned());
+        let added = "<Annotation name=\"env\">prod</Annotation><Annotation name=\"version\">a&lt;b</Annotation>";
+        let inject = |element| inject_into(element, &injected, &ScanOptions::default()).into_owned();
+
+        assert_eq!(inject("<Cube name=\"c\" />"), format!("<Cube name=\"c\"><Annotations>{}</Annotations></Cube>", added));
+        assert_eq!(
+            inject("<Cube name=\"c\">\n  <Table name=\"t\"/>\n</Cube>"),
+            format!("<Cube name=\"c\"><Annotations>{}</Annotations>\n  <Table name=\"t\"/>\n</Cube>", added)
+        );
+        assert_eq!(
+            inject("<Cube name=\"c\"><Annotations/><Table name=\"t\"/></Cube>"),
+            format!("<Cube name=\"c\"><Annotations>{}</Annotations><Table name=\"t\"/></Cube>", added)
+        );
+        // the cube's own with those names are replaced
+        assert_eq!(
+            inject("<Cube name=\"c\"><Annotations>\n  <Annotation name=\"env\">dev</Annotation>\n  <Annotation name=\"owner\">bi</Annotation>\n</Annotations></Cube>"),
+            format!("<Cube name=\"c\"><Annotations>\n  <Annotation name=\"owner\">bi</Annotation>\n{}</Annotations></Cube>", added)
+        );
+        assert_eq!(inject_into("<Cube/>", &BTreeMap::new(), &ScanOptions::default()), Cow::Borrowed("<Cube/>"));
+    }
+}
@@ -10,23 +10,60 @@
 extern crate clap;
 #[macro_use]
 extern crate error_chain;
+extern crate glob;
+#[macro_use]
+extern crate log;
 extern crate mondrian_schema_cat;
+extern crate notify;
+#[macro_use]
+extern crate serde;
+extern crate serde_json;
 extern crate walkdir;
 
-use clap::{App, Arg, AppSettings};
-use mondrian_schema_cat::fragments_to_schema;
-use std::io::{Read, Write, BufWriter};
-use std::fs::{self, File};
+use clap::{App, Arg, AppSettings, Shell, SubCommand};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use mondrian_schema_cat::diagnostic::{self, Severity, Snippet};
+use mondrian_schema_cat::{directives, encoding, merge_by_schema, merge_fragments, ConflictStrategy, DefaultRolePolicy, DuplicatePolicy, ElementKind, FragmentStats, MergeOptions, Metamodel, SortOrder, Merged, MergedElement, Namespace, ScanOptions, SchemaAttributePolicy};
+use mondrian_schema_cat::diff::diff_schemas;
+use mondrian_schema_cat::directives::Expanded;
+use mondrian_schema_cat::docs::schema_docs;
+use mondrian_schema_cat::hoist::hoist_dimensions;
+use mondrian_schema_cat::format::format;
+use mondrian_schema_cat::lint;
+use mondrian_schema_cat::manifest::Manifest;
+use mondrian_schema_cat::prefix::prefix_names;
+use mondrian_schema_cat::split::split_schema;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::cmp::Reverse;
+use std::env;
+use std::fmt;
+use std::io::{self, IsTerminal, Read, Write, BufWriter};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
 use walkdir::{DirEntry, WalkDir};
 
 mod error {
+    #![allow(deprecated)]
+
+    use glob;
     use mondrian_schema_cat;
+    use notify;
     use walkdir;
 
+    use super::Located;
+
     error_chain! {
         foreign_links {
             Io(::std::io::Error);
             WalkDir(walkdir::Error);
+            GlobPattern(glob::PatternError);
+            Glob(glob::GlobError);
+            Notify(notify::Error);
         }
 
         links {
@@ -35,65 +72,1097 @@ mod error {
                 mondrian_schema_cat::error::ErrorKind
             );
         }
+
+        errors {
+            /// The arguments, or a manifest, don't make sense
+            Usage(message: String) {
+                description("usage error")
+                display("{}", message)
+            }
+
+            Read(path: String) {
+                description("could not read a file")
+                display("Could not read {}", path)
+            }
+
+            Write(path: String) {
+                description("could not write a file")
+                display("Could not write {}", path)
+            }
+
+            Decode(path: String) {
+                description("could not decode a fragment")
+                display("Could not decode {}", path)
+            }
+
+            /// A fragment isn't well formed
+            Malformed(problem: Located) {
+                description("malformed fragment")
+                display("{}", problem)
+            }
+
+            /// The fragments name more than one schema
+            SchemaNameMismatch(first: String, first_path: String, second: String, second_path: String) {
+                description("more than one schema name found")
+                display("More than one schema name found: {} in {} and {} in {}", first, first_path, second, second_path)
+            }
+
+            /// Merging found problems at level deny, or `--check`
+            /// found warnings
+            Problems(count: usize) {
+                description("problems found in the fragments")
+                display("{} problem(s) found in the fragments", count)
+            }
+
+            /// `fmt --check` found files which aren't formatted
+            Unformatted(count: usize) {
+                description("files not formatted")
+                display("{} file(s) not formatted", count)
+            }
+        }
     }
 }
 
 use error ::*;
 
+/// A problem at a position in a fragment, along with the
+/// fragment's text to show it in
+#[derive(Debug)]
+pub struct Located {
+    path: String,
+    source: String,
+    line: usize,
+    column: usize,
+    message: String,
+}
+
+impl Located {
+    fn snippet(&self) -> Snippet<'_> {
+        Snippet { path: &self.path, source: &self.source, line: self.line, column: self.column }
+    }
+}
+
+impl fmt::Display for Located {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.path, self.line, self.column, self.message)
+    }
+}
+
+const APP_NAME: &str = "moncat";
+
+// File arg standing for a fragment read from stdin
+const STDIN_PATH: &str = "-";
+
+// Where fragments are found in a layout directory, in the
+// order they're merged
+const LAYOUT_SCHEMA_FILE: &str = "schema.xml";
+const LAYOUT_DIRS: &[&str] = &["parameters", "dimensions", "cubes", "virtual_cubes", "named_sets", "roles", "functions"];
+
+// How long to wait for more changes before rebuilding, since
+// saving a file usually shows up as several events
+const WATCH_SETTLE: Duration = Duration::from_millis(200);
+
+// Exit codes, by what went wrong
+const EXIT_INTERNAL: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_IO: i32 = 3;
+const EXIT_PARSE: i32 = 4;
+const EXIT_VALIDATION: i32 = 5;
+
 fn main() {
     if let Err(ref err) = run() {
-        println!("error: {}", err);
+        print_error(err);
+        ::std::process::exit(exit_code(err));
+    }
+}
 
-        for e in err.iter().skip(1) {
-            println!(" cause by: {}", e);
-        }
+/// The exit code for an error, so scripts can tell what kind
+/// of failure it was
+fn exit_code(err: &Error) -> i32 {
+    use mondrian_schema_cat::error::ErrorKind as MonCatErrorKind;
 
-        if let Some(backtrace) = err.backtrace() {
-            println!("backtrace: {:?}", backtrace);
-        }
+    match *err.kind() {
+        ErrorKind::Usage(_) | ErrorKind::GlobPattern(_) => EXIT_USAGE,
+        ErrorKind::Io(_) | ErrorKind::WalkDir(_) | ErrorKind::Glob(_) | ErrorKind::Notify(_) => EXIT_IO,
+        ErrorKind::Read(_) | ErrorKind::Write(_) => EXIT_IO,
+        ErrorKind::Decode(_) | ErrorKind::Malformed(_) => EXIT_PARSE,
+        ErrorKind::SchemaNameMismatch(..) | ErrorKind::Problems(_) => EXIT_VALIDATION,
+        ErrorKind::Unformatted(_) => EXIT_VALIDATION,
+        ErrorKind::MonCat(ref kind) => match *kind {
+            MonCatErrorKind::Io(_) => EXIT_IO,
+            MonCatErrorKind::MalformedFragment(..) => EXIT_PARSE,
+            MonCatErrorKind::Denied(..)
+            | MonCatErrorKind::SchemaNameMismatch(..)
+            | MonCatErrorKind::NoSchemaName
+            | MonCatErrorKind::SchemaNotFound(_) => EXIT_VALIDATION,
+            MonCatErrorKind::InvalidManifest(_)
+            | MonCatErrorKind::InvalidNamingConvention(..)
+            | MonCatErrorKind::InvalidFilter(..)
+            | MonCatErrorKind::InvalidRecipe(..) => EXIT_USAGE,
+            _ => EXIT_INTERNAL,
+        },
+        _ => EXIT_INTERNAL,
+    }
+}
 
-        ::std::process::exit(1);
+/// The rule an error broke, for `--message-format=json`
+fn error_rule(err: &Error) -> &'static str {
+    match *err.kind() {
+        ErrorKind::Malformed(_) => "malformed",
+        _ => match exit_code(err) {
+            EXIT_USAGE => "usage",
+            EXIT_IO => "io",
+            EXIT_PARSE => "parse",
+            EXIT_VALIDATION => "validation",
+            _ => "internal",
+        },
+    }
+}
+
+fn print_error(err: &Error) {
+    let (message, snippet) = match *err.kind() {
+        ErrorKind::Malformed(ref problem) => (problem.message.clone(), Some(problem.snippet())),
+        _ => (err.to_string(), None),
+    };
+
+    if json_messages() {
+        // a record is one line, so the causes go in the message
+        let message = err.iter().skip(1).fold(message, |message, e| format!("{}: {}", message, e));
+        report(Severity::Error, error_rule(err), &message, snippet.as_ref(), None);
+        return;
+    }
+
+    report(Severity::Error, error_rule(err), &message, snippet.as_ref(), None);
+    for e in err.iter().skip(1) {
+        eprintln!(" caused by: {}", e);
+    }
+
+    if let Some(backtrace) = err.backtrace() {
+        eprintln!("backtrace: {:?}", backtrace);
     }
 }
 
 fn run() -> Result<()> {
     let config = get_cli_config();
+    init_logger(config.verbosity);
+    let color = config.color.unwrap_or_else(|| {
+        io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none()
+    });
+    COLOR.store(color, Ordering::Relaxed);
+    JSON_MESSAGES.store(config.json_messages, Ordering::Relaxed);
+
+    match config.command {
+        Command::Merge => {
+            if config.watch {
+                watch(&config)
+            } else if config.check {
+                check(&plan(&config)?, config.stats)
+            } else if config.dry_run {
+                dry_run(&plan(&config)?, config.stats)
+            } else if config.by_schema {
+                merge_by_schema_inputs(&plan(&config)?)
+            } else {
+                merge_inputs(&plan(&config)?, config.stats)
+            }
+        },
+        Command::Lint => check(&plan(&config)?, false),
+        Command::Stats => stats(&plan(&config)?),
+        Command::Split { ref schema_path, ref dir_path } => split(schema_path, dir_path),
+        Command::Diff { ref old_path, ref new_path } => diff(old_path, new_path),
+        Command::Docs { ref schema_path } => docs(schema_path, config.output_path.as_deref()),
+        Command::Hoist { ref schema_path } => {
+            let options = ScanOptions { ignore_case: config.ignore_case, namespace: config.namespace.clone(), ..ScanOptions::default() };
+            hoist(schema_path, config.output_path.as_deref(), &options)
+        },
+        Command::Fmt { ref paths, check } => fmt(paths.clone(), check),
+        Command::Completions(shell) => {
+            build_cli().gen_completions_to(APP_NAME, shell, &mut io::stdout());
+            Ok(())
+        },
+    }
+}
+
+/// Whether errors and warnings are colored
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+fn color() -> bool {
+    COLOR.load(Ordering::Relaxed)
+}
+
+/// Whether errors and warnings are printed as json records
+static JSON_MESSAGES: AtomicBool = AtomicBool::new(false);
+
+fn json_messages() -> bool {
+    JSON_MESSAGES.load(Ordering::Relaxed)
+}
+
+/// An error or warning as printed with `--message-format=json`,
+/// one record per line
+#[derive(Serialize)]
+struct JsonMessage<'a> {
+    severity: String,
+    rule: &'a str,
+    message: &'a str,
+    file: Option<&'a str>,
+    line: Option<usize>,
+    column: Option<usize>,
+    element: Option<&'a str>,
+}
+
+/// Print an error or warning to stderr, for people or as a json
+/// record. `rule` names the kind of problem, and `snippet` is
+/// where in a fragment it is.
+fn report(severity: Severity, rule: &str, message: &str, snippet: Option<&Snippet>, element: Option<&str>) {
+    // stdout may be the merged schema, so these never go there
+    if json_messages() {
+        let record = JsonMessage {
+            severity: severity.to_string(),
+            rule,
+            message,
+            file: snippet.map(|snippet| snippet.path),
+            line: snippet.map(|snippet| snippet.line),
+            column: snippet.map(|snippet| snippet.column),
+            element,
+        };
+        eprintln!("{}", serde_json::to_string(&record).expect("records always serialize"));
+    } else {
+        eprintln!("{}", diagnostic::render(severity, message, snippet, color()));
+    }
+}
+
+/// Logs to stderr, in the same style as errors
+struct StderrLogger;
+
+static LOGGER: StderrLogger = StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let message = record.args().to_string();
+            match record.level() {
+                Level::Error => report(Severity::Error, "log", &message, None, None),
+                Level::Warn => report(Severity::Warning, "log", &message, None, None),
+                level => eprintln!("{}: {}", level.to_string().to_lowercase(), message),
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Set up logging. `verbosity` is 0 for warnings (the default),
+/// -1 to leave them out and more for more detail. Errors are
+/// always printed.
+fn init_logger(verbosity: i64) {
+    let level = match verbosity {
+        v if v < 0 => LevelFilter::Error,
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+/// What to merge, how, and where to
+struct Plan {
+    inputs: Vec<Input>,
+    options: MergeOptions,
+    output_path: Option<String>,
+}
+
+/// A fragment file to merge
+struct Input {
+    path: String,
+    /// Encoding to decode the file with, instead of detecting it
+    encoding: Option<String>,
+    /// Where the file goes in the merge order, from the manifest
+    /// or the file itself
+    priority: Option<i64>,
+    /// Prefix for the names the file defines
+    prefix: Option<String>,
+}
+
+/// The fragments read, as they're merged, and the files they
+/// came from
+struct Fragments {
+    /// Each fragment, with its includes replaced and its names
+    /// prefixed
+    texts: Vec<String>,
+    /// Each fragment with its includes replaced, and where each
+    /// piece of it came from
+    expanded: Vec<Expanded>,
+}
 
+impl Fragments {
+    /// Fragments which include nothing
+    fn new(inputs: &[Input], texts: Vec<String>) -> Fragments {
+        let expanded = inputs.iter()
+            .zip(&texts)
+            .map(|(input, text)| Expanded::new(text, Path::new(&input.path)))
+            .collect();
+        Fragments { texts, expanded }
+    }
+
+    /// The path and text of the file `line` and `column` of
+    /// fragment `index` came from, and the line and column there
+    fn position(&self, inputs: &[Input], index: usize, line: usize, column: usize) -> (String, &str, usize, usize) {
+        let expanded = &self.expanded[index];
+        if expanded.files.len() == 1 {
+            return (display_path(&inputs[index].path).to_owned(), &self.texts[index], line, column);
+        }
+        let (file, line, column) = expanded.locate(offset_at(&expanded.text, line, column));
+        let path = match file {
+            0 => display_path(&inputs[index].path).to_owned(),
+            _ => expanded.files[file].0.display().to_string(),
+        };
+        (path, &expanded.files[file].1, line, column)
+    }
+}
+
+fn plan(config: &Config) -> Result<Plan> {
+    match config.manifest_path {
+        Some(ref manifest_path) => {
+            let mut plan = build_plan(manifest_path, config.output_path.clone())?;
+            // as with the output path, the command line wins
+            if config.schema_name.is_some() {
+                plan.options.schema_name = config.schema_name.clone();
+            }
+            if config.default_schema_name.is_some() {
+                plan.options.default_schema_name = config.default_schema_name.clone();
+            }
+            if config.combine {
+                plan.options.combine = true;
+            }
+            if let Some(duplicates) = config.duplicates {
+                plan.options.duplicates = duplicates;
+            }
+            if config.dedupe_identical {
+                plan.options.dedupe_identical = true;
+            }
+            if let Some(conflicts) = config.conflicts {
+                plan.options.conflicts = conflicts;
+            }
+            if config.merge_cubes {
+                plan.options.merge_cubes = true;
+            }
+            if let Some(default_roles) = config.default_roles {
+                plan.options.default_roles = default_roles;
+            }
+            if let Some(schema_attributes) = config.schema_attributes {
+                plan.options.schema_attributes = schema_attributes;
+            }
+            if let Some(metamodel) = config.metamodel {
+                plan.options.metamodel = metamodel;
+            }
+            if let Some(sort) = config.sort {
+                plan.options.sort = sort;
+            }
+            plan.options.lints.extend(config.lints.iter().cloned());
+            plan.options.naming.extend(config.naming.iter().cloned());
+            plan.options.include.extend(config.include.iter().cloned());
+            plan.options.exclude.extend(config.exclude.iter().cloned());
+            plan.options.annotations.extend(config.annotations.iter().cloned());
+            plan.options.cube_annotations.extend(config.cube_annotations.iter().cloned());
+            prioritize(&mut plan.inputs, plan.options.conflicts)?;
+            Ok(plan)
+        },
+        None => cat_plan(config),
+    }
+}
+
+/// Merge the fragments given on the command line
+fn cat_plan(config: &Config) -> Result<Plan> {
     let fragment_paths;
-    if let Some(dir_path) = config.dir_path {
-        fragment_paths = get_fragment_paths_dir(&dir_path)?;
+    if let Some(ref dir_path) = config.dir_path {
+        fragment_paths = get_fragment_paths_dir(dir_path)?;
+    } else if let Some(ref layout_path) = config.layout_path {
+        fragment_paths = get_fragment_paths_layout(layout_path)?;
     } else {
-        fragment_paths = config.arg_files;
+        fragment_paths = expand_globs(config.arg_files.clone())?;
     }
 
-    if fragment_paths.is_empty() {
-        return Err("No files found".into());
+    let mut inputs: Vec<_> = fragment_paths.into_iter()
+        .map(|path| Input { path, encoding: None, priority: None, prefix: None })
+        .collect();
+
+    let options = MergeOptions {
+        xml_declaration: config.xml_declaration,
+        scan: ScanOptions {
+            ignore_case: config.ignore_case,
+            namespace: config.namespace.clone(),
+            keep_comments: config.keep_comments,
+        },
+        normalize_case: config.normalize_case,
+        only_schema: config.only_schema.clone(),
+        schema_name: config.schema_name.clone(),
+        default_schema_name: config.default_schema_name.clone(),
+        combine: config.combine,
+        duplicates: config.duplicates.unwrap_or_default(),
+        dedupe_identical: config.dedupe_identical,
+        conflicts: config.conflicts.unwrap_or_default(),
+        merge_cubes: config.merge_cubes,
+        metamodel: config.metamodel.unwrap_or_default(),
+        default_roles: config.default_roles.unwrap_or_default(),
+        schema_attributes: config.schema_attributes.unwrap_or_default(),
+        annotations: config.annotations.iter().cloned().collect(),
+        cube_annotations: config.cube_annotations.iter().cloned().collect(),
+        sort: config.sort.unwrap_or_default(),
+        verify_output: config.verify_output,
+        lints: config.lints.iter().cloned().collect(),
+        naming: config.naming.iter().cloned().collect(),
+        include: config.include.iter().cloned().collect(),
+        exclude: config.exclude.iter().cloned().collect(),
+        virtual_cubes: Vec::new(),
+    };
+    prioritize(&mut inputs, options.conflicts)?;
+
+    Ok(Plan { inputs, options, output_path: config.output_path.clone() })
+}
+
+/// Merge the fragments listed in a manifest. `output_path`
+/// overrides the manifest's output.
+fn build_plan(manifest_path: &str, output_path: Option<String>) -> Result<Plan> {
+    let text = fs::read_to_string(manifest_path)
+        .chain_err(|| ErrorKind::Read(manifest_path.to_owned()))?;
+    let manifest = Manifest::from_toml(&text)
+        .chain_err(|| ErrorKind::Usage(format!("Could not load {}", manifest_path)))?;
+
+    // everything in the manifest is relative to it
+    let base = Path::new(manifest_path).parent().unwrap_or_else(|| Path::new(""));
+    let relative = |path: &str| base.join(path).to_str().expect("filepath is invalid str").to_owned();
+
+    let mut inputs = Vec::new();
+    for fragment in &manifest.fragments {
+        let path = relative(&fragment.path);
+        let paths = if is_glob(&path) {
+            expand_glob(&path)?
+        } else if fragment.optional && !Path::new(&path).exists() {
+            Vec::new()
+        } else {
+            vec![path.clone()]
+        };
+        if paths.is_empty() && !fragment.optional {
+            return Err(ErrorKind::Usage(format!("No files match {}", path)).into());
+        }
+        inputs.extend(paths.into_iter().map(|path| Input { path, encoding: fragment.encoding.clone(), priority: fragment.priority, prefix: fragment.prefix.clone() }));
+    }
+
+    let output_path = output_path.or_else(|| manifest.output.as_ref().map(|path| relative(path)));
+    Ok(Plan { inputs, options: manifest.merge_options(), output_path })
+}
+
+/// Order the inputs by priority, so the highest wins whatever
+/// the `conflicts` strategy: highest first, but lowest first
+/// where the last definition wins. Those with the same keep
+/// their order. Inputs the manifest gives no priority may
+/// declare their own; those which can't be read yet are left
+/// for reading them to report.
+fn prioritize(inputs: &mut [Input], conflicts: ConflictStrategy) -> Result<()> {
+    for input in inputs.iter_mut().filter(|input| input.priority.is_none() && input.path != STDIN_PATH) {
+        let fragment = match read_fragment(&input.path) {
+            Ok(buf) => match input.encoding {
+                Some(ref label) => encoding::decode_as(&buf, label),
+                None => encoding::decode(&buf),
+            },
+            Err(_) => continue,
+        };
+        if let Ok(fragment) = fragment {
+            input.priority = directives::priority(&fragment)
+                .chain_err(|| ErrorKind::Usage(format!("Could not load {}", display_path(&input.path))))?;
+        }
+    }
+    match conflicts {
+        ConflictStrategy::LastWins | ConflictStrategy::Overlay => inputs.sort_by_key(|input| input.priority.unwrap_or(0)),
+        _ => inputs.sort_by_key(|input| Reverse(input.priority.unwrap_or(0))),
+    }
+    Ok(())
+}
+
+/// Merge, then merge again whenever the inputs change, until
+/// interrupted. Errors in the fragments are reported and
+/// wait for the next change.
+fn watch(config: &Config) -> Result<()> {
+    if config.arg_files.iter().any(|path| path == STDIN_PATH) {
+        return Err(ErrorKind::Usage("stdin (-) can't be watched".to_owned()).into());
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    let mut watched = Vec::new();
+    for root in watch_roots(config) {
+        add_watch(&mut watcher, &mut watched, &root, RecursiveMode::Recursive)?;
+    }
+
+    let mut output = None;
+    loop {
+        match plan(config) {
+            Ok(plan) => {
+                let output_path = match plan.output_path {
+                    Some(ref path) => path.clone(),
+                    None => return Err(ErrorKind::Usage("--watch needs an output file".to_owned()).into()),
+                };
+                output = absolute_path(Path::new(&output_path));
+
+                // watch every input, in case some are outside the roots,
+                // and every file they include
+                for input in &plan.inputs {
+                    add_watch(&mut watcher, &mut watched, &parent_dir(&input.path), RecursiveMode::NonRecursive)?;
+                }
+                if let Ok(fragments) = read_inputs(&plan.inputs, &plan.options.scan) {
+                    for (path, _) in fragments.expanded.iter().flat_map(|expanded| &expanded.files[1..]) {
+                        add_watch(&mut watcher, &mut watched, &parent_dir(&path.to_string_lossy()), RecursiveMode::NonRecursive)?;
+                    }
+                }
+
+                match merge_inputs(&plan, config.stats) {
+                    Ok(()) => eprintln!("wrote {}", output_path),
+                    Err(err) => print_error(&err),
+                }
+            },
+            Err(err) => print_error(&err),
+        }
+
+        wait_for_change(&rx, output.as_ref())?;
     }
+}
 
-    let mut fragment_strs = Vec::new();
+/// The directories where new fragments may turn up
+fn watch_roots(config: &Config) -> Vec<PathBuf> {
+    if let Some(ref manifest_path) = config.manifest_path {
+        vec![parent_dir(manifest_path)]
+    } else if let Some(dir) = config.dir_path.as_ref().or(config.layout_path.as_ref()) {
+        vec![PathBuf::from(dir)]
+    } else {
+        config.arg_files.iter()
+            .filter(|arg| is_glob(arg))
+            .map(|pattern| glob_base(pattern))
+            .collect()
+    }
+}
 
-    for file_path in fragment_paths {
-        let mut f = File::open(file_path)?;
-        let mut buf = String::new();
+/// Watch `dir`, unless it's already watched
+fn add_watch(
+    watcher: &mut RecommendedWatcher,
+    watched: &mut Vec<(PathBuf, RecursiveMode)>,
+    dir: &Path,
+    mode: RecursiveMode,
+) -> Result<()>
+{
+    let dir = fs::canonicalize(dir)?;
+    let covered = watched.iter().any(|&(ref watched_dir, watched_mode)| {
+        *watched_dir == dir || (watched_mode == RecursiveMode::Recursive && dir.starts_with(watched_dir))
+    });
+    if !covered {
+        watcher.watch(&dir, mode)?;
+        watched.push((dir, mode));
+    }
+    Ok(())
+}
 
-        f.read_to_string(&mut buf)?;
-        fragment_strs.push(buf);
+/// Block until something other than the output changes, then
+/// until the changes settle
+fn wait_for_change(rx: &Receiver<notify::Result<Event>>, output: Option<&PathBuf>) -> Result<()> {
+    loop {
+        let event = rx.recv().map_err(|_| "File watcher stopped")??;
+        if !event.kind.is_access() && event.paths.iter().any(|path| !is_output(path, output)) {
+            break;
+        }
     }
+    while rx.recv_timeout(WATCH_SETTLE).is_ok() {}
+    Ok(())
+}
 
-    let res = fragments_to_schema(fragment_strs.as_slice())?;
+/// Whether `path` is the output, or the temporary file it's
+/// written through
+fn is_output(path: &Path, output: Option<&PathBuf>) -> bool {
+    let output = match output {
+        Some(output) => output,
+        None => return false,
+    };
+    if path == output {
+        return true;
+    }
+    let tmp_prefix = format!(".{}.", output.file_name().and_then(|name| name.to_str()).unwrap_or(""));
+    path.parent() == output.parent() && path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with(&tmp_prefix) && name.ends_with(".tmp"))
+}
 
-    match config.output_path {
-        Some(path) => {
-            let f = File::create(&path)?;
-            write(f, &res)?;
+/// The directory `path` is in
+fn parent_dir(path: &str) -> PathBuf {
+    match Path::new(path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_owned(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// The directory a glob pattern matches under
+fn glob_base(pattern: &str) -> PathBuf {
+    let base: PathBuf = Path::new(pattern).components()
+        .take_while(|c| !is_glob(&c.as_os_str().to_string_lossy()))
+        .collect();
+    if base.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        base
+    }
+}
+
+/// `path` made absolute, as the watcher reports paths
+fn absolute_path(path: &Path) -> Option<PathBuf> {
+    let file_name = path.file_name()?;
+    let dir = parent_dir(path.to_str()?);
+    fs::canonicalize(dir).ok().map(|dir| dir.join(file_name))
+}
+
+/// Merge the fragments without writing out the schema, and fail
+/// if anything was wrong with them, even if it's only a warning
+fn check(plan: &Plan, stats: bool) -> Result<()> {
+    let merged = merge_plan(plan, true)?;
+    if stats {
+        print_stats(io::stderr(), plan, &merged)?;
+    }
+    if !merged.warnings.is_empty() {
+        return Err(ErrorKind::Problems(merged.warnings.len()).into());
+    }
+    if !json_messages() {
+        eprintln!("ok: {} fragment(s) checked", plan.inputs.len());
+    }
+    Ok(())
+}
+
+/// Merge the fragments without writing out the schema, and
+/// print what would go into it instead: what each fragment
+/// contributes, and the order it's written in
+fn dry_run(plan: &Plan, stats: bool) -> Result<()> {
+    let merged = merge_plan(plan, false)?;
+    if stats {
+        print_stats(io::stderr(), plan, &merged)?;
+    }
+
+    let output = plan.output_path.as_deref().unwrap_or("stdout");
+    println!("Schema \"{}\" would be written to {}", merged.schema_name, output);
+
+    let describe = |element: &MergedElement| {
+        format!("{} {}", element.kind, element.name.as_deref().unwrap_or("(unnamed)"))
+    };
+
+    println!();
+    println!("Fragments, in the order given:");
+    for (index, input) in plan.inputs.iter().enumerate() {
+        println!("  {}", display_path(&input.path));
+        let mut contributed = merged.elements.iter()
+            .filter(|element| element.fragment == index)
+            .peekable();
+        if contributed.peek().is_none() {
+            println!("    (nothing)");
+        }
+        for element in contributed {
+            println!("    line {:<5} {}", element.line, describe(element));
+        }
+    }
+
+    println!();
+    println!("Output order:");
+    for (i, element) in merged.elements.iter().enumerate() {
+        println!(
+            "  {:>4}  {}  ({}, line {})",
+            i + 1,
+            describe(element),
+            display_path(&plan.inputs[element.fragment].path),
+            element.line
+        );
+    }
+    Ok(())
+}
+
+/// Read and merge the fragments, and write out the schema to
+/// the output path, or stdout
+fn merge_inputs(plan: &Plan, stats: bool) -> Result<()> {
+    let merged = merge_plan(plan, false)?;
+    if stats {
+        print_stats(io::stderr(), plan, &merged)?;
+    }
+    let res = merged.schema;
+
+    match plan.output_path {
+        Some(ref path) => {
+            write_file_atomic(Path::new(path), &res)
+                .chain_err(|| ErrorKind::Write(path.clone()))?;
         },
         None => {
-            write(std::io::stdout(), &res)?;
+            write(io::stdout(), &res)?;
+        }
+    }
+    Ok(())
+}
+
+/// Read the fragments and merge each schema they hold on its own,
+/// writing each to a file named for it in the output directory
+fn merge_by_schema_inputs(plan: &Plan) -> Result<()> {
+    let fragments = read_inputs(&plan.inputs, &plan.options.scan)?;
+    let dir = Path::new(plan.output_path.as_deref().expect("output is required"));
+    let merged = merge_by_schema(fragments.texts.as_slice(), &plan.options)
+        .map_err(|err| locate(err, &plan.inputs, &fragments))?;
+    let mut errors = 0;
+    for merged in merged.values() {
+        errors += report_findings(&plan.inputs, &fragments, merged, false);
+    }
+    if errors > 0 {
+        return Err(ErrorKind::Problems(errors).into());
+    }
+
+    fs::create_dir_all(dir).chain_err(|| ErrorKind::Write(dir.display().to_string()))?;
+    for (name, merged) in merged {
+        let path = dir.join(format!("{}.xml", file_stem(&name)));
+        let display = path.display().to_string();
+        write_file_atomic(&path, &merged.schema).chain_err(|| ErrorKind::Write(display.clone()))?;
+        info!("wrote schema {} to {}", name, display);
+    }
+    Ok(())
+}
+
+/// Merge the fragments, and print only the summary of what went
+/// into the schema, to stdout
+fn stats(plan: &Plan) -> Result<()> {
+    let merged = merge_plan(plan, false)?;
+    print_stats(io::stdout(), plan, &merged)
+}
+
+/// Print a summary of what went into the merged schema, in
+/// total and per fragment
+fn print_stats<W: Write>(mut wtr: W, plan: &Plan, merged: &Merged) -> Result<()> {
+    let total = merged.fragments.iter().fold(FragmentStats::default(), |total, stats| FragmentStats {
+        parameters: total.parameters + stats.parameters,
+        shared_dims: total.shared_dims + stats.shared_dims,
+        cubes: total.cubes + stats.cubes,
+        virtual_cubes: total.virtual_cubes + stats.virtual_cubes,
+        named_sets: total.named_sets + stats.named_sets,
+        roles: total.roles + stats.roles,
+        functions: total.functions + stats.functions,
+    });
+
+    writeln!(wtr, "fragments:         {}", merged.fragments.len())?;
+    writeln!(wtr, "parameters:        {}", total.parameters)?;
+    writeln!(wtr, "shared dimensions: {}", total.shared_dims)?;
+    writeln!(wtr, "cubes:             {}", total.cubes)?;
+    writeln!(wtr, "virtual cubes:     {}", total.virtual_cubes)?;
+    writeln!(wtr, "named sets:        {}", total.named_sets)?;
+    writeln!(wtr, "roles:             {}", total.roles)?;
+    writeln!(wtr, "functions:         {}", total.functions)?;
+    writeln!(wtr, "output size:       {} bytes", merged.schema.len())?;
+    writeln!(wtr)?;
+    writeln!(
+        wtr,
+        "{:>10} {:>6} {:>6} {:>14} {:>11} {:>6} {:>10}  fragment",
+        "parameters", "dims", "cubes", "virtual cubes", "named sets", "roles", "functions"
+    )?;
+    for (input, stats) in plan.inputs.iter().zip(&merged.fragments) {
+        writeln!(
+            wtr,
+            "{:>10} {:>6} {:>6} {:>14} {:>11} {:>6} {:>10}  {}",
+            stats.parameters,
+            stats.shared_dims,
+            stats.cubes,
+            stats.virtual_cubes,
+            stats.named_sets,
+            stats.roles,
+            stats.functions,
+            display_path(&input.path)
+        )?;
+    }
+    Ok(())
+}
+
+/// Split a schema into fragments laid out in `dir_path` the way
+/// `--layout` expects, so merging them gives the schema back
+fn split(schema_path: &str, dir_path: &str) -> Result<()> {
+    let source = read_schema(schema_path)?;
+    let split = split_schema(&source)
+        .map_err(|err| locate_file(err, schema_path, &source))?;
+
+    let dir = Path::new(dir_path);
+    let is_empty = |dir: &Path| fs::read_dir(dir).map(|mut entries| entries.next().is_none()).unwrap_or(false);
+    if dir.exists() && !is_empty(dir) {
+        return Err(ErrorKind::Usage(format!("{} already exists and isn't empty", dir_path)).into());
+    }
+
+    let mut files = vec![(dir.join(LAYOUT_SCHEMA_FILE), split.schema)];
+    let mut taken: Vec<PathBuf> = Vec::new();
+    for part in split.parts {
+        let sub_dir = dir.join(match part.kind {
+            ElementKind::Parameter => LAYOUT_DIRS[0],
+            ElementKind::SharedDimension => LAYOUT_DIRS[1],
+            ElementKind::Cube => LAYOUT_DIRS[2],
+            ElementKind::VirtualCube => LAYOUT_DIRS[3],
+            ElementKind::NamedSet => LAYOUT_DIRS[4],
+            ElementKind::Role => LAYOUT_DIRS[5],
+            ElementKind::UserDefinedFunction => LAYOUT_DIRS[6],
+        });
+        let stem = file_stem(part.name.as_deref().unwrap_or("unnamed"));
+        let mut path = sub_dir.join(format!("{}.xml", stem));
+        let mut n = 1;
+        while taken.contains(&path) {
+            n += 1;
+            path = sub_dir.join(format!("{}_{}.xml", stem, n));
+        }
+        taken.push(path.clone());
+        files.push((path, format!("{}\n", part.text)));
+    }
+
+    for (path, text) in files {
+        let display = path.to_str().expect("filepath is invalid str").to_owned();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).chain_err(|| ErrorKind::Write(display.clone()))?;
+        }
+        write_file_atomic(&path, &text).chain_err(|| ErrorKind::Write(display.clone()))?;
+        info!("wrote {}", display);
+    }
+    Ok(())
+}
+
+/// A file name for an element: its name, with anything but
+/// letters, digits, `-` and `_` replaced by `_`
+fn file_stem(name: &str) -> String {
+    let stem: String = name.trim()
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    if stem.is_empty() { "unnamed".to_owned() } else { stem }
+}
+
+/// Print the shared dimensions, cubes and virtual cubes which
+/// were added (+), removed (-) or changed (~) between two
+/// schemas
+fn diff(old_path: &str, new_path: &str) -> Result<()> {
+    let old = read_schema(old_path)?;
+    let new = read_schema(new_path)?;
+    let inputs = [
+        Input { path: old_path.to_owned(), encoding: None, priority: None, prefix: None },
+        Input { path: new_path.to_owned(), encoding: None, priority: None, prefix: None },
+    ];
+    let differences = diff_schemas(&old, &new)
+        .map_err(|err| locate(err, &inputs, &Fragments::new(&inputs, vec![old.clone(), new.clone()])))?;
+
+    let stdout = io::stdout();
+    let mut wtr = BufWriter::new(stdout.lock());
+    for difference in &differences {
+        writeln!(wtr, "{}", difference)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Write Markdown documentation of a schema to `output_path`,
+/// or stdout
+fn docs(schema_path: &str, output_path: Option<&str>) -> Result<()> {
+    let source = read_schema(schema_path)?;
+    let res = schema_docs(&source)
+        .map_err(|err| locate_file(err, schema_path, &source))?;
+
+    match output_path {
+        Some(path) => write_file_atomic(Path::new(path), &res).chain_err(|| ErrorKind::Write(path.to_owned())),
+        None => write(io::stdout(), &res),
+    }
+}
+
+/// Write a schema with the private dimensions repeated in
+/// several cubes made shared to `output_path`, or stdout
+fn hoist(schema_path: &str, output_path: Option<&str>, options: &ScanOptions) -> Result<()> {
+    let source = read_schema(schema_path)?;
+    let res = hoist_dimensions(&source, options)
+        .map_err(|err| locate_file(err, schema_path, &source))?;
+    for (name, cubes) in &res.dimensions {
+        info!("hoisted dimension {} from {}", name, cubes.join(", "));
+    }
+
+    match output_path {
+        Some(path) => write_file_atomic(Path::new(path), &res.schema).chain_err(|| ErrorKind::Write(path.to_owned())),
+        None => write(io::stdout(), &res.schema),
+    }
+}
+
+/// Reformat fragments or schemas in place, or print the
+/// reformatted text if the path is `-`. With `check`, only
+/// report the files which would change.
+fn fmt(args: Vec<String>, check: bool) -> Result<()> {
+    let paths = expand_globs(args)?;
+    let mut unformatted = 0;
+    for path in &paths {
+        let source = read_schema(path)?;
+        let res = format(&source)
+            .map_err(|err| locate_file(err, path, &source))?;
+
+        if check {
+            if res != source {
+                unformatted += 1;
+                report(Severity::Warning, "unformatted", &format!("{} is not formatted", display_path(path)), None, None);
+            }
+        } else if path == STDIN_PATH {
+            write(io::stdout(), &res)?;
+        } else if res != source {
+            write_file_atomic(Path::new(path), &res).chain_err(|| ErrorKind::Write(path.clone()))?;
+            info!("formatted {}", path);
         }
     }
+
+    if unformatted > 0 {
+        return Err(ErrorKind::Unformatted(unformatted).into());
+    }
     Ok(())
 }
 
+/// Read and decode a whole schema, or fragment
+fn read_schema(path: &str) -> Result<String> {
+    let file_path = display_path(path);
+    let buf = read_fragment(path).chain_err(|| ErrorKind::Read(file_path.to_owned()))?;
+    encoding::decode(&buf).chain_err(|| ErrorKind::Decode(file_path.to_owned()))
+}
+
+/// Read and merge the fragments, printing any warnings. Where
+/// warnings `fail` the run, they're printed even under `-q`.
+fn merge_plan(plan: &Plan, fail: bool) -> Result<Merged> {
+    let inputs = &plan.inputs;
+    let fragments = read_inputs(inputs, &plan.options.scan)?;
+
+    let started = Instant::now();
+    let merged = merge_fragments(fragments.texts.as_slice(), &plan.options)
+        .map_err(|err| locate(err, inputs, &fragments))?;
+    let errors = report_findings(inputs, &fragments, &merged, fail);
+    info!("merged {} fragment(s) in {:?}", inputs.len(), started.elapsed());
+    for name in &merged.generated {
+        info!("generated virtual cube {}", name);
+    }
+    if errors > 0 {
+        return Err(ErrorKind::Problems(errors).into());
+    }
+    Ok(merged)
+}
+
+/// Read and decode the fragments, with their includes and
+/// prefixes
+fn read_inputs(inputs: &[Input], scan: &ScanOptions) -> Result<Fragments> {
+    if inputs.is_empty() {
+        return Err(ErrorKind::Usage("No files found".to_owned()).into());
+    }
+
+    if inputs.iter().filter(|input| input.path == STDIN_PATH).count() > 1 {
+        return Err(ErrorKind::Usage("stdin (-) can only be given once".to_owned()).into());
+    }
+
+    let mut texts = Vec::new();
+    let mut expanded = Vec::new();
+
+    let started = Instant::now();
+    for input in inputs {
+        let file_path = display_path(&input.path);
+        let buf = read_fragment(&input.path)
+            .chain_err(|| ErrorKind::Read(file_path.to_owned()))?;
+        let fragment = match input.encoding {
+            Some(ref label) => encoding::decode_as(&buf, label),
+            None => encoding::decode(&buf),
+        };
+        let fragment = fragment.chain_err(|| ErrorKind::Decode(file_path.to_owned()))?;
+        // a file that can't be read is reported as such, rather
+        // than as a problem with the includes
+        let mut unread = None;
+        let fragment = directives::expand_includes(&fragment, Path::new(&input.path), &mut |path: &Path| {
+            read_include(path).map_err(|err| {
+                let message = err.to_string();
+                unread = Some(err);
+                message.into()
+            })
+        });
+        if let Some(err) = unread {
+            return Err(err);
+        }
+        let fragment = fragment
+            .chain_err(|| ErrorKind::Usage(format!("Could not load the includes of {}", file_path)))?;
+        match input.prefix {
+            Some(ref prefix) => texts.push(prefix_names(&fragment.text, prefix, scan).into_owned()),
+            None => texts.push(fragment.text.clone()),
+        }
+        expanded.push(fragment);
+        info!("read {} ({} bytes)", file_path, buf.len());
+    }
+    debug!("read {} fragment(s) in {:?}", inputs.len(), started.elapsed());
+    Ok(Fragments { texts, expanded })
+}
+
+/// Print the findings of a merge, and what each fragment
+/// contributed, returning how many were errors. Warnings which
+/// `fail` the run are printed even when warnings aren't.
+fn report_findings(inputs: &[Input], fragments: &Fragments, merged: &Merged, fail: bool) -> usize {
+    // denied findings are errors, so they're printed even when
+    // warnings aren't
+    let findings = merged.errors.iter()
+        .map(|finding| (Severity::Error, finding))
+        .chain(merged.warnings.iter()
+            .filter(|_| fail || log_enabled!(Level::Warn))
+            .map(|finding| (Severity::Warning, finding)));
+    for (severity, warning) in findings {
+        let (path, source, line, column) = fragments.position(inputs, warning.fragment, warning.line, warning.column);
+        let snippet = Snippet { path: &path, source, line, column };
+        let message = match warning.first {
+            Some((fragment, line)) => {
+                let (path, _, line, _) = fragments.position(inputs, fragment, line, 1);
+                format!("{}, first in {} at line {}", warning.message, path, line)
+            },
+            None => warning.message.clone(),
+        };
+        report(severity, warning.rule, &message, Some(&snippet), warning.element.as_deref());
+    }
+    for (input, stats) in inputs.iter().zip(&merged.fragments) {
+        info!(
+            "{}: {} parameter(s), {} shared dimension(s), {} cube(s), {} virtual cube(s), {} named set(s), {} role(s), {} function(s)",
+            display_path(&input.path),
+            stats.parameters,
+            stats.shared_dims,
+            stats.cubes,
+            stats.virtual_cubes,
+            stats.named_sets,
+            stats.roles,
+            stats.functions
+        );
+    }
+    merged.errors.len()
+}
+
+/// Point an error about the fragments at their files, so it can
+/// be shown
+fn locate(err: mondrian_schema_cat::error::Error, inputs: &[Input], fragments: &Fragments) -> Error {
+    use mondrian_schema_cat::error::ErrorKind as MonCatErrorKind;
+
+    let path = |index: usize| display_path(&inputs[index].path).to_owned();
+    let located = |index: usize, line, column, message: String| {
+        let (path, source, line, column) = fragments.position(inputs, index, line, column);
+        Located { path, source: source.to_owned(), line, column, message }
+    };
+    let kind = match *err.kind() {
+        MonCatErrorKind::MalformedFragment(index, line, column, ref message) => {
+            ErrorKind::Malformed(located(index, line, column, format!("Malformed fragment: {}", message)))
+        },
+        MonCatErrorKind::SchemaNameMismatch(ref first, first_index, ref second, second_index) => {
+            ErrorKind::SchemaNameMismatch(first.clone(), path(first_index), second.clone(), path(second_index))
+        },
+        _ => return err.into(),
+    };
+    kind.into()
+}
+
+/// Point an error about a whole schema at its file
+fn locate_file(err: mondrian_schema_cat::error::Error, path: &str, source: &str) -> Error {
+    let inputs = [Input { path: path.to_owned(), encoding: None, priority: None, prefix: None }];
+    locate(err, &inputs, &Fragments::new(&inputs, vec![source.to_owned()]))
+}
+
 fn get_fragment_paths_dir(dir_path: &str) -> Result<Vec<String>> {
     fn is_hidden(entry: &DirEntry) -> bool {
         entry.file_name()
@@ -116,11 +1185,13 @@ fn get_fragment_paths_dir(dir_path: &str) -> Result<Vec<String>> {
     }
 
     if !fs::metadata(dir_path)?.is_dir() {
-        return Err("Path is not a directory".into());
+        return Err(ErrorKind::Usage(format!("{} is not a directory", dir_path)).into());
     }
 
     let mut res = Vec::new();
-    let walker = WalkDir::new(dir_path).into_iter();
+    let walker = WalkDir::new(dir_path)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+        .into_iter();
     for entry in walker.filter_entry(|e| (!is_hidden(e)) && is_xml(e)) {
         let entry = entry?;
         if entry.file_type().is_file() {
@@ -131,36 +1202,312 @@ fn get_fragment_paths_dir(dir_path: &str) -> Result<Vec<String>> {
     Ok(res)
 }
 
+/// Find the fragments in a directory laid out by convention:
+/// `schema.xml`, then the xml files anywhere under `parameters/`,
+/// `dimensions/`, `cubes/`, `virtual_cubes/`, `named_sets/`,
+/// `roles/` and `functions/`, in that order. Any of them may be
+/// missing.
+fn get_fragment_paths_layout(dir_path: &str) -> Result<Vec<String>> {
+    let dir = Path::new(dir_path);
+    if !fs::metadata(dir)?.is_dir() {
+        return Err(ErrorKind::Usage(format!("{} is not a directory", dir_path)).into());
+    }
+
+    let mut res = Vec::new();
+    let schema_path = dir.join(LAYOUT_SCHEMA_FILE);
+    if schema_path.is_file() {
+        res.push(schema_path.to_str().expect("filepath is invalid str").to_owned());
+    }
+    for sub_dir in LAYOUT_DIRS {
+        let sub_path = dir.join(sub_dir);
+        if sub_path.is_dir() {
+            res.extend(get_fragment_paths_dir(sub_path.to_str().expect("filepath is invalid str"))?);
+        }
+    }
+
+    Ok(res)
+}
+
+/// Read the raw bytes of a fragment, from stdin if `path` is `-`
+fn read_fragment(path: &str) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    if path == STDIN_PATH {
+        io::stdin().read_to_end(&mut buf)?;
+    } else {
+        File::open(path)?.read_to_end(&mut buf)?;
+    }
+    Ok(buf)
+}
+
+/// Read and decode a fragment included by another
+fn read_include(path: &Path) -> Result<String> {
+    let file_path = path.display().to_string();
+    let buf = fs::read(path).chain_err(|| ErrorKind::Read(file_path.clone()))?;
+    encoding::decode(&buf).chain_err(|| ErrorKind::Decode(file_path))
+}
+
+/// The offset of 1-based `line` and `column` in `text`, or of
+/// the end of the line if the column is past it
+fn offset_at(text: &str, line: usize, column: usize) -> usize {
+    let start: usize = text.split_inclusive('\n').take(line.saturating_sub(1)).map(str::len).sum();
+    let rest = &text[start..];
+    let end = rest.find('\n').unwrap_or(rest.len());
+    start + rest[..end].char_indices().nth(column.saturating_sub(1)).map_or(end, |(i, _)| i)
+}
+
+/// How to refer to a fragment's path in messages
+fn display_path(path: &str) -> &str {
+    if path == STDIN_PATH {
+        "<stdin>"
+    } else {
+        path
+    }
+}
+
+/// Expand the glob patterns among the file args into the
+/// files they match, sorted so output is reproducible. Args
+/// which aren't patterns are kept as is, in the order given.
+fn expand_globs(args: Vec<String>) -> Result<Vec<String>> {
+    let mut res = Vec::new();
+    for arg in args {
+        if !is_glob(&arg) {
+            res.push(arg);
+            continue;
+        }
+
+        let paths = expand_glob(&arg)?;
+        if paths.is_empty() {
+            return Err(ErrorKind::Usage(format!("No files match {}", arg)).into());
+        }
+        res.extend(paths);
+    }
+    Ok(res)
+}
+
+fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// The files matching a glob pattern, sorted
+fn expand_glob(pattern: &str) -> Result<Vec<String>> {
+    let mut paths = Vec::new();
+    for entry in glob::glob(pattern)? {
+        let path: PathBuf = entry?;
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths.into_iter().map(|p| p.to_str().expect("filepath is invalid str").to_owned()).collect())
+}
+
+/// Which subcommand to run
+enum Command {
+    /// Merge the fragments, the default
+    Merge,
+    /// Check the fragments without writing the schema
+    Lint,
+    /// Print what went into the schema without writing it
+    Stats,
+    Split { schema_path: String, dir_path: String },
+    Diff { old_path: String, new_path: String },
+    Docs { schema_path: String },
+    Hoist { schema_path: String },
+    Fmt { paths: Vec<String>, check: bool },
+    Completions(Shell),
+}
+
 struct Config {
+    command: Command,
     arg_files: Vec<String>,
     dir_path: Option<String>,
+    layout_path: Option<String>,
     output_path: Option<String>,
+    manifest_path: Option<String>,
+    watch: bool,
+    check: bool,
+    dry_run: bool,
+    by_schema: bool,
+    stats: bool,
+    verbosity: i64,
+    /// None to color only when stderr is a terminal
+    color: Option<bool>,
+    json_messages: bool,
+    xml_declaration: bool,
+    ignore_case: bool,
+    normalize_case: bool,
+    only_schema: Option<String>,
+    schema_name: Option<String>,
+    default_schema_name: Option<String>,
+    combine: bool,
+    /// None unless given, so a manifest's policy can stand
+    duplicates: Option<DuplicatePolicy>,
+    dedupe_identical: bool,
+    conflicts: Option<ConflictStrategy>,
+    merge_cubes: bool,
+    default_roles: Option<DefaultRolePolicy>,
+    schema_attributes: Option<SchemaAttributePolicy>,
+    metamodel: Option<Metamodel>,
+    sort: Option<SortOrder>,
+    namespace: Option<Namespace>,
+    keep_comments: bool,
+    verify_output: bool,
+    /// Levels of lint rules, in the order given, so later ones win
+    lints: Vec<(String, lint::Level)>,
+    /// Naming conventions, element to pattern
+    naming: Vec<(String, String)>,
+    include: Vec<(String, String)>,
+    exclude: Vec<(String, String)>,
+    annotations: Vec<(String, String)>,
+    cube_annotations: Vec<(String, String)>,
 }
 
-fn get_cli_config() -> Config {
-    let app_m = App::new("moncat")
+fn build_cli() -> App<'static, 'static> {
+    App::new(APP_NAME)
         .version(crate_version!())
         .author(crate_authors!())
         .about(crate_description!())
         .setting(AppSettings::ArgRequiredElseHelp)
-        .arg(Arg::with_name("arg_files")
-            .takes_value(true)
-            .value_name("PATH")
+        .arg(Arg::with_name("verbose")
+            .short("v")
+            .long("verbose")
             .multiple(true)
-            .help("file paths to fragments. Specify multiple"))
-        .arg(Arg::with_name("dir_path")
-            .short("d")
-            .long("dir")
+            .global(true)
+            .help("report the fragments read and what each contributed. Repeat for more detail"))
+        .arg(Arg::with_name("quiet")
+            .short("q")
+            .long("quiet")
+            .global(true)
+            .conflicts_with("verbose")
+            .help("don't print warnings"))
+        .arg(Arg::with_name("color")
+            .long("color")
             .takes_value(true)
-            .value_name("PATH")
-            .conflicts_with("arg_files")
-            .help("optional dir path, exclusive of files from args"))
-        .arg(Arg::with_name("output_path")
-            .short("o")
-            .long("output")
+            .value_name("WHEN")
+            .possible_values(&["auto", "always", "never"])
+            .global(true)
+            .help("color errors and warnings: auto (when stderr is a terminal and NO_COLOR isn't set), always or never"))
+        .arg(Arg::with_name("message_format")
+            .long("message-format")
             .takes_value(true)
-            .value_name("PATH")
-            .help("optional output path, otherwise stdout"))
+            .value_name("FORMAT")
+            .possible_values(&["human", "json"])
+            .global(true)
+            .help("print errors and warnings for people (the default), or as json records, one per line"))
+        // without a subcommand, moncat merges
+        .args(&input_args())
+        .args(&merge_args())
+        .args(&lint_args())
+        .args(&output_args())
+        .subcommand(SubCommand::with_name("cat")
+            .about("merge fragments into a schema, the same as without a subcommand")
+            .args(&input_args())
+            .args(&merge_args())
+            .args(&lint_args())
+            .args(&output_args()))
+        .subcommand(SubCommand::with_name("lint")
+            .about("check fragments, failing on any error or warning, without writing the schema")
+            .args(&input_args())
+            .args(&merge_args())
+            .args(&lint_args()))
+        .subcommand(SubCommand::with_name("stats")
+            .about("print what each fragment contributes to the schema, without writing it")
+            .args(&input_args())
+            .args(&merge_args())
+            .args(&lint_args()))
+        .subcommand(SubCommand::with_name("split")
+            .about("split a schema into fragments, laid out for --layout")
+            .arg(Arg::with_name("schema_path")
+                .required(true)
+                .value_name("SCHEMA")
+                .help("schema to split, or - for stdin"))
+            .arg(Arg::with_name("output_dir")
+                .short("o")
+                .long("output")
+                .required(true)
+                .takes_value(true)
+                .value_name("DIR")
+                .help("dir to write schema.xml, parameters/, dimensions/, cubes/, virtual_cubes/, named_sets/, roles/ and functions/ to, which must be empty")))
+        .subcommand(SubCommand::with_name("diff")
+            .about("print the shared dimensions, cubes and virtual cubes added (+), removed (-) or changed (~)")
+            .arg(Arg::with_name("old_path")
+                .required(true)
+                .value_name("OLD")
+                .help("schema before"))
+            .arg(Arg::with_name("new_path")
+                .required(true)
+                .value_name("NEW")
+                .help("schema after")))
+        .subcommand(SubCommand::with_name("docs")
+            .about("document a schema's dimensions, cubes and measures in Markdown")
+            .arg(Arg::with_name("schema_path")
+                .required(true)
+                .value_name("SCHEMA")
+                .help("schema to document, or - for stdin"))
+            .arg(Arg::with_name("output_path")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("optional output path, otherwise stdout")))
+        .subcommand(SubCommand::with_name("hoist")
+            .about("make private dimensions repeated in several cubes shared")
+            .arg(Arg::with_name("schema_path")
+                .required(true)
+                .value_name("SCHEMA")
+                .help("schema to rewrite, or - for stdin"))
+            .arg(Arg::with_name("output_path")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("optional output path, otherwise stdout")))
+        .subcommand(SubCommand::with_name("fmt")
+            .about("reformat fragments or schemas in place, one tag per line and indented")
+            .arg(Arg::with_name("paths")
+                .required(true)
+                .multiple(true)
+                .value_name("PATH")
+                .help("files to reformat, or quoted glob patterns, or - to print stdin reformatted"))
+            .arg(Arg::with_name("check")
+                .long("check")
+                .help("only report the files which aren't formatted, failing if there are any")))
+        .subcommand(SubCommand::with_name("completions")
+            .about("print a completion script for a shell to stdout")
+            .arg(Arg::with_name("shell")
+                .required(true)
+                .possible_values(&Shell::variants())
+                .help("shell to complete in")))
+        .subcommand(SubCommand::with_name("build")
+            .about("merge the fragments listed in a manifest")
+            .arg(Arg::with_name("manifest_path")
+                .takes_value(true)
+                .value_name("MANIFEST")
+                .default_value("schema.toml")
+                .help("manifest listing the fragments and how to merge them"))
+            .args(&merge_args())
+            .args(&lint_args())
+            .arg(Arg::with_name("output_path")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("output path, overriding the manifest's"))
+            .arg(Arg::with_name("watch")
+                .short("w")
+                .long("watch")
+                .help("merge again whenever a fragment or the manifest changes, until interrupted"))
+            .arg(Arg::with_name("check")
+                .long("check")
+                .conflicts_with_all(&["output_path", "watch"])
+                .help("only check the fragments, failing on any error or warning, without writing the schema"))
+            .arg(Arg::with_name("dry_run")
+                .long("dry-run")
+                .conflicts_with_all(&["watch", "check"])
+                .help("print what each fragment contributes and the order it's merged in, without writing the schema"))
+            .arg(Arg::with_name("stats")
+                .long("stats")
+                .help("print a summary of what each fragment contributed to the schema")))
         .after_help("ABOUT:\n\
             \tA utility for concatenating together fragments of a Mondrian schema.\n\
             \n\
@@ -172,24 +1519,428 @@ fn get_cli_config() -> Config {
             \tand then concatenates the fragement sections in the correct\n\
             \torder (schema wraps shared dims and then cubes, in that order).\n\
             \n\
-            \tFragments can be any of the above three in any combination, but\n\
-            \teach fragment's internals must be in the same order as a full schema.")
-        .get_matches();
+            \tFragments can be any of the above three in any combination, and\n\
+            \tin any order within a fragment.\n\
+            \n\
+            \tMerging is the default; the subcommands do the rest.\n\
+            \n\
+            EXIT CODES:\n\
+            \t0  success\n\
+            \t1  internal error\n\
+            \t2  usage error: bad arguments or manifest\n\
+            \t3  i/o error: a file couldn't be read or written\n\
+            \t4  parse error: a fragment is malformed or can't be decoded\n\
+            \t5  validation error: schema names don't match, a lint rule at level\n\
+            \t   deny found problems, --check or lint found problems, or\n\
+            \t   fmt --check found unformatted files")
+}
 
-    let arg_files = app_m.values_of("arg_files");
-    let arg_files = if arg_files.is_some() {
-        arg_files.unwrap().map(|s| s.to_owned()).collect()
-    } else {
-        Vec::new()
+/// The args choosing the fragments to merge, and how to read
+/// them: for merging, and for the subcommands which merge first
+fn input_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("arg_files")
+            .takes_value(true)
+            .value_name("PATH")
+            .multiple(true)
+            .help("file paths to fragments, or quoted glob patterns like 'schemas/**/*.xml', or - for stdin. Specify multiple"),
+        Arg::with_name("dir_path")
+            .short("d")
+            .long("dir")
+            .takes_value(true)
+            .value_name("PATH")
+            .conflicts_with("arg_files")
+            .help("optional dir path, exclusive of files from args"),
+        Arg::with_name("layout_path")
+            .short("l")
+            .long("layout")
+            .takes_value(true)
+            .value_name("PATH")
+            .conflicts_with_all(&["arg_files", "dir_path"])
+            .help("dir laid out as schema.xml, parameters/, dimensions/, cubes/, virtual_cubes/, named_sets/, roles/ and functions/, merged in that order"),
+        Arg::with_name("xml_declaration")
+            .long("xml-declaration")
+            .help("emit an xml declaration at the top of the schema"),
+        Arg::with_name("ignore_case")
+            .long("ignore-case")
+            .help("recognize elements regardless of case, e.g. <cube>"),
+        Arg::with_name("normalize_case")
+            .long("normalize-case")
+            .requires("ignore_case")
+            .help("rewrite element names in the output to Mondrian's casing"),
+        Arg::with_name("only_schema")
+            .long("only-schema")
+            .takes_value(true)
+            .value_name("NAME")
+            .help("only merge schema blocks with this name, when fragments hold several schemas"),
+        Arg::with_name("ns_prefix")
+            .long("ns-prefix")
+            .takes_value(true)
+            .value_name("PREFIX")
+            .conflicts_with("ns_uri")
+            .help("namespace prefix of Mondrian elements, e.g. m for <m:Cube>"),
+        Arg::with_name("ns_uri")
+            .long("ns-uri")
+            .takes_value(true)
+            .value_name("URI")
+            .help("namespace uri of Mondrian elements, prefix is taken from each fragment"),
+        Arg::with_name("keep_comments")
+            .long("keep-comments")
+            .help("keep the comments just before each cube or dimension"),
+        Arg::with_name("verify_output")
+            .long("verify-output")
+            .help("check the merged schema is well formed xml before writing it"),
+        Arg::with_name("validate_structure")
+            .long("validate-structure")
+            .help("also warn about anything the Mondrian 3 schema doesn't allow where it is, or required attributes left out; it's a built-in model of the schema, not the XSD, and doesn't check the order or number of elements"),
+        Arg::with_name("require_captions")
+            .long("require-captions")
+            .help("warn about cubes, dimensions, hierarchies, levels and measures without a caption"),
+        Arg::with_name("require_descriptions")
+            .long("require-descriptions")
+            .help("warn about cubes, dimensions, hierarchies, levels and measures without a description"),
+    ]
+}
+
+/// The args for how to merge the fragments, given to every
+/// subcommand which merges
+fn merge_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("schema_name")
+            .long("schema-name")
+            .takes_value(true)
+            .value_name("NAME")
+            .help("name the merged schema this, instead of the name in the fragments"),
+        Arg::with_name("default_schema_name")
+            .long("default-schema-name")
+            .takes_value(true)
+            .value_name("NAME")
+            .help("name the merged schema this if none of the fragments has a Schema tag"),
+        Arg::with_name("combine")
+            .long("combine")
+            .requires("schema_name")
+            .help("merge schemas with different names into one, named with --schema-name"),
+        Arg::with_name("include")
+            .long("include")
+            .takes_value(true)
+            .value_name("ELEMENT=REGEX")
+            .multiple(true)
+            .number_of_values(1)
+            .validator(filter)
+            .help("only merge the ELEMENTs, like Cube or VirtualCube, whose names match REGEX as a whole"),
+        Arg::with_name("exclude")
+            .long("exclude")
+            .takes_value(true)
+            .value_name("ELEMENT=REGEX")
+            .multiple(true)
+            .number_of_values(1)
+            .validator(filter)
+            .help("leave out the ELEMENTs whose names match REGEX as a whole"),
+        Arg::with_name("duplicates")
+            .long("duplicates")
+            .takes_value(true)
+            .value_name("POLICY")
+            .possible_values(&["error", "warn", "allow"])
+            .help("whether shared dimensions or cubes with the same name are an error, a warning (the default) or allowed"),
+        Arg::with_name("dedupe_identical")
+            .long("dedupe-identical")
+            .help("leave out shared dimensions and cubes identical to one with the same name, instead of reporting them"),
+        Arg::with_name("conflicts")
+            .long("conflicts")
+            .takes_value(true)
+            .value_name("STRATEGY")
+            .possible_values(&["keep-all", "error", "first-wins", "last-wins", "rename-with-suffix", "overlay"])
+            .help("how elements with the same name are merged: all kept and reported (the default), all kept with each an error, the first or the last kept, later ones renamed Name_2, Name_3..., or the last put in place of the first"),
+        Arg::with_name("merge_cubes")
+            .long("merge-cubes")
+            .help("merge cubes with the same name into one, each adding its dimensions, measures and so on"),
+        Arg::with_name("annotate")
+            .long("annotate")
+            .takes_value(true)
+            .value_name("NAME=VALUE")
+            .multiple(true)
+            .number_of_values(1)
+            .validator(annotation)
+            .help("set the schema annotation NAME to VALUE, e.g. to tag a build with its environment"),
+        Arg::with_name("annotate_cubes")
+            .long("annotate-cubes")
+            .takes_value(true)
+            .value_name("NAME=VALUE")
+            .multiple(true)
+            .number_of_values(1)
+            .validator(annotation)
+            .help("set the annotation NAME to VALUE on every cube"),
+        Arg::with_name("metamodel")
+            .long("metamodel")
+            .takes_value(true)
+            .value_name("VERSION")
+            .possible_values(&["auto", "3", "4"])
+            .help("the Mondrian version the fragments are written for; auto (the default) looks for 4.x metamodelVersions and PhysicalSchemas"),
+        Arg::with_name("default_roles")
+            .long("default-roles")
+            .takes_value(true)
+            .value_name("POLICY")
+            .possible_values(&["error", "first", "last", "drop"])
+            .help("when fragments give different defaultRoles, report an error (the default), keep the first or last, or drop it"),
+        Arg::with_name("schema_attributes")
+            .long("schema-attributes")
+            .takes_value(true)
+            .value_name("POLICY")
+            .possible_values(&["error", "first", "last", "concat"])
+            .help("when fragments give other Schema attributes different values, report an error (the default), keep the first or last, or join them"),
+        Arg::with_name("sort")
+            .long("sort")
+            .takes_value(true)
+            .value_name("ORDER")
+            .possible_values(&["input", "name", "dependency"])
+            .help("write shared dimensions, cubes and virtual cubes in input order (the default), sorted by name, or by name with each in the order of its first use"),
+    ]
+}
+
+/// Check an annotation given as NAME=VALUE
+fn annotation(annotation: String) -> ::std::result::Result<(), String> {
+    match annotation.split_once('=') {
+        Some((name, _)) if !name.is_empty() => Ok(()),
+        _ => Err(format!("expected NAME=VALUE, like environment=staging, not {}", annotation)),
+    }
+}
+
+/// Check a filter given as ELEMENT=REGEX
+fn filter(filter: String) -> ::std::result::Result<(), String> {
+    match filter.split_once('=') {
+        Some((element, _)) if !element.is_empty() => Ok(()),
+        _ => Err(format!("expected ELEMENT=REGEX, like Cube=Sales.*, not {}", filter)),
+    }
+}
+
+/// The args setting the levels of lint rules, given to every
+/// subcommand which merges
+fn lint_args() -> Vec<Arg<'static, 'static>> {
+    let levels = [
+        ("allow", "leave out problems found by RULE"),
+        ("warn", "report problems found by RULE as warnings"),
+        ("deny", "report problems found by RULE as errors, failing the merge"),
+    ];
+    let mut args: Vec<_> = levels.iter()
+        .map(|&(level, help)| Arg::with_name(level)
+            .long(level)
+            .takes_value(true)
+            .value_name("RULE")
+            .multiple(true)
+            .number_of_values(1)
+            .validator(|rule| match lint::rule(&rule) {
+                Some(_) => Ok(()),
+                None => Err(format!("unknown lint rule {}", rule)),
+            })
+            .help(help))
+        .collect();
+    args.push(Arg::with_name("naming")
+        .long("naming")
+        .takes_value(true)
+        .value_name("ELEMENT=REGEX")
+        .multiple(true)
+        .number_of_values(1)
+        .validator(|convention| match convention.split_once('=') {
+            Some((element, _)) if !element.is_empty() => Ok(()),
+            _ => Err(format!("expected ELEMENT=REGEX, like Cube=[A-Z].*, not {}", convention)),
+        })
+        .help("warn about names of ELEMENTs which don't match REGEX as a whole"));
+    args
+}
+
+/// The args for what to do with the merged schema
+fn output_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("output_path")
+            .short("o")
+            .long("output")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("optional output path, otherwise stdout. The file is only replaced once the whole schema is written"),
+        Arg::with_name("watch")
+            .short("w")
+            .long("watch")
+            .requires("output_path")
+            .help("merge again whenever a fragment changes, until interrupted"),
+        Arg::with_name("check")
+            .long("check")
+            .conflicts_with_all(&["output_path", "watch"])
+            .help("only check the fragments, failing on any error or warning, without writing the schema"),
+        Arg::with_name("dry_run")
+            .long("dry-run")
+            .conflicts_with_all(&["watch", "check"])
+            .help("print what each fragment contributes and the order it's merged in, without writing the schema"),
+        Arg::with_name("stats")
+            .long("stats")
+            .help("print a summary of what each fragment contributed to the schema"),
+        Arg::with_name("by_schema")
+            .long("by-schema")
+            .requires("output_path")
+            .conflicts_with_all(&["watch", "dry_run", "stats"])
+            .help("merge each schema the fragments hold on its own, writing NAME.xml for each into the output directory"),
+    ]
+}
+
+fn get_cli_config() -> Config {
+    let app_m = build_cli().get_matches_safe().unwrap_or_else(|err| {
+        // help and version aren't errors
+        if !err.use_stderr() {
+            err.exit();
+        }
+        eprintln!("{}", err.message);
+        process::exit(EXIT_USAGE);
+    });
+
+    // the merge args are given to the subcommands which merge,
+    // or without a subcommand
+    let (name, sub_m) = app_m.subcommand();
+    let sub_m = sub_m.unwrap_or(&app_m);
+    let merge_m = match name {
+        "cat" | "lint" | "stats" => sub_m,
+        _ => &app_m,
+    };
+    let value = |m: &clap::ArgMatches, name| m.value_of(name).map(|s| s.to_owned());
+    let values = |m: &clap::ArgMatches, name| match m.values_of(name) {
+        Some(values) => values.map(|s| s.to_owned()).collect(),
+        None => Vec::new(),
     };
 
+    let command = match name {
+        "lint" => Command::Lint,
+        "stats" => Command::Stats,
+        "split" => Command::Split {
+            schema_path: value(sub_m, "schema_path").expect("schema is required"),
+            dir_path: value(sub_m, "output_dir").expect("output is required"),
+        },
+        "diff" => Command::Diff {
+            old_path: value(sub_m, "old_path").expect("old is required"),
+            new_path: value(sub_m, "new_path").expect("new is required"),
+        },
+        "docs" => Command::Docs { schema_path: value(sub_m, "schema_path").expect("schema is required") },
+        "hoist" => Command::Hoist { schema_path: value(sub_m, "schema_path").expect("schema is required") },
+        "fmt" => Command::Fmt { paths: values(sub_m, "paths"), check: sub_m.is_present("check") },
+        "completions" => Command::Completions(
+            sub_m.value_of("shell")
+                .map(|shell| shell.parse().expect("shell is one of the possible values"))
+                .expect("shell is required")
+        ),
+        _ => Command::Merge,
+    };
+
+    let namespace = value(merge_m, "ns_prefix")
+        .map(Namespace::Prefix)
+        .or_else(|| value(merge_m, "ns_uri").map(Namespace::Uri));
+
+    let build_m = app_m.subcommand_matches("build");
+
+    // the shorthands come first, so a level given for the same
+    // rule wins
+    let mut lints = Vec::new();
+    let shorthands = [
+        ("validate_structure", "invalid-structure"),
+        ("require_captions", "missing-caption"),
+        ("require_descriptions", "missing-description"),
+    ];
+    for &(arg, rule) in &shorthands {
+        if merge_m.is_present(arg) {
+            lints.push((rule.to_owned(), lint::Level::Warn));
+        }
+    }
+    let mut levels = Vec::new();
+    for &(arg, level) in &[("allow", lint::Level::Allow), ("warn", lint::Level::Warn), ("deny", lint::Level::Deny)] {
+        if let (Some(indices), Some(rules)) = (sub_m.indices_of(arg), sub_m.values_of(arg)) {
+            levels.extend(indices.zip(rules).map(|(index, rule)| (index, rule.to_owned(), level)));
+        }
+    }
+    levels.sort_by_key(|&(index, _, _)| index);
+    lints.extend(levels.into_iter().map(|(_, rule, level)| (rule, level)));
+    let pairs = |name| sub_m.values_of(name)
+        .map(|pairs| pairs
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .collect())
+        .unwrap_or_default();
+
      Config {
-         arg_files: arg_files,
-         dir_path: app_m.value_of("dir_path").map(|s| s.to_owned()),
-         output_path: app_m.value_of("output_path").map(|s| s.to_owned()),
+         command,
+         arg_files: values(merge_m, "arg_files"),
+         dir_path: value(merge_m, "dir_path"),
+         layout_path: value(merge_m, "layout_path"),
+         output_path: value(sub_m, "output_path"),
+         manifest_path: build_m.and_then(|m| value(m, "manifest_path")),
+         watch: sub_m.is_present("watch"),
+         check: sub_m.is_present("check"),
+         dry_run: sub_m.is_present("dry_run"),
+         by_schema: sub_m.is_present("by_schema"),
+         stats: sub_m.is_present("stats"),
+         color: match sub_m.value_of("color") {
+             Some("always") => Some(true),
+             Some("never") => Some(false),
+             _ => None,
+         },
+         json_messages: sub_m.value_of("message_format") == Some("json"),
+         verbosity: sub_m.occurrences_of("verbose") as i64 - sub_m.occurrences_of("quiet") as i64,
+         xml_declaration: merge_m.is_present("xml_declaration"),
+         ignore_case: merge_m.is_present("ignore_case"),
+         normalize_case: merge_m.is_present("normalize_case"),
+         only_schema: value(merge_m, "only_schema"),
+         schema_name: value(sub_m, "schema_name"),
+         default_schema_name: value(sub_m, "default_schema_name"),
+         combine: sub_m.is_present("combine"),
+         duplicates: sub_m.value_of("duplicates")
+             .map(|policy| policy.parse().expect("policy is one of the possible values")),
+         dedupe_identical: sub_m.is_present("dedupe_identical"),
+         conflicts: sub_m.value_of("conflicts")
+             .map(|strategy| strategy.parse().expect("strategy is one of the possible values")),
+         merge_cubes: sub_m.is_present("merge_cubes"),
+         metamodel: sub_m.value_of("metamodel")
+             .map(|version| version.parse().expect("version is one of the possible values")),
+         default_roles: sub_m.value_of("default_roles")
+             .map(|policy| policy.parse().expect("policy is one of the possible values")),
+         schema_attributes: sub_m.value_of("schema_attributes")
+             .map(|policy| policy.parse().expect("policy is one of the possible values")),
+         sort: sub_m.value_of("sort")
+             .map(|order| order.parse().expect("order is one of the possible values")),
+         namespace,
+         keep_comments: merge_m.is_present("keep_comments"),
+         verify_output: merge_m.is_present("verify_output"),
+         lints,
+         naming: pairs("naming"),
+         include: pairs("include"),
+         exclude: pairs("exclude"),
+         annotations: pairs("annotate"),
+         cube_annotations: pairs("annotate_cubes"),
      }
 }
 
+/// Write the schema to a temporary file next to `path`, and only
+/// rename it over `path` once it's all written, so a failure
+/// never leaves a truncated schema behind.
+fn write_file_atomic(path: &Path, schema: &str) -> Result<()> {
+    let file_name = path.file_name()
+        .and_then(|name| name.to_str())
+        .ok_or("Output path is not a file name")?;
+    let tmp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, process::id()));
+
+    let res = write_tmp_file(&tmp_path, path, schema)
+        .and_then(|_| fs::rename(&tmp_path, path).map_err(Error::from));
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    res
+}
+
+fn write_tmp_file(tmp_path: &Path, path: &Path, schema: &str) -> Result<()> {
+    let f = OpenOptions::new().write(true).create_new(true).open(tmp_path)?;
+    // a schema being replaced keeps its permissions
+    if let Ok(metadata) = fs::metadata(path) {
+        f.set_permissions(metadata.permissions())?;
+    }
+    write(&f, schema)?;
+    f.sync_all()?;
+    Ok(())
+}
+
 fn write<W: Write>(wtr: W, schema: &str) -> Result<()> {
     let mut wtr = BufWriter::new(wtr);
 
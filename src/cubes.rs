@@ -0,0 +1,412 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Merging the parts of a cube defined in several fragments into
+// one cube.
+//
+// Cubes with the same name can be parts of one cube, e.g. one
+// fragment with its measures and another with its dimensions
+// and calculated members, each kept by those who own them. The
+// merged cube has the first part's start tag, with the
+// attributes only later parts have added, then the children of
+// all the parts, grouped the way Mondrian 3 has them in a cube,
+// each group in the order of the parts. A child with the name of
+// one already kept in its group (or with no name), or a second
+// fact table, is dropped if identical to it (but for how it's
+// written), and is a `duplicate-conflict` otherwise, as is an
+// attribute with another value than the first part's. The
+// annotations of the parts are merged as the schema's are.
+//
+// Cubes overlaid on each other keep the last, but with the
+// annotations of all of them, merged as the schema's are.
+
+use std::collections::HashMap;
+
+use annotations::{self, ANNOTATIONS_TAG};
+use diff;
+use escape;
+use scan::{self, ScanOptions};
+use tokenizer;
+use tree;
+use validate::Source;
+use Warning;
+
+/// The children of a cube, group by group in the order they're
+/// written. Others go last.
+const GROUPS: &[&[&str]] = &[
+    &["Annotations"],
+    &["Table", "View", "InlineTable"],
+    &["Dimension", "DimensionUsage"],
+    &["Measure"],
+    &["CalculatedMember"],
+    &["NamedSet"],
+    &["WritebackTable"],
+];
+
+/// The group of the fact table, of which a cube has one
+const FACT_GROUP: usize = 1;
+
+/// A cube merged from its parts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergedCube<'a> {
+    pub text: String,
+    /// Where the pieces of `text` came from
+    pub sources: Vec<Source<'a>>,
+}
+
+/// Merge `parts`, each a `<Cube>` element as (fragment, input,
+/// text), with `text` a slice of `input`, into one cube with
+/// each child on its own line. There must be at least one part.
+pub fn merge_cube<'a>(parts: &[(usize, &'a str, &'a str)], options: &ScanOptions) -> (MergedCube<'a>, Vec<Warning>) {
+    let mut warnings = Vec::new();
+    let roots: Vec<_> = parts.iter()
+        .map(|&(_, _, text)| tree::parse(text).ok().and_then(|roots| roots.into_iter().next()))
+        .collect();
+    let (first_fragment, first_input, first_text) = parts[0];
+    let first_root = match roots[0] {
+        Some(ref root) => root,
+        None => {
+            let source = Source { start: 0, fragment: first_fragment, input: first_input, offset: scan::offset_in(first_input, first_text) };
+            return (MergedCube { text: first_text.to_owned(), sources: vec![source] }, warnings);
+        },
+    };
+    let cube = first_root.name_attr().unwrap_or_default().into_owned();
+    let conflict = |fragment: usize, input: &str, start: usize, len: usize, path: String, message: String| {
+        let (line, column) = tokenizer::line_col(input, start);
+        Warning {
+            fragment,
+            line,
+            column,
+            rule: "duplicate-conflict",
+            element: Some(format!("Cube \"{}\"", cube)),
+            path: vec![format!("{} \"{}\"", first_root.name, cube), path],
+            span: start..start + len,
+            message,
+            first: None,
+        }
+    };
+
+    // attributes of the start tag, unescaped, to the first value
+    let value = |raw: &str| escape::unescape(raw).map(|value| value.into_owned()).unwrap_or_else(|_| raw.to_owned());
+    let mut attributes: HashMap<&str, String> = first_root.attributes.iter().map(|&(name, raw)| (name, value(raw))).collect();
+    // (fragment, input, attribute as written) of those added
+    let mut added = Vec::new();
+    for (&(fragment, input, text), root) in parts.iter().zip(&roots).skip(1) {
+        let root = match *root {
+            Some(ref root) => root,
+            None => continue,
+        };
+        for &(name, raw) in &root.attributes {
+            let start = scan::offset_in(text, name);
+            // past the closing quote
+            let written = &text[start..scan::offset_in(text, raw) + raw.len() + 1];
+            match attributes.get(name) {
+                Some(first) if *first != value(raw) => {
+                    let message = format!(
+                        "Attribute \"{}\" of cube \"{}\" is \"{}\" here, but was \"{}\" first",
+                        name, cube, value(raw), first
+                    );
+                    warnings.push(conflict(fragment, input, scan::offset_in(input, written), written.len(), name.to_owned(), message));
+                },
+                Some(_) => {},
+                None => {
+                    attributes.insert(name, value(raw));
+                    added.push((fragment, input, written));
+                },
+            }
+        }
+    }
+
+    // (group, part, child with the comments before it) of those
+    // kept, and (group, tag if unnamed, name) to the first. A
+    // cube has one fact table, whatever its name
+    let mut kept = Vec::new();
+    let mut seen: HashMap<(usize, &str, String), &str> = HashMap::new();
+    // the `<Annotations>` of the parts, and the first as written
+    let mut blocks = Vec::new();
+    let mut first_block = None;
+    for (&(fragment, input, text), root) in parts.iter().zip(&roots) {
+        let root = match *root {
+            Some(ref root) => root,
+            None => continue,
+        };
+        for child in &root.children {
+            if scan::names_match(child.name, ANNOTATIONS_TAG, options) {
+                let start = scan::leading_comments_start(text, child.start);
+                first_block.get_or_insert((fragment, input, &text[start..child.start + child.text.len()]));
+                blocks.push((fragment, input, child.text));
+                continue;
+            }
+            let group = GROUPS.iter()
+                .position(|tags| tags.iter().any(|tag| scan::names_match(child.name, tag, options)))
+                .unwrap_or(GROUPS.len());
+            let name = child.name_attr().map(|name| name.into_owned());
+            let label = match name {
+                Some(ref name) => format!("{} \"{}\"", child.name, name),
+                None => child.name.to_owned(),
+            };
+            let key = match group {
+                FACT_GROUP => (group, "", String::new()),
+                _ => (group, if name.is_some() { "" } else { child.name }, name.unwrap_or_default()),
+            };
+            let first = match seen.get(&key) {
+                Some(&first) => first,
+                None => {
+                    seen.insert(key, child.text);
+                    let start = scan::leading_comments_start(text, child.start);
+                    kept.push((group, fragment, input, &text[start..child.start + child.text.len()]));
+                    continue;
+                },
+            };
+            if diff::normalize(first) == diff::normalize(child.text) {
+                continue;
+            }
+            let start = scan::offset_in(input, text) + child.start;
+            let message = format!(
+                "{} of cube \"{}\" differs from its first definition:\n{}",
+                if group == FACT_GROUP { "The fact table" } else { &label },
+                cube,
+                diff::diff_lines(first, child.text).trim_end()
+            );
+            warnings.push(conflict(fragment, input, start, child.text.len(), label, message));
+        }
+    }
+    let annotations = match (blocks.len(), first_block) {
+        (1, Some((fragment, input, block))) => {
+            kept.push((0, fragment, input, block));
+            Vec::new()
+        },
+        (0, _) => Vec::new(),
+        _ => {
+            let (annotations, conflicts) = annotations::merge_annotations(&blocks, Some(&cube), options);
+            warnings.extend(conflicts);
+            annotations
+        },
+    };
+    kept.sort_by_key(|&(group, ..)| group);
+
+    // the first part's start tag, with the comments before it
+    // and the attributes added
+    let tag_end = if first_root.text.ends_with("/>") {
+        first_root.start + first_root.text.len() - 2
+    } else {
+        scan::offset_in(first_text, first_root.body) - 1
+    };
+    let mut res = first_text[..tag_end].trim_end().to_owned();
+    let mut sources = vec![Source { start: 0, fragment: first_fragment, input: first_input, offset: scan::offset_in(first_input, first_text) }];
+    for (fragment, input, attribute) in added {
+        res.push(' ');
+        sources.push(Source { start: res.len(), fragment, input, offset: scan::offset_in(input, attribute) });
+        res.push_str(attribute);
+    }
+    res.push('>');
+    if let Some(&(fragment, input, block)) = blocks.first().filter(|_| !annotations.is_empty()) {
+        res.push_str("\n  ");
+        sources.push(Source { start: res.len(), fragment, input, offset: scan::offset_in(input, block) });
+        res.push_str("<Annotations>");
+        for annotation in &annotations {
+            res.push_str("\n    ");
+            if let Some(&(fragment, input, _)) = blocks.iter().find(|&&(_, input, _)| is_within(input, &annotation.text)) {
+                sources.push(Source { start: res.len(), fragment, input, offset: scan::offset_in(input, &annotation.text) });
+            }
+            res.push_str(&annotation.text);
+        }
+        res.push_str("\n  </Annotations>");
+    }
+    for (_, fragment, input, child) in kept {
+        res.push_str("\n  ");
+        sources.push(Source { start: res.len(), fragment, input, offset: scan::offset_in(input, child) });
+        res.push_str(child);
+    }
+    res.push_str(&format!("\n</{}>", first_root.name));
+    (MergedCube { text: res, sources }, warnings)
+}
+
+/// Overlay `versions` of a cube, each a `<Cube>` element as
+/// (fragment, input, text), with `text` a slice of `input`: the
+/// last, with the annotations of all of them as its own. `None`
+/// if only the last has any, so it's kept as it is.
+pub fn overlay_cube<'a>(versions: &[(usize, &'a str, &'a str)], options: &ScanOptions) -> (Option<MergedCube<'a>>, Vec<Warning>) {
+    let mut blocks = Vec::new();
+    let mut last = None;
+    for &(fragment, input, text) in versions {
+        let root = match tree::parse(text).ok().and_then(|roots| roots.into_iter().next()) {
+            Some(root) => root,
+            None => continue,
+        };
+        let block = root.children.iter()
+            .find(|child| scan::names_match(child.name, ANNOTATIONS_TAG, options))
+            .map(|block| block.start..block.start + block.text.len());
+        if let Some(ref block) = block {
+            blocks.push((fragment, input, &text[block.clone()]));
+        }
+        last = Some((fragment, input, text, root, block));
+    }
+    let (fragment, input, text, root, block) = match last {
+        Some(last) => last,
+        None => return (None, Vec::new()),
+    };
+    if blocks.iter().all(|&(_, _, block)| is_within(text, block)) {
+        return (None, Vec::new());
+    }
+    let cube = root.name_attr().unwrap_or_default();
+    let (merged, warnings) = annotations::merge_annotations(&blocks, Some(&cube), options);
+
+    // the block goes where the last's is, or first in the cube
+    let (start, end) = match block {
+        Some(block) => (block.start, block.end),
+        None if root.text.ends_with("/>") => (root.text.len() - 2, root.text.len()),
+        None => {
+            let body = scan::offset_in(text, root.body);
+            (body, body)
+        },
+    };
+    let offset = scan::offset_in(input, text);
+    let mut res = text[..start].to_owned();
+    if end == text.len() {
+        res = format!("{}>", res.trim_end());
+    }
+    let mut sources = vec![Source { start: 0, fragment, input, offset }];
+    res.push_str("<Annotations>");
+    for annotation in &merged {
+        res.push_str("\n  ");
+        if let Some(&(fragment, input, _)) = blocks.iter().find(|&&(_, input, _)| is_within(input, &annotation.text)) {
+            sources.push(Source { start: res.len(), fragment, input, offset: scan::offset_in(input, &annotation.text) });
+        }
+        res.push_str(&annotation.text);
+    }
+    res.push_str("\n</Annotations>");
+    if end == text.len() {
+        res.push_str(&format!("</{}>", root.name));
+    } else {
+        sources.push(Source { start: res.len(), fragment, input, offset: offset + end });
+        res.push_str(&text[end..]);
+    }
+    (Some(MergedCube { text: res, sources }), warnings)
+}
+
+/// Whether `inner` is a slice of `outer`
+fn is_within(outer: &str, inner: &str) -> bool {
+    let start = outer.as_ptr() as usize;
+    let inner_start = inner.as_ptr() as usize;
+    inner_start >= start && inner_start + inner.len() <= start + outer.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_cube() {
+        let f0 = "<Cube name=\"Sales\" defaultMeasure=\"Units\">\n  <Table name=\"sales_fact\"/>\n  \
+                  <Measure name=\"Units\" column=\"units\" aggregator=\"sum\"/>\n</Cube>";
+        let f1 = "<Cube name=\"Sales\">\n  <!-- by store -->\n  <DimensionUsage name=\"Store\" source=\"Store\" foreignKey=\"store_id\"/>\n  \
+                  <Table name='sales_fact'></Table>\n  <CalculatedMember name=\"Double\" dimension=\"Measures\" formula=\"[Measures].[Units] * 2\"/>\n  \
+                  <Measure name=\"Units\" column=\"units\" aggregator=\"count\"/>\n</Cube>";
+        let f2 = "<Cube name=\"Sales\"/>";
+        let parts = vec![(0, f0, f0), (1, f1, f1), (2, f2, f2)];
+        let (cube, warnings) = merge_cube(&parts, &ScanOptions::default());
+        assert_eq!(
+            cube.text,
+            "<Cube name=\"Sales\" defaultMeasure=\"Units\">\n  <Table name=\"sales_fact\"/>\n  \
+             <!-- by store -->\n  <DimensionUsage name=\"Store\" source=\"Store\" foreignKey=\"store_id\"/>\n  \
+             <Measure name=\"Units\" column=\"units\" aggregator=\"sum\"/>\n  \
+             <CalculatedMember name=\"Double\" dimension=\"Measures\" formula=\"[Measures].[Units] * 2\"/>\n</Cube>"
+        );
+        let found: Vec<_> = cube.sources.iter().map(|source| (source.start, source.fragment, source.offset)).collect();
+        assert_eq!(found, vec![(0, 0, 0), (45, 0, 45), (74, 1, 22), (164, 0, 74), (222, 1, 148)]);
+
+        let found: Vec<_> = warnings.iter().map(|w| (w.fragment, w.line, w.column, w.rule, w.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![(1, 6, 3, "duplicate-conflict",
+                  "Measure \"Units\" of cube \"Sales\" differs from its first definition:\n\
+                   - <Measure name=\"Units\" column=\"units\" aggregator=\"sum\"/>\n\
+                   + <Measure name=\"Units\" column=\"units\" aggregator=\"count\"/>")]
+        );
+
+        // a cube on its own is only laid out again
+        let (cube, warnings) = merge_cube(&parts[2..], &ScanOptions::default());
+        assert_eq!(cube.text, "<Cube name=\"Sales\">\n</Cube>");
+        assert!(warnings.is_empty());
+
+        // a cube has one fact table, and the attributes of them all
+        let f3 = "<Cube name=\"Sales\" cache=\"false\" defaultMeasure='Cnt'>\n  <View alias=\"sales\"><SQL>select 1</SQL></View>\n</Cube>";
+        let f4 = "<Cube caption=\"Sales &amp; more\" name=\"Sales\"><Table name=\"sales_v2\"/></Cube>";
+        let parts = vec![(0, f0, f0), (3, f3, f3), (4, f4, f4)];
+        let (cube, warnings) = merge_cube(&parts, &ScanOptions::default());
+        assert_eq!(
+            cube.text,
+            "<Cube name=\"Sales\" defaultMeasure=\"Units\" cache=\"false\" caption=\"Sales &amp; more\">\n  \
+             <Table name=\"sales_fact\"/>\n  <Measure name=\"Units\" column=\"units\" aggregator=\"sum\"/>\n</Cube>"
+        );
+        let found: Vec<_> = cube.sources.iter().map(|source| (source.start, source.fragment, source.offset)).collect();
+        assert_eq!(found, vec![(0, 0, 0), (42, 3, 19), (56, 4, 6), (86, 0, 45), (115, 0, 74)]);
+        // the annotations of the parts are merged
+        let f5 = "<Cube name=\"Sales\">\n  <Annotations><Annotation name=\"owner\">bi</Annotation></Annotations>\n  <Measure name=\"Units\" column=\"units\" aggregator=\"sum\"/>\n</Cube>";
+        let f6 = "<Cube name=\"Sales\">\n  <Annotations>\n    <Annotation name=\"owner\">ops</Annotation>\n    \
+                  <Annotation name=\"tier\">1</Annotation>\n  </Annotations>\n</Cube>";
+        let (merged, conflicts) = merge_cube(&[(5, f5, f5), (6, f6, f6)], &ScanOptions::default());
+        assert_eq!(
+            merged.text,
+            "<Cube name=\"Sales\">\n  <Annotations>\n    <Annotation name=\"owner\">bi</Annotation>\n    \
+             <Annotation name=\"tier\">1</Annotation>\n  </Annotations>\n  \
+             <Measure name=\"Units\" column=\"units\" aggregator=\"sum\"/>\n</Cube>"
+        );
+        let found: Vec<_> = merged.sources.iter().map(|source| (source.start, source.fragment, source.offset)).collect();
+        assert_eq!(found, vec![(0, 5, 0), (22, 5, 22), (40, 5, 35), (85, 6, 86), (143, 5, 92)]);
+        let found: Vec<_> = conflicts.iter().map(|w| (w.fragment, w.line, w.rule, w.message.as_str())).collect();
+        assert_eq!(found, vec![(6, 3, "annotation-conflict", "Annotation \"owner\" of cube \"Sales\" is \"ops\" here, but was \"bi\" first")]);
+        // one block is kept as it is
+        let (merged, _) = merge_cube(&[(5, f5, f5), (0, f0, f0)], &ScanOptions::default());
+        assert!(merged.text.contains("\n  <Annotations><Annotation name=\"owner\">bi</Annotation></Annotations>\n  <Table"));
+
+        let found: Vec<_> = warnings.iter().map(|w| (w.fragment, w.line, w.column, w.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![(3, 1, 34, "Attribute \"defaultMeasure\" of cube \"Sales\" is \"Cnt\" here, but was \"Units\" first"),
+                 (3, 2, 3, "The fact table of cube \"Sales\" differs from its first definition:\n\
+                            - <Table name=\"sales_fact\"/>\n\
+                            + <View alias=\"sales\"><SQL>select 1</SQL></View>"),
+                 (4, 1, 47, "The fact table of cube \"Sales\" differs from its first definition:\n\
+                             - <Table name=\"sales_fact\"/>\n\
+                             + <Table name=\"sales_v2\"/>")]
+        );
+    }
+
+    #[test]
+    fn test_overlay_cube() {
+        let f0 = "<Cube name=\"Sales\">\n  <Annotations>\n    <Annotation name=\"owner\">bi</Annotation>\n    \
+                  <Annotation name=\"tier\">1</Annotation>\n  </Annotations>\n  <Table name=\"sales\"/>\n</Cube>";
+        let f1 = "<Cube name=\"Sales\">\n  <Annotations><Annotation name=\"tier\">2</Annotation>\
+                  <Annotation name=\"team\">ops</Annotation></Annotations>\n  <Table name=\"sales_2018\"/>\n</Cube>";
+        let (cube, warnings) = overlay_cube(&[(0, f0, f0), (1, f1, f1)], &ScanOptions::default());
+        let cube = cube.unwrap();
+        assert_eq!(
+            cube.text,
+            "<Cube name=\"Sales\">\n  <Annotations>\n  <Annotation name=\"owner\">bi</Annotation>\n  \
+             <Annotation name=\"tier\">1</Annotation>\n  <Annotation name=\"team\">ops</Annotation>\n</Annotations>\n  \
+             <Table name=\"sales_2018\"/>\n</Cube>"
+        );
+        let found: Vec<_> = cube.sources.iter().map(|source| (source.start, source.fragment, source.offset)).collect();
+        assert_eq!(found, vec![(0, 1, 0), (38, 0, 40), (81, 0, 85), (122, 1, 73), (177, 1, 127)]);
+
+        let found: Vec<_> = warnings.iter().map(|w| (w.fragment, w.line, w.rule, w.message.as_str())).collect();
+        assert_eq!(found, vec![(1, 2, "annotation-conflict", "Annotation \"tier\" of cube \"Sales\" is \"2\" here, but was \"1\" first")]);
+
+        // the annotations go first in a cube without any
+        let f2 = "<Cube name=\"Sales\" />";
+        let (cube, _) = overlay_cube(&[(0, f0, f0), (2, f2, f2)], &ScanOptions::default());
+        assert_eq!(
+            cube.unwrap().text,
+            "<Cube name=\"Sales\"><Annotations>\n  <Annotation name=\"owner\">bi</Annotation>\n  \
+             <Annotation name=\"tier\">1</Annotation>\n</Annotations></Cube>"
+        );
+        // nothing to add to the last's
+        assert_eq!(overlay_cube(&[(2, f2, f2), (0, f0, f0)], &ScanOptions::default()), (None, Vec::new()));
+    }
+}
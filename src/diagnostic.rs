@@ -0,0 +1,147 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Rendering of errors and warnings in the style of rustc: the
+// message, then the fragment and the line it's about, with a
+// caret under the column.
+//
+// ```text
+// error: expected `>`
+//  --> cubes/sales.xml:3:12
+//   |
+// 3 | <Cube name="a"
+//   |            ^
+// ```
+
+use std::fmt::{self, Write};
+
+// ansi escapes
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const BOLD_RED: &str = "\x1b[1;31m";
+const BOLD_YELLOW: &str = "\x1b[1;33m";
+const BOLD_BLUE: &str = "\x1b[1;34m";
+
+/// How bad a problem is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+impl Severity {
+    fn label(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn color(self) -> &'static str {
+        match self {
+            Severity::Error => BOLD_RED,
+            Severity::Warning => BOLD_YELLOW,
+        }
+    }
+}
+
+/// Where in a fragment a problem is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snippet<'a> {
+    /// Name of the fragment, as shown to the user
+    pub path: &'a str,
+    /// The whole text of the fragment
+    pub source: &'a str,
+    /// 1-based line
+    pub line: usize,
+    /// 1-based column, in characters
+    pub column: usize,
+}
+
+/// Render a problem, and the line of the fragment it's at if
+/// there's a `snippet`, colored with ansi escapes if `color`.
+/// There's no newline at the end.
+pub fn render(severity: Severity, message: &str, snippet: Option<&Snippet>, color: bool) -> String {
+    let paint = |style: &str, text: &str| {
+        if color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_owned()
+        }
+    };
+
+    let mut res = format!(
+        "{}{}",
+        paint(severity.color(), severity.label()),
+        paint(BOLD, &format!(": {}", message))
+    );
+
+    let snippet = match snippet {
+        Some(snippet) => snippet,
+        None => return res,
+    };
+
+    let line_no = snippet.line.to_string();
+    let gutter = " ".repeat(line_no.len());
+    let _ = write!(
+        res,
+        "\n{}{} {}:{}:{}",
+        gutter,
+        paint(BOLD_BLUE, "-->"),
+        snippet.path,
+        snippet.line,
+        snippet.column
+    );
+
+    let source_line = match snippet.source.lines().nth(snippet.line - 1) {
+        Some(line) => line.trim_end_matches('\r'),
+        None => return res,
+    };
+    // tabs are kept under the caret, so it lines up however
+    // wide they're shown
+    let indent: String = source_line.chars()
+        .take(snippet.column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let bar = paint(BOLD_BLUE, "|");
+    let _ = write!(res, "\n{} {}", gutter, bar);
+    let _ = write!(res, "\n{} {} {}", paint(BOLD_BLUE, &line_no), bar, source_line);
+    let _ = write!(res, "\n{} {} {}{}", gutter, bar, indent, paint(severity.color(), "^"));
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let source = "<Schema name=\"a\">\n\t<Cube name=\"b\"\n</Schema>\n";
+        let snippet = Snippet { path: "cubes/b.xml", source, line: 2, column: 3 };
+        assert_eq!(
+            render(Severity::Error, "expected `>`", Some(&snippet), false),
+            "error: expected `>`\n --> cubes/b.xml:2:3\n  |\n2 | \t<Cube name=\"b\"\n  | \t ^"
+        );
+
+        assert_eq!(render(Severity::Warning, "careful", None, false), "warning: careful");
+        assert_eq!(Severity::Warning.to_string(), "warning");
+        assert_eq!(
+            render(Severity::Warning, "careful", None, true),
+            "\x1b[1;33mwarning\x1b[0m\x1b[1m: careful\x1b[0m"
+        );
+
+        // a line past the end only gets the location
+        let snippet = Snippet { path: "a.xml", source: "<Cube/>", line: 12, column: 1 };
+        assert_eq!(render(Severity::Error, "oops", Some(&snippet), false), "error: oops\n  --> a.xml:12:1");
+    }
+}
@@ -0,0 +1,260 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Comparing two schemas element by element: which shared
+// dimensions, cubes and virtual cubes were added, removed or
+// changed, matched up by kind and name.
+//
+// Elements are compared ignoring how they're written, like
+// whitespace between tags, comments or the order of attributes,
+// so reindenting a schema doesn't change it.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use error::*;
+use escape;
+use scan;
+use tokenizer::{TokenKind, Tokenizer};
+use tree;
+use ElementKind;
+
+const VALUE_TAG: &str = "Value";
+
+/// How an element differs between two schemas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Change {
+    Removed,
+    Added,
+    Changed,
+}
+
+/// One element which differs between two schemas.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub change: Change,
+    pub kind: ElementKind,
+    /// The element's name, unescaped; empty if it has none
+    pub name: String,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = match self.change {
+            Change::Removed => '-',
+            Change::Added => '+',
+            Change::Changed => '~',
+        };
+        write!(f, "{} {} {}", sign, self.kind, self.name)
+    }
+}
+
+/// Compare the shared dimensions, cubes and virtual cubes of two
+/// schemas. Differences are ordered by kind, then by change
+/// (removed, added, changed), then by name. Errors in `old` are
+/// reported as being in fragment 0, and in `new` fragment 1.
+pub fn diff_schemas(old: &str, new: &str) -> Result<Vec<Difference>> {
+    let old = scan::strip_prolog(old);
+    let new = scan::strip_prolog(new);
+    let old_roots = tree::parse_schema(old, 0)?;
+    let new_roots = tree::parse_schema(new, 1)?;
+    let old_elements = comparable(&old_roots);
+    let new_elements = comparable(&new_roots);
+
+    let mut res = Vec::new();
+    for (key, old_text) in &old_elements {
+        match new_elements.get(key) {
+            None => res.push((key.clone(), Change::Removed)),
+            Some(new_text) if new_text != old_text => res.push((key.clone(), Change::Changed)),
+            Some(_) => {},
+        }
+    }
+    for key in new_elements.keys() {
+        if !old_elements.contains_key(key) {
+            res.push((key.clone(), Change::Added));
+        }
+    }
+
+    res.sort_by(|&((kind_a, ref name_a), change_a), &((kind_b, ref name_b), change_b)| {
+        kind_a.cmp(&kind_b)
+            .then(change_a.cmp(&change_b))
+            .then(name_a.cmp(name_b))
+    });
+    Ok(res.into_iter()
+        .map(|((kind, name), change)| Difference { change, kind, name })
+        .collect())
+}
+
+/// The lines which differ between two texts: those only in
+/// `old` marked with `-`, those only in `new` with `+`, in the
+/// order they come. Lines are compared without the whitespace
+/// around them.
+pub fn diff_lines(old: &str, new: &str) -> String {
+    let old: Vec<_> = old.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+    let new: Vec<_> = new.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+
+    // lengths of the longest common subsequences of the tails
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut res = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && common[i + 1][j] >= common[i][j + 1]) {
+            res.push_str("- ");
+            res.push_str(old[i]);
+            res.push('\n');
+            i += 1;
+        } else {
+            res.push_str("+ ");
+            res.push_str(new[j]);
+            res.push('\n');
+            j += 1;
+        }
+    }
+    res
+}
+
+/// The merged elements of a schema by kind and name, each with
+/// its text normalized for comparison
+fn comparable(roots: &[tree::Element]) -> HashMap<(ElementKind, String), String> {
+    let (_, elements) = tree::top_level(roots);
+    elements.into_iter()
+        .filter_map(|element| {
+            let kind = ElementKind::from_tag(element.name)?;
+            let name = element.name_attr().map(|name| name.into_owned()).unwrap_or_default();
+            Some(((kind, name), normalize(element.text)))
+        })
+        .collect()
+}
+
+/// `s` in a canonical form, so that elements which only differ
+/// in how they're written compare equal: without comments or
+/// whitespace between tags, with runs of whitespace squeezed to
+/// one space, attributes sorted by name and double quoted,
+/// references written the same way, and elements without
+/// content as empty element tags. The text of an inline
+/// table's `<Value>` is data, and kept as written.
+pub fn normalize(s: &str) -> String {
+    let squeeze = |s: &str| s.split_whitespace().collect::<Vec<_>>().join(" ");
+    let canonical = |s: &str| {
+        let squeezed = squeeze(s);
+        let unescaped = escape::unescape(&squeezed).map(|unescaped| escape::escape(&unescaped).into_owned());
+        unescaped.unwrap_or(squeezed)
+    };
+
+    let mut res = String::with_capacity(s.len());
+    let mut in_value = false;
+    // where the last start tag ended, while nothing follows it
+    let mut open_end = None;
+    for token in Tokenizer::new(s).filter_map(|token| token.ok()) {
+        match token.kind {
+            TokenKind::StartTag { name, self_closing } => {
+                in_value = !self_closing && name == VALUE_TAG;
+                let mut attributes = scan::attributes(token.text);
+                attributes.sort_by_key(|&(name, _)| name);
+                res.push('<');
+                res.push_str(name);
+                for (name, value) in attributes {
+                    res.push_str(&format!(" {}=\"{}\"", name, canonical(value)));
+                }
+                if self_closing {
+                    res.push_str("/>");
+                } else {
+                    res.push('>');
+                    open_end = Some(res.len());
+                }
+            },
+            TokenKind::EndTag { name } => {
+                in_value = false;
+                if open_end == Some(res.len()) {
+                    res.pop();
+                    res.push_str("/>");
+                } else {
+                    res.push_str(&format!("</{}>", name));
+                }
+            },
+            TokenKind::Comment => {},
+            TokenKind::Text if in_value => res.push_str(token.text),
+            TokenKind::Text if token.text.trim().is_empty() => {},
+            TokenKind::Text => res.push_str(&canonical(token.text)),
+            _ => res.push_str(&squeeze(token.text)),
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_schemas() {
+        let old = r#"<Schema name="s">
+  <SharedDimension name="Time"/>
+  <Cube name="Sales">
+    <Measure name="m" aggregator="sum"/>
+  </Cube>
+  <Cube name="Old"/>
+</Schema>"#;
+        let new = r#"<Schema name="s"><SharedDimension   name="Time" />
+<!-- reindented -->
+<Cube name="Sales"><Measure name="m" aggregator="count"/></Cube>
+<Cube name="New"/>
+<VirtualCube name="v"/>
+</Schema>"#;
+        let diff = diff_schemas(old, new).unwrap();
+        let lines: Vec<_> = diff.iter().map(|d| d.to_string()).collect();
+        assert_eq!(lines, vec!["- cube Old", "+ cube New", "~ cube Sales", "+ virtual cube v"]);
+
+        assert_eq!(diff_schemas(old, old).unwrap(), vec![]);
+        assert!(matches!(
+            *diff_schemas(old, "<Schema><Cube></Schema>").unwrap_err().kind(),
+            ErrorKind::MalformedFragment(1, ..)
+        ));
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        let old = "<Cube name=\"a\">\n  <Measure name=\"m\" aggregator=\"sum\"/>\n  <Measure name=\"n\"/>\n</Cube>";
+        let new = "<Cube name=\"a\">\n    <Measure name=\"m\" aggregator=\"count\"/>\n    <Measure name=\"n\"/>\n</Cube>";
+        assert_eq!(
+            diff_lines(old, new),
+            "- <Measure name=\"m\" aggregator=\"sum\"/>\n+ <Measure name=\"m\" aggregator=\"count\"/>\n"
+        );
+        assert_eq!(diff_lines(old, old), "");
+        assert_eq!(diff_lines("<Cube/>", ""), "- <Cube/>\n");
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalize("<Cube  name=\"a\">\n  <!-- c -->\n  <Measure\n    name=\"m\"/>\n</Cube>"),
+            "<Cube name=\"a\"><Measure name=\"m\"/></Cube>"
+        );
+        assert_eq!(
+            normalize("<Cube visible='true' name=\"a &amp; b\"><Table name=\"t\"></Table><Measure name=\"m\"><!-- c --></Measure></Cube>"),
+            normalize("<Cube name=\"a &#38; b\"  visible=\"true\"><Table name='t'/><Measure name=\"m\"/></Cube>")
+        );
+        assert_ne!(normalize("<Cube name=\"a\"/>"), normalize("<Cube name=\"b\"/>"));
+        assert_eq!(
+            normalize("<Row>\n  <Value column=\"a\"> x  y </Value>\n  <Value column=\"b\">\n</Value>\n</Row>"),
+            "<Row><Value column=\"a\"> x  y </Value><Value column=\"b\">\n</Value></Row>"
+        );
+    }
+}
@@ -0,0 +1,251 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Directives to moncat written in the fragments themselves, as
+// comments like `<!-- msc:priority 10 -->`, so Mondrian (and
+// anything else reading the fragments) takes no notice of them.
+//
+// A fragment can declare its priority, deciding where it goes
+// in the merge order: highest first, fragments with the same
+// (by default 0) keeping the order they're given in.
+//
+// A fragment can also include another, e.g. a block of
+// calculated members several cubes share, with
+// `<!-- msc:include common/members.xml -->`: the directive is
+// replaced by the other fragment (without its prolog) before
+// the merge sees either. What's found in the result is traced
+// back to the file it came from by the pieces it's made of.
+
+use std::path::{Component, Path, PathBuf};
+
+use error::*;
+use scan;
+use tokenizer::{TokenKind, Tokenizer};
+use validate::{self, Source};
+
+const PREFIX: &str = "msc:";
+const PRIORITY: &str = "priority";
+const INCLUDE: &str = "include";
+
+/// A directive found in a fragment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Directive<'a> {
+    /// What comes right after `msc:`
+    pub name: &'a str,
+    /// The rest of the comment, trimmed
+    pub argument: &'a str,
+    /// Byte offset of the comment in the fragment
+    pub start: usize,
+    /// The whole comment
+    pub text: &'a str,
+}
+
+/// The directives in `fragment`, in the order written. Comments
+/// not starting with `msc:` are none.
+pub fn directives(fragment: &str) -> Vec<Directive<'_>> {
+    Tokenizer::new(fragment)
+        .filter_map(|token| token.ok())
+        .filter(|token| token.kind == TokenKind::Comment)
+        .filter_map(|token| {
+            let body = token.text.trim_start_matches("<!--").trim_end_matches("-->").trim();
+            if !body.starts_with(PREFIX) {
+                return None;
+            }
+            let body = &body[PREFIX.len()..];
+            let end = body.find(char::is_whitespace).unwrap_or(body.len());
+            Some(Directive {
+                name: &body[..end],
+                argument: body[end..].trim(),
+                start: token.start,
+                text: token.text,
+            })
+        })
+        .collect()
+}
+
+/// The priority `fragment` declares, if any. It's an error to
+/// declare one that isn't a whole number, or more than one.
+pub fn priority(fragment: &str) -> Result<Option<i64>> {
+    let mut res = None;
+    for directive in directives(fragment).into_iter().filter(|directive| directive.name == PRIORITY) {
+        if res.is_some() {
+            return Err("More than one priority declared".into());
+        }
+        let priority = directive.argument.parse()
+            .map_err(|_| format!("Invalid priority {}, expected a whole number", directive.argument))?;
+        res = Some(priority);
+    }
+    Ok(res)
+}
+
+/// A fragment with its includes replaced, and the files each
+/// piece of it was copied from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expanded {
+    /// The fragment, includes and all
+    pub text: String,
+    /// The path and text of the fragment, then of each fragment
+    /// it includes, in the order they're included
+    pub files: Vec<(PathBuf, String)>,
+    /// Where each piece of `text` starts, the position in `files`
+    /// of the file it was copied from and its offset there
+    pieces: Vec<(usize, usize, usize)>,
+}
+
+impl Expanded {
+    /// `fragment`, read from `path`, as it is
+    pub fn new(fragment: &str, path: &Path) -> Expanded {
+        Expanded {
+            text: fragment.to_owned(),
+            files: vec![(path.to_owned(), fragment.to_owned())],
+            pieces: vec![(0, 0, 0)],
+        }
+    }
+
+    /// The pieces of the text, each `Source`'s fragment being the
+    /// position in `files` of the file it was copied from
+    fn sources(&self) -> Vec<Source<'_>> {
+        self.pieces.iter()
+            .map(|&(start, file, offset)| Source { start, fragment: file, input: &self.files[file].1, offset })
+            .collect()
+    }
+
+    /// The position in `files` of the file `pos` in the text was
+    /// copied from, and its line and column there
+    pub fn locate(&self, pos: usize) -> (usize, usize, usize) {
+        let (file, line, column, _) = validate::locate(&self.sources(), pos);
+        (file, line, column)
+    }
+
+    fn push(&mut self, file: usize, offset: usize, piece: &str) {
+        self.pieces.push((self.text.len(), file, offset));
+        self.text.push_str(piece);
+    }
+}
+
+/// `fragment`, read from `path`, with each include directive
+/// replaced by the fragment at the path it gives, relative to
+/// the including fragment, itself with its includes replaced.
+/// `read` reads a fragment. A fragment including itself, by way
+/// of others or not, is an `IncludeCycle`.
+pub fn expand_includes<F>(fragment: &str, path: &Path, read: &mut F) -> Result<Expanded>
+    where F: FnMut(&Path) -> Result<String>
+{
+    let mut res = Expanded {
+        text: String::with_capacity(fragment.len()),
+        files: vec![(path.to_owned(), fragment.to_owned())],
+        pieces: Vec::new(),
+    };
+    let mut including = vec![normalize(path)];
+    expand(0, 0, &mut including, read, &mut res)?;
+    Ok(res)
+}
+
+/// Add file `file` of `res`, from `start` on, to its text
+fn expand<F>(file: usize, start: usize, including: &mut Vec<PathBuf>, read: &mut F, res: &mut Expanded) -> Result<()>
+    where F: FnMut(&Path) -> Result<String>
+{
+    let text = res.files[file].1.clone();
+    let fragment = &text[start..];
+    let includes: Vec<_> = directives(fragment).into_iter().filter(|directive| directive.name == INCLUDE).collect();
+    let base = including.last().and_then(|path| path.parent()).map(Path::to_path_buf).unwrap_or_default();
+    let mut pos = 0;
+    for directive in includes {
+        let path = normalize(&base.join(directive.argument));
+        if let Some(i) = including.iter().position(|seen| *seen == path) {
+            let chain = including[i..].iter().chain(Some(&path)).map(|path| path.display().to_string()).collect();
+            return Err(ErrorKind::IncludeCycle(chain).into());
+        }
+        let included = read(&path)?;
+        let prolog = included.len() - scan::strip_prolog(&included).len();
+
+        res.push(file, start + pos, &fragment[pos..directive.start]);
+        res.files.push((path.clone(), included));
+        including.push(path);
+        expand(res.files.len() - 1, prolog, including, read, res)?;
+        including.pop();
+        pos = directive.start + directive.text.len();
+    }
+    res.push(file, start + pos, &fragment[pos..]);
+    Ok(())
+}
+
+/// `path` without `.`, and with `..` taking off the directory
+/// before it where there's one, so a fragment is known however
+/// its path is written
+fn normalize(path: &Path) -> PathBuf {
+    let mut res = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir if matches!(res.components().next_back(), Some(Component::Normal(_))) => {
+                res.pop();
+            },
+            component => res.push(component.as_os_str()),
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directives() {
+        let fragment = "<!-- msc:priority 10 -->\n<Schema name=\"s\">\n  <!-- cubes -->\n  <!--msc:include\tcommon/time.xml-->\n</Schema>";
+        let found: Vec<_> = directives(fragment).iter().map(|d| (d.name, d.argument, d.start)).collect();
+        assert_eq!(found, vec![("priority", "10", 0), ("include", "common/time.xml", 62)]);
+
+        assert_eq!(priority(fragment).unwrap(), Some(10));
+        assert_eq!(priority("<Schema name=\"s\"><!-- msc:priority -3 --></Schema>").unwrap(), Some(-3));
+        assert_eq!(priority("<Schema name=\"s\"/>").unwrap(), None);
+        assert!(priority("<!-- msc:priority high -->").is_err());
+        assert!(priority("<!-- msc:priority 1 --><!-- msc:priority 2 -->").is_err());
+    }
+
+    #[test]
+    fn test_expand_includes() {
+        let files = |path: &Path| -> Result<String> {
+            let text = match path.to_str().unwrap() {
+                "common/members.xml" => "<?xml version=\"1.0\"?>\n<CalculatedMember name=\"Double\"/>\n<!-- msc:include time.xml -->",
+                "common/time.xml" => "<NamedSet name=\"Recent\"/>",
+                "common/loop.xml" => "<!-- msc:include ../cubes/./sales.xml -->",
+                _ => return Err(format!("No such file {}", path.display()).into()),
+            };
+            Ok(text.to_owned())
+        };
+        let mut read = files;
+        let sales = "<Schema name=\"s\">\n<Cube name=\"Sales\">\n  <!-- msc:include ../common/members.xml -->\n</Cube>\n</Schema>";
+        let expanded = expand_includes(sales, Path::new("cubes/sales.xml"), &mut read).unwrap();
+        assert_eq!(
+            expanded.text,
+            "<Schema name=\"s\">\n<Cube name=\"Sales\">\n  <CalculatedMember name=\"Double\"/>\n<NamedSet name=\"Recent\"/>\n</Cube>\n</Schema>"
+        );
+        let files: Vec<_> = expanded.files.iter().map(|(path, _)| path.to_str().unwrap()).collect();
+        assert_eq!(files, vec!["cubes/sales.xml", "common/members.xml", "common/time.xml"]);
+        // each piece is found in the file it came from
+        let locate = |needle: &str| expanded.locate(expanded.text.find(needle).unwrap());
+        assert_eq!(locate("<Cube"), (0, 2, 1));
+        assert_eq!(locate("<CalculatedMember"), (1, 2, 1));
+        assert_eq!(locate("<NamedSet"), (2, 1, 1));
+        assert_eq!(locate("</Cube>"), (0, 4, 1));
+        assert_eq!(locate("</Schema>"), (0, 5, 1));
+
+        let expanded = expand_includes("<Cube name=\"c\"/>", Path::new("c.xml"), &mut read).unwrap();
+        assert_eq!(expanded, Expanded::new("<Cube name=\"c\"/>", Path::new("c.xml")));
+
+        let looped = "<Schema name=\"s\"><!-- msc:include ../common/loop.xml --></Schema>";
+        match *expand_includes(looped, Path::new("cubes/sales.xml"), &mut read).unwrap_err().kind() {
+            ErrorKind::IncludeCycle(ref chain) => {
+                assert_eq!(chain, &vec!["cubes/sales.xml".to_owned(), "common/loop.xml".to_owned(), "cubes/sales.xml".to_owned()])
+            },
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        assert!(expand_includes("<!-- msc:include missing.xml -->", Path::new("c.xml"), &mut read).is_err());
+    }
+}
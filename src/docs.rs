@@ -0,0 +1,217 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Markdown documentation of a schema, for readers who'd rather
+// not read the XML: its shared dimensions with their
+// hierarchies and levels, its cubes with their dimensions and
+// measures, and its virtual cubes.
+
+use std::fmt::Write;
+
+use error::*;
+use scan;
+use tree::{self, Element};
+
+/// Document a schema in Markdown.
+pub fn schema_docs(schema: &str) -> Result<String> {
+    let schema = scan::strip_prolog(schema);
+    let roots = tree::parse_schema(schema, 0)?;
+    let (schema_element, elements) = tree::top_level(&roots);
+
+    let mut res = String::new();
+    match schema_element.and_then(|element| element.name_attr()) {
+        Some(name) => { let _ = writeln!(res, "# {}", name); },
+        None => res.push_str("# Schema\n"),
+    }
+    if let Some(element) = schema_element {
+        describe(&mut res, element);
+    }
+
+    let section = |tag: &str| elements.iter().filter(|element| element.name == tag).cloned().collect::<Vec<_>>();
+    let mut dimensions = section("Dimension");
+    dimensions.extend(section("SharedDimension"));
+    let cubes = section("Cube");
+    let virtual_cubes = section("VirtualCube");
+
+    if !dimensions.is_empty() {
+        res.push_str("\n## Shared dimensions\n");
+        for dimension in dimensions {
+            heading(&mut res, dimension);
+            describe(&mut res, dimension);
+            hierarchies(&mut res, dimension);
+        }
+    }
+
+    if !cubes.is_empty() {
+        res.push_str("\n## Cubes\n");
+        for cube in cubes {
+            heading(&mut res, cube);
+            describe(&mut res, cube);
+            let dimensions: Vec<_> = cube.children.iter()
+                .filter(|child| child.name == "Dimension" || child.name == "DimensionUsage")
+                .collect();
+            if !dimensions.is_empty() {
+                res.push_str("\nDimensions:\n\n");
+                for dimension in dimensions {
+                    let name = display_name(dimension);
+                    match dimension.attr("source") {
+                        Some(source) if dimension.name == "DimensionUsage" => {
+                            let _ = writeln!(res, "- {} (shared dimension {})", name, source);
+                        },
+                        _ => { let _ = writeln!(res, "- {}", name); },
+                    }
+                }
+            }
+            measures(&mut res, cube.children_named("Measure").collect());
+        }
+    }
+
+    if !virtual_cubes.is_empty() {
+        res.push_str("\n## Virtual cubes\n");
+        for cube in virtual_cubes {
+            heading(&mut res, cube);
+            describe(&mut res, cube);
+            let mut base_cubes: Vec<String> = Vec::new();
+            for element in cube.descendants() {
+                if let Some(name) = element.attr("cubeName") {
+                    if !base_cubes.iter().any(|base| *base == name) {
+                        base_cubes.push(name.into_owned());
+                    }
+                }
+            }
+            if !base_cubes.is_empty() {
+                let _ = writeln!(res, "\nBased on: {}", base_cubes.join(", "));
+            }
+            let measures: Vec<_> = cube.children_named("VirtualCubeMeasure").collect();
+            if !measures.is_empty() {
+                res.push_str("\nMeasures:\n\n");
+                for measure in measures {
+                    let _ = writeln!(res, "- {}", display_name(measure));
+                }
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+fn heading(res: &mut String, element: &Element) {
+    let _ = writeln!(res, "\n### {}", display_name(element));
+}
+
+/// The caption and description of an element, if it has them
+fn describe(res: &mut String, element: &Element) {
+    if let Some(caption) = element.attr("caption") {
+        if Some(&caption) != element.name_attr().as_ref() {
+            let _ = writeln!(res, "\nCaption: {}", caption);
+        }
+    }
+    if let Some(description) = element.attr("description") {
+        let _ = writeln!(res, "\n{}", description.trim());
+    }
+}
+
+fn hierarchies(res: &mut String, dimension: &Element) {
+    for hierarchy in dimension.children_named("Hierarchy") {
+        let name = hierarchy.name_attr().unwrap_or_else(|| display_name(dimension).into());
+        let levels: Vec<_> = hierarchy.children_named("Level").map(display_name).collect();
+        let _ = writeln!(res, "\n- Hierarchy {}: {}", name, levels.join(" > "));
+    }
+}
+
+fn measures(res: &mut String, measures: Vec<&Element>) {
+    if measures.is_empty() {
+        return;
+    }
+    res.push_str("\n| Measure | Aggregator | Format |\n| --- | --- | --- |\n");
+    for measure in measures {
+        let _ = writeln!(
+            res,
+            "| {} | {} | {} |",
+            table_cell(&display_name(measure)),
+            table_cell(&measure.attr("aggregator").unwrap_or_default()),
+            table_cell(&measure.attr("formatString").unwrap_or_default())
+        );
+    }
+}
+
+/// The element's name, or a placeholder if it has none
+fn display_name(element: &Element) -> String {
+    element.name_attr().map(|name| name.into_owned()).unwrap_or_else(|| "(unnamed)".to_owned())
+}
+
+/// `s` with the pipes escaped, so it stays in its table cell
+fn table_cell(s: &str) -> String {
+    s.replace('|', "\\|")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_docs() {
+        let schema = r##"<?xml version="1.0"?>
+<Schema name="Sales" description="Everything sold">
+  <SharedDimension name="Time">
+    <Hierarchy name="Calendar"><Level name="Year"/><Level name="Month"/></Hierarchy>
+  </SharedDimension>
+  <Cube name="Sales" caption="All sales">
+    <DimensionUsage name="Date" source="Time"/>
+    <Dimension name="Store"/>
+    <Measure name="Units" aggregator="sum" formatString="#|##"/>
+    <Measure name="Count" aggregator="count"/>
+  </Cube>
+  <VirtualCube name="Both">
+    <VirtualCubeDimension cubeName="Sales" name="Store"/>
+    <VirtualCubeMeasure cubeName="Sales" name="[Measures].[Units]"/>
+  </VirtualCube>
+</Schema>
+"##;
+        assert_eq!(
+            schema_docs(schema).unwrap(),
+            r##"# Sales
+
+Everything sold
+
+## Shared dimensions
+
+### Time
+
+- Hierarchy Calendar: Year > Month
+
+## Cubes
+
+### Sales
+
+Caption: All sales
+
+Dimensions:
+
+- Date (shared dimension Time)
+- Store
+
+| Measure | Aggregator | Format |
+| --- | --- | --- |
+| Units | sum | #\|## |
+| Count | count |  |
+
+## Virtual cubes
+
+### Both
+
+Based on: Sales
+
+Measures:
+
+- [Measures].[Units]
+"##
+        );
+
+        assert!(schema_docs("<Schema><Cube></Schema>").is_err());
+    }
+}
@@ -0,0 +1,167 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Decoding of fragment files into utf-8.
+//
+// The encoding is detected the same way an xml parser would:
+// a byte order mark wins, then the `encoding` attribute of the
+// xml declaration, and otherwise the fragment is utf-8.
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+use error::*;
+
+// Only the start of the file is searched for a declaration
+const DECL_SNIFF_LEN: usize = 1024;
+
+/// Decode the raw bytes of a fragment file into a `String`.
+///
+/// Returns an error if the declared encoding is unknown, or if
+/// the bytes are not valid in the detected encoding.
+pub fn decode(bytes: &[u8]) -> Result<String> {
+    let (encoding, bom_len) = match Encoding::for_bom(bytes) {
+        Some(found) => found,
+        None => (detect_encoding(bytes)?, 0),
+    };
+
+    encoding
+        .decode_without_bom_handling_and_without_replacement(&bytes[bom_len..])
+        .map(|s| s.into_owned())
+        .ok_or_else(|| format!("Fragment is not valid {}", encoding.name()).into())
+}
+
+/// Decode the raw bytes of a fragment known to be in the
+/// encoding named by `label`, instead of detecting it. A byte
+/// order mark for that encoding is skipped.
+pub fn decode_as(bytes: &[u8], label: &str) -> Result<String> {
+    let encoding = Encoding::for_label(label.as_bytes())
+        .ok_or_else(|| Error::from(format!("Unknown encoding: {}", label)))?;
+    let bom_len = match Encoding::for_bom(bytes) {
+        Some((found, len)) if found == encoding => len,
+        _ => 0,
+    };
+
+    encoding
+        .decode_without_bom_handling_and_without_replacement(&bytes[bom_len..])
+        .map(|s| s.into_owned())
+        .ok_or_else(|| format!("Fragment is not valid {}", encoding.name()).into())
+}
+
+/// Detect the encoding of a fragment with no byte order mark.
+fn detect_encoding(bytes: &[u8]) -> Result<&'static Encoding> {
+    // utf-16 with no bom can still be recognized by how
+    // the leading `<?` is laid out
+    if bytes.starts_with(&[0x3c, 0x00, 0x3f, 0x00]) {
+        return Ok(UTF_16LE);
+    }
+    if bytes.starts_with(&[0x00, 0x3c, 0x00, 0x3f]) {
+        return Ok(UTF_16BE);
+    }
+
+    match declared_encoding(bytes) {
+        Some(label) => {
+            Encoding::for_label(label.as_bytes())
+                .ok_or_else(|| format!("Unknown encoding declared: {}", label).into())
+        },
+        None => Ok(UTF_8),
+    }
+}
+
+/// The value of the `encoding` attribute of the xml declaration,
+/// if there is one.
+fn declared_encoding(bytes: &[u8]) -> Option<String> {
+    // the declaration itself is always ascii, so it's safe to
+    // look at the leading bytes as latin-1
+    let head: String = bytes.iter()
+        .take(DECL_SNIFF_LEN)
+        .map(|&b| b as char)
+        .collect();
+
+    let head = head.trim_start();
+    if !head.starts_with("<?xml") {
+        return None;
+    }
+    let decl = &head[..head.find("?>")?];
+    let i = decl.find("encoding")? + "encoding".len();
+    let value = decl[i..].trim_start().strip_prefix('=')?.trim_start();
+    let quote = value.chars().next().filter(|&c| c == '"' || c == '\'')?;
+    let value = &value[1..];
+
+    value.find(quote).map(|j| value[..j].to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16le(s: &str, bom: bool) -> Vec<u8> {
+        let mut res = if bom { vec![0xff, 0xfe] } else { Vec::new() };
+        for unit in s.encode_utf16() {
+            res.push(unit as u8);
+            res.push((unit >> 8) as u8);
+        }
+        res
+    }
+
+    #[test]
+    fn test_decode_utf8() {
+        let s = r#"<Cube name="Ventes é"></Cube>"#;
+        assert_eq!(decode(s.as_bytes()).unwrap(), s);
+
+        let mut bytes = vec![0xef, 0xbb, 0xbf];
+        bytes.extend_from_slice(s.as_bytes());
+        assert_eq!(decode(&bytes).unwrap(), s);
+
+        assert!(decode(&[0x3c, 0xe9, 0x3e]).is_err());
+    }
+
+    #[test]
+    fn test_decode_utf16() {
+        let s = r#"<?xml version="1.0" encoding="UTF-16"?><Cube name="é"></Cube>"#;
+        assert_eq!(decode(&utf16le(s, true)).unwrap(), s);
+        assert_eq!(decode(&utf16le(s, false)).unwrap(), s);
+
+        let mut be = vec![0xfe, 0xff];
+        for unit in s.encode_utf16() {
+            be.push((unit >> 8) as u8);
+            be.push(unit as u8);
+        }
+        assert_eq!(decode(&be).unwrap(), s);
+    }
+
+    #[test]
+    fn test_decode_declared() {
+        let mut bytes = br#"<?xml version="1.0" encoding="windows-1252"?><Cube name="caf"#.to_vec();
+        bytes.extend_from_slice(&[0xe9, 0x80]);
+        bytes.extend_from_slice(br#""></Cube>"#);
+        assert_eq!(
+            decode(&bytes).unwrap(),
+            "<?xml version=\"1.0\" encoding=\"windows-1252\"?><Cube name=\"café€\"></Cube>"
+        );
+
+        let bytes = b"<?xml version='1.0' encoding = 'ISO-8859-1' ?><Cube name=\"\xe9\"/>";
+        assert_eq!(decode(bytes).unwrap(), "<?xml version='1.0' encoding = 'ISO-8859-1' ?><Cube name=\"\u{e9}\"/>");
+
+        let bytes = br#"<?xml version="1.0" encoding="klingon"?><Cube/>"#;
+        assert!(decode(bytes).is_err());
+    }
+
+    #[test]
+    fn test_decode_as() {
+        // no declaration, so it would have been read as utf-8
+        let bytes = b"<Cube name=\"caf\xe9\"/>";
+        assert!(decode(bytes).is_err());
+        assert_eq!(decode_as(bytes, "windows-1252").unwrap(), "<Cube name=\"caf\u{e9}\"/>");
+
+        let s = "<Cube name=\"\u{e9}\"/>";
+        assert_eq!(decode_as(&utf16le(s, true), "utf-16le").unwrap(), s);
+        assert_eq!(decode_as(&utf16le(s, false), "utf-16le").unwrap(), s);
+
+        assert!(decode_as(bytes, "klingon").is_err());
+        assert!(decode_as(bytes, "utf-8").is_err());
+    }
+}
@@ -1,5 +1,95 @@
+// error-chain 0.11 implements `description` and `cause`,
+// both deprecated on `std::error::Error` now.
+#![allow(deprecated)]
+
 error_chain!{
     foreign_links {
         Io(::std::io::Error);
     }
+
+    errors {
+        /// A fragment isn't well formed xml. `index` is the
+        /// position of the fragment in the input, `line` and
+        /// `column` are 1-based.
+        MalformedFragment(index: usize, line: usize, column: usize, message: String) {
+            description("malformed fragment")
+            display("Fragment {} is malformed at line {}, column {}: {}", index, line, column, message)
+        }
+
+        /// The merged schema isn't well formed xml, which is a bug
+        /// in merging. `line` and `column` are 1-based, in the
+        /// merged schema.
+        MalformedOutput(line: usize, column: usize, message: String) {
+            description("malformed output")
+            display("The merged schema is malformed at line {}, column {}: {}", line, column, message)
+        }
+
+        /// Lint rules at level deny found problems in the
+        /// fragments. `report` has them all, warnings included.
+        Denied(report: ::report::ValidationReport) {
+            description("problems denied by lint rules")
+            display(
+                "{} problem(s) denied by lint rules, the first: {}",
+                report.errors().count(),
+                report.errors().next().map(|finding| finding.to_string()).unwrap_or_default()
+            )
+        }
+
+        /// The fragments name more than one schema, the first
+        /// two of them in fragments `first_index` and
+        /// `second_index`.
+        SchemaNameMismatch(first: String, first_index: usize, second: String, second_index: usize) {
+            description("more than one schema name found")
+            display(
+                "More than one schema name found: {} in fragment {} and {} in fragment {}",
+                first, first_index, second, second_index
+            )
+        }
+
+        /// None of the fragments name the schema.
+        NoSchemaName {
+            description("no schema name found")
+            display("No schema name found")
+        }
+
+        /// None of the fragments have the schema asked for.
+        SchemaNotFound(name: String) {
+            description("schema not found")
+            display("No schema named {} found", name)
+        }
+
+        /// The naming convention for `element` isn't a valid
+        /// regular expression.
+        InvalidNamingConvention(element: String, message: String) {
+            description("invalid naming convention")
+            display("Invalid naming convention for {}: {}", element, message)
+        }
+
+        /// Fragments include each other, `chain` being the paths
+        /// from the first to include itself again.
+        IncludeCycle(chain: Vec<String>) {
+            description("fragments include each other")
+            display("Fragments include each other: {}", chain.join(" -> "))
+        }
+
+        /// The include or exclude filter for `element` isn't a
+        /// valid regular expression.
+        InvalidFilter(element: String, message: String) {
+            description("invalid filter")
+            display("Invalid filter for {}: {}", element, message)
+        }
+
+        /// The recipe of virtual cube `name` can't be followed
+        /// with the merged schema.
+        InvalidRecipe(name: String, message: String) {
+            description("invalid virtual cube recipe")
+            display("Virtual cube \"{}\" can't be generated: {}", name, message)
+        }
+
+        /// A manifest couldn't be understood.
+        InvalidManifest(message: String) {
+            description("invalid manifest")
+            display("Invalid manifest: {}", message)
+        }
+    }
 }
@@ -0,0 +1,133 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Escaping and unescaping of xml text and attribute values.
+//
+// Text copied out of the fragments is never touched; these are
+// for values the tool reads to compare (like names), and for any
+// text it writes itself.
+
+use std::borrow::Cow;
+
+use tokenizer::SyntaxError;
+
+/// The five entities predefined by xml, and what they stand for
+const ENTITIES: &[(&str, char)] = &[
+    ("amp", '&'),
+    ("lt", '<'),
+    ("gt", '>'),
+    ("quot", '"'),
+    ("apos", '\''),
+];
+
+/// Escape `s` so that it's safe as text or as an attribute
+/// value, whichever quote is used.
+pub fn escape(s: &str) -> Cow<'_, str> {
+    if !s.contains(|c| ENTITIES.iter().any(|&(_, e)| e == c)) {
+        return Cow::Borrowed(s);
+    }
+
+    let mut res = String::with_capacity(s.len() + 8);
+    for c in s.chars() {
+        match ENTITIES.iter().find(|&&(_, e)| e == c) {
+            Some(&(name, _)) => {
+                res.push('&');
+                res.push_str(name);
+                res.push(';');
+            },
+            None => res.push(c),
+        }
+    }
+    Cow::Owned(res)
+}
+
+/// Replace entity and character references in `s` with the
+/// characters they stand for.
+///
+/// Only the predefined entities are known; any other reference,
+/// or an `&` which doesn't start a reference, is an error at
+/// its byte offset in `s`.
+pub fn unescape(s: &str) -> Result<Cow<'_, str>, SyntaxError> {
+    if !s.contains('&') {
+        return Ok(Cow::Borrowed(s));
+    }
+
+    let mut res = String::with_capacity(s.len());
+    let mut pos = 0;
+    while let Some(i) = s[pos..].find('&') {
+        let amp = pos + i;
+        res.push_str(&s[pos..amp]);
+        let (c, len) = reference(&s[amp..]).map_err(|message| SyntaxError { pos: amp, message })?;
+        res.push(c);
+        pos = amp + len;
+    }
+    res.push_str(&s[pos..]);
+    Ok(Cow::Owned(res))
+}
+
+/// Check that every `&` in `s` starts a valid reference.
+pub fn check_references(s: &str) -> Result<(), SyntaxError> {
+    unescape(s).map(|_| ())
+}
+
+/// Parse the reference at the start of `s` (which starts with
+/// `&`), returning the character and the reference's length.
+fn reference(s: &str) -> Result<(char, usize), String> {
+    let end = match s.find(';') {
+        Some(end) if end > 1 => end,
+        _ => return Err("`&` must start an entity reference, write &amp; for a literal `&`".to_owned()),
+    };
+    let body = &s[1..end];
+
+    let c = if let Some(hex) = body.strip_prefix("#x") {
+        u32::from_str_radix(hex, 16).ok().and_then(::std::char::from_u32)
+    } else if let Some(dec) = body.strip_prefix('#') {
+        dec.parse().ok().and_then(::std::char::from_u32)
+    } else {
+        ENTITIES.iter().find(|&&(name, _)| name == body).map(|&(_, c)| c)
+    };
+
+    c.map(|c| (c, end + 1))
+        .ok_or_else(|| format!("unknown reference &{};", body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("Sales"), "Sales");
+        assert_eq!(escape("a & b"), "a &amp; b");
+        assert_eq!(escape("<x>"), "&lt;x&gt;");
+        assert_eq!(escape(r#"Sales "EU""#), "Sales &quot;EU&quot;");
+        assert_eq!(escape("it's"), "it&apos;s");
+        assert_eq!(escape("&amp;"), "&amp;amp;");
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("Sales").unwrap(), "Sales");
+        assert_eq!(unescape("a &amp; b").unwrap(), "a & b");
+        assert_eq!(unescape("&lt;x&gt;").unwrap(), "<x>");
+        assert_eq!(unescape("Sales &quot;EU&quot;").unwrap(), r#"Sales "EU""#);
+        assert_eq!(unescape("it&apos;s").unwrap(), "it's");
+        assert_eq!(unescape("&#233;t&#xE9;").unwrap(), "été");
+        assert_eq!(unescape("&amp;amp;").unwrap(), "&amp;");
+
+        assert_eq!(unescape("a & b").unwrap_err().pos, 2);
+        assert_eq!(unescape("a &nbsp; b").unwrap_err().pos, 2);
+        assert_eq!(unescape("&#xZZ;").unwrap_err().pos, 0);
+        assert_eq!(unescape("x&;").unwrap_err().pos, 1);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let s = r#"<a & 'b' "c">"#;
+        assert_eq!(unescape(&escape(s)).unwrap(), s);
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Reformatting fragments and schemas: one tag per line,
+// indented two spaces per level.
+//
+// Tags, comments and text are otherwise kept as written. An
+// element holding nothing but text stays on one line with its
+// text untouched, since whitespace there can matter (SQL, MDX).
+
+use error::*;
+use tokenizer::{self, Token, TokenKind, Tokenizer};
+
+const INDENT: &str = "  ";
+
+/// Reformat `src`, which must be well formed.
+pub fn format(src: &str) -> Result<String> {
+    let tokens = Tokenizer::new(src).collect::<::std::result::Result<Vec<_>, _>>();
+    let tokens = tokenizer::check_well_formed(src, false).and(tokens).map_err(|err| {
+        let (line, column) = tokenizer::line_col(src, err.pos);
+        Error::from(ErrorKind::MalformedFragment(0, line, column, err.message))
+    })?;
+
+    let mut lines: Vec<String> = Vec::new();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = &tokens[i];
+        i += 1;
+        let indent = INDENT.repeat(depth);
+        match token.kind {
+            TokenKind::StartTag { self_closing: false, .. } => {
+                if let Some(end) = text_only(&tokens[i..]) {
+                    let body = &tokens[i..i + end];
+                    let end_tag = &tokens[i + end];
+                    let line = if body.iter().all(|token| token.text.trim().is_empty()) {
+                        format!("{}{}{}", indent, token.text, end_tag.text)
+                    } else {
+                        format!("{}{}", indent, &src[token.start..end_tag.end()])
+                    };
+                    lines.push(line);
+                    i += end + 1;
+                } else {
+                    lines.push(format!("{}{}", indent, token.text));
+                    depth += 1;
+                }
+            },
+            TokenKind::EndTag { .. } => {
+                depth = depth.saturating_sub(1);
+                lines.push(format!("{}{}", INDENT.repeat(depth), token.text));
+            },
+            TokenKind::Text => {
+                let text = token.text.trim();
+                if !text.is_empty() {
+                    lines.push(format!("{}{}", indent, text));
+                }
+            },
+            _ => lines.push(format!("{}{}", indent, token.text)),
+        }
+    }
+
+    let mut res = lines.join("\n");
+    res.push('\n');
+    Ok(res)
+}
+
+/// If the tokens up to the next end tag are all text or CDATA,
+/// the index of that end tag
+fn text_only(tokens: &[Token]) -> Option<usize> {
+    for (i, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::Text | TokenKind::CData => {},
+            TokenKind::EndTag { .. } => return Some(i),
+            _ => return None,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let src = "<?xml version=\"1.0\"?><Schema name=\"s\">\n<!-- cubes --><Cube name=\"a\">\n      <Table name=\"t\"/>\n<Measure name=\"m\"></Measure>\n<SQL dialect=\"generic\">select  1\n  from t</SQL>\n    <Formula><![CDATA[a < b]]></Formula></Cube></Schema>";
+        let formatted = format(src).unwrap();
+        assert_eq!(
+            formatted,
+            "<?xml version=\"1.0\"?>
+<Schema name=\"s\">
+  <!-- cubes -->
+  <Cube name=\"a\">
+    <Table name=\"t\"/>
+    <Measure name=\"m\"></Measure>
+    <SQL dialect=\"generic\">select  1
+  from t</SQL>
+    <Formula><![CDATA[a < b]]></Formula>
+  </Cube>
+</Schema>
+"
+        );
+        assert_eq!(format(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_format_malformed() {
+        assert!(matches!(
+            *format("<Cube>\n<Measure></Cube>").unwrap_err().kind(),
+            ErrorKind::MalformedFragment(0, 2, 10, _)
+        ));
+    }
+}
@@ -0,0 +1,267 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Hoisting private dimensions repeated in several cubes into
+// shared dimensions, for schemas grown by copy and paste.
+//
+// Private dimensions of different cubes which are the same but
+// for how they're written (see `diff::normalize`) and their
+// `foreignKey` become one shared dimension, written before the
+// first cube, and each cube uses it with a `<DimensionUsage>`
+// of its own foreign key instead. A dimension is left alone if
+// a shared dimension which differs already has its name, or if
+// a hierarchy of it has no relation of its own: a degenerate
+// dimension uses the cube's fact table, which a shared one
+// doesn't have.
+
+use std::collections::{HashMap, HashSet};
+
+use diff;
+use error::*;
+use escape;
+use scan::{self, ScanOptions};
+use tree::{self, Element};
+use validate::RELATION_TAGS;
+use {CUBE_TAG, DIM_TAG, SCHEMA_TAG, SHAREDDIM_TAG};
+
+const FOREIGN_KEY_ATTR: &str = "foreignKey";
+
+/// A schema with its repeated private dimensions hoisted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hoisted {
+    pub schema: String,
+    /// The name of each shared dimension the cubes now use
+    /// instead of their own, with the names of those cubes
+    pub dimensions: Vec<(String, Vec<String>)>,
+}
+
+/// Hoist the private dimensions repeated in several cubes of
+/// `schema` into shared dimensions.
+pub fn hoist_dimensions(schema: &str, options: &ScanOptions) -> Result<Hoisted> {
+    let roots = tree::parse_schema(schema, 0)?;
+    let is = |element: &Element, tags: &[&str]| {
+        tags.iter().any(|tag| scan::names_match(scan::local_name(element.name, options), tag, options))
+    };
+    let elements: Vec<&Element> = roots.iter()
+        .flat_map(|root| if is(root, &[SCHEMA_TAG]) { root.children.iter().collect() } else { vec![root] })
+        .collect();
+
+    // names taken by shared dimensions, to what they are
+    let mut taken: HashMap<String, String> = elements.iter()
+        .filter(|element| is(element, &[DIM_TAG, SHAREDDIM_TAG]))
+        .filter_map(|element| Some((element.name_attr()?.into_owned(), diff::normalize(element.text))))
+        .collect();
+    let cubes: Vec<&Element> = elements.iter().filter(|element| is(element, &[CUBE_TAG])).cloned().collect();
+    let first_cube = match cubes.first() {
+        Some(cube) => cube,
+        None => return Ok(Hoisted { schema: schema.to_owned(), dimensions: Vec::new() }),
+    };
+
+    // the private dimensions, grouped by what they'd be shared
+    let mut groups: Vec<(String, Vec<(&Element, &Element)>)> = Vec::new();
+    for &cube in &cubes {
+        let dims = cube.children.iter().filter(|child| is(child, &[DIM_TAG]) && child.attr("source").is_none());
+        for dim in dims.filter(|dim| has_relations(dim, options)) {
+            let shared = diff::normalize(&without_foreign_key(dim));
+            match groups.iter().position(|(seen, _)| *seen == shared) {
+                Some(i) => groups[i].1.push((cube, dim)),
+                None => groups.push((shared, vec![(cube, dim)])),
+            }
+        }
+    }
+
+    let insert_at = scan::leading_comments_start(schema, first_cube.start);
+    let indent = indentation(schema, insert_at);
+    let mut added = String::new();
+    // (start, end, replacement) of each dimension replaced
+    let mut edits = Vec::new();
+    let mut dimensions = Vec::new();
+    for (shared, uses) in groups {
+        if uses.iter().map(|&(cube, _)| cube.start).collect::<HashSet<_>>().len() < 2 {
+            continue;
+        }
+        let (_, first) = uses[0];
+        let name = match first.name_attr() {
+            Some(name) => name.into_owned(),
+            None => continue,
+        };
+        match taken.get(&name) {
+            Some(other) if *other != shared => continue,
+            Some(_) => {},
+            None => {
+                let text = reindent(&without_foreign_key(first), indentation(schema, first.start), indent);
+                added.push_str(&format!("{}\n{}", text, indent));
+                taken.insert(name.clone(), shared);
+            },
+        }
+
+        for &(_, dim) in &uses {
+            let foreign_key = dim.attr(FOREIGN_KEY_ATTR)
+                .map(|key| format!(" {}=\"{}\"", FOREIGN_KEY_ATTR, escape::escape(&key)))
+                .unwrap_or_default();
+            // with the namespace prefix the dimension has
+            let prefix = &dim.name[..dim.name.len() - scan::local_name(dim.name, options).len()];
+            let usage = format!("<{}DimensionUsage name=\"{2}\" source=\"{2}\"{1}/>", prefix, foreign_key, escape::escape(&name));
+            edits.push((dim.start, dim.start + dim.text.len(), usage));
+        }
+        let cube_names = uses.iter().map(|&(cube, _)| cube.name_attr().unwrap_or_default().into_owned()).collect();
+        dimensions.push((name, cube_names));
+    }
+    if dimensions.is_empty() {
+        return Ok(Hoisted { schema: schema.to_owned(), dimensions });
+    }
+
+    edits.push((insert_at, insert_at, added));
+    edits.sort_by_key(|&(start, ..)| start);
+    let mut res = String::with_capacity(schema.len());
+    let mut pos = 0;
+    for (start, end, replacement) in edits {
+        res.push_str(&schema[pos..start]);
+        res.push_str(&replacement);
+        pos = end;
+    }
+    res.push_str(&schema[pos..]);
+    Ok(Hoisted { schema: res, dimensions })
+}
+
+/// Whether `dim` has hierarchies, each with a relation of its
+/// own
+fn has_relations(dim: &Element, options: &ScanOptions) -> bool {
+    let is = |element: &Element, tag: &str| scan::names_match(scan::local_name(element.name, options), tag, options);
+    let mut hierarchies = dim.children.iter().filter(|child| is(child, "Hierarchy")).peekable();
+    hierarchies.peek().is_some()
+        && hierarchies.all(|hierarchy| hierarchy.children.iter().any(|child| RELATION_TAGS.iter().any(|tag| is(child, tag))))
+}
+
+/// A private dimension without its foreign key, as it would be
+/// shared
+fn without_foreign_key(dim: &Element) -> String {
+    let text = dim.text;
+    match dim.attributes.iter().find(|&&(name, _)| name == FOREIGN_KEY_ATTR) {
+        Some(&(name, value)) => {
+            let start = text[..scan::offset_in(text, name)].trim_end().len();
+            // past the closing quote
+            let end = scan::offset_in(text, value) + value.len() + 1;
+            format!("{}{}", &text[..start], &text[end..])
+        },
+        None => text.to_owned(),
+    }
+}
+
+/// The whitespace before `pos` on its line
+fn indentation(s: &str, pos: usize) -> &str {
+    let line_start = s[..pos].rfind('\n').map_or(0, |i| i + 1);
+    let before = &s[line_start..pos];
+    &before[..before.len() - before.trim_start().len()]
+}
+
+/// `s` with the lines after the first indented by `to` instead
+/// of `from`
+fn reindent(s: &str, from: &str, to: &str) -> String {
+    s.split('\n')
+        .enumerate()
+        .map(|(i, line)| match line.strip_prefix(from) {
+            Some(rest) if i > 0 => format!("{}{}", to, rest),
+            _ => line.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hoist_dimensions() {
+        let schema = "<Schema name=\"s\">\n  \
+                      <Dimension name=\"Product\"><Hierarchy/></Dimension>\n  \
+                      <!-- sales -->\n  \
+                      <Cube name=\"Sales\">\n    \
+                        <Table name=\"sales\"/>\n    \
+                        <Dimension name=\"Time\" foreignKey=\"time_id\">\n      \
+                          <Hierarchy hasAll=\"true\"><Table name=\"time\"/><Level name=\"Year\" column=\"year\"/></Hierarchy>\n    \
+                        </Dimension>\n    \
+                        <Dimension name=\"Product\" foreignKey=\"product_id\"><Hierarchy><Table name=\"p\"/></Hierarchy></Dimension>\n  \
+                      </Cube>\n  \
+                      <Cube name=\"Stock\">\n    \
+                        <Dimension foreignKey=\"day_id\" name='Time'>\n      \
+                          <Hierarchy hasAll=\"true\">\n        <Table name=\"time\"/>\n        <Level name=\"Year\" column=\"year\"/>\n      </Hierarchy>\n    \
+                        </Dimension>\n    \
+                        <Dimension name=\"Product\" foreignKey=\"product_id\"><Hierarchy><Table name=\"p\"/></Hierarchy></Dimension>\n    \
+                        <Dimension name=\"Store\" foreignKey=\"store_id\"/>\n  \
+                      </Cube>\n\
+                      </Schema>";
+        let hoisted = hoist_dimensions(schema, &ScanOptions::default()).unwrap();
+        // Product is taken by another shared dimension, and Store
+        // isn't repeated
+        assert_eq!(hoisted.dimensions, vec![("Time".to_owned(), vec!["Sales".to_owned(), "Stock".to_owned()])]);
+        assert_eq!(
+            hoisted.schema,
+            "<Schema name=\"s\">\n  \
+             <Dimension name=\"Product\"><Hierarchy/></Dimension>\n  \
+             <Dimension name=\"Time\">\n    \
+               <Hierarchy hasAll=\"true\"><Table name=\"time\"/><Level name=\"Year\" column=\"year\"/></Hierarchy>\n  \
+             </Dimension>\n  \
+             <!-- sales -->\n  \
+             <Cube name=\"Sales\">\n    \
+               <Table name=\"sales\"/>\n    \
+               <DimensionUsage name=\"Time\" source=\"Time\" foreignKey=\"time_id\"/>\n    \
+               <Dimension name=\"Product\" foreignKey=\"product_id\"><Hierarchy><Table name=\"p\"/></Hierarchy></Dimension>\n  \
+             </Cube>\n  \
+             <Cube name=\"Stock\">\n    \
+               <DimensionUsage name=\"Time\" source=\"Time\" foreignKey=\"day_id\"/>\n    \
+               <Dimension name=\"Product\" foreignKey=\"product_id\"><Hierarchy><Table name=\"p\"/></Hierarchy></Dimension>\n    \
+               <Dimension name=\"Store\" foreignKey=\"store_id\"/>\n  \
+             </Cube>\n\
+             </Schema>"
+        );
+
+        // a shared dimension which is the same is used as it is
+        let store = "<Hierarchy><Table name=\"store\"/></Hierarchy>";
+        let schema = format!(
+            "<Schema name=\"s\"><Dimension name=\"Store\">{0}</Dimension><Cube name=\"a\"><Dimension name=\"Store\" foreignKey=\"s\">{0}</Dimension></Cube>\
+             <Cube name=\"b\"><Dimension name=\"Store\" foreignKey=\"s\">{0}</Dimension></Cube></Schema>",
+            store
+        );
+        assert_eq!(
+            hoist_dimensions(&schema, &ScanOptions::default()).unwrap().schema,
+            format!(
+                "<Schema name=\"s\"><Dimension name=\"Store\">{}</Dimension><Cube name=\"a\"><DimensionUsage name=\"Store\" source=\"Store\" foreignKey=\"s\"/></Cube>\
+                 <Cube name=\"b\"><DimensionUsage name=\"Store\" source=\"Store\" foreignKey=\"s\"/></Cube></Schema>",
+                store
+            )
+        );
+
+        let unchanged = |schema: &str| assert_eq!(hoist_dimensions(schema, &ScanOptions::default()).unwrap(), Hoisted { schema: schema.to_owned(), dimensions: Vec::new() });
+        unchanged(&format!("<Schema name=\"s\"><Cube name=\"a\"><Dimension name=\"Store\">{}</Dimension></Cube></Schema>", store));
+        // degenerate dimensions use the fact table of their cube
+        unchanged("<Schema name=\"s\"><Cube name=\"a\"><Dimension name=\"Flag\"><Hierarchy><Level name=\"f\"/></Hierarchy></Dimension></Cube>\
+                   <Cube name=\"b\"><Dimension name=\"Flag\"><Hierarchy><Level name=\"f\"/></Hierarchy></Dimension></Cube></Schema>");
+        unchanged("<Schema name=\"s\"><Cube name=\"a\"><Dimension name=\"Store\"/></Cube><Cube name=\"b\"><Dimension name=\"Store\"/></Cube></Schema>");
+
+        // names and foreign keys are written escaped, and tags
+        // keep their namespace prefix
+        let schema = format!(
+            "<m:Schema name=\"s\"><m:Cube name=\"a\"><m:dimension name='Say \"hi\"' foreignKey='k\"'>{0}</m:dimension></m:Cube>\
+             <m:Cube name=\"b\"><m:dimension name='Say \"hi\"'>{0}</m:dimension></m:Cube></m:Schema>",
+            "<m:Hierarchy><m:table name=\"t\"/></m:Hierarchy>"
+        );
+        let options = ScanOptions {
+            ignore_case: true,
+            namespace: Some(scan::Namespace::Prefix("m".to_owned())),
+            ..ScanOptions::default()
+        };
+        let hoisted = hoist_dimensions(&schema, &options).unwrap();
+        assert_eq!(hoisted.dimensions, vec![("Say \"hi\"".to_owned(), vec!["a".to_owned(), "b".to_owned()])]);
+        assert!(hoisted.schema.contains("<m:dimension name='Say \"hi\"'><m:Hierarchy>"));
+        assert!(hoisted.schema.contains(
+            "<m:Cube name=\"a\"><m:DimensionUsage name=\"Say &quot;hi&quot;\" source=\"Say &quot;hi&quot;\" foreignKey=\"k&quot;\"/></m:Cube>"
+        ));
+        assert!(hoisted.schema.contains("<m:Cube name=\"b\"><m:DimensionUsage name=\"Say &quot;hi&quot;\" source=\"Say &quot;hi&quot;\"/></m:Cube>"));
+    }
+}
@@ -20,17 +20,20 @@
 // in that order).
 //
 // Fragments can be any of the above three in any combination,
-// but must be in the same order as a full schema.
+// and in any order within a fragment.
 //
 // The simple implementation (first pass) is a simple text
 // processor, with no deep knowledge of the schema.
-// This means that there's no checking for dupliates or
-// correctness and no rewriting of anything internal to
-// a cube or shared dim (it just ignore schema tags
-// basically).
+// This means that there's no checking for correctness
+// and no rewriting of anything internal to a cube or
+// shared dim (it just ignore schema tags basically).
 //
-// (Update: nevermind, I can still check for duplicate
-// names at least, even with simple processing)
+// Duplicate names can still be found though, even with
+// simple processing; see `DuplicatePolicy`. The merged
+// elements are also parsed on their own, to check references
+// between them (see `validate`). Every finding has a lint rule,
+// whose level decides whether it's a warning, an error or
+// nothing at all (see `lint`).
 //
 // For a given cube or shared dim, it will simply pull out
 // the tags and anything between the tags.
@@ -39,397 +42,3469 @@
 // probably not worthwhile since the concatenated file
 // can easily be checked by an actual Mondrian instance.
 
+extern crate encoding_rs;
 #[macro_use]
 extern crate error_chain;
+#[macro_use]
+extern crate log;
+extern crate regex;
+#[macro_use]
+extern crate serde;
+extern crate toml;
 
+mod accumulator;
+mod annotations;
+mod cubes;
+pub mod diagnostic;
+pub mod diff;
+pub mod directives;
+pub mod docs;
+pub mod encoding;
 pub mod error;
+pub mod escape;
+pub mod format;
+pub mod hoist;
+pub mod lint;
+pub mod manifest;
+mod mondrian;
+mod patch;
+mod physical;
+pub mod prefix;
+mod recipes;
+pub mod report;
+mod scan;
+pub mod split;
+mod tokenizer;
+mod tree;
+mod validate;
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+use diagnostic::Severity;
 use error::*;
+pub use accumulator::SchemaAccumulator;
+pub use recipes::VirtualCubeRecipe;
+pub use scan::{Namespace, ScanOptions};
+use regex::Regex;
+use tokenizer::{TokenKind, Tokenizer};
+
+// I assume tags follow the convention of CamelCase
+const SCHEMA_TAG: &str = "Schema";
+const CUBE_TAG: &str = "Cube";
+const SHAREDDIM_TAG: &str = "SharedDimension";
+const DIM_TAG: &str = "Dimension";
+const VIRTUALCUBE_TAG: &str = "VirtualCube";
+const NAMEDSET_TAG: &str = "NamedSet";
+const ROLE_TAG: &str = "Role";
+const UDF_TAG: &str = "UserDefinedFunction";
+const PARAMETER_TAG: &str = "Parameter";
+const ANNOTATIONS_TAG: &str = "Annotations";
+const ROLE_USAGE_TAG: &str = "RoleUsage";
+/// The elements of a virtual cube which name a cube
+const CUBE_USAGE_TAGS: &[&str] = &["CubeUsage", "VirtualCubeDimension", "VirtualCubeMeasure"];
+const PHYSICALSCHEMA_TAG: &str = "PhysicalSchema";
+const DEFAULT_ROLE_ATTR: &str = "defaultRole";
+/// What joins the values of a Schema attribute under
+/// `SchemaAttributePolicy::Concat`
+const CONCAT_SEPARATOR: &str = "; ";
+const METAMODEL_VERSION_ATTR: &str = "metamodelVersion";
+
+// Every top level element which ends up in the merged schema
+const MERGED_TAGS: &[&str] = &[DIM_TAG, SHAREDDIM_TAG, CUBE_TAG, VIRTUALCUBE_TAG, NAMEDSET_TAG, ROLE_TAG, UDF_TAG, PARAMETER_TAG, ANNOTATIONS_TAG, PHYSICALSCHEMA_TAG];
+
+// How much of a run of stray text is quoted in a warning
+const TEXT_PREVIEW_LEN: usize = 30;
+
+// Merged output is always utf-8, whatever the fragments declared
+const XML_DECLARATION: &str = r#"<?xml version="1.0" encoding="UTF-8"?>"#;
+
+/// Options controlling how fragments are merged.
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    /// Emit an xml declaration at the top of the merged schema.
+    /// Declarations and DOCTYPEs in the fragments themselves are
+    /// always stripped.
+    pub xml_declaration: bool,
+    /// How elements are recognized in the fragments
+    pub scan: ScanOptions,
+    /// Rewrite Mondrian element names in the output to their
+    /// canonical casing. Only useful along with
+    /// `scan.ignore_case`.
+    pub normalize_case: bool,
+    /// Only merge the Schema blocks with this name (along with
+    /// fragments that have no Schema tag), leaving out other
+    /// schemas kept in the same files.
+    pub only_schema: Option<String>,
+    /// Name the merged schema this, instead of the name found in
+    /// the fragments. The fragments then don't need a Schema tag
+    /// at all.
+    pub schema_name: Option<String>,
+    /// Name the merged schema this when none of the fragments
+    /// has a Schema tag, instead of failing with
+    /// `ErrorKind::NoSchemaName`
+    pub default_schema_name: Option<String>,
+    /// Merge Schema blocks with different names into one schema,
+    /// instead of failing with `ErrorKind::SchemaNameMismatch`.
+    /// It's named `schema_name`, or else after the first.
+    pub combine: bool,
+    /// What to do about shared dimensions, cubes or virtual cubes
+    /// with the same name, unless `duplicate-name` has a level
+    /// in `lints`. Those which differ from the first definition
+    /// are also a `duplicate-conflict`, denied unless duplicates
+    /// are allowed.
+    pub duplicates: DuplicatePolicy,
+    /// Leave out shared dimensions, cubes and virtual cubes
+    /// identical to the first with their name (but for how
+    /// they're written, see `diff::normalize`), instead of
+    /// reporting them as duplicates
+    pub dedupe_identical: bool,
+    /// How elements with the same name are merged: kept and
+    /// reported (the default), or resolved, without a
+    /// `duplicate-conflict`
+    pub conflicts: ConflictStrategy,
+    /// Merge cubes with the same name into one, each part
+    /// adding its dimensions, measures and so on, instead of
+    /// taking them for duplicates (see `cubes`)
+    pub merge_cubes: bool,
+    /// Which version of Mondrian the fragments are written for,
+    /// deciding which checks hold for them
+    pub metamodel: Metamodel,
+    /// Which `defaultRole` the merged schema gets when the
+    /// fragments' Schema tags disagree
+    pub default_roles: DefaultRolePolicy,
+    /// Which value the other attributes of the merged Schema tag
+    /// get when the fragments' disagree
+    pub schema_attributes: SchemaAttributePolicy,
+    /// Annotations set on the merged schema, name to value, e.g.
+    /// to tag a build with its environment. They replace any of
+    /// the fragments' with the same name.
+    pub annotations: BTreeMap<String, String>,
+    /// Annotations set the same way on every cube
+    pub cube_annotations: BTreeMap<String, String>,
+    /// The order shared dimensions, cubes and virtual cubes are
+    /// written in, within each kind
+    pub sort: SortOrder,
+    /// Check that the merged schema is well formed before
+    /// returning it, failing with `ErrorKind::MalformedOutput`
+    /// otherwise
+    pub verify_output: bool,
+    /// Levels of lint rules, by rule id, overriding their
+    /// defaults. `invalid-structure` (checking the merged
+    /// elements against the Mondrian 3 content model),
+    /// `missing-caption` and `missing-description` are only
+    /// checked when set here.
+    pub lints: BTreeMap<String, lint::Level>,
+    /// Naming conventions: element tag, like `Cube` or
+    /// `Measure`, to a regular expression the names of those
+    /// elements must match as a whole, checked under the
+    /// `naming-convention` rule
+    pub naming: BTreeMap<String, String>,
+    /// Filters on what's merged: element tag, like `Cube` or
+    /// `VirtualCube`, to a regular expression. Of the merged
+    /// elements with that tag, only those whose names match it
+    /// as a whole are kept.
+    pub include: BTreeMap<String, String>,
+    /// Element tag to a regular expression names of merged
+    /// elements with that tag are left out for matching as a
+    /// whole
+    pub exclude: BTreeMap<String, String>,
+    /// Virtual cubes generated from the merged cubes, merged
+    /// after the fragments' own and before the patches (see
+    /// `recipes`)
+    pub virtual_cubes: Vec<VirtualCubeRecipe>,
+}
+
+impl MergeOptions {
+    /// The level of rule `rule`: as set in `lints`, or else its
+    /// default.
+    pub fn level(&self, rule: &str) -> lint::Level {
+        if let Some(&level) = self.lints.get(rule) {
+            return level;
+        }
+        if rule == "duplicate-name" && self.conflicts == ConflictStrategy::Error {
+            return lint::Level::Deny;
+        }
+        if rule == "duplicate-name" {
+            return match self.duplicates {
+                DuplicatePolicy::Error => lint::Level::Deny,
+                DuplicatePolicy::Warn => lint::Level::Warn,
+                DuplicatePolicy::Allow => lint::Level::Allow,
+            };
+        }
+        if rule == "duplicate-conflict" && self.duplicates == DuplicatePolicy::Allow {
+            return lint::Level::Allow;
+        }
+        lint::rule(rule).map_or(lint::Level::Warn, |rule| rule.level)
+    }
+}
+
+/// How shared dimensions, cubes and virtual cubes with the same
+/// name are treated. Cubes and virtual cubes share names, since
+/// Mondrian looks both up the same way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicatePolicy {
+    /// Report each duplicate as an error, failing the merge
+    Error,
+    /// Add a warning for each duplicate
+    #[default]
+    Warn,
+    /// Merge duplicates without a word
+    Allow,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<DuplicatePolicy> {
+        match s {
+            "error" => Ok(DuplicatePolicy::Error),
+            "warn" => Ok(DuplicatePolicy::Warn),
+            "allow" => Ok(DuplicatePolicy::Allow),
+            _ => Err(format!("Unknown duplicate policy {}, expected error, warn or allow", s).into()),
+        }
+    }
+}
+
+/// How shared dimensions, cubes and the like with the same name
+/// are merged. `FirstWins`, `LastWins` and `RenameWithSuffix`
+/// resolve the collision, so it isn't reported at all.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictStrategy {
+    /// Keep them all, reporting each as a `duplicate-name` and
+    /// those which differ from the first as a
+    /// `duplicate-conflict`
+    #[default]
+    KeepAll,
+    /// Refuse them: as `KeepAll`, but each `duplicate-name` is an
+    /// error, whatever `MergeOptions::duplicates` says
+    Error,
+    /// Keep the first, leaving out the others
+    FirstWins,
+    /// Keep the last, leaving out the others, e.g. for fragments
+    /// which override those before them
+    LastWins,
+    /// Keep them all, naming the second `Name_2`, the third
+    /// `Name_3` and so on
+    RenameWithSuffix,
+    /// Put the last in the place of the first, leaving out the
+    /// others, e.g. for variants of cubes layered over a base
+    /// set. Each replacement is `overridden` rather than a
+    /// `duplicate-name`.
+    Overlay,
+}
+
+impl FromStr for ConflictStrategy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ConflictStrategy> {
+        match s {
+            "keep-all" => Ok(ConflictStrategy::KeepAll),
+            "error" => Ok(ConflictStrategy::Error),
+            "first-wins" => Ok(ConflictStrategy::FirstWins),
+            "last-wins" => Ok(ConflictStrategy::LastWins),
+            "rename-with-suffix" => Ok(ConflictStrategy::RenameWithSuffix),
+            "overlay" => Ok(ConflictStrategy::Overlay),
+            _ => Err(format!(
+                "Unknown conflict strategy {}, expected keep-all, error, first-wins, last-wins, rename-with-suffix or overlay", s
+            ).into()),
+        }
+    }
+}
+
+/// The generation of Mondrian schema the fragments are written
+/// for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum Metamodel {
+    /// Mondrian 4 if any Schema tag has a 4.x `metamodelVersion`
+    /// or any fragment has a `<PhysicalSchema>`, Mondrian 3
+    /// otherwise
+    #[default]
+    #[serde(rename = "auto")]
+    Auto,
+    /// Mondrian 3, with tables in the dimensions and cubes, and
+    /// virtual cubes
+    #[serde(rename = "3")]
+    Mondrian3,
+    /// Mondrian 4, with a `<PhysicalSchema>`, attribute based
+    /// dimensions and measure groups
+    #[serde(rename = "4")]
+    Mondrian4,
+}
+
+impl FromStr for Metamodel {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Metamodel> {
+        match s {
+            "auto" => Ok(Metamodel::Auto),
+            "3" => Ok(Metamodel::Mondrian3),
+            "4" => Ok(Metamodel::Mondrian4),
+            _ => Err(format!("Unknown metamodel {}, expected auto, 3 or 4", s).into()),
+        }
+    }
+}
+
+/// What to do when the Schema tags of the fragments give
+/// different `defaultRole`s.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DefaultRolePolicy {
+    /// Keep the first, and report a `schema-attribute-conflict`
+    #[default]
+    Error,
+    /// Keep the first fragment's
+    First,
+    /// Keep the last fragment's
+    Last,
+    /// Leave the merged schema without a default role
+    Drop,
+}
+
+impl FromStr for DefaultRolePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<DefaultRolePolicy> {
+        match s {
+            "error" => Ok(DefaultRolePolicy::Error),
+            "first" => Ok(DefaultRolePolicy::First),
+            "last" => Ok(DefaultRolePolicy::Last),
+            "drop" => Ok(DefaultRolePolicy::Drop),
+            _ => Err(format!("Unknown default role policy {}, expected error, first, last or drop", s).into()),
+        }
+    }
+}
+
+/// What to do when the Schema tags of the fragments give an
+/// attribute other than `defaultRole` different values.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SchemaAttributePolicy {
+    /// Keep the first, and report a `schema-attribute-conflict`
+    #[default]
+    Error,
+    /// Keep the first fragment's
+    First,
+    /// Keep the last fragment's
+    Last,
+    /// Join the different values, in the order first seen, with
+    /// `"; "`
+    Concat,
+}
+
+impl FromStr for SchemaAttributePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<SchemaAttributePolicy> {
+        match s {
+            "error" => Ok(SchemaAttributePolicy::Error),
+            "first" => Ok(SchemaAttributePolicy::First),
+            "last" => Ok(SchemaAttributePolicy::Last),
+            "concat" => Ok(SchemaAttributePolicy::Concat),
+            _ => Err(format!("Unknown schema attribute policy {}, expected error, first, last or concat", s).into()),
+        }
+    }
+}
+
+/// The order merged elements of one kind are written in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// The order of the fragments, and within each fragment
+    #[default]
+    Input,
+    /// Sorted by name, so the output doesn't depend on which
+    /// fragment anything is in. Elements with the same name keep
+    /// their input order.
+    Name,
+    /// Sorted by name, but with the shared dimensions in the
+    /// order the cubes and virtual cubes use them, and the cubes
+    /// in the order the virtual cubes use them, those not used
+    /// last. What's used always comes before what uses it, as
+    /// the kinds are written in that order.
+    Dependency,
+}
+
+impl FromStr for SortOrder {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<SortOrder> {
+        match s {
+            "input" => Ok(SortOrder::Input),
+            "name" => Ok(SortOrder::Name),
+            "dependency" => Ok(SortOrder::Dependency),
+            _ => Err(format!("Unknown sort order {}, expected input, name or dependency", s).into()),
+        }
+    }
+}
+
+/// Something in the fragments which was left out of the
+/// merged schema, or which looks wrong in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    /// Position of the fragment in the input
+    pub fragment: usize,
+    /// 1-based line in the fragment
+    pub line: usize,
+    /// 1-based column in the fragment
+    pub column: usize,
+    /// What kind of problem it is, the id of one of
+    /// `lint::RULES`, like `duplicate-name`
+    pub rule: &'static str,
+    /// The element it's about, like `<Role>` or `Cube "Sales"`
+    pub element: Option<String>,
+    /// The elements from the merged one down to the one it's
+    /// about, as in `report::Finding`
+    pub path: Vec<String>,
+    /// Byte range of what it's about in the fragment
+    pub span: Range<usize>,
+    pub message: String,
+    /// For a duplicate name, the fragment and line it was first
+    /// defined at
+    pub first: Option<(usize, usize)>,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Fragment {} line {}, column {}: {}", self.fragment, self.line, self.column, self.message)?;
+        if let Some((fragment, line)) = self.first {
+            write!(f, ", first in fragment {} at line {}", fragment, line)?;
+        }
+        Ok(())
+    }
+}
+
+/// A merged schema, along with warnings about the fragments
+/// it was merged from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Merged {
+    pub schema: String,
+    /// Name of the merged schema, unescaped
+    pub schema_name: String,
+    /// Findings of rules at level `warn`
+    pub warnings: Vec<Warning>,
+    /// Findings of rules at level `deny`. The schema is still
+    /// merged, but shouldn't be used.
+    pub errors: Vec<Warning>,
+    /// What each fragment contributed, in the order given
+    pub fragments: Vec<FragmentStats>,
+    /// Every element in the merged schema, in output order
+    pub elements: Vec<MergedElement>,
+    /// Names of the virtual cubes generated from
+    /// `MergeOptions::virtual_cubes`. They're in no fragment, so
+    /// their `elements` are placed at the start tag of their
+    /// first cube
+    pub generated: Vec<String>,
+}
+
+impl Merged {
+    /// Everything the lint rules found, errors and warnings
+    /// alike, in the order it's in the fragments
+    pub fn report(&self) -> report::ValidationReport {
+        let mut findings: Vec<_> = self.errors.iter()
+            .map(|warning| report::Finding::new(Severity::Error, warning))
+            .chain(self.warnings.iter().map(|warning| report::Finding::new(Severity::Warning, warning)))
+            .collect();
+        findings.sort_by_key(|finding| (finding.fragment, finding.span.start));
+        report::ValidationReport { findings }
+    }
+}
+
+/// How many of each element one fragment contributed to a
+/// merged schema.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FragmentStats {
+    pub parameters: usize,
+    pub shared_dims: usize,
+    pub cubes: usize,
+    pub virtual_cubes: usize,
+    pub named_sets: usize,
+    pub roles: usize,
+    pub functions: usize,
+}
+
+/// One element of a merged schema, and where it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergedElement {
+    pub kind: ElementKind,
+    /// The element's name, unescaped, if it has one
+    pub name: Option<String>,
+    /// Position of the fragment it came from in the input
+    pub fragment: usize,
+    /// 1-based line of its start tag in the fragment
+    pub line: usize,
+}
+
+/// The kinds of element which are merged, ordered as Mondrian's
+/// DTD has them in a schema, which is the order they're written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ElementKind {
+    /// A Parameter, written first
+    Parameter,
+    /// A SharedDimension, or a top level Dimension
+    SharedDimension,
+    Cube,
+    VirtualCube,
+    /// A top level NamedSet, written after the virtual cubes
+    NamedSet,
+    /// A Role, written after the named sets
+    Role,
+    /// A UserDefinedFunction, written last
+    UserDefinedFunction,
+}
+
+impl ElementKind {
+    /// Every kind, in order
+    pub const ALL: [ElementKind; 7] = [
+        ElementKind::Parameter,
+        ElementKind::SharedDimension,
+        ElementKind::Cube,
+        ElementKind::VirtualCube,
+        ElementKind::NamedSet,
+        ElementKind::Role,
+        ElementKind::UserDefinedFunction,
+    ];
+
+    /// The kind of a top level element with tag `name`, if it's
+    /// one which is merged
+    fn from_tag(name: &str) -> Option<ElementKind> {
+        match name {
+            DIM_TAG | SHAREDDIM_TAG => Some(ElementKind::SharedDimension),
+            CUBE_TAG => Some(ElementKind::Cube),
+            VIRTUALCUBE_TAG => Some(ElementKind::VirtualCube),
+            NAMEDSET_TAG => Some(ElementKind::NamedSet),
+            ROLE_TAG => Some(ElementKind::Role),
+            UDF_TAG => Some(ElementKind::UserDefinedFunction),
+            PARAMETER_TAG => Some(ElementKind::Parameter),
+            _ => None,
+        }
+    }
+
+    /// The kind whose names it shares. Cubes and virtual cubes
+    /// share names, since Mondrian looks both up the same way
+    pub fn namespace(self) -> ElementKind {
+        if self == ElementKind::VirtualCube { ElementKind::Cube } else { self }
+    }
+
+    /// Whether it's a cube or a virtual cube
+    pub fn is_cube(self) -> bool {
+        self == ElementKind::Cube || self == ElementKind::VirtualCube
+    }
+}
+
+impl fmt::Display for ElementKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match *self {
+            ElementKind::Parameter => "parameter",
+            ElementKind::SharedDimension => "shared dimension",
+            ElementKind::Cube => "cube",
+            ElementKind::VirtualCube => "virtual cube",
+            ElementKind::NamedSet => "named set",
+            ElementKind::Role => "role",
+            ElementKind::UserDefinedFunction => "user defined function",
+        };
+        f.write_str(kind)
+    }
+}
+
+/// Top level content of a fragment which none of the
+/// extractors took.
+#[derive(Debug, PartialEq)]
+enum Skipped<'a> {
+    Element { name: &'a str, text: &'a str },
+    Text(&'a str),
+}
+
+impl<'a> Skipped<'a> {
+    fn text(&self) -> &'a str {
+        match *self {
+            Skipped::Element { text, .. } | Skipped::Text(text) => text,
+        }
+    }
+
+    fn rule(&self) -> &'static str {
+        match *self {
+            Skipped::Element { name, .. } => match mondrian::canonical_element(name) {
+                Some(canonical) if canonical == name => "not-merged",
+                _ => "unknown-element",
+            },
+            Skipped::Text(_) => "stray-text",
+        }
+    }
+
+    fn element(&self) -> Option<String> {
+        match *self {
+            Skipped::Element { name, .. } => Some(format!("<{}>", name)),
+            Skipped::Text(_) => None,
+        }
+    }
+
+    fn message(&self) -> String {
+        match *self {
+            Skipped::Element { name, .. } => match mondrian::canonical_element(name) {
+                Some(canonical) if canonical != name => {
+                    format!("Unknown element <{}> was left out, did you mean <{}>?", name, canonical)
+                },
+                Some(_) => format!("<{}> is not merged and was left out", name),
+                None => format!("Unknown element <{}> was left out", name),
+            },
+            Skipped::Text(text) => {
+                let preview: String = text.chars().take(TEXT_PREVIEW_LEN).collect();
+                let ellipsis = if preview.len() < text.len() { "..." } else { "" };
+                format!("Stray text \"{}{}\" was left out", preview, ellipsis)
+            },
+        }
+    }
+}
+
+/// Struct to hold the results of parsing
+/// a string fragment of schema.
+#[derive(Debug, PartialEq)]
+pub struct Fragment<'a> {
+    schema_name: Option<Cow<'a, str>>,
+    schema_attributes: Vec<(&'a str, &'a str)>,
+    annotations: Vec<&'a str>,
+    physical_schemas: Vec<&'a str>,
+    parameters: Vec<&'a str>,
+    shared_dims: Vec<&'a str>,
+    cubes: Vec<&'a str>,
+    virtual_cubes: Vec<&'a str>,
+    named_sets: Vec<&'a str>,
+    roles: Vec<&'a str>,
+    functions: Vec<&'a str>,
+    patches: Vec<&'a str>,
+    skipped: Vec<Skipped<'a>>,
+}
+
+impl<'a> Fragment<'a> {
+
+    /// Get the attributes of the Schema tag from one fragment,
+    /// in the order written.
+    /// None if there's no Schema tags
+    /// Takes first schema tag
+    fn get_schema_attributes(fragment: &'a str, options: &ScanOptions) -> Result<Option<Vec<(&'a str, &'a str)>>> {
+        let res = scan::find_start_tag(fragment, SCHEMA_TAG, options)
+            .map_err(|err| format!("Malformed fragment: {}", err))?
+            .map(scan::attributes);
+        Ok(res)
+    }
+
+    /// Get the Schema name from one fragment, unescaped
+    /// None if there's no Schema tags or it has no name
+    /// Takes first schema tag
+    fn get_schema_name(fragment: &'a str, options: &ScanOptions) -> Result<Option<Cow<'a, str>>> {
+        let name = Fragment::get_schema_attributes(fragment, options)?
+            .and_then(|attrs| {
+                attrs.into_iter()
+                    .find(|&(name, _)| name == "name")
+                    .map(|(_, value)| value)
+            });
+        match name {
+            Some(name) => {
+                escape::unescape(name)
+                    .map(Some)
+                    .map_err(|err| format!("Invalid schema name {}: {}", name, err.message).into())
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Get the top level elements named any of `names` from one
+    /// fragment, wherever they are in it.
+    /// Top level is either directly in the fragment or
+    /// directly inside its Schema tags
+    fn get_elements(fragment: &'a str, names: &[&str], options: &ScanOptions) -> Result<Vec<&'a str>> {
+        let elements = scan::top_level_elements(fragment, SCHEMA_TAG, options)
+            .map_err(|err| format!("Malformed fragment: {}", err))?;
+
+        let res = elements.into_iter()
+            .filter(|e| names.iter().any(|name| scan::names_match(e.name, name, options)))
+            .map(|e| {
+                // with keep_comments, an element takes the
+                // comments just before it along
+                let start = if options.keep_comments {
+                    scan::leading_comments_start(fragment, e.start)
+                } else {
+                    e.start
+                };
+                &fragment[start..e.start + e.text.len()]
+            })
+            .collect();
+        Ok(res)
+    }
+
+    /// Get shared dims from one fragment
+    /// Both Dimension and SharedDimension tags are shared dims;
+    /// dims inside cubes aren't top level, so they're left alone
+    fn get_shared_dims(fragment: &'a str, options: &ScanOptions) -> Result<Vec<&'a str>> {
+        Fragment::get_elements(fragment, &[DIM_TAG, SHAREDDIM_TAG], options)
+    }
+
+    // Get cubes from one fragment
+    fn get_cubes(fragment: &'a str, options: &ScanOptions) -> Result<Vec<&'a str>> {
+        Fragment::get_elements(fragment, &[CUBE_TAG], options)
+    }
+
+    // Get virtual cubes from one fragment
+    fn get_virtual_cubes(fragment: &'a str, options: &ScanOptions) -> Result<Vec<&'a str>> {
+        Fragment::get_elements(fragment, &[VIRTUALCUBE_TAG], options)
+    }
+
+    // Get named sets from one fragment; those inside cubes
+    // aren't top level, so they stay with their cube
+    fn get_named_sets(fragment: &'a str, options: &ScanOptions) -> Result<Vec<&'a str>> {
+        Fragment::get_elements(fragment, &[NAMEDSET_TAG], options)
+    }
+
+    // Get roles from one fragment
+    fn get_roles(fragment: &'a str, options: &ScanOptions) -> Result<Vec<&'a str>> {
+        Fragment::get_elements(fragment, &[ROLE_TAG], options)
+    }
+
+    // Get the Annotations blocks from one fragment; those of
+    // cubes and the like stay with them
+    fn get_annotations(fragment: &'a str, options: &ScanOptions) -> Result<Vec<&'a str>> {
+        Fragment::get_elements(fragment, &[ANNOTATIONS_TAG], options)
+    }
+
+    // Get the PhysicalSchema blocks of a Mondrian 4 fragment
+    fn get_physical_schemas(fragment: &'a str, options: &ScanOptions) -> Result<Vec<&'a str>> {
+        Fragment::get_elements(fragment, &[PHYSICALSCHEMA_TAG], options)
+    }
+
+    // Get parameters from one fragment
+    fn get_parameters(fragment: &'a str, options: &ScanOptions) -> Result<Vec<&'a str>> {
+        Fragment::get_elements(fragment, &[PARAMETER_TAG], options)
+    }
+
+    // Get user defined functions from one fragment, with their
+    // scripts
+    fn get_functions(fragment: &'a str, options: &ScanOptions) -> Result<Vec<&'a str>> {
+        Fragment::get_elements(fragment, &[UDF_TAG], options)
+    }
+
+    // Get the patches of elements from one fragment
+    fn get_patches(fragment: &'a str, options: &ScanOptions) -> Result<Vec<&'a str>> {
+        Fragment::get_elements(fragment, &[patch::PATCH_TAG], options)
+    }
+
+    /// The elements of `kind` taken from the fragment, in the
+    /// order written
+    fn elements(&self, kind: ElementKind) -> &[&'a str] {
+        match kind {
+            ElementKind::Parameter => &self.parameters,
+            ElementKind::SharedDimension => &self.shared_dims,
+            ElementKind::Cube => &self.cubes,
+            ElementKind::VirtualCube => &self.virtual_cubes,
+            ElementKind::NamedSet => &self.named_sets,
+            ElementKind::Role => &self.roles,
+            ElementKind::UserDefinedFunction => &self.functions,
+        }
+    }
+
+    /// The merged elements of every kind
+    fn elements_mut(&mut self) -> [&mut Vec<&'a str>; 7] {
+        [
+            &mut self.parameters,
+            &mut self.shared_dims,
+            &mut self.cubes,
+            &mut self.virtual_cubes,
+            &mut self.named_sets,
+            &mut self.roles,
+            &mut self.functions,
+        ]
+    }
+
+    /// Get the top level content from one fragment which isn't
+    /// merged, in the order written
+    fn get_skipped(fragment: &'a str, options: &ScanOptions) -> Result<Vec<Skipped<'a>>> {
+        let elements = scan::top_level_elements(fragment, SCHEMA_TAG, options)
+            .map_err(|err| format!("Malformed fragment: {}", err))?;
+        let text = scan::top_level_text(fragment, SCHEMA_TAG, options)
+            .map_err(|err| format!("Malformed fragment: {}", err))?;
+
+        let mut res: Vec<_> = elements.into_iter()
+            .filter(|e| !MERGED_TAGS.iter().chain(&[SCHEMA_TAG, patch::PATCH_TAG]).any(|name| scan::names_match(e.name, name, options)))
+            .map(|e| (e.start, Skipped::Element { name: e.name, text: e.text }))
+            .chain(text.into_iter().map(|(start, text)| (start, Skipped::Text(text))))
+            .collect();
+        res.sort_by_key(|&(start, _)| start);
+        Ok(res.into_iter().map(|(_, skipped)| skipped).collect())
+    }
+
+    pub fn process_fragment(fragment: &'a str) -> Result<Fragment<'a>> {
+        Fragment::process_fragment_with_options(fragment, &ScanOptions::default())
+    }
+
+    /// Process an input which may hold several Schema blocks,
+    /// into one Fragment per block. Content outside of the
+    /// blocks becomes a Fragment of its own.
+    pub fn process_fragments(input: &'a str, options: &ScanOptions) -> Result<Vec<Fragment<'a>>> {
+        let input = scan::strip_prolog(input);
+        scan::split_elements(input, SCHEMA_TAG, options)
+            .map_err(|err| format!("Malformed fragment: {}", err))?
+            .into_iter()
+            .map(|block| Fragment::process_fragment_with_options(block, options))
+            .collect()
+    }
+
+    pub fn process_fragment_with_options(fragment: &'a str, options: &ScanOptions) -> Result<Fragment<'a>> {
+        // TODO make this work with string parse fn?
+
+        let fragment = scan::strip_prolog(fragment);
+        let schema_name = Fragment::get_schema_name(fragment, options)?;
+        let schema_attributes = Fragment::get_schema_attributes(fragment, options)?.unwrap_or_default();
+        let annotations = Fragment::get_annotations(fragment, options)?;
+        let physical_schemas = Fragment::get_physical_schemas(fragment, options)?;
+        let parameters = Fragment::get_parameters(fragment, options)?;
+        let shared_dims = Fragment::get_shared_dims(fragment, options)?;
+        let cubes = Fragment::get_cubes(fragment, options)?;
+        let virtual_cubes = Fragment::get_virtual_cubes(fragment, options)?;
+        let named_sets = Fragment::get_named_sets(fragment, options)?;
+        let roles = Fragment::get_roles(fragment, options)?;
+        let functions = Fragment::get_functions(fragment, options)?;
+        let patches = Fragment::get_patches(fragment, options)?;
+        let skipped = Fragment::get_skipped(fragment, options)?;
+        Ok(Fragment {
+            schema_name,
+            schema_attributes,
+            annotations,
+            physical_schemas,
+            parameters,
+            shared_dims,
+            cubes,
+            virtual_cubes,
+            named_sets,
+            roles,
+            functions,
+            patches,
+            skipped,
+        })
+    }
+}
+
+/// Convenience method for turning unprocessed fragments
+/// into one schema
+pub fn fragments_to_schema(fragment: &[String]) -> Result<String> {
+    fragments_to_schema_with_options(fragment, &MergeOptions::default())
+}
+
+/// Turn unprocessed fragments into one schema, using `options`.
+/// Any warnings are dropped, see `merge_fragments` to get them;
+/// denied findings fail with `ErrorKind::Denied`, holding the
+/// report.
+pub fn fragments_to_schema_with_options(fragment: &[String], options: &MergeOptions) -> Result<String> {
+    let merged = merge_fragments(fragment, options)?;
+    if !merged.errors.is_empty() {
+        return Err(ErrorKind::Denied(merged.report()).into());
+    }
+    Ok(merged.schema)
+}
+
+/// Merge each schema the fragments hold on its own, as
+/// `merge_fragments` would with `only_schema` set to its name,
+/// for fragments which keep several schemas side by side.
+/// Fragments with no Schema tag go into every schema. Without
+/// any Schema tags, there's the one schema, named as usual.
+pub fn merge_by_schema(fragment: &[String], options: &MergeOptions) -> Result<BTreeMap<String, Merged>> {
+    let mut names = Vec::new();
+    for (index, input) in fragment.iter().enumerate() {
+        if let Err(err) = tokenizer::check_well_formed(input, options.scan.ignore_case) {
+            let (line, column) = tokenizer::line_col(input, err.pos);
+            return Err(ErrorKind::MalformedFragment(index, line, column, err.message).into());
+        }
+        let input = scan::strip_namespace(input, &options.scan);
+        let processed = Fragment::process_fragments(&input, &options.scan)
+            .chain_err(|| format!("Could not process fragment {}", index))?;
+        for name in processed.into_iter().filter_map(|frag| frag.schema_name) {
+            let name = name.into_owned();
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+
+    let mut res = BTreeMap::new();
+    if names.is_empty() {
+        let merged = merge_fragments(fragment, options)?;
+        res.insert(merged.schema_name.clone(), merged);
+        return Ok(res);
+    }
+    for name in names {
+        let options = MergeOptions { only_schema: Some(name.clone()), ..options.clone() };
+        res.insert(name, merge_fragments(fragment, &options)?);
+    }
+    Ok(res)
+}
+
+/// Turn unprocessed fragments into one schema, using `options`,
+/// and report any top level content of the fragments which was
+/// left out of it, duplicate names and anything else the lint
+/// rules find, each as a warning or error by its rule's level
+pub fn merge_fragments(fragment: &[String], options: &MergeOptions) -> Result<Merged> {
+    // Get Schema names from all fragments
+    // and check for non-duplicates (there should only
+    // be one schema name). Error is returned if
+    // there's more than one schema name
+    //
+    // Otherwise, process all fragments, then write their
+    // elements kind by kind, in the order of Mondrian's DTD.
+    // Each element goes on its own line.
+
+    // process fragments
+    // reject anything that isn't well formed before it
+    // can produce strange slices
+    for (index, input) in fragment.iter().enumerate() {
+        if let Err(err) = tokenizer::check_well_formed(input, options.scan.ignore_case) {
+            let (line, column) = tokenizer::line_col(input, err.pos);
+            return Err(ErrorKind::MalformedFragment(index, line, column, err.message).into());
+        }
+    }
+    let naming = validate::naming_conventions(&options.naming)?;
+    let include = name_filters(&options.include)?;
+    let exclude = name_filters(&options.exclude)?;
+
+    // namespace prefixes are removed up front, so the
+    // extraction only ever sees plain Mondrian elements
+    let inputs: Vec<_> = fragment.iter()
+        .map(|s| scan::strip_namespace(s, &options.scan))
+        .collect();
+
+    let mut fragments = Vec::new();
+    let mut warnings = Vec::new();
+    // where each name was first seen, by the kinds sharing it
+    let mut names: Names<&str> = HashMap::new();
+    let mut stats = Vec::new();
+    // the blocks and elements to merge, in the order found
+    let mut annotation_blocks = Vec::new();
+    let mut physical_blocks = Vec::new();
+    let mut patch_blocks = Vec::new();
+    let mut merged_elements = Vec::new();
+    for (index, input) in inputs.iter().enumerate() {
+        let mut processed = Fragment::process_fragments(input, &options.scan)
+            .chain_err(|| format!("Could not process fragment {}", index))?;
+
+        // route schema blocks by name
+        if let Some(ref only) = options.only_schema {
+            processed.retain(|frag| match frag.schema_name {
+                Some(ref name) if name != only => {
+                    debug!("Fragment {}: leaving out schema {}", index, name);
+                    false
+                },
+                _ => true,
+            });
+        }
+        if !include.is_empty() || !exclude.is_empty() {
+            for frag in &mut processed {
+                for elements in frag.elements_mut() {
+                    elements.retain(|element| filtered_in(element, &include, &exclude, &options.scan));
+                }
+            }
+        }
+
+        let mut frag_stats = FragmentStats::default();
+        for frag in &processed {
+            frag_stats.parameters += frag.parameters.len();
+            frag_stats.shared_dims += frag.shared_dims.len();
+            frag_stats.cubes += frag.cubes.len();
+            frag_stats.virtual_cubes += frag.virtual_cubes.len();
+            frag_stats.named_sets += frag.named_sets.len();
+            frag_stats.roles += frag.roles.len();
+            frag_stats.functions += frag.functions.len();
+        }
+        stats.push(frag_stats);
+
+        for skipped in processed.iter().flat_map(|frag| &frag.skipped) {
+            let offset = scan::offset_in(input, skipped.text());
+            let (line, column) = tokenizer::line_col(input, offset);
+            warnings.push(Warning {
+                fragment: index,
+                line,
+                column,
+                rule: skipped.rule(),
+                element: skipped.element(),
+                path: Vec::new(),
+                span: offset..offset + skipped.text().len(),
+                message: skipped.message(),
+                first: None,
+            });
+        }
+        let mut duplicates = Vec::new();
+        for frag in &mut processed {
+            find_fragment_duplicates(index, input, frag, &mut names, options, &mut duplicates);
+        }
+        warnings.extend(duplicates);
+        for frag in &processed {
+            annotation_blocks.extend(frag.annotations.iter().map(|&block| (index, input.as_ref(), block)));
+            physical_blocks.extend(frag.physical_schemas.iter().map(|&block| (index, input.as_ref(), block)));
+            patch_blocks.extend(frag.patches.iter().map(|&block| (index, input.as_ref(), block)));
+            for &kind in &ElementKind::ALL {
+                merged_elements.extend(frag.elements(kind).iter()
+                    .map(|&element| (merged_element(index, input, element, kind), element)));
+            }
+        }
+        fragments.extend(processed.into_iter().map(|frag| (index, frag)));
+    }
+    // grouped by kind, each kind in the order of the fragments
+    merged_elements.sort_by_key(|(element, _)| element.kind);
+    // the parts of each cube go into the first
+    let mut merged_cubes = Vec::new();
+    if options.merge_cubes {
+        // each cube's first place, and its parts
+        let mut parts = Vec::new();
+        let mut by_name = HashMap::new();
+        let mut merged_parts = HashSet::new();
+        for (i, (element, text)) in merged_elements.iter().enumerate() {
+            let name = match element.name {
+                Some(ref name) if element.kind == ElementKind::Cube => name,
+                _ => continue,
+            };
+            let j = *by_name.entry(name.clone()).or_insert_with(|| {
+                parts.push((i, Vec::new()));
+                parts.len() - 1
+            });
+            if !parts[j].1.is_empty() {
+                merged_parts.insert(i);
+            }
+            parts[j].1.push((element.fragment, inputs[element.fragment].as_ref(), *text));
+        }
+        for (i, parts) in parts.into_iter().filter(|(_, parts)| parts.len() > 1) {
+            let (cube, conflicts) = cubes::merge_cube(&parts, &options.scan);
+            warnings.extend(conflicts);
+            merged_cubes.push((i, cube));
+        }
+        for (i, cube) in &merged_cubes {
+            merged_elements[*i].1 = &cube.text;
+        }
+        let mut i = 0;
+        merged_elements.retain(|_| {
+            i += 1;
+            !merged_parts.contains(&(i - 1))
+        });
+    } else if options.conflicts == ConflictStrategy::Overlay {
+        // the last version of each cube, which overlays the
+        // others, gets the annotations of them all
+        let mut versions: Vec<(usize, Vec<_>)> = Vec::new();
+        let mut by_name = HashMap::new();
+        for (i, (element, text)) in merged_elements.iter().enumerate() {
+            let name = match element.name {
+                Some(ref name) if element.kind == ElementKind::Cube => name,
+                _ => continue,
+            };
+            let j = *by_name.entry(name.clone()).or_insert_with(|| {
+                versions.push((i, Vec::new()));
+                versions.len() - 1
+            });
+            versions[j].0 = i;
+            versions[j].1.push((element.fragment, inputs[element.fragment].as_ref(), *text));
+        }
+        for (i, versions) in versions.into_iter().filter(|(_, versions)| versions.len() > 1) {
+            let (cube, conflicts) = cubes::overlay_cube(&versions, &options.scan);
+            warnings.extend(conflicts);
+            merged_cubes.extend(cube.map(|cube| (i, cube)));
+        }
+        for (i, cube) in &merged_cubes {
+            merged_elements[*i].1 = &cube.text;
+        }
+    }
+    resolve_conflicts(&mut merged_elements, options.conflicts);
+    // the generated virtual cubes are merged like the fragments'
+    // own, after them, so they're patched and checked too. Each
+    // is placed at the start tag of its first cube
+    let mut generated: Vec<(MergedElement, String, Vec<validate::Source>)> = Vec::new();
+    if !options.virtual_cubes.is_empty() {
+        let named = |kind| -> Vec<(&MergedElement, &str, &str)> {
+            merged_elements.iter()
+                .filter(|(element, _)| element.kind == kind)
+                .filter_map(|(element, text)| Some((element, element.name.as_deref()?, *text)))
+                .collect()
+        };
+        let cube_elements = named(ElementKind::Cube);
+        let cubes: Vec<(&str, &str)> = cube_elements.iter().map(|&(_, name, text)| (name, text)).collect();
+        let dimensions: Vec<&str> = named(ElementKind::SharedDimension).into_iter().map(|(_, name, _)| name).collect();
+        let mut taken: HashSet<&str> = cube_elements.iter().chain(&named(ElementKind::VirtualCube)).map(|&(_, name, _)| name).collect();
+        for recipe in &options.virtual_cubes {
+            if !taken.insert(&recipe.name) {
+                return Err(ErrorKind::InvalidRecipe(recipe.name.clone(), "its name is taken".to_owned()).into());
+            }
+            let text = recipe.generate(&cubes, &dimensions, &options.scan)?;
+            let &(base, _, base_text) = cube_elements.iter()
+                .find(|&&(_, name, _)| Some(name) == recipe.cubes.first().map(|cube| cube.as_str()))
+                .expect("generate checks the cubes");
+            let base_source = element_sources(&merged_cubes, &inputs, base, base_text).remove(0);
+            let sources = text.match_indices('<')
+                .map(|(start, _)| validate::Source { start, ..base_source.clone() })
+                .collect();
+            let element = MergedElement { kind: ElementKind::VirtualCube, name: Some(recipe.name.clone()), ..base.clone() };
+            generated.push((element, text, sources));
+        }
+    }
+    merged_elements.extend(generated.iter().map(|(element, text, _)| (element.clone(), text.as_str())));
+    merged_elements.sort_by_key(|(element, _)| element.kind);
+    // patches change what's been merged, one after the other,
+    // each element patched keeping where its pieces came from
+    let mut patched: Vec<(usize, String, Vec<validate::Source>)> = Vec::new();
+    for &(index, input, text) in &patch_blocks {
+        let patch = match patch::Patch::parse(index, input, text, &options.scan, &mut warnings) {
+            Some(patch) => patch,
+            None => continue,
+        };
+        let target = merged_elements.iter()
+            .position(|(element, _)| element.kind == patch.kind && element.name.as_deref() == Some(patch.name.as_str()));
+        let i = match target {
+            Some(i) => i,
+            None => {
+                warnings.push(patch.unknown_target());
+                continue;
+            },
+        };
+        match patched.iter_mut().find(|(j, ..)| *j == i) {
+            Some(&mut (_, ref mut text, ref mut sources)) => {
+                let (text_patched, sources_patched) = patch.apply(text, sources);
+                *text = text_patched;
+                *sources = sources_patched;
+            },
+            None => {
+                let (element, text) = &merged_elements[i];
+                let sources = match generated.iter().find(|(_, generated, _)| generated.as_ptr() == text.as_ptr()) {
+                    Some((_, _, sources)) => sources.clone(),
+                    None => element_sources(&merged_cubes, &inputs, element, text),
+                };
+                let (text, sources) = patch.apply(text, &sources);
+                patched.push((i, text, sources));
+            },
+        }
+    }
+    for (i, text, _) in &patched {
+        merged_elements[*i].1 = text;
+    }
+    let (schema_attributes, conflicts) = merge_schema_attributes(&inputs, &fragments, options.default_roles, options.schema_attributes);
+    warnings.extend(conflicts);
+    if let Some(default_role) = schema_attributes.iter().find(|attribute| attribute.name == DEFAULT_ROLE_ATTR) {
+        let is_default = |element: &MergedElement| {
+            element.kind == ElementKind::Role && element.name.as_deref() == Some(&default_role.value)
+        };
+        if !merged_elements.iter().any(|(element, _)| is_default(element)) {
+            let message = format!("The schema's default role \"{}\" isn't defined", default_role.value);
+            warnings.push(default_role.warning("unknown-default-role", message));
+        }
+    }
+    let (mut schema_annotations, conflicts) = annotations::merge_annotations(&annotation_blocks, None, &options.scan);
+    warnings.extend(conflicts);
+    annotations::inject(&mut schema_annotations, &options.annotations);
+    let (physical_elements, conflicts) = physical::merge_physical_schemas(&physical_blocks, &options.scan);
+    warnings.extend(conflicts);
+    let metamodel = match options.metamodel {
+        Metamodel::Auto => {
+            let version_4 = schema_attributes.iter()
+                .any(|attribute| attribute.name == METAMODEL_VERSION_ATTR && attribute.value.starts_with('4'));
+            if version_4 || !physical_blocks.is_empty() {
+                Metamodel::Mondrian4
+            } else {
+                Metamodel::Mondrian3
+            }
+        },
+        metamodel => metamodel,
+    };
+
+    if let Some(ref only) = options.only_schema {
+        if !fragments.iter().any(|(_, frag)| frag.schema_name.is_some()) {
+            return Err(ErrorKind::SchemaNotFound(only.clone()).into());
+        }
+    }
+
+    // references between elements can only be checked once
+    // everything in the schema is known
+    let checked: Vec<_> = merged_elements.iter()
+        .filter_map(|(element, text)| {
+            let combined = merged_cubes.iter()
+                .map(|(_, cube)| (&cube.text, &cube.sources))
+                .chain(generated.iter().map(|(_, text, sources)| (text, sources)))
+                .chain(patched.iter().map(|(_, text, sources)| (text, sources)))
+                .find(|(combined, _)| combined.as_ptr() == text.as_ptr());
+            match combined {
+                Some((_, sources)) => validate::Checked::combined(element.kind, text, sources.clone()),
+                None => validate::Checked::new(element.kind, element.fragment, &inputs[element.fragment], text),
+            }
+        })
+        .collect();
+    // Mondrian 4 lays dimensions and cubes out differently, so
+    // only the checks which hold for both generations run on it
+    let mondrian3 = metamodel == Metamodel::Mondrian3;
+    if mondrian3 {
+        warnings.extend(validate::check_dimension_usages(&checked, &options.scan));
+        warnings.extend(validate::check_unused_dimensions(&checked, &options.scan));
+        warnings.extend(validate::check_virtual_cubes(&checked, &options.scan));
+        warnings.extend(validate::check_measures(&checked, &options.scan));
+        warnings.extend(validate::check_hierarchy_keys(&checked, &options.scan));
+        warnings.extend(validate::check_cubes_have_measures(&checked, &options.scan));
+        warnings.extend(validate::check_duplicate_measures(&checked, &options.scan));
+        warnings.extend(validate::check_levels(&checked, &options.scan));
+        warnings.extend(validate::check_foreign_keys(&checked, &options.scan));
+    }
+    warnings.extend(validate::check_formulas(&checked, &options.scan));
+    if mondrian3 {
+        warnings.extend(validate::check_default_measures(&checked, &options.scan));
+    }
+    warnings.extend(validate::check_names(&checked, &options.scan));
+    if mondrian3 {
+        warnings.extend(validate::check_roles(&checked, &options.scan));
+    }
+    warnings.extend(validate::check_cycles(&checked, &options.scan));
+    warnings.extend(validate::check_named_sets(&checked, &options.scan));
+    if mondrian3 {
+        warnings.extend(validate::check_aggregates(&checked, &options.scan));
+        warnings.extend(validate::check_writeback(&checked, &options.scan));
+    }
+    warnings.extend(validate::check_attribute_values(&checked));
+    if mondrian3 {
+        warnings.extend(validate::check_deprecated_attributes(&checked, &options.scan));
+    }
+    warnings.extend(validate::check_duplicate_hierarchies(&checked, &options.scan));
+    if mondrian3 {
+        warnings.extend(validate::check_hierarchy_relations(&checked, &options.scan));
+    }
+    warnings.extend(validate::check_naming(&checked, &naming, &options.scan));
+    // these are allowed by default, so only checked when asked
+    if mondrian3 && options.level("invalid-structure") != lint::Level::Allow {
+        warnings.extend(validate::check_structure(&checked, &options.scan));
+    }
+    if options.level("missing-caption") != lint::Level::Allow {
+        warnings.extend(validate::check_captions(&checked, &options.scan, "caption", "missing-caption"));
+    }
+    if options.level("missing-description") != lint::Level::Allow {
+        warnings.extend(validate::check_captions(&checked, &options.scan, "description", "missing-description"));
+    }
+
+    // everything found so far is sorted out by level
+    let mut errors = Vec::new();
+    warnings.retain(|warning| match options.level(warning.rule) {
+        lint::Level::Allow => false,
+        lint::Level::Warn => true,
+        lint::Level::Deny => {
+            errors.push(warning.clone());
+            false
+        },
+    });
+
+    // schema name handling
+    let mut schema_name: Option<(usize, &Cow<str>)> = None;
+    for &(index, ref frag) in &fragments {
+        if let Some(ref current_name) = frag.schema_name {
+            if let Some((stored_index, stored_name)) = schema_name {
+                if stored_name != current_name && !options.combine {
+                    let kind = ErrorKind::SchemaNameMismatch(
+                        stored_name.to_string(),
+                        stored_index,
+                        current_name.to_string(),
+                        index,
+                    );
+                    return Err(kind.into());
+                }
+            } else {
+                schema_name = Some((index, current_name));
+            }
+        } else {
+            continue
+        }
+    }
+
+    // now push onto final str
+    let mut final_schema = String::new();
+    if options.xml_declaration {
+        final_schema.push_str(XML_DECLARATION);
+        final_schema.push('\n');
+    }
+    let schema_name = match (options.schema_name.as_ref(), schema_name, options.default_schema_name.as_ref()) {
+        (Some(name), _, _) => name.as_str(),
+        (None, Some((_, name)), _) => name.as_ref(),
+        (None, None, Some(name)) => name.as_str(),
+        (None, None, None) => return Err(ErrorKind::NoSchemaName.into()),
+    };
+    final_schema.push_str("<Schema name=\"");
+    final_schema.push_str(&escape::escape(schema_name));
+    final_schema.push('"');
+    for attribute in &schema_attributes {
+        final_schema.push_str(&format!(" {}=\"{}\"", attribute.name, escape::escape(&attribute.value)));
+    }
+    final_schema.push_str(">\n");
+
+    if options.sort != SortOrder::Input {
+        schema_annotations.sort_by(|a, b| a.name.cmp(&b.name));
+        merged_elements.sort_by(|a, b| a.0.kind.cmp(&b.0.kind).then_with(|| a.0.name.cmp(&b.0.name)));
+    }
+    if options.sort == SortOrder::Dependency {
+        // the dimensions follow the cubes as they're ordered
+        order_by_use(&mut merged_elements, ElementKind::Cube, &options.scan);
+        order_by_use(&mut merged_elements, ElementKind::SharedDimension, &options.scan);
+    }
+
+    order_references(&mut merged_elements, ElementKind::VirtualCube, CUBE_USAGE_TAGS, "cubeName", &options.scan);
+    order_references(&mut merged_elements, ElementKind::Role, &[ROLE_USAGE_TAG], "roleName", &options.scan);
+
+    // the annotations come first, then the elements kind by
+    // kind, with a Mondrian 4 schema's tables between its
+    // parameters and its dimensions
+    if !schema_annotations.is_empty() {
+        final_schema.push_str(&annotations::annotations_block(&schema_annotations));
+        final_schema.push('\n');
+    }
+    let mut physical_schema = Some(physical_elements).filter(|elements| !elements.is_empty());
+    let mut elements = Vec::with_capacity(merged_elements.len());
+    for (element, text) in merged_elements {
+        if element.kind > ElementKind::Parameter {
+            if let Some(physical_elements) = physical_schema.take() {
+                final_schema.push_str(&physical::physical_schema_block(&physical_elements));
+                final_schema.push('\n');
+            }
+        }
+        let text = match element.name {
+            Some(ref name) if options.conflicts == ConflictStrategy::RenameWithSuffix => rename(text, name),
+            _ => Cow::Borrowed(text),
+        };
+        if element.kind == ElementKind::Cube {
+            final_schema.push_str(&annotations::inject_into(&text, &options.cube_annotations, &options.scan));
+        } else {
+            final_schema.push_str(&text);
+        }
+        final_schema.push('\n');
+        elements.push(element);
+    }
+    if let Some(physical_elements) = physical_schema {
+        final_schema.push_str(&physical::physical_schema_block(&physical_elements));
+        final_schema.push('\n');
+    }
+
+    final_schema.push_str("</Schema>");
+
+    if options.normalize_case {
+        final_schema = scan::normalize_case(&final_schema);
+    }
+
+    if options.verify_output {
+        verify_output(&final_schema, options.scan.ignore_case)?;
+    }
+
+    Ok(Merged {
+        schema: final_schema,
+        schema_name: schema_name.to_owned(),
+        warnings,
+        errors,
+        fragments: stats,
+        elements,
+        generated: options.virtual_cubes.iter().map(|recipe| recipe.name.clone()).collect(),
+    })
+}
+
+/// Check that a merged schema is well formed, so nothing
+/// Mondrian can't parse is ever written out
+fn verify_output(schema: &str, ignore_case: bool) -> Result<()> {
+    tokenizer::check_well_formed(schema, ignore_case).map_err(|err| {
+        let (line, column) = tokenizer::line_col(schema, err.pos);
+        ErrorKind::MalformedOutput(line, column, err.message).into()
+    })
+}
+
+/// Where each name of the fragments was first seen, and its
+/// definition, by `ElementKind::namespace`
+type Names<S> = HashMap<ElementKind, HashMap<String, (usize, usize, S)>>;
+
+/// Find the duplicates among the elements of `frag` (from
+/// fragment `index`, a slice of `input`) kind by kind, as
+/// `find_duplicates` does
+fn find_fragment_duplicates<'a, S: AsRef<str> + From<&'a str>>(
+    index: usize,
+    input: &str,
+    frag: &mut Fragment<'a>,
+    seen: &mut Names<S>,
+    options: &MergeOptions,
+    warnings: &mut Vec<Warning>,
+)
+{
+    for (&kind, elements) in ElementKind::ALL.iter().zip(frag.elements_mut()) {
+        find_duplicates(index, input, elements, kind, seen.entry(kind.namespace()).or_default(), options, warnings);
+    }
+}
+
+/// Warn about each of `elements` of `kind` (from fragment
+/// `index`, a slice of `input`) with the same name as one seen
+/// before, and remember where and how the new names were
+/// defined. One which
+/// differs from the first is also a `duplicate-conflict`, with
+/// the lines which differ, unless the conflict is resolved; one
+/// which doesn't is left out of `elements` with
+/// `dedupe_identical`, without a word. A cube or virtual cube
+/// which is the first byte for byte is left out too, as an
+/// `identical-copy`, while duplicate names are only warned about.
+fn find_duplicates<'a, S: AsRef<str> + From<&'a str>>(
+    index: usize,
+    input: &str,
+    elements: &mut Vec<&'a str>,
+    kind: ElementKind,
+    seen: &mut HashMap<String, (usize, usize, S)>,
+    options: &MergeOptions,
+    warnings: &mut Vec<Warning>,
+)
+{
+    let mut kind = kind.to_string();
+    kind[..1].make_ascii_uppercase();
+    elements.retain(|&element| {
+        let (start, tag, name) = match element_name(element) {
+            Some(found) => found,
+            None => return true,
+        };
+        let offset = scan::offset_in(input, element);
+        let (line, column) = tokenizer::line_col(input, offset + start);
+        let (first_index, first_line, first_text) = match seen.get(&name) {
+            Some(&(first_index, first_line, ref first_text)) => (first_index, first_line, first_text.as_ref()),
+            None => {
+                seen.insert(name, (index, line, S::from(element)));
+                return true;
+            },
+        };
+        let identical = diff::normalize(first_text) == diff::normalize(element);
+        // parts of the same cube are merged later
+        let is_cube = |tag| scan::names_match(tag, CUBE_TAG, &options.scan);
+        if options.merge_cubes && is_cube(tag) && element_name(first_text).is_some_and(|(_, first_tag, _)| is_cube(first_tag)) {
+            return true;
+        }
+        let warning = Warning {
+            fragment: index,
+            line,
+            column,
+            rule: "duplicate-name",
+            element: Some(format!("{} \"{}\"", kind, name)),
+            path: vec![format!("{} \"{}\"", tag, name)],
+            span: offset + start..offset + element.len(),
+            message: format!("{} \"{}\" is defined more than once", kind, name),
+            first: Some((first_index, first_line)),
+        };
+        if identical {
+            if options.dedupe_identical {
+                debug!("Fragment {}: leaving out {} {}, identical to the one in fragment {}", index, kind, name, first_index);
+                return false;
+            }
+            // most likely the same fragment given twice, e.g. by
+            // overlapping globs, which Mondrian would refuse
+            let is_any_cube = |tag| [CUBE_TAG, VIRTUALCUBE_TAG].iter().any(|cube| scan::names_match(tag, cube, &options.scan));
+            if element == first_text && is_any_cube(tag) && options.level("duplicate-name") == lint::Level::Warn {
+                let message = format!("{} \"{}\" is a copy of an earlier one, and is left out", kind, name);
+                warnings.push(Warning { rule: "identical-copy", message, ..warning });
+                return false;
+            }
+        }
+        if options.conflicts == ConflictStrategy::Overlay {
+            let message = format!("{} \"{}\" overrides an earlier definition", kind, name);
+            warnings.push(Warning { rule: "overridden", message, ..warning });
+            return true;
+        }
+        if let ConflictStrategy::FirstWins | ConflictStrategy::LastWins | ConflictStrategy::RenameWithSuffix = options.conflicts {
+            debug!("Fragment {}: {} {} is defined again, which the conflict strategy resolves", index, kind, name);
+            return true;
+        }
+        let conflict = if identical {
+            None
+        } else {
+            // where the first is comes with `duplicate-name`, and
+            // would only come after the lines here
+            let message = format!(
+                "{} \"{}\" differs from its first definition:\n{}",
+                kind,
+                name,
+                diff::diff_lines(first_text, element).trim_end()
+            );
+            Some(Warning { rule: "duplicate-conflict", message, first: None, ..warning.clone() })
+        };
+        warnings.push(warning);
+        warnings.extend(conflict);
+        true
+    });
+}
+
+/// Where the pieces of `text`, `element` of the merged schema,
+/// came from: the parts of a merged cube, or the fragment it's a
+/// slice of
+fn element_sources<'a>(
+    merged_cubes: &[(usize, cubes::MergedCube<'a>)],
+    inputs: &'a [Cow<str>],
+    element: &MergedElement,
+    text: &str,
+) -> Vec<validate::Source<'a>>
+{
+    match merged_cubes.iter().find(|(_, cube)| cube.text.as_ptr() == text.as_ptr()) {
+        Some((_, cube)) => cube.sources.clone(),
+        None => {
+            let input: &str = &inputs[element.fragment];
+            vec![validate::Source { start: 0, fragment: element.fragment, input, offset: scan::offset_in(input, text) }]
+        },
+    }
+}
+
+/// Describe an extracted `element` of fragment `index`, a slice
+/// of `input`
+fn merged_element(index: usize, input: &str, element: &str, kind: ElementKind) -> MergedElement {
+    let (start, name) = match element_name(element) {
+        Some((start, _, name)) => (start, Some(name)),
+        None => (0, None),
+    };
+    let (line, _) = tokenizer::line_col(input, scan::offset_in(input, element) + start);
+    MergedElement { kind, name, fragment: index, line }
+}
+
+/// Resolve the names `elements` (grouped by kind) share as
+/// `strategy` says. Cubes and virtual cubes share names.
+fn resolve_conflicts(elements: &mut Vec<(MergedElement, &str)>, strategy: ConflictStrategy) {
+    let namespace = ElementKind::namespace;
+    let mut count: HashMap<(ElementKind, String), usize> = HashMap::new();
+    for (element, _) in elements.iter() {
+        if let Some(ref name) = element.name {
+            *count.entry((namespace(element.kind), name.clone())).or_insert(0) += 1;
+        }
+    }
+    match strategy {
+        ConflictStrategy::KeepAll | ConflictStrategy::Error => {},
+        ConflictStrategy::FirstWins => {
+            let mut seen = HashSet::new();
+            elements.retain(|(element, _)| match element.name {
+                Some(ref name) => seen.insert((namespace(element.kind), name.clone())),
+                None => true,
+            });
+        },
+        ConflictStrategy::LastWins => {
+            // how many of each name are still to come
+            let mut left = count;
+            elements.retain(|(element, _)| match element.name {
+                Some(ref name) => {
+                    let left = left.get_mut(&(namespace(element.kind), name.clone())).expect("every name is counted");
+                    *left -= 1;
+                    *left == 0
+                },
+                None => true,
+            });
+        },
+        ConflictStrategy::Overlay => {
+            let mut places: HashMap<_, Vec<usize>> = HashMap::new();
+            for (i, (element, _)) in elements.iter().enumerate() {
+                if let Some(ref name) = element.name {
+                    places.entry((namespace(element.kind), name.clone())).or_default().push(i);
+                }
+            }
+            // the last goes where the first is, unless one's a
+            // cube and the other a virtual cube
+            let mut left_out: HashSet<usize> = HashSet::new();
+            for places in places.values().filter(|places| places.len() > 1) {
+                let (first, last) = (places[0], places[places.len() - 1]);
+                if elements[first].0.kind == elements[last].0.kind {
+                    elements[first] = elements[last].clone();
+                    left_out.extend(&places[1..]);
+                } else {
+                    left_out.extend(&places[..places.len() - 1]);
+                }
+            }
+            let mut i = 0;
+            elements.retain(|_| {
+                i += 1;
+                !left_out.contains(&(i - 1))
+            });
+        },
+        ConflictStrategy::RenameWithSuffix => {
+            let mut taken: HashSet<_> = count.into_keys().collect();
+            let mut seen = HashSet::new();
+            for (element, _) in elements.iter_mut() {
+                let kind = namespace(element.kind);
+                let name = match element.name {
+                    Some(ref name) => name.clone(),
+                    None => continue,
+                };
+                if seen.insert((kind, name.clone())) {
+                    continue;
+                }
+                let renamed = (2..)
+                    .map(|suffix| format!("{}_{}", name, suffix))
+                    .find(|renamed| !taken.contains(&(kind, renamed.clone())))
+                    .expect("some suffix is free");
+                taken.insert((kind, renamed.clone()));
+                element.name = Some(renamed);
+            }
+        },
+    }
+}
+
+/// `element` with the name of its start tag set to `name`, if
+/// it isn't already
+fn rename<'a>(element: &'a str, name: &str) -> Cow<'a, str> {
+    let tag = match Tokenizer::new(element)
+        .filter_map(|token| token.ok())
+        .find(|token| matches!(token.kind, TokenKind::StartTag { .. }))
+    {
+        Some(tag) => tag,
+        None => return Cow::Borrowed(element),
+    };
+    let value = match scan::attributes(tag.text).into_iter().find(|&(attr, _)| attr == "name") {
+        Some((_, value)) => value,
+        None => return Cow::Borrowed(element),
+    };
+    if escape::unescape(value).ok().is_some_and(|value| value == name) {
+        return Cow::Borrowed(element);
+    }
+    let start = scan::offset_in(element, value);
+    Cow::Owned(format!("{}{}{}", &element[..start], escape::escape(name), &element[start + value.len()..]))
+}
+
+/// Move the elements of `kind` among `elements` (grouped by kind)
+/// into the order the cubes and virtual cubes after them first
+/// use them, leaving those not used at the end, as they are
+fn order_by_use(elements: &mut [(MergedElement, &str)], kind: ElementKind, options: &ScanOptions) {
+    let is = |element: &tree::Element, tag| scan::names_match(element.name, tag, options);
+    let mut used: HashMap<String, usize> = HashMap::new();
+    for &(_, text) in elements.iter().filter(|(element, _)| element.kind > kind && element.kind.is_cube()) {
+        let root = match tree::parse(text).ok().and_then(|roots| roots.into_iter().next()) {
+            Some(root) => root,
+            None => continue,
+        };
+        for element in root.descendants() {
+            let name = match kind {
+                ElementKind::Cube if is(element, "CubeUsage") || is(element, "VirtualCubeDimension") || is(element, "VirtualCubeMeasure") => {
+                    element.attr("cubeName")
+                },
+                ElementKind::SharedDimension if is(element, "VirtualCubeDimension") && element.attr("cubeName").is_none() => {
+                    element.name_attr()
+                },
+                // a Mondrian 4 cube's dimensions have a source too
+                ElementKind::SharedDimension if is(element, "DimensionUsage") || is(element, DIM_TAG) => element.attr("source"),
+                _ => None,
+            };
+            if let Some(name) = name {
+                let next = used.len();
+                used.entry(name.into_owned()).or_insert(next);
+            }
+        }
+    }
+    let start = match elements.iter().position(|(element, _)| element.kind == kind) {
+        Some(start) => start,
+        None => return,
+    };
+    let len = elements[start..].iter().take_while(|(element, _)| element.kind == kind).count();
+    elements[start..start + len].sort_by_key(|(element, _)| {
+        element.name.as_ref().and_then(|name| used.get(name)).cloned().unwrap_or(usize::MAX)
+    });
+}
+
+/// Move the elements of `kind` among `elements` (grouped by kind)
+/// so that those each refers to, with `attribute` of a `tags`
+/// element in it, come before it: roles before the unions made
+/// of them, as Mondrian only finds roles it has already read,
+/// and virtual cubes before those built on them. Otherwise they
+/// keep their order, and those in a cycle stay as they are.
+fn order_references(elements: &mut [(MergedElement, &str)], kind: ElementKind, tags: &[&str], attribute: &str, options: &ScanOptions) {
+    let start = match elements.iter().position(|(element, _)| element.kind == kind) {
+        Some(start) => start,
+        None => return,
+    };
+    let len = elements[start..].iter().take_while(|(element, _)| element.kind == kind).count();
+    let same_kind = &mut elements[start..start + len];
+
+    let mut names = HashMap::new();
+    for (i, (element, _)) in same_kind.iter().enumerate() {
+        if let Some(ref name) = element.name {
+            names.entry(name.clone()).or_insert(i);
+        }
+    }
+    // those each one refers to, in the order they're in
+    let uses: Vec<Vec<usize>> = same_kind.iter()
+        .map(|&(_, text)| {
+            let root = tree::parse(text).ok().and_then(|roots| roots.into_iter().next());
+            let mut used: Vec<usize> = root.map(|root| root.descendants().into_iter()
+                    .filter(|element| tags.iter().any(|tag| scan::names_match(element.name, tag, options)))
+                    .filter_map(|element| names.get(element.attr(attribute)?.as_ref()).cloned())
+                    .collect())
+                .unwrap_or_default();
+            used.sort();
+            used
+        })
+        .collect();
+
+    // depth first, each after those it uses
+    fn visit(i: usize, uses: &[Vec<usize>], visiting: &mut Vec<bool>, order: &mut Vec<usize>) {
+        if visiting[i] || order.contains(&i) {
+            return;
+        }
+        visiting[i] = true;
+        for &used in &uses[i] {
+            visit(used, uses, visiting, order);
+        }
+        visiting[i] = false;
+        order.push(i);
+    }
+    let mut order = Vec::with_capacity(len);
+    let mut visiting = vec![false; len];
+    for i in 0..len {
+        visit(i, &uses, &mut visiting, &mut order);
+    }
+    let ordered: Vec<_> = order.into_iter().map(|i| same_kind[i].clone()).collect();
+    same_kind.clone_from_slice(&ordered);
+}
+
+/// An attribute of the merged Schema tag, and where its value
+/// was taken from.
+struct SchemaAttribute<'a> {
+    name: &'a str,
+    /// The value, unescaped
+    value: Cow<'a, str>,
+    /// Where the value is, as in `Warning`
+    fragment: usize,
+    line: usize,
+    column: usize,
+    span: Range<usize>,
+}
+
+impl<'a> SchemaAttribute<'a> {
+    fn warning(&self, rule: &'static str, message: String) -> Warning {
+        Warning {
+            fragment: self.fragment,
+            line: self.line,
+            column: self.column,
+            rule,
+            element: Some(format!("<{}>", SCHEMA_TAG)),
+            path: vec![SCHEMA_TAG.to_owned()],
+            span: self.span.clone(),
+            message,
+            first: None,
+        }
+    }
+}
+
+/// The attributes of the Schema tags other than the name and
+/// namespace declarations, in the order first seen. An attribute
+/// given another value by a later fragment follows `policy`,
+/// except for `defaultRole`, which follows `default_roles`.
+fn merge_schema_attributes<'a, S: AsRef<str>>(
+    inputs: &[S],
+    fragments: &[(usize, Fragment<'a>)],
+    default_roles: DefaultRolePolicy,
+    policy: SchemaAttributePolicy,
+) -> (Vec<SchemaAttribute<'a>>, Vec<Warning>) {
+    let mut res: Vec<SchemaAttribute> = Vec::new();
+    let mut warnings = Vec::new();
+    let mut dropped = Vec::new();
+    for &(index, ref frag) in fragments {
+        let input = inputs[index].as_ref();
+        let merged = frag.schema_attributes.iter()
+            .filter(|&&(name, _)| name != "name" && name != "xmlns" && !name.starts_with("xmlns:"));
+        for &(name, raw) in merged {
+            let offset = scan::offset_in(input, raw);
+            let (line, column) = tokenizer::line_col(input, offset);
+            let attribute = SchemaAttribute {
+                name,
+                value: escape::unescape(raw).unwrap_or(Cow::Borrowed(raw)),
+                fragment: index,
+                line,
+                column,
+                span: offset..offset + raw.len(),
+            };
+            let i = match res.iter().position(|seen| seen.name == name) {
+                Some(i) => i,
+                None => {
+                    res.push(attribute);
+                    continue;
+                },
+            };
+            if res[i].value == attribute.value {
+                continue;
+            }
+            let policy = match (name == DEFAULT_ROLE_ATTR, policy) {
+                (true, _) => default_roles,
+                (false, SchemaAttributePolicy::Error) => DefaultRolePolicy::Error,
+                (false, SchemaAttributePolicy::First) => DefaultRolePolicy::First,
+                (false, SchemaAttributePolicy::Last) => DefaultRolePolicy::Last,
+                (false, SchemaAttributePolicy::Concat) => {
+                    if !res[i].value.split(CONCAT_SEPARATOR).any(|value| value == attribute.value) {
+                        let value = format!("{}{}{}", res[i].value, CONCAT_SEPARATOR, attribute.value);
+                        res[i].value = Cow::Owned(value);
+                    }
+                    continue;
+                },
+            };
+            match policy {
+                DefaultRolePolicy::Error => {
+                    let message = format!(
+                        "Schema attribute {} is \"{}\" here, but was \"{}\" first",
+                        name, attribute.value, res[i].value
+                    );
+                    let first = (res[i].fragment, res[i].line);
+                    warnings.push(Warning { first: Some(first), ..attribute.warning("schema-attribute-conflict", message) });
+                },
+                DefaultRolePolicy::First => {},
+                DefaultRolePolicy::Last => res[i] = attribute,
+                DefaultRolePolicy::Drop => dropped.push(name),
+            }
+        }
+    }
+    res.retain(|attribute| !dropped.contains(&attribute.name));
+    (res, warnings)
+}
+
+/// Compile filters (see `MergeOptions::include`), so that each
+/// matches names as a whole
+fn name_filters(filters: &BTreeMap<String, String>) -> Result<Vec<(&str, Regex)>> {
+    filters.iter()
+        .map(|(element, pattern)| match Regex::new(&format!("^(?:{})$", pattern)) {
+            Ok(regex) => Ok((element.as_str(), regex)),
+            Err(err) => Err(ErrorKind::InvalidFilter(element.clone(), err.to_string()).into()),
+        })
+        .collect()
+}
+
+/// Whether an extracted element gets past the filters for its
+/// tag: its name matches the one included, if any, and not the
+/// one excluded
+fn filtered_in(element: &str, include: &[(&str, Regex)], exclude: &[(&str, Regex)], options: &ScanOptions) -> bool {
+    let (_, tag, name) = match element_name(element) {
+        Some(found) => found,
+        None => return true,
+    };
+    let applies = |&&(element, _): &&(&str, Regex)| scan::names_match(tag, element, options);
+    include.iter().filter(applies).all(|(_, regex)| regex.is_match(&name))
+        && !exclude.iter().filter(applies).any(|(_, regex)| regex.is_match(&name))
+}
+
+/// The offset of the start tag of an extracted element (which
+/// may have comments before it), its tag and its unescaped name
+fn element_name(element: &str) -> Option<(usize, &str, String)> {
+    let tag = Tokenizer::new(element)
+        .filter_map(|token| token.ok())
+        .find(|token| matches!(token.kind, TokenKind::StartTag { .. }))?;
+    let name = scan::attributes(tag.text)
+        .into_iter()
+        .find(|&(name, _)| name == "name")?
+        .1;
+    let tag_name = match tag.kind {
+        TokenKind::StartTag { name, .. } => name,
+        _ => unreachable!("only start tags are found"),
+    };
+    escape::unescape(name).ok().map(|name| (tag.start, tag_name, name.into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_schema_name() {
+        let fragment = r#"<Schema name="testname"></Schema>"#;
+        assert_eq!(Fragment::get_schema_name(fragment, &ScanOptions::default()).unwrap(), Some("testname".into()));
+        let fragment = r#"<Cube name="testname"></Cube>"#;
+        assert_eq!(Fragment::get_schema_name(fragment, &ScanOptions::default()).unwrap(), None);
+
+        // name doesn't have to be the first attribute
+        let fragment = r#"<Schema description="test schema" name="testname" measuresCaption="Measures"></Schema>"#;
+        assert_eq!(Fragment::get_schema_name(fragment, &ScanOptions::default()).unwrap(), Some("testname".into()));
+        assert_eq!(
+            Fragment::get_schema_attributes(fragment, &ScanOptions::default()).unwrap(),
+            Some(vec![("description", "test schema"), ("name", "testname"), ("measuresCaption", "Measures")])
+        );
+
+        let fragment = r#"<Schema description="no name"></Schema>"#;
+        assert_eq!(Fragment::get_schema_name(fragment, &ScanOptions::default()).unwrap(), None);
+
+        // names are unescaped, and quotes of the other kind
+        // don't end the value
+        let fragment = r#"<Schema name="Sales &quot;EU&quot;"></Schema>"#;
+        assert_eq!(Fragment::get_schema_name(fragment, &ScanOptions::default()).unwrap(), Some(r#"Sales "EU""#.into()));
+        let fragment = r#"<Schema name='Sales "EU"' description="x"></Schema>"#;
+        assert_eq!(Fragment::get_schema_name(fragment, &ScanOptions::default()).unwrap(), Some(r#"Sales "EU""#.into()));
+        let fragment = r#"<Schema name="O&apos;Brien's"></Schema>"#;
+        assert_eq!(Fragment::get_schema_name(fragment, &ScanOptions::default()).unwrap(), Some("O'Brien's".into()));
+
+        let fragment = r#"<Schema name="a & b"></Schema>"#;
+        assert!(Fragment::get_schema_name(fragment, &ScanOptions::default()).is_err());
+
+        // a Schema tag commented out isn't one
+        let fragment = r#"<!-- <Schema name="old"> --><Schema name="new"></Schema>"#;
+        assert_eq!(Fragment::get_schema_name(fragment, &ScanOptions::default()).unwrap(), Some("new".into()));
+    }
+
+    #[test]
+    fn test_get_share_dims() {
+        let fragment = r#"<Schema name="testname">
+            <Cube name="testcube"></Cube></Schema>"#;
+        assert!(Fragment::get_shared_dims(fragment, &ScanOptions::default()).unwrap().is_empty());
+
+        // having separate test cases for the Dimension tag and SharedDimension tag
+        // is to be able to support the existing Mondrian Schema which have the Dimension tag.
+        // Whereas moving forward we can use SharedDimension tags to define SharedDimension in the schmeas
+        // gets shareddims tag and dims tag  before cubes
+        let fragment = r#"<Schema name="testname">
+            <SharedDimension></SharedDimension><Cube name="testcube"></Cube></Schema>"#;
+        assert_eq!(
+            Fragment::get_shared_dims(fragment, &ScanOptions::default()).unwrap(),
+            vec!["<SharedDimension></SharedDimension>"]
+        );
+
+        let fragment = r#"<Schema name="testname">
+            <Dimension></Dimension><Cube name="testcube"></Cube></Schema>"#;
+        assert_eq!(
+            Fragment::get_shared_dims(fragment, &ScanOptions::default()).unwrap(),
+            vec!["<Dimension></Dimension>"]
+        );
+
+        // does not get internal dims within cube
+        // (this test has an extra Cube to make sure
+        // that adding a Cube tag match after the dim
+        // in this case doesn't trigger getting the
+        // intermal dim
+        let fragment = r#"<Schema name="testname">
+            <Cube name="testcube"><Dimension></Dimension></Cube>
+            <Cube name="a"></Cube>
+            </Schema>"#;
+        assert!(Fragment::get_shared_dims(fragment, &ScanOptions::default()).unwrap().is_empty());
+
+        // Test only shared dims, both with and without schema tag
+        let fragment = r#"<Schema name="test">
+            <Dimension name="a"></Dimension></Schema>"#;
+        assert_eq!(
+            Fragment::get_shared_dims(fragment, &ScanOptions::default()).unwrap(),
+            vec![r#"<Dimension name="a"></Dimension>"#]
+        );
+
+        let fragment = r#"<Schema name="test">
+            <SharedDimension name="a"></SharedDimension></Schema>"#;
+        assert_eq!(
+            Fragment::get_shared_dims(fragment, &ScanOptions::default()).unwrap(),
+            vec![r#"<SharedDimension name="a"></SharedDimension>"#]
+        );
+
+        let fragment = r#"<SharedDimension name="a"></SharedDimension>"#;
+        assert_eq!(
+            Fragment::get_shared_dims(fragment, &ScanOptions::default()).unwrap(),
+            vec![r#"<SharedDimension name="a"></SharedDimension>"#]
+        );
+
+        let fragment = r#"<Dimension name="a"></Dimension>"#;
+        assert_eq!(
+            Fragment::get_shared_dims(fragment, &ScanOptions::default()).unwrap(),
+            vec![r#"<Dimension name="a"></Dimension>"#]
+        );
+    }
+
+    #[test]
+    fn test_get_cubes() {
+        let fragment = r#"<Cube name="a"></Cube><VirtualCube name="vc1"></VirtualCube>"#;
+        assert_eq!(
+            Fragment::get_cubes(fragment, &ScanOptions::default()).unwrap(),
+            vec![r#"<Cube name="a"></Cube>"#]
+        );
+
+        let fragment = r#"<Schema name="b"><Cube name="a"></Cube></Schema>"#;
+        assert_eq!(
+            Fragment::get_cubes(fragment, &ScanOptions::default()).unwrap(),
+            vec![r#"<Cube name="a"></Cube>"#]
+        );
+    }
+
+    #[test]
+    fn test_get_virtual_cubes() {
+        let fragment = r#"<Cube name="a"></Cube><VirtualCube name="vc1"></VirtualCube>"#;
+        assert_eq!(
+            Fragment::get_virtual_cubes(fragment, &ScanOptions::default()).unwrap(),
+            vec![r#"<VirtualCube name="vc1"></VirtualCube>"#]
+        );
+
+        let fragment = r#"<Schema name="s1"><VirtualCube name="vc1"></VirtualCube></Schema>"#;
+        assert_eq!(
+            Fragment::get_virtual_cubes(fragment, &ScanOptions::default()).unwrap(),
+            vec![r#"<VirtualCube name="vc1"></VirtualCube>"#]
+        );
+    }
+
+    #[test]
+    fn test_get_cubes_cdata() {
+        let fragment = r#"<Cube name="a"><View alias="v"><SQL><![CDATA[select '<VirtualCube' from t]]></SQL></View></Cube><VirtualCube name="vc1"></VirtualCube>"#;
+        assert_eq!(
+            Fragment::get_cubes(fragment, &ScanOptions::default()).unwrap(),
+            vec![r#"<Cube name="a"><View alias="v"><SQL><![CDATA[select '<VirtualCube' from t]]></SQL></View></Cube>"#]
+        );
+        assert_eq!(
+            Fragment::get_virtual_cubes(fragment, &ScanOptions::default()).unwrap(),
+            vec![r#"<VirtualCube name="vc1"></VirtualCube>"#]
+        );
+
+        let fragment = r#"<Dimension name="d"><Hierarchy><View alias="v"><SQL><![CDATA[select '<Cube' from t]]></SQL></View></Hierarchy></Dimension><Cube name="a"></Cube>"#;
+        assert_eq!(
+            Fragment::get_shared_dims(fragment, &ScanOptions::default()).unwrap(),
+            vec![r#"<Dimension name="d"><Hierarchy><View alias="v"><SQL><![CDATA[select '<Cube' from t]]></SQL></View></Hierarchy></Dimension>"#]
+        );
+    }
+
+    #[test]
+    fn test_process_fragment() {
+        let fragment = r#"<Schema name="testname">
+            <Dimension name="shareddim"></Dimension><Cube name="testcube"><Dimension name="inner"></Dimension></Cube><Cube name="a"></Cube><VirtualCube name="testvirtualcube"><Dimension name="inner_virtual"></Dimension></VirtualCube><VirtualCube name="a"></VirtualCube></Schema>"#;
+        assert_eq!(
+            Fragment::process_fragment(fragment).unwrap(),
+            Fragment {
+                schema_name: Some("testname".into()),
+                schema_attributes: vec![("name", "testname")],
+                annotations: vec![],
+                physical_schemas: vec![],
+                parameters: vec![],
+                shared_dims: vec![r#"<Dimension name="shareddim"></Dimension>"#],
+                cubes: vec![r#"<Cube name="testcube"><Dimension name="inner"></Dimension></Cube>"#, r#"<Cube name="a"></Cube>"#],
+                virtual_cubes: vec![r#"<VirtualCube name="testvirtualcube"><Dimension name="inner_virtual"></Dimension></VirtualCube>"#, r#"<VirtualCube name="a"></VirtualCube>"#],
+                named_sets: vec![],
+                roles: vec![],
+                functions: vec![],
+                patches: vec![],
+                skipped: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn test_process_fragment_shareddimension() {
+        let fragment = r#"<Schema name="testname">
+            <SharedDimension name="shareddim"></SharedDimension><Cube name="testcube"><Dimension name="inner"></Dimension></Cube><Cube name="a"></Cube><VirtualCube name="testvirtualcube"><Dimension name="inner_virtual"></Dimension></VirtualCube><VirtualCube name="a"></VirtualCube></Schema>"#;
+        assert_eq!(
+            Fragment::process_fragment(fragment).unwrap(),
+            Fragment {
+                schema_name: Some("testname".into()),
+                schema_attributes: vec![("name", "testname")],
+                annotations: vec![],
+                physical_schemas: vec![],
+                parameters: vec![],
+                shared_dims: vec![r#"<SharedDimension name="shareddim"></SharedDimension>"#],
+                cubes: vec![r#"<Cube name="testcube"><Dimension name="inner"></Dimension></Cube>"#, r#"<Cube name="a"></Cube>"#],
+                virtual_cubes: vec![r#"<VirtualCube name="testvirtualcube"><Dimension name="inner_virtual"></Dimension></VirtualCube>"#, r#"<VirtualCube name="a"></VirtualCube>"#],
+                named_sets: vec![],
+                roles: vec![],
+                functions: vec![],
+                patches: vec![],
+                skipped: vec![],
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_fragments_to_schema_empty() {
+        fragments_to_schema(&["".to_owned()]).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_fragments_to_schema_no_schema() {
+        fragments_to_schema(&["<Cube></Cube>".to_owned()]).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_fragments_to_schema_different_names() {
+        fragments_to_schema(&["<Schema name=\"a\"></Schema>".to_owned(), "<Schema name=\"b\"></Schema>".to_owned()]).unwrap();
+    }
+
+    #[test]
+    fn test_fragments_to_schema_error_kinds() {
+        let err = fragments_to_schema(&["<Schema name=\"a\"></Schema>".to_owned(), "<Schema name=\"b\"></Schema>".to_owned()]).unwrap_err();
+        match *err.kind() {
+            ErrorKind::SchemaNameMismatch(ref first, 0, ref second, 1) => assert_eq!((first.as_str(), second.as_str()), ("a", "b")),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+
+        let err = fragments_to_schema(&["<Cube></Cube>".to_owned()]).unwrap_err();
+        assert!(matches!(*err.kind(), ErrorKind::NoSchemaName));
+        // unless there's a name to fall back on, which a Schema
+        // tag still wins over
+        let options = MergeOptions { default_schema_name: Some("d".to_owned()), ..MergeOptions::default() };
+        assert_eq!(
+            fragments_to_schema_with_options(&["<Cube></Cube>".to_owned()], &options).unwrap(),
+            "<Schema name=\"d\">\n<Cube></Cube>\n</Schema>"
+        );
+        let fragments = ["<Cube></Cube>".to_owned(), "<Schema name=\"a\"/>".to_owned()];
+        assert!(fragments_to_schema_with_options(&fragments, &options).unwrap().starts_with("<Schema name=\"a\">"));
+
+        let options = MergeOptions { only_schema: Some("c".to_owned()), ..MergeOptions::default() };
+        let err = fragments_to_schema_with_options(&["<Schema name=\"a\"></Schema>".to_owned()], &options).unwrap_err();
+        assert!(matches!(*err.kind(), ErrorKind::SchemaNotFound(ref name) if name == "c"));
+
+        let err = fragments_to_schema(&["<Schema name=\"a\"><Cube>".to_owned()]).unwrap_err();
+        assert!(matches!(*err.kind(), ErrorKind::MalformedFragment(0, 1, 18, _)));
+    }
+
+    #[test]
+    fn test_fragments_to_schema_schema_name() {
+        let options = MergeOptions { schema_name: Some("Sales & Co_Staging".to_owned()), ..MergeOptions::default() };
+        let fragments = vec![r#"<Schema name="Sales"><Cube name="a"></Cube></Schema>"#.to_owned()];
+        assert_eq!(
+            fragments_to_schema_with_options(&fragments, &options).unwrap(),
+            "<Schema name=\"Sales &amp; Co_Staging\">\n<Cube name=\"a\"></Cube>\n</Schema>"
+        );
+
+        // supplies a name when the fragments have none
+        let fragments = vec![r#"<Cube name="a"></Cube>"#.to_owned()];
+        assert_eq!(
+            fragments_to_schema_with_options(&fragments, &options).unwrap(),
+            "<Schema name=\"Sales &amp; Co_Staging\">\n<Cube name=\"a\"></Cube>\n</Schema>"
+        );
+
+        // a Schema tag commented out doesn't disagree
+        let fragments = vec!["<!-- <Schema name=\"old\"> -->\n<Schema name=\"new\"><Cube name=\"a\"></Cube></Schema>".to_owned()];
+        let merged = fragments_to_schema_with_options(&fragments, &MergeOptions::default()).unwrap();
+        assert!(merged.starts_with("<Schema name=\"new\">"), "{}", merged);
+
+        // but the fragments still have to agree
+        let fragments = vec![r#"<Schema name="a"></Schema>"#.to_owned(), r#"<Schema name="b"></Schema>"#.to_owned()];
+        assert!(fragments_to_schema_with_options(&fragments, &options).is_err());
+
+        // unless they're combined
+        let fragments = vec![
+            r#"<Schema name="Retail"><Cube name="Sales"></Cube></Schema>"#.to_owned(),
+            r#"<Schema name="Acquired"><Cube name="Orders"></Cube></Schema>"#.to_owned(),
+        ];
+        let options = MergeOptions { combine: true, ..options };
+        assert_eq!(
+            fragments_to_schema_with_options(&fragments, &options).unwrap(),
+            "<Schema name=\"Sales &amp; Co_Staging\">\n<Cube name=\"Sales\"></Cube>\n<Cube name=\"Orders\"></Cube>\n</Schema>"
+        );
+        let options = MergeOptions { combine: true, ..MergeOptions::default() };
+        assert!(fragments_to_schema_with_options(&fragments, &options).unwrap().starts_with("<Schema name=\"Retail\">"));
+    }
+
+    #[test]
+    fn test_fragments_to_schema() {
+        // First make sure that feeding through just one works
+        let fragment = r#"<Schema name="testname"><SharedDimension name="shareddim"></SharedDimension><Cube name="testcube"><Dimension name="inner"></Dimension></Cube><Cube name="a"></Cube></Schema>"#.to_owned();
+        let fragments = vec![fragment];
+        assert_eq!(
+            fragments_to_schema(&fragments).unwrap(),
+            "<Schema name=\"testname\">\n<SharedDimension name=\"shareddim\"></SharedDimension>\n<Cube name=\"testcube\"><Dimension name=\"inner\"></Dimension></Cube>\n<Cube name=\"a\"></Cube>\n</Schema>"
+        );
+
+        let fragment = r#"<Schema name="testname"><Dimension name="shareddim"></Dimension><Cube name="testcube"><Dimension name="inner"></Dimension></Cube><Cube name="a"></Cube></Schema>"#.to_owned();
+        let fragments = vec![fragment];
+        assert_eq!(
+            fragments_to_schema(&fragments).unwrap(),
+            "<Schema name=\"testname\">\n<Dimension name=\"shareddim\"></Dimension>\n<Cube name=\"testcube\"><Dimension name=\"inner\"></Dimension></Cube>\n<Cube name=\"a\"></Cube>\n</Schema>"
+        );
+
+        // Now multiple
+        let f1 = r#"<Schema name="testname"><SharedDimension name="shareddim"></SharedDimension><Cube name="testcube"><Dimension name="inner"></Dimension></Cube><Cube name="a"></Cube></Schema>"#.to_owned();
+        let f2 = r#"<SharedDimension name="shareddim2"></SharedDimension><Cube name="cube2"><Dimension name="inner2"></Dimension></Cube><Cube name="b"></Cube>"#.to_owned();
+        let fragments = vec![f1, f2];
+        assert_eq!(
+            fragments_to_schema(&fragments).unwrap(),
+            "<Schema name=\"testname\">\n<SharedDimension name=\"shareddim\"></SharedDimension>\n<SharedDimension name=\"shareddim2\"></SharedDimension>\n<Cube name=\"testcube\"><Dimension name=\"inner\"></Dimension></Cube>\n<Cube name=\"a\"></Cube>\n<Cube name=\"cube2\"><Dimension name=\"inner2\"></Dimension></Cube>\n<Cube name=\"b\"></Cube>\n</Schema>"
+        );
+
+        let f1 = r#"<Schema name="testname"><Dimension name="shareddim"></Dimension><Cube name="testcube"><Dimension name="inner"></Dimension></Cube><Cube name="a"></Cube></Schema>"#.to_owned();
+        let f2 = r#"<Dimension name="shareddim2"></Dimension><Cube name="cube2"><Dimension name="inner2"></Dimension></Cube><Cube name="b"></Cube>"#.to_owned();
+        let fragments = vec![f1, f2];
+        assert_eq!(
+            fragments_to_schema(&fragments).unwrap(),
+            "<Schema name=\"testname\">\n<Dimension name=\"shareddim\"></Dimension>\n<Dimension name=\"shareddim2\"></Dimension>\n<Cube name=\"testcube\"><Dimension name=\"inner\"></Dimension></Cube>\n<Cube name=\"a\"></Cube>\n<Cube name=\"cube2\"><Dimension name=\"inner2\"></Dimension></Cube>\n<Cube name=\"b\"></Cube>\n</Schema>"
+        );
+    }
+
+    #[test]
+    fn test_fragments_to_schema_prolog() {
+        let f1 = "<?xml version=\"1.0\"?>\n<!DOCTYPE Schema SYSTEM \"mondrian.dtd\">\n<Schema name=\"testname\"><Dimension name=\"d\"></Dimension></Schema>".to_owned();
+        let f2 = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Cube name=\"a\"></Cube>".to_owned();
+        let fragments = vec![f1, f2];
+        assert_eq!(
+            fragments_to_schema(&fragments).unwrap(),
+            "<Schema name=\"testname\">\n<Dimension name=\"d\"></Dimension>\n<Cube name=\"a\"></Cube>\n</Schema>"
+        );
+
+        let options = MergeOptions { xml_declaration: true, ..MergeOptions::default() };
+        assert_eq!(
+            fragments_to_schema_with_options(&fragments, &options).unwrap(),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Schema name=\"testname\">\n<Dimension name=\"d\"></Dimension>\n<Cube name=\"a\"></Cube>\n</Schema>"
+        );
+    }
+
+    #[test]
+    fn test_fragments_to_schema_ignore_case() {
+        let f1 = r#"<schema name="testname"><DIMENSION name="d"></DIMENSION></schema>"#.to_owned();
+        let f2 = r#"<cube name="a"><dimension name="inner"></dimension></cube>"#.to_owned();
+        let fragments = vec![f1, f2];
+
+        // by default, lowercase tags aren't recognized
+        assert!(fragments_to_schema(&fragments).is_err());
+
+        let mut options = MergeOptions::default();
+        options.scan.ignore_case = true;
+        assert_eq!(
+            fragments_to_schema_with_options(&fragments, &options).unwrap(),
+            "<Schema name=\"testname\">\n<DIMENSION name=\"d\"></DIMENSION>\n<cube name=\"a\"><dimension name=\"inner\"></dimension></cube>\n</Schema>"
+        );
+
+        options.normalize_case = true;
+        assert_eq!(
+            fragments_to_schema_with_options(&fragments, &options).unwrap(),
+            "<Schema name=\"testname\">\n<Dimension name=\"d\"></Dimension>\n<Cube name=\"a\"><Dimension name=\"inner\"></Dimension></Cube>\n</Schema>"
+        );
+    }
+
+    #[test]
+    fn test_process_fragments() {
+        let input = r#"<Schema name="a"><Cube name="a1"></Cube></Schema>
+            <Schema name="b"><Dimension name="b1"></Dimension><Cube name="b2"></Cube></Schema>"#;
+        let fragments = Fragment::process_fragments(input, &ScanOptions::default()).unwrap();
+        assert_eq!(fragments.len(), 2);
+        assert_eq!(fragments[0].schema_name, Some("a".into()));
+        assert_eq!(fragments[0].cubes, vec![r#"<Cube name="a1"></Cube>"#]);
+        assert_eq!(fragments[1].schema_name, Some("b".into()));
+        assert_eq!(fragments[1].shared_dims, vec![r#"<Dimension name="b1"></Dimension>"#]);
+        assert_eq!(fragments[1].cubes, vec![r#"<Cube name="b2"></Cube>"#]);
+    }
+
+    #[test]
+    fn test_merge_by_schema() {
+        let fragments = vec![
+            r#"<Schema name="b"><Cube name="b1"></Cube></Schema><Schema name="a"><Cube name="a1"></Cube></Schema>"#.to_owned(),
+            r#"<Cube name="c"></Cube>"#.to_owned(),
+            r#"<Schema name="b"><Cube name="b2"></Cube></Schema>"#.to_owned(),
+        ];
+        let merged = merge_by_schema(&fragments, &MergeOptions::default()).unwrap();
+        let schemas: Vec<_> = merged.iter().map(|(name, merged)| (name.as_str(), merged.schema.as_str())).collect();
+        assert_eq!(
+            schemas,
+            vec![
+                ("a", "<Schema name=\"a\">\n<Cube name=\"a1\"></Cube>\n<Cube name=\"c\"></Cube>\n</Schema>"),
+                ("b", "<Schema name=\"b\">\n<Cube name=\"b1\"></Cube>\n<Cube name=\"c\"></Cube>\n<Cube name=\"b2\"></Cube>\n</Schema>"),
+            ]
+        );
+        // where things came from is still by fragment
+        let fragments_of_b: Vec<_> = merged["b"].elements.iter().map(|element| element.fragment).collect();
+        assert_eq!(fragments_of_b, vec![0, 1, 2]);
+
+        // no Schema tag at all is the one schema
+        let options = MergeOptions { default_schema_name: Some("d".to_owned()), ..MergeOptions::default() };
+        let merged = merge_by_schema(&fragments[1..2], &options).unwrap();
+        assert_eq!(merged.keys().collect::<Vec<_>>(), vec!["d"]);
+        assert!(merge_by_schema(&fragments[1..2], &MergeOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_merge_fragments_filters() {
+        let fragments = vec![
+            r#"<Schema name="s"><Dimension name="Time"/><Cube name="Sales"/><Cube name="SalesEU"/><Cube name="Stock"/></Schema>"#.to_owned(),
+            r#"<VirtualCube name="All"/><VirtualCube name="Legacy"/><Role name="Sales"/>"#.to_owned(),
+        ];
+        let mut options = MergeOptions::default();
+        options.include.insert("Cube".to_owned(), "Sales.*".to_owned());
+        options.exclude.insert("Cube".to_owned(), "SalesEU".to_owned());
+        options.exclude.insert("VirtualCube".to_owned(), "Legacy|Old".to_owned());
+        let merged = merge_fragments(&fragments, &options).unwrap();
+        let names: Vec<_> = merged.elements.iter().map(|element| element.name.as_deref().unwrap_or_default()).collect();
+        assert_eq!(names, vec!["Time", "Sales", "All", "Sales"]);
+        assert_eq!(merged.fragments[0].cubes, 1);
+
+        options.include.insert("Role".to_owned(), "(".to_owned());
+        let err = merge_fragments(&fragments, &options).unwrap_err();
+        assert!(matches!(*err.kind(), ErrorKind::InvalidFilter(ref element, _) if element == "Role"));
+    }
+
+    #[test]
+    fn test_fragments_to_schema_multiple_blocks() {
+        let f1 = r#"<Schema name="a"><Cube name="a1"></Cube></Schema>
+            <Schema name="b"><Cube name="b1"></Cube></Schema>"#.to_owned();
+        let f2 = r#"<Cube name="c"></Cube>"#.to_owned();
+        let fragments = vec![f1, f2];
+
+        assert!(fragments_to_schema(&fragments).is_err());
+
+        let mut options = MergeOptions {
+            only_schema: Some("b".to_owned()),
+            ..MergeOptions::default()
+        };
+        assert_eq!(
+            fragments_to_schema_with_options(&fragments, &options).unwrap(),
+            "<Schema name=\"b\">\n<Cube name=\"b1\"></Cube>\n<Cube name=\"c\"></Cube>\n</Schema>"
+        );
+
+        options.only_schema = Some("z".to_owned());
+        assert!(fragments_to_schema_with_options(&fragments, &options).is_err());
+
+        // several blocks with the same name are merged
+        let f1 = r#"<Schema name="a"><Cube name="a1"></Cube></Schema><Schema name="a"><Cube name="a2"></Cube></Schema>"#.to_owned();
+        assert_eq!(
+            fragments_to_schema(&[f1]).unwrap(),
+            "<Schema name=\"a\">\n<Cube name=\"a1\"></Cube>\n<Cube name=\"a2\"></Cube>\n</Schema>"
+        );
+    }
+
+    #[test]
+    fn test_fragments_to_schema_namespace() {
+        let f1 = r#"<m:Schema xmlns:m="http://mondrian" name="testname"><m:Dimension name="d"></m:Dimension></m:Schema>"#.to_owned();
+        let f2 = r#"<x:Cube xmlns:x="http://mondrian" name="a"><x:Measure name="m"/></x:Cube>"#.to_owned();
+        let fragments = vec![f1, f2];
+
+        assert!(fragments_to_schema(&fragments).is_err());
+
+        let mut options = MergeOptions::default();
+        options.scan.namespace = Some(Namespace::Uri("http://mondrian".to_owned()));
+        assert_eq!(
+            fragments_to_schema_with_options(&fragments, &options).unwrap(),
+            "<Schema name=\"testname\">\n<Dimension name=\"d\"></Dimension>\n<Cube xmlns:x=\"http://mondrian\" name=\"a\"><Measure name=\"m\"/></Cube>\n</Schema>"
+        );
+    }
+
+    #[test]
+    fn test_fragments_to_schema_malformed() {
+        let f1 = r#"<Schema name="testname"></Schema>"#.to_owned();
+        let f2 = "<Cube name=\"a\">\n  <Measure name=\"m\">\n</Cube>".to_owned();
+        match fragments_to_schema(&[f1, f2]) {
+            Err(Error(ErrorKind::MalformedFragment(index, line, column, message), _)) => {
+                assert_eq!((index, line, column), (1, 3, 1));
+                assert_eq!(message, "expected </Measure>, found </Cube>");
+            },
+            res => panic!("expected malformed fragment, got {:?}", res),
+        }
+
+        let f1 = "<Schema name=\"testname\"><Cube name=\"a\"".to_owned();
+        match fragments_to_schema(&[f1]) {
+            Err(Error(ErrorKind::MalformedFragment(0, 1, 25, _), _)) => {},
+            res => panic!("expected malformed fragment, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_process_fragment_keep_comments() {
+        let fragment = r#"<Schema name="testname">
+<!-- time -->
+<Dimension name="d"></Dimension>
+<!-- owner: team-x -->
+<Cube name="a"></Cube>
+<!-- owner: team-y --><VirtualCube name="vc"></VirtualCube>
+<!-- trailing -->
+</Schema>"#;
+
+        let frag = Fragment::process_fragment(fragment).unwrap();
+        assert_eq!(frag.shared_dims, vec!["<Dimension name=\"d\"></Dimension>"]);
+        assert_eq!(frag.cubes, vec!["<Cube name=\"a\"></Cube>"]);
+        assert_eq!(frag.virtual_cubes, vec!["<VirtualCube name=\"vc\"></VirtualCube>"]);
+
+        let options = ScanOptions { keep_comments: true, ..ScanOptions::default() };
+        let frag = Fragment::process_fragment_with_options(fragment, &options).unwrap();
+        assert_eq!(frag.shared_dims, vec!["<!-- time -->\n<Dimension name=\"d\"></Dimension>"]);
+        assert_eq!(frag.cubes, vec!["<!-- owner: team-x -->\n<Cube name=\"a\"></Cube>"]);
+        assert_eq!(frag.virtual_cubes, vec!["<!-- owner: team-y --><VirtualCube name=\"vc\"></VirtualCube>"]);
+    }
+
+    #[test]
+    fn test_process_fragment_any_order() {
+        let fragment = r#"<Schema name="testname"><Cube name="a"></Cube><Dimension name="d"/><VirtualCube name="vc"></VirtualCube><Cube name="b"/><SharedDimension name="e"></SharedDimension></Schema>"#;
+        let frag = Fragment::process_fragment(fragment).unwrap();
+        assert_eq!(frag.shared_dims, vec![r#"<Dimension name="d"/>"#, r#"<SharedDimension name="e"></SharedDimension>"#]);
+        assert_eq!(frag.cubes, vec![r#"<Cube name="a"></Cube>"#, r#"<Cube name="b"/>"#]);
+        assert_eq!(frag.virtual_cubes, vec![r#"<VirtualCube name="vc"></VirtualCube>"#]);
+
+        // shared dimensions are still written before the cubes using them
+        assert_eq!(
+            fragments_to_schema(&[fragment.to_owned()]).unwrap(),
+            "<Schema name=\"testname\">\n<Dimension name=\"d\"/>\n<SharedDimension name=\"e\"></SharedDimension>\n<Cube name=\"a\"></Cube>\n<Cube name=\"b\"/>\n<VirtualCube name=\"vc\"></VirtualCube>\n</Schema>"
+        );
+    }
+
+    #[test]
+    fn test_shared_dims_before_virtual_cube() {
+        // no regular cube after the dims to end them
+        let fragment = r#"<Schema name="testname"><SharedDimension name="d"></SharedDimension><VirtualCube name="vc"></VirtualCube></Schema>"#;
+        let frag = Fragment::process_fragment(fragment).unwrap();
+        assert_eq!(frag.shared_dims, vec![r#"<SharedDimension name="d"></SharedDimension>"#]);
+        assert_eq!(frag.virtual_cubes, vec![r#"<VirtualCube name="vc"></VirtualCube>"#]);
+        assert_eq!(
+            fragments_to_schema(&[fragment.to_owned()]).unwrap(),
+            "<Schema name=\"testname\">\n<SharedDimension name=\"d\"></SharedDimension>\n<VirtualCube name=\"vc\"></VirtualCube>\n</Schema>"
+        );
+
+        // nor do other schema level elements end up in them
+        let fragment = r#"<Schema name="testname"><Dimension name="d"></Dimension><NamedSet name="n"><Formula>{}</Formula></NamedSet><Role name="r"></Role></Schema>"#;
+        let frag = Fragment::process_fragment(fragment).unwrap();
+        assert_eq!(frag.shared_dims, vec![r#"<Dimension name="d"></Dimension>"#]);
+        assert_eq!(frag.named_sets, vec![r#"<NamedSet name="n"><Formula>{}</Formula></NamedSet>"#]);
+        assert_eq!(frag.roles, vec![r#"<Role name="r"></Role>"#]);
+        assert_eq!(frag.skipped, vec![]);
+    }
+
+    #[test]
+    fn test_fragments_to_schema_named_sets() {
+        // top level named sets go after the virtual cubes, whichever
+        // fragment they're in
+        let f1 = r#"<Schema name="s"><NamedSet name="Top" formula="TopCount([d].Members, 5)"/><Cube name="c"><Dimension name="d"/><NamedSet name="Inner" formula="[Top]"/></Cube></Schema>"#.to_owned();
+        let f2 = r#"<VirtualCube name="v"/><NamedSet name="All"><Formula>[d].Members</Formula></NamedSet>"#.to_owned();
+        let merged = merge_fragments(&[f1.clone(), f2], &MergeOptions::default()).unwrap();
+        assert_eq!(
+            merged.schema,
+            "<Schema name=\"s\">\n<Cube name=\"c\"><Dimension name=\"d\"/><NamedSet name=\"Inner\" formula=\"[Top]\"/></Cube>\n\
+             <VirtualCube name=\"v\"/>\n<NamedSet name=\"Top\" formula=\"TopCount([d].Members, 5)\"/>\n\
+             <NamedSet name=\"All\"><Formula>[d].Members</Formula></NamedSet>\n</Schema>"
+        );
+        assert_eq!((merged.fragments[0].named_sets, merged.fragments[1].named_sets), (1, 1));
+        assert!(merged.warnings.iter().all(|warning| warning.rule != "unknown-named-set"), "{:?}", merged.warnings);
+
+        // and two with the same name are duplicates
+        let f3 = r#"<NamedSet name="Top" formula="[d].Members"/>"#.to_owned();
+        let merged = merge_fragments(&[f1, f3], &MergeOptions::default()).unwrap();
+        let rules: Vec<_> = merged.warnings.iter().chain(&merged.errors).map(|warning| warning.rule).collect();
+        assert!(rules.contains(&"duplicate-name") && rules.contains(&"duplicate-conflict"), "{:?}", rules);
+    }
+
+    #[test]
+    fn test_fragments_to_schema_roles() {
+        // roles go last, after the named sets, and what they grant
+        // is checked against the rest of the schema
+        let f1 = r#"<Role name="Admin"><SchemaGrant access="all"/></Role><Cube name="c"/>"#.to_owned();
+        let f2 = r#"<Schema name="s"><Role name="Analyst"><SchemaGrant access="none"><CubeGrant cube="x" access="all"/></SchemaGrant></Role><NamedSet name="n" formula="{}"/></Schema>"#.to_owned();
+        let merged = merge_fragments(&[f1.clone(), f2], &MergeOptions::default()).unwrap();
+        assert_eq!(
+            merged.schema,
+            "<Schema name=\"s\">\n<Cube name=\"c\"/>\n<NamedSet name=\"n\" formula=\"{}\"/>\n\
+             <Role name=\"Admin\"><SchemaGrant access=\"all\"/></Role>\n\
+             <Role name=\"Analyst\"><SchemaGrant access=\"none\"><CubeGrant cube=\"x\" access=\"all\"/></SchemaGrant></Role>\n</Schema>"
+        );
+        assert_eq!((merged.fragments[0].roles, merged.fragments[1].roles), (1, 1));
+        let rules: Vec<_> = merged.warnings.iter().map(|warning| warning.rule).collect();
+        assert!(rules.contains(&"unknown-grant-target") && !rules.contains(&"not-merged"), "{:?}", rules);
 
-// I assume tags follow the convention of CamelCase
-const SCHEMA_TAG_OPEN: &str = r#"<Schema name=""#;
-const SCHEMA_TAG_CLOSE: &str = r#"</Schema>"#;
-const CUBE_TAG_OPEN: &str = "<Cube";
-const SHAREDDIM_TAG_OPEN: &str = "<SharedDimension";
-const DIM_TAG_OPEN: &str = "<Dimension";
-const VIRTUALCUBE_TAG_OPEN: &str = r#"<VirtualCube"#;
+        // and two with the same name are duplicates
+        let f3 = r#"<Schema name="s"><Role name="Admin"><SchemaGrant access="all"/></Role></Schema>"#.to_owned();
+        let options = MergeOptions { dedupe_identical: true, ..MergeOptions::default() };
+        let merged = merge_fragments(&[f1.clone(), f3.clone()], &options).unwrap();
+        assert_eq!(merged.fragments[1].roles, 1);
+        assert_eq!(merged.schema.matches("<Role ").count(), 1);
+        let merged = merge_fragments(&[f1, f3], &MergeOptions::default()).unwrap();
+        let rules: Vec<_> = merged.warnings.iter().map(|warning| warning.rule).collect();
+        assert!(rules.contains(&"duplicate-name"), "{:?}", rules);
+    }
 
+    #[test]
+    fn test_fragments_to_schema_union_roles() {
+        // the roles a union is made of are written before it
+        let f1 = "<Schema name=\"s\">\n<Role name=\"All\"><Union><RoleUsage roleName=\"Sales\"/><RoleUsage roleName=\"HR\"/></Union></Role>\n\
+                  <Role name=\"Admin\"/>\n</Schema>".to_owned();
+        let f2 = "<Role name=\"Both\"><Union><RoleUsage roleName=\"All\"/><RoleUsage roleName=\"Admin\"/></Union></Role>\n\
+                  <Role name=\"HR\"/>\n<Role name=\"Sales\"/>".to_owned();
+        let merged = merge_fragments(&[f1.clone(), f2.clone()], &MergeOptions::default()).unwrap();
+        let roles: Vec<_> = merged.elements.iter().filter_map(|element| element.name.as_deref()).collect();
+        assert_eq!(roles, vec!["HR", "Sales", "All", "Admin", "Both"]);
+        assert!(merged.errors.is_empty(), "{:?}", merged.errors);
 
-/// Struct to hold the results of parsing
-/// a string fragment of schema.
-#[derive(Debug, PartialEq)]
-pub struct Fragment<'a> {
-    schema_name: Option<&'a str>,
-    shared_dims: Option<&'a str>,
-    cubes: Option<&'a str>,
-    virtual_cubes: Option<&'a str>,
-}
+        let options = MergeOptions { sort: SortOrder::Name, ..MergeOptions::default() };
+        let merged = merge_fragments(&[f1, f2], &options).unwrap();
+        let roles: Vec<_> = merged.elements.iter().filter_map(|element| element.name.as_deref()).collect();
+        assert_eq!(roles, vec!["Admin", "HR", "Sales", "All", "Both"]);
 
-impl<'a> Fragment<'a> {
+        // a cycle can't be ordered, and is left as it is
+        let f3 = "<Schema name=\"s\"><Role name=\"A\"><Union><RoleUsage roleName=\"B\"/></Union></Role>\
+                  <Role name=\"B\"><Union><RoleUsage roleName=\"A\"/></Union></Role><Role name=\"C\"/></Schema>".to_owned();
+        let merged = merge_fragments(&[f3], &MergeOptions::default()).unwrap();
+        let roles: Vec<_> = merged.elements.iter().filter_map(|element| element.name.as_deref()).collect();
+        assert_eq!(roles, vec!["B", "A", "C"]);
+        let errors: Vec<_> = merged.errors.iter().map(|error| (error.rule, error.message.as_str())).collect();
+        assert_eq!(errors, vec![("circular-reference", "Role \"B\" refers back to itself: B -> A -> B")]);
 
-    /// Get the Schema name from one fragment
-    /// None if there's no Schema tags
-    /// Takes first schema tag and first name attr
-    fn get_schema_name(fragment: &'a str) -> Result<Option<&'a str>> {
-        let res = fragment
-            .find(SCHEMA_TAG_OPEN)
-            .map(|i| i + SCHEMA_TAG_OPEN.len())
-            .and_then(|i| {
-                fragment[i..]
-                    .find('\"')
-                    .and_then(|j| {
-                        fragment.get(i..i+j)
-                    })
-            });
-        Ok(res)
+        // virtual cubes are ordered the same way, after the cubes
+        let f4 = "<Schema name=\"s\"><VirtualCube name=\"Top\"><CubeUsages><CubeUsage cubeName=\"Base\"/></CubeUsages></VirtualCube>\
+                  <VirtualCube name=\"Base\"><CubeUsages><CubeUsage cubeName=\"Sales\"/></CubeUsages></VirtualCube></Schema>".to_owned();
+        let f5 = "<Cube name=\"Sales\"/>".to_owned();
+        let merged = merge_fragments(&[f4, f5], &MergeOptions::default()).unwrap();
+        let cubes: Vec<_> = merged.elements.iter().filter_map(|element| element.name.as_deref()).collect();
+        assert_eq!(cubes, vec!["Sales", "Base", "Top"]);
     }
 
-    /// Get shared dims from one fragment
-    fn get_shared_dims(fragment: &'a str) -> Result<Option<&'a str>> {
-        // Finds the location of the first encount of the tag SharedDimension
-        // If the first occurence is after the cube/ virtualcube will return an error
-        let res;
-        if let Some(cube_index) = fragment.find(SHAREDDIM_TAG_OPEN) {
-            res = fragment
-                .find(SHAREDDIM_TAG_OPEN)
-                .and_then(|i| {
-                    fragment[i..]
-                        .find(CUBE_TAG_OPEN)
-                        .or_else(|| fragment[i..].find(VIRTUALCUBE_TAG_OPEN))
-                        .or_else(|| fragment[i..].find(SCHEMA_TAG_CLOSE))
-                        .or(Some(fragment.len()-i))
-                        .and_then(|j|{
-                            match fragment[..j].find(CUBE_TAG_OPEN).or_else(|| fragment[..j].find(VIRTUALCUBE_TAG_OPEN)){
-                                Some(_) =>{
-                                    Some("-11")  // Falg used for Raising an error if the sahred dimension is defined between the cubes or at the end of the cubes
-                                }
-                                None => {
-                                    fragment.get(i..i+j)
-                                }
-                            }
-                        })
-                });
-        } else {
-            res = fragment
-                .find(CUBE_TAG_OPEN)
-                .or_else(|| fragment.find(VIRTUALCUBE_TAG_OPEN))
-                .or_else(|| fragment.find(SCHEMA_TAG_CLOSE))
-                .or(Some(fragment.len()))
-                .and_then(|i| {
-                    fragment[..i]
-                        .find(DIM_TAG_OPEN)
-                        .and_then(|j|{
-                            fragment.get(j..i)
-                        })
-                });
-        }
-        if res != Some("-11"){
-            Ok(res)
-        } else {
-            return Err("Shared Dimension is in the wrong place".into())  // if the flag value is raised we generate an error in the program
-        }
+    #[test]
+    fn test_fragments_to_schema_functions() {
+        // user defined functions go last, scripts and all, even
+        // when a script looks like markup
+        let f1 = "<Schema name=\"s\">\n<UserDefinedFunction name=\"Twice\">\n  <Script language=\"JavaScript\"><![CDATA[\n    function execute(x) { return x < 0 ? '</UserDefinedFunction>' : 2 * x; }\n  ]]></Script>\n</UserDefinedFunction>\n<Role name=\"r\"/>\n</Schema>".to_owned();
+        let f2 = "<UserDefinedFunction name=\"Plus\" className=\"com.example.Plus\"/>\n<Cube name=\"c\"/>".to_owned();
+        let merged = merge_fragments(&[f1, f2], &MergeOptions::default()).unwrap();
+        assert_eq!(
+            merged.schema,
+            "<Schema name=\"s\">\n<Cube name=\"c\"/>\n<Role name=\"r\"/>\n\
+             <UserDefinedFunction name=\"Twice\">\n  <Script language=\"JavaScript\"><![CDATA[\n    function execute(x) { return x < 0 ? '</UserDefinedFunction>' : 2 * x; }\n  ]]></Script>\n</UserDefinedFunction>\n\
+             <UserDefinedFunction name=\"Plus\" className=\"com.example.Plus\"/>\n</Schema>"
+        );
+        assert_eq!((merged.fragments[0].functions, merged.fragments[1].functions), (1, 1));
+        assert!(merged.warnings.iter().all(|warning| warning.rule != "not-merged"), "{:?}", merged.warnings);
     }
 
-    // Get cubes from one fragment
-    fn get_cubes(fragment: &'a str) -> Result<Option<&'a str>> {
-        // println!("{}", fragment.find(CUBE_TAG_CLOSE).unwrap());
-        let res = fragment.find(CUBE_TAG_OPEN)
-            .and_then(|i| {
-                fragment[i..]
-                    .find(VIRTUALCUBE_TAG_OPEN)
-                    .or_else(|| fragment[i..].find(SCHEMA_TAG_CLOSE))
-                    .or(Some(fragment.len()-i)) // eof
-                    .and_then(|j| {
-                        fragment.get(i..i+j)
-                    })
-            });
-        Ok(res)
+    #[test]
+    fn test_fragments_to_schema_scripts() {
+        // scripts without CDATA are written as they are
+        let f1 = "<Schema name=\"s\">\n<cube name=\"c\"><Table name=\"t\"/><Measure name=\"m\" column=\"m\" aggregator=\"sum\">\
+                  <CellFormatter><Script language=\"JavaScript\">return value <cube ? '</Measure></cube>' : value;</Script></CellFormatter>\
+                  </Measure></cube>\n</Schema>".to_owned();
+        let f2 = "<UserDefinedFunction name=\"Neg\"><Script language=\"JavaScript\">function execute(x) { return x < 0 && x > -1 ? '<Cube name=\"x\">' : -x; }</Script></UserDefinedFunction>".to_owned();
+        let options = MergeOptions { scan: ScanOptions { ignore_case: true, ..ScanOptions::default() }, normalize_case: true, ..MergeOptions::default() };
+        let merged = merge_fragments(&[f1, f2], &options).unwrap();
+        assert_eq!(
+            merged.schema,
+            "<Schema name=\"s\">\n<Cube name=\"c\"><Table name=\"t\"/><Measure name=\"m\" column=\"m\" aggregator=\"sum\">\
+             <CellFormatter><Script language=\"JavaScript\">return value <cube ? '</Measure></cube>' : value;</Script></CellFormatter>\
+             </Measure></Cube>\n\
+             <UserDefinedFunction name=\"Neg\"><Script language=\"JavaScript\">function execute(x) { return x < 0 && x > -1 ? '<Cube name=\"x\">' : -x; }</Script></UserDefinedFunction>\n\
+             </Schema>"
+        );
+        assert!(merged.warnings.is_empty() && merged.errors.is_empty(), "{:?} {:?}", merged.warnings, merged.errors);
     }
 
-    // Get virtual cubes from one fragment
-    fn get_virtual_cubes(fragment: &'a str) -> Result<Option<&'a str>> {
-        let res = fragment.find(VIRTUALCUBE_TAG_OPEN)
-            .and_then(|i| {
-                fragment[i..]
-                    .find(SCHEMA_TAG_CLOSE)
-                    .or(Some(fragment.len()-i)) // eof
-                    .and_then(|j| {
-                        fragment.get(i..i+j)
-                    })
-            });
-        Ok(res)
+    #[test]
+    fn test_fragments_to_schema_inline_tables() {
+        let dim = "<Dimension name=\"Kind\">\n  <Hierarchy hasAll=\"true\" primaryKey=\"id\">\n    <InlineTable alias=\"kind\">\n\
+                   \x20     <ColumnDefs><ColumnDef name=\"id\" type=\"Numeric\"/><ColumnDef name=\"label\" type=\"String\"/></ColumnDefs>\n\
+                   \x20     <Rows>\n        <Row><Value column=\"id\">1</Value><Value column=\"label\">Cube</Value></Row>\n\
+                   \x20       <Row><Value column=\"id\">2</Value><Value column=\"label\">&lt;Cube name=&quot;x&quot;&gt; &amp; <![CDATA[</Dimension><Cube>]]></Value></Row>\n\
+                   \x20       <Row><Value column=\"id\">3</Value><Value column=\"label\">  VirtualCube\t</Value></Row>\n\
+                   \x20     </Rows>\n    </InlineTable>\n    <Level name=\"Kind\" column=\"label\" uniqueMembers=\"true\"/>\n  </Hierarchy>\n</Dimension>";
+        let f1 = format!("<Schema name=\"s\">\n{}\n</Schema>", dim);
+        let f2 = "<Cube name=\"c\"><Table name=\"t\"/><DimensionUsage name=\"Kind\" source=\"Kind\" foreignKey=\"kind_id\"/>\
+                  <Measure name=\"m\" column=\"m\" aggregator=\"sum\"/></Cube>".to_owned();
+        // the same table, written on one line
+        let f3: String = dim.lines().map(str::trim_start).collect();
+        // a row which differs only in a value's whitespace
+        let f4 = dim.replace("<Value column=\"label\">Cube</Value>", "<Value column=\"label\">Cube </Value>");
+        let mut options = MergeOptions { dedupe_identical: true, verify_output: true, ..MergeOptions::default() };
+        options.lints.insert("invalid-structure".to_owned(), lint::Level::Deny);
+        let merged = merge_fragments(&[f1.clone(), f2.clone(), f3], &options).unwrap();
+        assert_eq!(merged.schema, format!("<Schema name=\"s\">\n{}\n{}\n</Schema>", dim, f2));
+        assert!(merged.warnings.is_empty() && merged.errors.is_empty(), "{:?} {:?}", merged.warnings, merged.errors);
+
+        let merged = merge_fragments(&[f1, f2, f4], &options).unwrap();
+        let errors: Vec<_> = merged.errors.iter().map(|error| (error.fragment, error.rule)).collect();
+        assert_eq!(errors, vec![(2, "duplicate-conflict")]);
     }
 
-    pub fn process_fragment(fragment: &'a str) -> Result<Fragment<'a>> {
-        // TODO make this work with string parse fn?
+    #[test]
+    fn test_merge_fragments_partial_kinds() {
+        // fragments holding only roles, named sets, parameters or
+        // functions are inputs like any other
+        let cubes = "<Schema name=\"s\">\n<Cube name=\"Sales\"><Table name=\"t\"/><Measure name=\"m\" column=\"m\" aggregator=\"sum\"/></Cube>\n</Schema>".to_owned();
+        let roles = "<!-- owned by security -->\n<Role name=\"Analyst\"><SchemaGrant access=\"none\"><CubeGrant cube=\"Sales\" access=\"all\"/></SchemaGrant></Role>\n\
+                     <Role name=\"Admin\"><SchemaGrant access=\"all\"/></Role>\n".to_owned();
+        let sets = "<Schema name=\"s\"><NamedSet name=\"Top\" formula=\"TopCount([Measures].Members, 1)\"/></Schema>".to_owned();
+        let parameters = "<Parameter name=\"p\" type=\"String\" defaultValue=\"'x'\"/>".to_owned();
+        let functions = "<UserDefinedFunction name=\"Plus\" className=\"com.example.Plus\"/>".to_owned();
+        let inputs = [cubes, roles, sets, parameters, functions];
+        let merged = merge_fragments(&inputs, &MergeOptions { only_schema: Some("s".to_owned()), ..MergeOptions::default() }).unwrap();
+        assert_eq!(
+            merged.schema,
+            "<Schema name=\"s\">\n<Parameter name=\"p\" type=\"String\" defaultValue=\"'x'\"/>\n\
+             <Cube name=\"Sales\"><Table name=\"t\"/><Measure name=\"m\" column=\"m\" aggregator=\"sum\"/></Cube>\n\
+             <NamedSet name=\"Top\" formula=\"TopCount([Measures].Members, 1)\"/>\n\
+             <Role name=\"Analyst\"><SchemaGrant access=\"none\"><CubeGrant cube=\"Sales\" access=\"all\"/></SchemaGrant></Role>\n\
+             <Role name=\"Admin\"><SchemaGrant access=\"all\"/></Role>\n\
+             <UserDefinedFunction name=\"Plus\" className=\"com.example.Plus\"/>\n</Schema>"
+        );
+        assert!(merged.warnings.is_empty() && merged.errors.is_empty(), "{:?} {:?}", merged.warnings, merged.errors);
+        let counts: Vec<_> = merged.fragments.iter()
+            .map(|stats| (stats.cubes, stats.roles, stats.named_sets, stats.parameters, stats.functions))
+            .collect();
+        assert_eq!(counts, vec![(1, 0, 0, 0, 0), (0, 2, 0, 0, 0), (0, 0, 1, 0, 0), (0, 0, 0, 1, 0), (0, 0, 0, 0, 1)]);
 
-        let schema_name = Fragment::get_schema_name(fragment)?;
-        let shared_dims = Fragment::get_shared_dims(fragment)?;
-        let cubes = Fragment::get_cubes(fragment)?;
-        let virtual_cubes = Fragment::get_virtual_cubes(fragment)?;
-        Ok(Fragment {
-            schema_name: schema_name,
-            shared_dims: shared_dims,
-            cubes: cubes,
-            virtual_cubes: virtual_cubes,
-        })
+        // or even the whole schema, named on the command line
+        let options = MergeOptions { schema_name: Some("Security".to_owned()), ..MergeOptions::default() };
+        let merged = merge_fragments(&inputs[1..2], &options).unwrap();
+        assert!(merged.schema.starts_with("<Schema name=\"Security\">\n<Role name=\"Analyst\">"));
     }
-}
 
-/// Convenience method for turning unprocessed fragments
-/// into one schema
-pub fn fragments_to_schema(fragment: &[String]) -> Result<String> {
-    // Get Schema names from all fragments
-    // and check for non-duplicates (there should only
-    // be one schema name). Error is returned if
-    // there's more than one schema name
-    //
-    // Otherwise, process all fragments, then iterate through
-    // 2 passes to first push all shared dims, then
-    // to push all cubes.
+    #[test]
+    fn test_fragments_to_schema_dtd_order() {
+        // whatever order the fragments have them in
+        let f1 = "<Schema name=\"s\">\n<UserDefinedFunction name=\"f\" className=\"F\"/>\n<Role name=\"r\"/>\n\
+                  <NamedSet name=\"n\" formula=\"{}\"/>\n<VirtualCube name=\"v\"/>\n<Cube name=\"c\"/>\n<Dimension name=\"d\"/>\n\
+                  <Parameter name=\"p\" type=\"String\"/>\n<Annotations><Annotation name=\"a\">1</Annotation></Annotations>\n</Schema>".to_owned();
+        let f2 = "<Role name=\"r2\"/><Cube name=\"c2\"/><Parameter name=\"p2\" type=\"String\"/>".to_owned();
+        let merged = merge_fragments(&[f1, f2], &MergeOptions::default()).unwrap();
+        assert_eq!(
+            merged.schema,
+            "<Schema name=\"s\">\n<Annotations>\n  <Annotation name=\"a\">1</Annotation>\n</Annotations>\n\
+             <Parameter name=\"p\" type=\"String\"/>\n<Parameter name=\"p2\" type=\"String\"/>\n<Dimension name=\"d\"/>\n\
+             <Cube name=\"c\"/>\n<Cube name=\"c2\"/>\n<VirtualCube name=\"v\"/>\n<NamedSet name=\"n\" formula=\"{}\"/>\n\
+             <Role name=\"r\"/>\n<Role name=\"r2\"/>\n<UserDefinedFunction name=\"f\" className=\"F\"/>\n</Schema>"
+        );
+        let kinds: Vec<_> = merged.elements.iter().map(|element| element.kind).collect();
+        let mut sorted = kinds.clone();
+        sorted.sort();
+        assert_eq!(kinds, sorted);
+        assert_eq!(merged.elements.len(), 10);
 
-    // process fragments
-    let fragments: Vec<_>;
-    match fragment.iter().map(|s| Fragment::process_fragment(&s)).collect() {
-        Ok(f) => fragments = f,
-        Err(e) => return Err(e)
+        // a physical schema with nothing after it still goes last
+        let f3 = "<Schema name=\"s\"><Parameter name=\"p\" type=\"String\"/><PhysicalSchema><Table name=\"t\"/></PhysicalSchema></Schema>".to_owned();
+        let merged = merge_fragments(&[f3], &MergeOptions::default()).unwrap();
+        assert_eq!(
+            merged.schema,
+            "<Schema name=\"s\">\n<Parameter name=\"p\" type=\"String\"/>\n<PhysicalSchema>\n  <Table name=\"t\"/>\n</PhysicalSchema>\n</Schema>"
+        );
     }
 
-    // schema name handling
-    let mut schema_name = None;
-    for frag in &fragments {
-        if let Some(current_name) = frag.schema_name {
-            if let Some(stored_name) = schema_name {
-                if stored_name != current_name {
-                    return Err("More than one schema name found".into());
-                }
-            } else {
-                schema_name = Some(current_name);
-            }
-        } else {
-            continue
-        }
+    #[test]
+    fn test_merge_fragments_writeback() {
+        let cube = "<Cube name=\"Budget\">\n  <Table name=\"budget\"/>\n  <DimensionUsage name=\"Time\" source=\"Time\" foreignKey=\"time_id\"/>\n  \
+                    <Measure name=\"Amount\" column=\"amount\" aggregator=\"sum\"/>\n  <WritebackTable name=\"budget_wb\">\n    \
+                    <WritebackAttribute dimension=\"Time\" column=\"time_id\"/>\n    <WritebackMeasure name=\"Amount\" column=\"amount\"/>\n    \
+                    <WritebackMeasure name=\"Cost\" column=\"cost\"/>\n  </WritebackTable>\n</Cube>";
+        let f1 = "<Schema name=\"s\"><Dimension name=\"Time\"><Hierarchy hasAll=\"true\" primaryKey=\"id\"><Table name=\"time\"/>\
+                  <Level name=\"Year\" column=\"year\"/></Hierarchy></Dimension></Schema>".to_owned();
+        let mut options = MergeOptions::default();
+        options.lints.insert("invalid-structure".to_owned(), lint::Level::Deny);
+        let merged = merge_fragments(&[f1, cube.to_owned()], &options).unwrap();
+        assert!(merged.schema.contains(&format!("\n{}\n", cube)));
+        assert!(merged.errors.is_empty(), "{:?}", merged.errors);
+        let warnings: Vec<_> = merged.warnings.iter().map(|warning| (warning.fragment, warning.line, warning.rule)).collect();
+        assert_eq!(warnings, vec![(1, 8, "unknown-writeback-target")]);
     }
 
-    // now push onto final str
-    let mut final_schema = String::new();
-    final_schema.push_str("<Schema name=\"");
-    if let Some(name) = schema_name {
-        final_schema.push_str(name);
-        final_schema.push_str("\">\n");
-    } else {
-        return Err("No schema name found".into());
+    #[test]
+    fn test_fragments_to_schema_parameters() {
+        // parameters go first, before the shared dimensions
+        let f1 = "<Schema name=\"s\">\n<Dimension name=\"d\"/>\n<Parameter name=\"Year\" type=\"Numeric\" defaultValue=\"2024\"/>\n</Schema>".to_owned();
+        let f2 = "<Cube name=\"c\"/>\n<Parameter name=\"Region\" type=\"String\"/>\n<Parameter name=\"Year\"/>".to_owned();
+        let merged = merge_fragments(&[f1, f2], &MergeOptions::default()).unwrap();
+        assert_eq!(
+            merged.schema,
+            "<Schema name=\"s\">\n<Parameter name=\"Year\" type=\"Numeric\" defaultValue=\"2024\"/>\n\
+             <Parameter name=\"Region\" type=\"String\"/>\n<Parameter name=\"Year\"/>\n<Dimension name=\"d\"/>\n\
+             <Cube name=\"c\"/>\n</Schema>"
+        );
+        assert_eq!((merged.fragments[0].parameters, merged.fragments[1].parameters), (1, 2));
+        let duplicates: Vec<_> = merged.warnings.iter()
+            .filter(|warning| warning.rule == "duplicate-name")
+            .map(|warning| (warning.fragment, warning.line, warning.first))
+            .collect();
+        assert_eq!(duplicates, vec![(1, 3, Some((0, 3)))]);
     }
 
-    for frag in &fragments {
-        if let Some(shared_dims) = frag.shared_dims {
-            final_schema.push_str(shared_dims);
-        }
-    }
-    for frag in &fragments {
-        if let Some(cubes) = frag.cubes {
-            final_schema.push_str(cubes);
-        }
-    }
-    for frag in &fragments {
-        if let Some(virtual_cubes) = frag.virtual_cubes {
-            final_schema.push_str(virtual_cubes);
-        }
+    #[test]
+    fn test_fragments_to_schema_annotations() {
+        // the Schema blocks' annotations become one block, first
+        let f1 = "<Schema name=\"s\">\n<Cube name=\"c\"><Annotations><Annotation name=\"a\">cube</Annotation></Annotations></Cube>\n\
+                  <Annotations>\n  <Annotation name=\"a\">1</Annotation>\n</Annotations>\n</Schema>".to_owned();
+        let f2 = "<Parameter name=\"p\"/>\n<Annotations><Annotation name=\"b\">2</Annotation><Annotation name=\"a\">1</Annotation></Annotations>".to_owned();
+        let merged = merge_fragments(&[f1.clone(), f2], &MergeOptions::default()).unwrap();
+        assert_eq!(
+            merged.schema,
+            "<Schema name=\"s\">\n<Annotations>\n  <Annotation name=\"a\">1</Annotation>\n  <Annotation name=\"b\">2</Annotation>\n</Annotations>\n\
+             <Parameter name=\"p\"/>\n<Cube name=\"c\"><Annotations><Annotation name=\"a\">cube</Annotation></Annotations></Cube>\n</Schema>"
+        );
+        assert!(merged.warnings.iter().chain(&merged.errors).all(|warning| !warning.rule.contains("conflict")), "{:?}", merged.warnings);
+
+        // different values for one name are an error
+        let f3 = "<Annotations><Annotation name=\"a\">2</Annotation></Annotations>".to_owned();
+        let merged = merge_fragments(&[f1, f3], &MergeOptions::default()).unwrap();
+        let errors: Vec<_> = merged.errors.iter().map(|error| (error.fragment, error.rule, error.first)).collect();
+        assert_eq!(errors, vec![(1, "annotation-conflict", Some((0, 4)))]);
+        assert!(merged.schema.contains("<Annotation name=\"a\">1</Annotation>"));
     }
 
-    final_schema.push_str("\n</Schema>");
-    println!("{:?}", fragments[0]);
+    #[test]
+    fn test_merge_fragments_mondrian4() {
+        let f1 = "<Schema name=\"s\" metamodelVersion=\"4.0\">\n<PhysicalSchema><Table name=\"store\"/></PhysicalSchema>\n\
+                  <Dimension name=\"Store\" table=\"store\" key=\"Id\"><Attributes><Attribute name=\"Id\" keyColumn=\"id\"/></Attributes></Dimension>\n</Schema>".to_owned();
+        let f2 = "<Schema name=\"s\">\n<PhysicalSchema><Table name=\"store\"/><Table name=\"sales\"/></PhysicalSchema>\n\
+                  <Cube name=\"Sales\"><Dimensions><Dimension source=\"Store\"/></Dimensions>\
+                  <MeasureGroups><MeasureGroup table=\"sales\"><Measures><Measure name=\"Units\" column=\"units\" aggregator=\"sum\"/></Measures>\
+                  </MeasureGroup></MeasureGroups></Cube>\n</Schema>".to_owned();
+        // the metamodelVersion makes these Mondrian 4 fragments: one
+        // physical schema, and none of the Mondrian 3 checks
+        let merged = merge_fragments(&[f1.clone(), f2.clone()], &MergeOptions::default()).unwrap();
+        assert_eq!(
+            merged.schema,
+            "<Schema name=\"s\" metamodelVersion=\"4.0\">\n<PhysicalSchema>\n  <Table name=\"store\"/>\n  <Table name=\"sales\"/>\n</PhysicalSchema>\n\
+             <Dimension name=\"Store\" table=\"store\" key=\"Id\"><Attributes><Attribute name=\"Id\" keyColumn=\"id\"/></Attributes></Dimension>\n\
+             <Cube name=\"Sales\"><Dimensions><Dimension source=\"Store\"/></Dimensions><MeasureGroups><MeasureGroup table=\"sales\"><Measures>\
+             <Measure name=\"Units\" column=\"units\" aggregator=\"sum\"/></Measures></MeasureGroup></MeasureGroups></Cube>\n</Schema>"
+        );
+        assert!(merged.warnings.is_empty() && merged.errors.is_empty(), "{:?} {:?}", merged.warnings, merged.errors);
 
-    Ok(final_schema)
-}
+        // which reads them as Mondrian 3 when told to
+        let options = MergeOptions { metamodel: Metamodel::Mondrian3, ..MergeOptions::default() };
+        let merged = merge_fragments(&[f1, f2], &options).unwrap();
+        let rules: Vec<_> = merged.warnings.iter().chain(&merged.errors).map(|warning| warning.rule).collect();
+        assert_eq!(rules, vec!["unused-dimension", "cube-without-measures"]);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        assert_eq!("4".parse::<Metamodel>().unwrap(), Metamodel::Mondrian4);
+        assert!("5".parse::<Metamodel>().is_err());
+    }
 
     #[test]
-    fn test_get_schema_name() {
-        let fragment = r#"<Schema name="testname"></Schema>"#;
-        assert_eq!(Fragment::get_schema_name(fragment).unwrap(), Some("testname"));
-        let fragment = r#"<Cube name="testname"></Cube>"#;
-        assert_eq!(Fragment::get_schema_name(fragment).unwrap(), None);
+    fn test_merge_fragments_injected_annotations() {
+        let f1 = "<Schema name=\"s\">\n<Annotations><Annotation name=\"env\">dev</Annotation><Annotation name=\"owner\">bi</Annotation></Annotations>\n\
+                  <Cube name=\"c\"/>\n<VirtualCube name=\"v\"/>\n</Schema>".to_owned();
+        let mut options = MergeOptions::default();
+        options.annotations.insert("env".to_owned(), "prod".to_owned());
+        options.cube_annotations.insert("data_version".to_owned(), "42".to_owned());
+        let merged = merge_fragments(&[f1], &options).unwrap();
+        assert_eq!(
+            merged.schema,
+            "<Schema name=\"s\">\n<Annotations>\n  <Annotation name=\"owner\">bi</Annotation>\n  <Annotation name=\"env\">prod</Annotation>\n</Annotations>\n\
+             <Cube name=\"c\"><Annotations><Annotation name=\"data_version\">42</Annotation></Annotations></Cube>\n<VirtualCube name=\"v\"/>\n</Schema>"
+        );
+        assert!(merged.errors.is_empty(), "{:?}", merged.errors);
     }
 
     #[test]
-    fn test_get_share_dims() {
-        let fragment = r#"<Schema name="testname">
-            <Cube name="testcube"></Cube></Schema>"#;
-        assert_eq!(Fragment::get_shared_dims(fragment).unwrap(), None);
+    fn test_merge_fragments_schema_attributes() {
+        // every attribute of the Schema tags is kept, the first
+        // value winning
+        let f1 = r#"<Schema name="s" description='Sales "EU"' measuresCaption="Measures"><Cube name="c"/></Schema>"#.to_owned();
+        let f2 = "<Schema name=\"s\"\n  measuresCaption=\"Figures\" metamodelVersion=\"3.6\"></Schema>".to_owned();
+        let f3 = r#"<Schema xmlns:m="http://mondrian" name="s" description="Sales &quot;EU&quot;"></Schema>"#.to_owned();
+        let merged = merge_fragments(&[f1.clone(), f2.clone(), f3.clone()], &MergeOptions::default()).unwrap();
+        assert!(merged.schema.starts_with(
+            "<Schema name=\"s\" description=\"Sales &quot;EU&quot;\" measuresCaption=\"Measures\" metamodelVersion=\"3.6\">\n"
+        ));
+        let errors: Vec<_> = merged.errors.iter()
+            .map(|error| (error.fragment, error.line, error.column, error.rule, error.message.as_str(), error.first))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![(1, 2, 20, "schema-attribute-conflict",
+                  "Schema attribute measuresCaption is \"Figures\" here, but was \"Measures\" first", Some((0, 1)))]
+        );
 
-        // having separate test cases for the Dimension tag and SharedDimension tag
-        // is to be able to support the existing Mondrian Schema which have the Dimension tag.
-        // Whereas moving forward we can use SharedDimension tags to define SharedDimension in the schmeas
-        // gets shareddims tag and dims tag  before cubes
-        let fragment = r#"<Schema name="testname">
-            <SharedDimension></SharedDimension><Cube name="testcube"></Cube></Schema>"#;
+        let f4 = r#"<Schema name="s" description="Sales US" measuresCaption="Measures"></Schema>"#.to_owned();
+        let fragments = [f1, f2, f3, f4];
+        let merge = |schema_attributes| {
+            let options = MergeOptions { schema_attributes, ..MergeOptions::default() };
+            let merged = merge_fragments(&fragments, &options).unwrap();
+            let rules: Vec<_> = merged.errors.iter().map(|error| error.rule).collect();
+            assert!(rules.is_empty(), "{:?}", rules);
+            merged.schema.lines().next().unwrap().to_owned()
+        };
+        assert_eq!(
+            merge(SchemaAttributePolicy::First),
+            "<Schema name=\"s\" description=\"Sales &quot;EU&quot;\" measuresCaption=\"Measures\" metamodelVersion=\"3.6\">"
+        );
+        assert_eq!(
+            merge(SchemaAttributePolicy::Last),
+            "<Schema name=\"s\" description=\"Sales US\" measuresCaption=\"Measures\" metamodelVersion=\"3.6\">"
+        );
+        // each value once
         assert_eq!(
-            Fragment::get_shared_dims(fragment).unwrap(),
-            Some("<SharedDimension></SharedDimension>")
+            merge(SchemaAttributePolicy::Concat),
+            "<Schema name=\"s\" description=\"Sales &quot;EU&quot;; Sales US\" measuresCaption=\"Measures; Figures\" metamodelVersion=\"3.6\">"
         );
+        assert_eq!("concat".parse::<SchemaAttributePolicy>().unwrap(), SchemaAttributePolicy::Concat);
+        assert!("join".parse::<SchemaAttributePolicy>().is_err());
+    }
 
-        let fragment = r#"<Schema name="testname">
-            <Dimension></Dimension><Cube name="testcube"></Cube></Schema>"#;
+    #[test]
+    fn test_merge_fragments_default_role() {
+        let f1 = r#"<Schema name="s" defaultRole="Analyst"><Role name="Analyst"/></Schema>"#.to_owned();
+        let f2 = r#"<Schema name="s" defaultRole="Admin"><Role name="Admin"/></Schema>"#.to_owned();
+        let merge = |fragments: &[String], default_roles| {
+            let options = MergeOptions { default_roles, ..MergeOptions::default() };
+            let merged = merge_fragments(fragments, &options).unwrap();
+            let schema_tag = merged.schema.lines().next().unwrap().to_owned();
+            let rules: Vec<_> = merged.errors.iter().map(|error| error.rule).collect();
+            (schema_tag, rules)
+        };
+        let fragments = [f1.clone(), f2];
         assert_eq!(
-            Fragment::get_shared_dims(fragment).unwrap(),
-            Some("<Dimension></Dimension>")
+            merge(&fragments, DefaultRolePolicy::Error),
+            ("<Schema name=\"s\" defaultRole=\"Analyst\">".to_owned(), vec!["schema-attribute-conflict"])
         );
+        assert_eq!(merge(&fragments, DefaultRolePolicy::First), ("<Schema name=\"s\" defaultRole=\"Analyst\">".to_owned(), vec![]));
+        assert_eq!(merge(&fragments, DefaultRolePolicy::Last), ("<Schema name=\"s\" defaultRole=\"Admin\">".to_owned(), vec![]));
+        assert_eq!(merge(&fragments, DefaultRolePolicy::Drop), ("<Schema name=\"s\">".to_owned(), vec![]));
 
-        // does not get internal dims within cube
-        // (this test has an extra Cube to make sure
-        // that adding a Cube tag match after the dim
-        // in this case doesn't trigger getting the
-        // intermal dim
-        let fragment = r#"<Schema name="testname">
-            <Cube name="testcube"><Dimension></Dimension></Cube>
-            <Cube name="a"></Cube>
-            </Schema>"#;
+        // the default role must be one of the merged roles
+        let f3 = r#"<Schema name="s" defaultRole="Guest"></Schema>"#.to_owned();
+        let (_, rules) = merge(&[f1, f3], DefaultRolePolicy::Last);
+        assert_eq!(rules, vec!["unknown-default-role"]);
+
+        assert_eq!("last".parse::<DefaultRolePolicy>().unwrap(), DefaultRolePolicy::Last);
+        assert!("random".parse::<DefaultRolePolicy>().is_err());
+    }
+
+    #[test]
+    fn test_fragments_to_schema_multiline_tags() {
+        let f1 = "<Schema\n    description=\"d\"\n\tname =\r\n  'testname'\n>\n<Dimension\n  name=\"d\"\n/>\n<Cube\n  name=\"a\"\n  cache=\"true\"\n><Measure\n name=\"m\"/></Cube\n>\n</Schema\n  >".to_owned();
         assert_eq!(
-            Fragment::get_shared_dims(fragment).unwrap(),
-            None
+            fragments_to_schema(&[f1]).unwrap(),
+            "<Schema name=\"testname\" description=\"d\">\n<Dimension\n  name=\"d\"\n/>\n<Cube\n  name=\"a\"\n  cache=\"true\"\n><Measure\n name=\"m\"/></Cube\n>\n</Schema>"
         );
 
-        // Test only shared dims, both with and without schema tag
-        let fragment = r#"<Schema name="test">
-            <Dimension name="a"></Dimension></Schema>"#;
+        // a Schema end tag split over lines still ends its block
+        let f1 = "<Schema\n name=\"a\"><Cube\n name=\"x\"/></Schema\n><Schema name=\"b\"><Cube name=\"y\"/></Schema >".to_owned();
+        let options = MergeOptions { only_schema: Some("a".to_owned()), ..MergeOptions::default() };
         assert_eq!(
-            Fragment::get_shared_dims(fragment).unwrap(),
-            Some(r#"<Dimension name="a"></Dimension>"#)
+            fragments_to_schema_with_options(&[f1], &options).unwrap(),
+            "<Schema name=\"a\">\n<Cube\n name=\"x\"/>\n</Schema>"
         );
 
-        let fragment = r#"<Schema name="test">
-            <SharedDimension name="a"></SharedDimension></Schema>"#;
+        let f1 = "<m:Schema\n xmlns:m=\"u\"\n name=\"a\"><m:cube\n name=\"x\"></m:cube\n></m:Schema\n>".to_owned();
+        let mut options = MergeOptions { normalize_case: true, ..MergeOptions::default() };
+        options.scan.ignore_case = true;
+        options.scan.namespace = Some(Namespace::Uri("u".to_owned()));
+        assert_eq!(
+            fragments_to_schema_with_options(&[f1], &options).unwrap(),
+            "<Schema name=\"a\">\n<Cube\n name=\"x\"></Cube\n>\n</Schema>"
+        );
+    }
+
+    /// The warnings about a merge, besides cubes without
+    /// measures and unused shared dimensions, which most cubes
+    /// and dimensions here are
+    fn warnings(merged: &Merged) -> Vec<Warning> {
+        merged.warnings.iter()
+            .filter(|w| w.rule != "cube-without-measures" && w.rule != "unused-dimension")
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_fragments_warnings() {
+        let f1 = "<Schema name=\"testname\">\n<Cubee name=\"a\"></Cubee>\n<Cube name=\"b\"/>\n</Schema>".to_owned();
+        let f2 = "<Dimension name=\"d\"/> oops\n<Join name=\"r\"/><cube name=\"c\"/>".to_owned();
+        let merged = merge_fragments(&[f1, f2], &MergeOptions::default()).unwrap();
+        assert_eq!(
+            merged.schema,
+            "<Schema name=\"testname\">\n<Dimension name=\"d\"/>\n<Cube name=\"b\"/>\n</Schema>"
+        );
         assert_eq!(
-            Fragment::get_shared_dims(fragment).unwrap(),
-            Some(r#"<SharedDimension name="a"></SharedDimension>"#)
+            warnings(&merged),
+            vec![
+                Warning {
+                    fragment: 0,
+                    line: 2,
+                    column: 1,
+                    rule: "unknown-element",
+                    element: Some("<Cubee>".to_owned()),
+                    path: vec![],
+                    span: 25..49,
+                    message: "Unknown element <Cubee> was left out".to_owned(),
+                    first: None,
+                },
+                Warning {
+                    fragment: 1,
+                    line: 1,
+                    column: 23,
+                    rule: "stray-text",
+                    element: None,
+                    path: vec![],
+                    span: 22..26,
+                    message: "Stray text \"oops\" was left out".to_owned(),
+                    first: None,
+                },
+                Warning {
+                    fragment: 1,
+                    line: 2,
+                    column: 1,
+                    rule: "not-merged",
+                    element: Some("<Join>".to_owned()),
+                    path: vec![],
+                    span: 27..43,
+                    message: "<Join> is not merged and was left out".to_owned(),
+                    first: None,
+                },
+                Warning {
+                    fragment: 1,
+                    line: 2,
+                    column: 17,
+                    rule: "unknown-element",
+                    element: Some("<cube>".to_owned()),
+                    path: vec![],
+                    span: 43..59,
+                    message: "Unknown element <cube> was left out, did you mean <Cube>?".to_owned(),
+                    first: None,
+                },
+            ]
+        );
+        assert_eq!(warnings(&merged)[0].to_string(), "Fragment 0 line 2, column 1: Unknown element <Cubee> was left out");
+        assert_eq!(
+            merged.fragments,
+            vec![
+                FragmentStats { parameters: 0, shared_dims: 0, cubes: 1, virtual_cubes: 0, named_sets: 0, roles: 0, functions: 0 },
+                FragmentStats { parameters: 0, shared_dims: 1, cubes: 0, virtual_cubes: 0, named_sets: 0, roles: 0, functions: 0 },
+            ]
         );
 
-        let fragment = r#"<SharedDimension name="a"></SharedDimension>"#;
+        // nothing to warn about
+        let f1 = "<Schema name=\"testname\">\n  <!-- c -->\n<Dimension name=\"d\"/><Cube name=\"b\"/></Schema>\n".to_owned();
+        assert_eq!(warnings(&merge_fragments(&[f1], &MergeOptions::default()).unwrap()), vec![]);
+
+        // schemas which aren't merged aren't warned about either
+        let f1 = "<Schema name=\"a\"><Cube/></Schema><Schema name=\"b\"><Cubee/></Schema>".to_owned();
+        let options = MergeOptions { only_schema: Some("a".to_owned()), ..MergeOptions::default() };
+        assert_eq!(warnings(&merge_fragments(&[f1], &options).unwrap()), vec![]);
+    }
+
+    #[test]
+    fn test_merge_fragments_duplicates() {
+        let f1 = "<Schema name=\"testname\">\n<Dimension name=\"d\"/>\n<Cube name=\"a\"/>\n</Schema>".to_owned();
+        let f2 = "<SharedDimension name=\"d\"/><Cube name=\"b\"/>\n<VirtualCube name='a'/>\n<!-- c -->\n<Cube name=\"&#98;\"/>".to_owned();
+        let options = MergeOptions { scan: ScanOptions { keep_comments: true, ..ScanOptions::default() }, ..MergeOptions::default() };
+        let merged = merge_fragments(&[f1, f2], &options).unwrap();
         assert_eq!(
-            Fragment::get_shared_dims(fragment).unwrap(),
-            Some(r#"<SharedDimension name="a"></SharedDimension>"#)
+            warnings(&merged),
+            vec![
+                Warning {
+                    fragment: 1,
+                    line: 1,
+                    column: 1,
+                    rule: "duplicate-name",
+                    element: Some("Shared dimension \"d\"".to_owned()),
+                    path: vec!["SharedDimension \"d\"".to_owned()],
+                    span: 0..27,
+                    message: "Shared dimension \"d\" is defined more than once".to_owned(),
+                    first: Some((0, 2)),
+                },
+                Warning {
+                    fragment: 1,
+                    line: 4,
+                    column: 1,
+                    rule: "duplicate-name",
+                    element: Some("Cube \"b\"".to_owned()),
+                    path: vec!["Cube \"b\"".to_owned()],
+                    span: 79..99,
+                    message: "Cube \"b\" is defined more than once".to_owned(),
+                    first: Some((1, 1)),
+                },
+                Warning {
+                    fragment: 1,
+                    line: 2,
+                    column: 1,
+                    rule: "duplicate-name",
+                    element: Some("Virtual cube \"a\"".to_owned()),
+                    path: vec!["VirtualCube \"a\"".to_owned()],
+                    span: 44..67,
+                    message: "Virtual cube \"a\" is defined more than once".to_owned(),
+                    first: Some((0, 3)),
+                },
+            ]
         );
 
-        let fragment = r#"<Dimension name="a"></Dimension>"#;
         assert_eq!(
-            Fragment::get_shared_dims(fragment).unwrap(),
-            Some(r#"<Dimension name="a"></Dimension>"#)
+            warnings(&merged)[0].to_string(),
+            "Fragment 1 line 1, column 1: Shared dimension \"d\" is defined more than once, first in fragment 0 at line 2"
         );
+
+        // the same name for a dimension and a cube is fine
+        let f1 = "<Schema name=\"testname\"><Dimension name=\"a\"/><Cube name=\"a\"/><Cube/></Schema>".to_owned();
+        assert_eq!(warnings(&merge_fragments(&[f1], &MergeOptions::default()).unwrap()), vec![]);
     }
 
     #[test]
-    fn test_get_cubes() {
-        let fragment = r#"<Cube name="a"></Cube><VirtualCube name="vc1"></VirtualCube>"#;
+    fn test_merge_fragments_dimension_usages() {
+        let f1 = "<Schema name=\"testname\">\n<Dimension name=\"Time\"/>\n</Schema>".to_owned();
+        let f2 = "<Cube name=\"a\">\n<DimensionUsage source=\"Time\"/>\n<DimensionUsage source=\"Store\"/>\n</Cube>".to_owned();
+        let merged = merge_fragments(&[f1.clone(), f2.clone()], &MergeOptions::default()).unwrap();
+        assert_eq!(warnings(&merged).len(), 1);
         assert_eq!(
-            Fragment::get_cubes(fragment).unwrap(),
-            Some(r#"<Cube name="a"></Cube>"#)
+            warnings(&merged)[0].to_string(),
+            "Fragment 1 line 3, column 1: Cube \"a\" uses shared dimension \"Store\", which isn't in the schema"
         );
+        assert_eq!(warnings(&merged)[0].rule, "unknown-dimension");
 
-        let fragment = r#"<Schema name="b"><Cube name="a"></Cube></Schema>"#;
+        // a shared dimension which was left out doesn't count
+        let options = MergeOptions { only_schema: Some("testname".to_owned()), ..MergeOptions::default() };
+        let f3 = "<Schema name=\"other\"><Dimension name=\"Store\"/></Schema>".to_owned();
+        assert_eq!(warnings(&merge_fragments(&[f1, f2, f3], &options).unwrap()).len(), 1);
+    }
+
+    #[test]
+    fn test_merge_fragments_captions() {
+        let fragments = vec![
+            "<Schema name=\"testname\"><Cube name=\"a\" caption=\"A\"><Measure name=\"m\" aggregator=\"sum\" column=\"c\"/></Cube></Schema>".to_owned(),
+        ];
+        assert_eq!(merge_fragments(&fragments, &MergeOptions::default()).unwrap().warnings, vec![]);
+
+        let mut options = MergeOptions::default();
+        options.lints.insert("missing-caption".to_owned(), lint::Level::Warn);
+        options.lints.insert("missing-description".to_owned(), lint::Level::Warn);
+        let rules: Vec<_> = merge_fragments(&fragments, &options).unwrap().warnings.iter().map(|w| w.rule).collect();
+        assert_eq!(rules, vec!["missing-caption", "missing-description", "missing-description"]);
+    }
+
+    #[test]
+    fn test_merge_fragments_duplicate_policy() {
+        let fragments = vec![
+            "<Schema name=\"testname\">\n<Cube name=\"a\"/>\n</Schema>".to_owned(),
+            "<Cube name=\"b\"/><Cubee/>\n<VirtualCube name=\"a\"/>".to_owned(),
+        ];
+
+        let options = MergeOptions { duplicates: DuplicatePolicy::Error, ..MergeOptions::default() };
+        let merged = merge_fragments(&fragments, &options).unwrap();
+        let rules: Vec<_> = merged.errors.iter().map(|w| w.rule).collect();
+        assert_eq!(rules, vec!["duplicate-name", "duplicate-conflict"]);
+        assert_eq!(
+            merged.errors[0].to_string(),
+            "Fragment 1 line 2, column 1: Virtual cube \"a\" is defined more than once, first in fragment 0 at line 2"
+        );
+        assert_eq!(warnings(&merged).len(), 1);
+        match *fragments_to_schema_with_options(&fragments, &options).unwrap_err().kind() {
+            ErrorKind::Denied(ref report) => {
+                assert_eq!(report.errors().count(), 2);
+                assert_eq!(report.with_rule("unknown-element").count(), 1);
+            },
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+
+        // other warnings are kept
+        let options = MergeOptions { duplicates: DuplicatePolicy::Allow, ..MergeOptions::default() };
+        let merged = merge_fragments(&fragments, &options).unwrap();
+        assert_eq!(warnings(&merged).len(), 1);
+        assert_eq!(warnings(&merged)[0].message, "Unknown element <Cubee> was left out");
         assert_eq!(
-            Fragment::get_cubes(fragment).unwrap(),
-            Some(r#"<Cube name="a"></Cube>"#)
+            merged.schema,
+            "<Schema name=\"testname\">\n<Cube name=\"a\"/>\n<Cube name=\"b\"/>\n<VirtualCube name=\"a\"/>\n</Schema>"
         );
+
+        assert_eq!(warnings(&merge_fragments(&fragments, &MergeOptions::default()).unwrap()).len(), 2);
+
+        assert_eq!("error".parse::<DuplicatePolicy>().unwrap(), DuplicatePolicy::Error);
+        assert_eq!("allow".parse::<DuplicatePolicy>().unwrap(), DuplicatePolicy::Allow);
+        assert!("Warn".parse::<DuplicatePolicy>().is_err());
     }
 
     #[test]
-    fn test_get_virtual_cubes() {
-        let fragment = r#"<Cube name="a"></Cube><VirtualCube name="vc1"></VirtualCube>"#;
+    fn test_merge_fragments_dedupe_shared_dimensions() {
+        // each cube fragment pastes in the Time dimension, written
+        // its own way
+        let time = [
+            "<Dimension name=\"Time\" type=\"TimeDimension\">\n  <Hierarchy hasAll=\"true\" primaryKey=\"id\">\n    <Table name=\"time\"/>\n    \
+             <Level name=\"Year\" column=\"year\" levelType=\"TimeYears\"></Level>\n  </Hierarchy>\n</Dimension>",
+            "<Dimension type='TimeDimension' name='Time'><Hierarchy primaryKey=\"id\" hasAll=\"true\"><Table name=\"time\"></Table>\
+             <Level levelType=\"TimeYears\" column=\"year\" name=\"Year\"/></Hierarchy></Dimension>",
+            "<Dimension name=\"Time\" type=\"TimeDimension\"><!-- for testing on its own --><Hierarchy hasAll=\"true\" primaryKey=\"id\">\
+             <Table name=\"time\"/><Level name=\"Year\" column=\"year\" levelType=\"TimeYears\"/></Hierarchy></Dimension>",
+        ];
+        let inputs: Vec<_> = time.iter().enumerate()
+            .map(|(i, time)| format!(
+                "<Schema name=\"s\">\n{}\n<Cube name=\"c{}\"><Table name=\"f\"/><DimensionUsage name=\"Time\" source=\"Time\" foreignKey=\"time_id\"/>\
+                 <Measure name=\"m\" column=\"m\" aggregator=\"sum\"/></Cube>\n</Schema>",
+                time, i
+            ))
+            .collect();
+        let options = MergeOptions { dedupe_identical: true, ..MergeOptions::default() };
+        let merged = merge_fragments(&inputs, &options).unwrap();
+        assert_eq!(merged.schema.matches("<Dimension name=\"Time\"").count(), 1);
+        assert!(merged.schema.contains(time[0]));
+        assert!(merged.warnings.is_empty() && merged.errors.is_empty(), "{:?} {:?}", merged.warnings, merged.errors);
+
+        // without dedupe they're still not a conflict
+        let merged = merge_fragments(&inputs, &MergeOptions::default()).unwrap();
+        let rules: Vec<_> = merged.warnings.iter().chain(&merged.errors).map(|warning| warning.rule).collect();
+        assert_eq!(rules, vec!["duplicate-name", "duplicate-name"]);
+        assert!(merged.errors.is_empty());
+
+        // but refused with the error strategy
+        let options = MergeOptions { conflicts: ConflictStrategy::Error, ..MergeOptions::default() };
+        let merged = merge_fragments(&inputs, &options).unwrap();
+        let rules: Vec<_> = merged.errors.iter().map(|warning| warning.rule).collect();
+        assert_eq!(rules, vec!["duplicate-name", "duplicate-name"]);
+        assert!(fragments_to_schema_with_options(&inputs, &options).is_err());
+    }
+
+    #[test]
+    fn test_merge_fragments_conflict_strategies() {
+        let fragments = vec![
+            "<Schema name=\"s\">\n<Cube name=\"Sales\"><Measure name=\"m\" aggregator=\"sum\" column=\"v1\"/></Cube>\n\
+             <Cube name=\"Sales_2\"><Measure name=\"m\" aggregator=\"sum\" column=\"v\"/></Cube>\n</Schema>".to_owned(),
+            "<Cube name=\"Sales\"><Measure name=\"m\" aggregator=\"sum\" column=\"v2\"/></Cube>".to_owned(),
+            "<VirtualCube name='Sales'><CubeUsages><CubeUsage cubeName=\"Sales_2\"/></CubeUsages></VirtualCube>".to_owned(),
+        ];
+        let merge = |conflicts| {
+            let options = MergeOptions { conflicts, ..MergeOptions::default() };
+            let merged = merge_fragments(&fragments, &options).unwrap();
+            let names: Vec<_> = merged.elements.iter().map(|element| (element.fragment, element.name.clone().unwrap())).collect();
+            let rules: Vec<_> = merged.warnings.iter().chain(&merged.errors).map(|w| w.rule).collect();
+            (merged.schema, names, rules, merged.errors.len())
+        };
+
+        let (_, names, rules, errors) = merge(ConflictStrategy::KeepAll);
+        assert_eq!(names.len(), 4);
+        assert_eq!(rules, vec!["duplicate-name", "duplicate-name", "duplicate-conflict", "duplicate-conflict"]);
+        assert_eq!(errors, 2);
+
+        // every collision is an error
+        let (_, names, rules, errors) = merge(ConflictStrategy::Error);
+        assert_eq!(names.len(), 4);
+        assert_eq!(rules, vec!["duplicate-name", "duplicate-conflict", "duplicate-name", "duplicate-conflict"]);
+        assert_eq!(errors, 4);
+
+        // a collision resolved isn't reported
+        let (schema, names, rules, errors) = merge(ConflictStrategy::FirstWins);
+        assert_eq!(names, vec![(0, "Sales".to_owned()), (0, "Sales_2".to_owned())]);
+        assert!(schema.contains("column=\"v1\""));
+        assert_eq!(rules, Vec::<&str>::new());
+        assert_eq!(errors, 0);
+
+        // the virtual cube is last, so it stands in for the cubes
+        let (schema, names, _, errors) = merge(ConflictStrategy::LastWins);
+        assert_eq!(names, vec![(0, "Sales_2".to_owned()), (2, "Sales".to_owned())]);
+        assert!(!schema.contains("column=\"v2\""));
+        assert_eq!(errors, 0);
+
+        // Sales_2 is taken, so the others go on to _3 and _4
+        let (schema, names, _, errors) = merge(ConflictStrategy::RenameWithSuffix);
         assert_eq!(
-            Fragment::get_virtual_cubes(fragment).unwrap(),
-            Some(r#"<VirtualCube name="vc1"></VirtualCube>"#)
+            names,
+            vec![(0, "Sales".to_owned()), (0, "Sales_2".to_owned()), (1, "Sales_3".to_owned()), (2, "Sales_4".to_owned())]
         );
+        assert!(schema.contains("<Cube name=\"Sales_3\"><Measure name=\"m\" aggregator=\"sum\" column=\"v2\"/></Cube>"));
+        assert!(schema.contains("<VirtualCube name='Sales_4'>"));
+        assert_eq!(errors, 0);
 
-        let fragment = r#"<Schema name="s1"><VirtualCube name="vc1"></VirtualCube></Schema>"#;
+        // the virtual cube can't go where the cube is, but the
+        // last cube can
+        let (schema, names, rules, errors) = merge(ConflictStrategy::Overlay);
+        assert_eq!(names, vec![(0, "Sales_2".to_owned()), (2, "Sales".to_owned())]);
+        assert_eq!(rules, vec!["overridden", "overridden"]);
+        assert_eq!(errors, 0);
+        assert!(schema.contains("<VirtualCube name='Sales'>"));
+        let base = vec![fragments[0].clone(), fragments[1].clone()];
+        let options = MergeOptions { conflicts: ConflictStrategy::Overlay, ..MergeOptions::default() };
+        let merged = merge_fragments(&base, &options).unwrap();
         assert_eq!(
-            Fragment::get_virtual_cubes(fragment).unwrap(),
-            Some(r#"<VirtualCube name="vc1"></VirtualCube>"#)
+            merged.schema,
+            "<Schema name=\"s\">\n<Cube name=\"Sales\"><Measure name=\"m\" aggregator=\"sum\" column=\"v2\"/></Cube>\n\
+             <Cube name=\"Sales_2\"><Measure name=\"m\" aggregator=\"sum\" column=\"v\"/></Cube>\n</Schema>"
         );
+        assert_eq!(merged.warnings[0].message, "Cube \"Sales\" overrides an earlier definition");
+        assert_eq!((merged.warnings[0].fragment, merged.warnings[0].first), (1, Some((0, 2))));
+
+        assert_eq!("last-wins".parse::<ConflictStrategy>().unwrap(), ConflictStrategy::LastWins);
+        assert!("rename".parse::<ConflictStrategy>().is_err());
     }
 
     #[test]
-    fn test_process_fragment() {
-        let fragment = r#"<Schema name="testname">
-            <Dimension name="shareddim"></Dimension><Cube name="testcube"><Dimension name="inner"></Dimension></Cube><Cube name="a"></Cube><VirtualCube name="testvirtualcube"><Dimension name="inner_virtual"></Dimension></VirtualCube><VirtualCube name="a"></VirtualCube></Schema>"#;
+    fn test_merge_fragments_overlay_annotations() {
+        let fragments = vec![
+            "<Schema name=\"s\">\n<Cube name=\"Sales\"><Annotations><Annotation name=\"owner\">bi</Annotation>\
+             <Annotation name=\"tier\">1</Annotation></Annotations><Table name=\"sales\"/></Cube>\n</Schema>".to_owned(),
+            "<Cube name=\"Sales\">\n<Annotations><Annotation name=\"tier\">2</Annotation></Annotations>\n<Table name=\"sales_2\"/>\n</Cube>".to_owned(),
+        ];
+        let options = MergeOptions { conflicts: ConflictStrategy::Overlay, ..MergeOptions::default() };
+        let merged = merge_fragments(&fragments, &options).unwrap();
         assert_eq!(
-            Fragment::process_fragment(fragment).unwrap(),
-            Fragment {
-                schema_name: Some("testname"),
-                shared_dims: Some(r#"<Dimension name="shareddim"></Dimension>"#),
-                cubes: Some(r#"<Cube name="testcube"><Dimension name="inner"></Dimension></Cube><Cube name="a"></Cube>"#),
-                virtual_cubes: Some(r#"<VirtualCube name="testvirtualcube"><Dimension name="inner_virtual"></Dimension></VirtualCube><VirtualCube name="a"></VirtualCube>"#),
-            }
+            merged.schema,
+            "<Schema name=\"s\">\n<Cube name=\"Sales\">\n<Annotations>\n  <Annotation name=\"owner\">bi</Annotation>\n  \
+             <Annotation name=\"tier\">1</Annotation>\n</Annotations>\n<Table name=\"sales_2\"/>\n</Cube>\n</Schema>"
         );
+        let found: Vec<_> = merged.errors.iter().map(|error| (error.fragment, error.line, error.rule)).collect();
+        assert_eq!(found, vec![(1, 2, "annotation-conflict")]);
     }
 
     #[test]
-    fn test_process_fragment_shareddimension() {
-        let fragment = r#"<Schema name="testname">
-            <SharedDimension name="shareddim"></SharedDimension><Cube name="testcube"><Dimension name="inner"></Dimension></Cube><Cube name="a"></Cube><VirtualCube name="testvirtualcube"><Dimension name="inner_virtual"></Dimension></VirtualCube><VirtualCube name="a"></VirtualCube></Schema>"#;
+    fn test_merge_fragments_virtual_cubes() {
+        let fragments = vec![
+            "<Schema name=\"s\">\n<Dimension name=\"Store\"/>\n\
+             <Cube name=\"Sales\"><Measure name=\"Units\" column=\"units\" aggregator=\"sum\"/></Cube>\n</Schema>".to_owned(),
+            "<Cube name=\"Stock\"><Measure name=\"Level\" column=\"level\" aggregator=\"sum\"/></Cube>\n<Role name=\"r\"/>".to_owned(),
+        ];
+        let recipe = VirtualCubeRecipe {
+            name: "Sales and Stock".to_owned(),
+            cubes: vec!["Sales".to_owned(), "Stock".to_owned()],
+            measures: vec!["Units".to_owned(), "Level".to_owned()],
+            dimensions: vec!["Store".to_owned()],
+        };
+        let mut options = MergeOptions { virtual_cubes: vec![recipe.clone()], ..MergeOptions::default() };
+        let merged = merge_fragments(&fragments, &options).unwrap();
         assert_eq!(
-            Fragment::process_fragment(fragment).unwrap(),
-            Fragment {
-                schema_name: Some("testname"),
-                shared_dims: Some(r#"<SharedDimension name="shareddim"></SharedDimension>"#),
-                cubes: Some(r#"<Cube name="testcube"><Dimension name="inner"></Dimension></Cube><Cube name="a"></Cube>"#),
-                virtual_cubes: Some(r#"<VirtualCube name="testvirtualcube"><Dimension name="inner_virtual"></Dimension></VirtualCube><VirtualCube name="a"></VirtualCube>"#),
-            }
+            merged.schema,
+            "<Schema name=\"s\">\n<Dimension name=\"Store\"/>\n\
+             <Cube name=\"Sales\"><Measure name=\"Units\" column=\"units\" aggregator=\"sum\"/></Cube>\n\
+             <Cube name=\"Stock\"><Measure name=\"Level\" column=\"level\" aggregator=\"sum\"/></Cube>\n\
+             <VirtualCube name=\"Sales and Stock\">\n  <CubeUsages>\n    <CubeUsage cubeName=\"Sales\"/>\n    \
+             <CubeUsage cubeName=\"Stock\"/>\n  </CubeUsages>\n  <VirtualCubeDimension name=\"Store\"/>\n  \
+             <VirtualCubeMeasure cubeName=\"Sales\" name=\"[Measures].[Units]\"/>\n  \
+             <VirtualCubeMeasure cubeName=\"Stock\" name=\"[Measures].[Level]\"/>\n</VirtualCube>\n\
+             <Role name=\"r\"/>\n</Schema>"
+        );
+        assert_eq!(merged.generated, vec!["Sales and Stock".to_owned()]);
+        // placed where its first cube is
+        assert_eq!(
+            merged.elements[3],
+            MergedElement { kind: ElementKind::VirtualCube, name: Some("Sales and Stock".to_owned()), fragment: 0, line: 3 }
+        );
+
+        // and checked and patched like the others
+        options.lints.insert("missing-caption".to_owned(), lint::Level::Warn);
+        let merged = merge_fragments(&fragments, &options).unwrap();
+        let found: Vec<_> = merged.warnings.iter()
+            .filter(|warning| warning.element.as_deref() == Some("Virtual cube \"Sales and Stock\""))
+            .map(|warning| (warning.fragment, warning.line, warning.column, warning.rule))
+            .collect();
+        assert_eq!(found, vec![(0, 3, 1, "missing-caption")]);
+        let mut patched = fragments.clone();
+        patched.push("<msc:patch virtualCube=\"Sales and Stock\"><set attribute=\"caption\" value=\"All\"/></msc:patch>".to_owned());
+        let merged = merge_fragments(&patched, &options).unwrap();
+        assert!(merged.schema.contains("<VirtualCube name=\"Sales and Stock\" caption=\"All\">"));
+        assert!(merged.warnings.iter().all(|warning| warning.element.as_deref() != Some("Virtual cube \"Sales and Stock\"")));
+        options.lints.clear();
+
+        options.virtual_cubes.push(VirtualCubeRecipe { name: "Sales".to_owned(), ..recipe });
+        match *merge_fragments(&fragments, &options).unwrap_err().kind() {
+            ErrorKind::InvalidRecipe(ref name, ref message) => assert_eq!((name.as_str(), message.as_str()), ("Sales", "its name is taken")),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_merge_fragments_patches() {
+        let fragments = vec![
+            "<Schema name=\"s\">\n<Cube name=\"Sales\" cache=\"true\">\n  <Table name=\"sales\"/>\n  \
+             <Measure name=\"Units\" column=\"units\" aggregator=\"sum\"/>\n</Cube>\n</Schema>".to_owned(),
+            "<msc:patch cube=\"Sales\"><set attribute=\"defaultMeasure\" value=\"Units\"/><remove attribute=\"cache\"/></msc:patch>\n\
+             <msc:patch cube=\"Sales\"><set attribute=\"caption\" value=\"Sales &amp; more\"/></msc:patch>\n\
+             <msc:patch cube=\"Returns\"><set attribute=\"caption\" value=\"Returns\"/></msc:patch>\n\
+             <msc:patch cube=\"Sales\"><set attribute=\"name\" value=\"Sold\"/></msc:patch>".to_owned(),
+        ];
+        let merged = merge_fragments(&fragments, &MergeOptions::default()).unwrap();
+        assert_eq!(
+            merged.schema,
+            "<Schema name=\"s\">\n<Cube name=\"Sales\" defaultMeasure=\"Units\" caption=\"Sales &amp; more\">\n  \
+             <Table name=\"sales\"/>\n  <Measure name=\"Units\" column=\"units\" aggregator=\"sum\"/>\n</Cube>\n</Schema>"
         );
+        let found: Vec<_> = merged.warnings.iter().chain(&merged.errors).map(|w| (w.fragment, w.line, w.rule)).collect();
+        assert_eq!(found, vec![(1, 3, "unknown-patch-target"), (1, 4, "invalid-patch")]);
+
+        // a patched cube is checked as patched, with what's found
+        // in it located in its fragment
+        let fragments = vec![
+            fragments[0].clone(),
+            "<msc:patch cube=\"Sales\"><set attribute=\"defaultMeasure\" value=\"Amount\"/></msc:patch>".to_owned(),
+        ];
+        let merged = merge_fragments(&fragments, &MergeOptions::default()).unwrap();
+        let found: Vec<_> = merged.errors.iter().map(|w| (w.fragment, w.line, w.rule)).collect();
+        assert_eq!(found, vec![(0, 2, "unknown-default-measure")]);
     }
 
     #[test]
-    #[should_panic]
-    fn test_fragments_to_schema_empty() {
-        fragments_to_schema(&vec!["".to_owned()]).unwrap();
+    fn test_merge_fragments_merge_cubes() {
+        let fragments = vec![
+            "<Schema name=\"s\">\n<Dimension name=\"Store\"><Hierarchy hasAll=\"true\" primaryKey=\"id\"><Table name=\"store\"/>\
+             <Level name=\"City\" column=\"city\"/></Hierarchy></Dimension>\n\
+             <Cube name=\"Sales\">\n  <Table name=\"sales_fact\"/>\n  <Measure name=\"Units\" column=\"units\" aggregator=\"sum\"/>\n</Cube>\n</Schema>".to_owned(),
+            "<Cube name=\"Sales\">\n  <DimensionUsage name=\"Store\" source=\"Store\" foreignKey=\"store_id\"/>\n  \
+             <DimensionUsage name=\"Time\" source=\"Time\" foreignKey=\"time_id\"/>\n  \
+             <CalculatedMember name=\"Double\" dimension=\"Measures\" formula=\"[Measures].[Units] * 2\"/>\n</Cube>".to_owned(),
+        ];
+        let options = MergeOptions { merge_cubes: true, ..MergeOptions::default() };
+        let merged = merge_fragments(&fragments, &options).unwrap();
+        assert!(merged.schema.contains(
+            "<Cube name=\"Sales\">\n  <Table name=\"sales_fact\"/>\n  \
+             <DimensionUsage name=\"Store\" source=\"Store\" foreignKey=\"store_id\"/>\n  \
+             <DimensionUsage name=\"Time\" source=\"Time\" foreignKey=\"time_id\"/>\n  \
+             <Measure name=\"Units\" column=\"units\" aggregator=\"sum\"/>\n  \
+             <CalculatedMember name=\"Double\" dimension=\"Measures\" formula=\"[Measures].[Units] * 2\"/>\n</Cube>"
+        ));
+        assert_eq!(merged.elements.iter().filter(|element| element.kind == ElementKind::Cube).count(), 1);
+        // the cube is checked as a whole, but found where each part is
+        let found: Vec<_> = merged.warnings.iter().map(|w| (w.fragment, w.line, w.rule)).collect();
+        assert_eq!(found, vec![(1, 3, "unknown-dimension")]);
+        assert!(merged.errors.is_empty());
+
+        // a part can add attributes, but not a second fact table
+        let mut parts = fragments.clone();
+        parts.push("<Cube name=\"Sales\" defaultMeasure=\"Units\"><Table name=\"sales_v2\"/></Cube>".to_owned());
+        let merged = merge_fragments(&parts, &options).unwrap();
+        assert!(merged.schema.contains("<Cube name=\"Sales\" defaultMeasure=\"Units\">\n  <Table name=\"sales_fact\"/>\n"));
+        let found: Vec<_> = merged.errors.iter().map(|w| (w.fragment, w.rule)).collect();
+        assert_eq!(found, vec![(2, "duplicate-conflict")]);
+
+        // and annotations, which are merged
+        let mut parts = fragments.clone();
+        parts[0] = parts[0].replace("<Cube name=\"Sales\">", "<Cube name=\"Sales\"><Annotations><Annotation name=\"owner\">bi</Annotation></Annotations>");
+        parts[1] = parts[1].replace("<Cube name=\"Sales\">", "<Cube name=\"Sales\"><Annotations><Annotation name=\"tier\">1</Annotation></Annotations>");
+        let merged = merge_fragments(&parts, &options).unwrap();
+        assert!(merged.errors.is_empty(), "{:?}", merged.errors);
+        assert!(merged.schema.contains(
+            "<Annotations>\n    <Annotation name=\"owner\">bi</Annotation>\n    <Annotation name=\"tier\">1</Annotation>\n  </Annotations>"
+        ));
+
+        // otherwise they're duplicates
+        let merged = merge_fragments(&fragments, &MergeOptions::default()).unwrap();
+        assert_eq!(merged.elements.iter().filter(|element| element.kind == ElementKind::Cube).count(), 2);
+        assert_eq!(merged.errors.iter().map(|w| w.rule).collect::<Vec<_>>(), vec!["duplicate-conflict"]);
     }
 
     #[test]
-    #[should_panic]
-    fn test_fragments_to_schema_no_schema() {
-        fragments_to_schema(&vec!["<Cube></Cube>".to_owned()]).unwrap();
+    fn test_merge_fragments_conflicts() {
+        let fragments = vec![
+            "<Schema name=\"testname\">\n<Cube name=\"a\">\n  <Measure name=\"m\" aggregator=\"sum\" column=\"c\"/>\n</Cube>\n</Schema>".to_owned(),
+            "<!-- again -->\n<Cube name=\"a\"><Measure   name=\"m\" aggregator=\"sum\" column=\"c\" /></Cube>".to_owned(),
+            "<Cube name=\"a\">\n  <Measure name=\"m\" aggregator=\"count\" column=\"c\"/>\n</Cube>".to_owned(),
+        ];
+        let merged = merge_fragments(&fragments, &MergeOptions::default()).unwrap();
+        let rules: Vec<_> = merged.warnings.iter().map(|w| (w.fragment, w.rule)).collect();
+        assert_eq!(rules, vec![(1, "duplicate-name"), (2, "duplicate-name")]);
+        assert_eq!(merged.errors.len(), 1);
+        assert_eq!(
+            merged.errors[0].message,
+            "Cube \"a\" differs from its first definition:\n\
+             - <Measure name=\"m\" aggregator=\"sum\" column=\"c\"/>\n\
+             + <Measure name=\"m\" aggregator=\"count\" column=\"c\"/>"
+        );
+
+        // identical ones can be left out
+        let options = MergeOptions { dedupe_identical: true, ..MergeOptions::default() };
+        let merged = merge_fragments(&fragments[..2], &options).unwrap();
+        assert_eq!(merged.warnings, vec![]);
+        assert_eq!(merged.elements.len(), 1);
+        assert_eq!(merged.schema.matches("<Cube").count(), 1);
+
+        // and a cube given again byte for byte always is
+        let copied = vec![fragments[2].clone(), fragments[0].clone(), fragments[2].clone()];
+        let merged = merge_fragments(&copied, &MergeOptions::default()).unwrap();
+        let found: Vec<_> = merged.warnings.iter().map(|w| (w.fragment, w.rule, w.message.as_str(), w.first)).collect();
+        assert_eq!(
+            found,
+            vec![(1, "duplicate-name", "Cube \"a\" is defined more than once", Some((0, 1))),
+                 (2, "identical-copy", "Cube \"a\" is a copy of an earlier one, and is left out", Some((0, 1)))]
+        );
+        assert_eq!(merged.schema.matches("<Cube").count(), 2);
+
+        // unless duplicates are an error
+        let options = MergeOptions { duplicates: DuplicatePolicy::Error, ..MergeOptions::default() };
+        let merged = merge_fragments(&copied, &options).unwrap();
+        let found: Vec<_> = merged.errors.iter().map(|w| (w.fragment, w.rule)).collect();
+        assert_eq!(found, vec![(1, "duplicate-name"), (1, "duplicate-conflict"), (2, "duplicate-name")]);
+        assert_eq!(merged.schema.matches("<Cube").count(), 3);
+        match *fragments_to_schema_with_options(&copied, &options).unwrap_err().kind() {
+            ErrorKind::Denied(ref report) => assert_eq!(report.with_rule("duplicate-name").count(), 2),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+
+        // allowing duplicates allows conflicts too
+        let options = MergeOptions { duplicates: DuplicatePolicy::Allow, ..MergeOptions::default() };
+        assert_eq!(merge_fragments(&fragments, &options).unwrap().errors, vec![]);
     }
 
     #[test]
-    #[should_panic]
-    fn test_fragments_to_schema_different_names() {
-        fragments_to_schema(&vec!["<Schema name=\"a\"></Schema>".to_owned(), "<Schema name=\"b\"></Schema>".to_owned()]).unwrap();
+    fn test_merge_fragments_lints() {
+        let fragments = vec![
+            "<Schema name=\"testname\">\n<Cube name=\"a\"/>\n</Schema>".to_owned(),
+            "<Cube name=\"a\"/><Cubee/>\n<Cube name=\"a\"/>".to_owned(),
+        ];
+
+        // every finding is collected, not just the first
+        let mut options = MergeOptions { duplicates: DuplicatePolicy::Allow, ..MergeOptions::default() };
+        options.lints.insert("duplicate-name".to_owned(), lint::Level::Deny);
+        options.lints.insert("cube-without-measures".to_owned(), lint::Level::Allow);
+        let merged = merge_fragments(&fragments, &options).unwrap();
+        let rules: Vec<_> = merged.errors.iter().map(|w| w.rule).collect();
+        assert_eq!(rules, vec!["duplicate-name", "duplicate-name"]);
+        let rules: Vec<_> = merged.warnings.iter().map(|w| w.rule).collect();
+        assert_eq!(rules, vec!["unknown-element"]);
+
+        options.lints.insert("unknown-element".to_owned(), lint::Level::Allow);
+        options.lints.insert("duplicate-name".to_owned(), lint::Level::Warn);
+        let merged = merge_fragments(&fragments, &options).unwrap();
+        assert_eq!(merged.errors, vec![]);
+        assert_eq!(merged.warnings.len(), 2);
+
+        assert_eq!(MergeOptions::default().level("invalid-structure"), lint::Level::Allow);
+        assert_eq!(MergeOptions::default().level("duplicate-name"), lint::Level::Warn);
     }
 
     #[test]
-    fn test_fragments_to_schema() {
-        // First make sure that feeding through just one works
-        let fragment = r#"<Schema name="testname"><SharedDimension name="shareddim"></SharedDimension><Cube name="testcube"><Dimension name="inner"></Dimension></Cube><Cube name="a"></Cube></Schema>"#.to_owned();
-        let fragments = vec![fragment];
+    fn test_verify_output() {
+        let fragments = vec!["<Schema name=\"testname\">\n<Cube name=\"a\"><Measure name=\"m\"/></Cube>\n</Schema>".to_owned()];
+        let options = MergeOptions { verify_output: true, ..MergeOptions::default() };
+        assert!(merge_fragments(&fragments, &options).is_ok());
+
+        match *verify_output("<Schema name=\"s\">\n<Cube name=\"a\">\n</Schema>", false).unwrap_err().kind() {
+            ErrorKind::MalformedOutput(3, 1, _) => {},
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_merge_fragments_elements() {
+        let f1 = "<Schema name=\"Sales &amp; Ops\">\n<VirtualCube name=\"v\"/>\n<Cube name=\"a\"/>\n</Schema>".to_owned();
+        let f2 = "<Cube name=\"&#98;\"/>\n<SharedDimension name=\"d\"/>\n<Cube/>".to_owned();
+        let merged = merge_fragments(&[f1, f2], &MergeOptions::default()).unwrap();
+        assert_eq!(merged.schema_name, "Sales & Ops");
+
+        let element = |kind, name: Option<&str>, fragment, line| {
+            MergedElement { kind, name: name.map(|name| name.to_owned()), fragment, line }
+        };
         assert_eq!(
-            fragments_to_schema(&fragments).unwrap(),
-            "<Schema name=\"testname\">\n<SharedDimension name=\"shareddim\"></SharedDimension><Cube name=\"testcube\"><Dimension name=\"inner\"></Dimension></Cube><Cube name=\"a\"></Cube>\n</Schema>"
+            merged.elements,
+            vec![
+                element(ElementKind::SharedDimension, Some("d"), 1, 2),
+                element(ElementKind::Cube, Some("a"), 0, 3),
+                element(ElementKind::Cube, Some("b"), 1, 1),
+                element(ElementKind::Cube, None, 1, 3),
+                element(ElementKind::VirtualCube, Some("v"), 0, 2),
+            ]
         );
+        assert_eq!(ElementKind::SharedDimension.to_string(), "shared dimension");
+    }
 
-        let fragment = r#"<Schema name="testname"><Dimension name="shareddim"></Dimension><Cube name="testcube"><Dimension name="inner"></Dimension></Cube><Cube name="a"></Cube></Schema>"#.to_owned();
-        let fragments = vec![fragment];
+    #[test]
+    fn test_merge_fragments_sort() {
+        let f1 = "<Schema name=\"s\">\n<Cube name=\"b\"/>\n<SharedDimension name=\"z\"/>\n<VirtualCube name=\"v\"/>\n</Schema>".to_owned();
+        let f2 = "<Cube name=\"a\"/>\n<Dimension name=\"y\"/>\n<Cube name=\"b\" caption=\"again\"/>".to_owned();
+        let options = MergeOptions { sort: SortOrder::Name, duplicates: DuplicatePolicy::Allow, ..MergeOptions::default() };
+        let merged = merge_fragments(&[f1, f2], &options).unwrap();
         assert_eq!(
-            fragments_to_schema(&fragments).unwrap(),
-            "<Schema name=\"testname\">\n<Dimension name=\"shareddim\"></Dimension><Cube name=\"testcube\"><Dimension name=\"inner\"></Dimension></Cube><Cube name=\"a\"></Cube>\n</Schema>"
+            merged.schema,
+            "<Schema name=\"s\">\n<Dimension name=\"y\"/>\n<SharedDimension name=\"z\"/>\n<Cube name=\"a\"/>\n<Cube name=\"b\"/>\n<Cube name=\"b\" caption=\"again\"/>\n<VirtualCube name=\"v\"/>\n</Schema>"
         );
+        let names: Vec<_> = merged.elements.iter().map(|element| element.name.as_deref().unwrap()).collect();
+        assert_eq!(names, vec!["y", "z", "a", "b", "b", "v"]);
+        assert_eq!((merged.elements[3].fragment, merged.elements[4].fragment), (0, 1));
 
-        // Now multiple
-        let f1 = r#"<Schema name="testname"><SharedDimension name="shareddim"></SharedDimension><Cube name="testcube"><Dimension name="inner"></Dimension></Cube><Cube name="a"></Cube></Schema>"#.to_owned();
-        let f2 = r#"<SharedDimension name="shareddim2"></SharedDimension><Cube name="cube2"><Dimension name="inner2"></Dimension></Cube><Cube name="b"></Cube>"#.to_owned();
-        let fragments = vec![f1, f2];
+        assert_eq!("name".parse::<SortOrder>().unwrap(), SortOrder::Name);
+        assert!("size".parse::<SortOrder>().is_err());
+    }
+
+    #[test]
+    fn test_merge_fragments_sort_dependency() {
+        let f1 = "<Schema name=\"s\">\n<Dimension name=\"Time\"/>\n<Dimension name=\"Store\"/>\n<Dimension name=\"Age\"/>\n\
+                  <Cube name=\"Warehouse\"><DimensionUsage name=\"Time\" source=\"Time\"/></Cube>\n</Schema>".to_owned();
+        let f2 = "<VirtualCube name=\"All\"><CubeUsages><CubeUsage cubeName=\"Warehouse\"/><CubeUsage cubeName=\"Sales\"/></CubeUsages></VirtualCube>\n\
+                  <Cube name=\"Sales\"><DimensionUsage name=\"Store\" source=\"Store\"/><DimensionUsage name=\"Time\" source=\"Time\"/></Cube>\n\
+                  <Cube name=\"Budget\"/>".to_owned();
+        let options = MergeOptions { sort: SortOrder::Dependency, ..MergeOptions::default() };
+        let merged = merge_fragments(&[f1, f2], &options).unwrap();
+        let names: Vec<_> = merged.elements.iter().map(|element| element.name.as_deref().unwrap()).collect();
+        // Warehouse is used first, and Budget not at all; then
+        // Warehouse uses Time first
+        assert_eq!(names, vec!["Time", "Store", "Age", "Warehouse", "Sales", "Budget", "All"]);
+
+        assert_eq!("dependency".parse::<SortOrder>().unwrap(), SortOrder::Dependency);
+    }
+
+    #[test]
+    fn test_fragments_to_schema_entities() {
+        // escaped text in fragments passes through untouched
+        let f1 = r##"<Schema name="Sales &amp; Ops"><Cube name="a &lt;b&gt;" caption="&quot;A&quot; &apos;B&apos;"><Measure name="m" formatString="#,##0"/></Cube></Schema>"##.to_owned();
+        let expected = "<Schema name=\"Sales &amp; Ops\">\n<Cube name=\"a &lt;b&gt;\" caption=\"&quot;A&quot; &apos;B&apos;\"><Measure name=\"m\" formatString=\"#,##0\"/></Cube>\n</Schema>";
+        let fragments = vec![f1];
+        assert_eq!(fragments_to_schema(&fragments).unwrap(), expected);
+
+        let options = MergeOptions { normalize_case: true, ..MergeOptions::default() };
+        assert_eq!(fragments_to_schema_with_options(&fragments, &options).unwrap(), expected);
+
+        // the schema name is written escaped, even if it wasn't
+        // escaped the same way in the fragment
+        let f1 = r#"<Schema name='Sales "EU" &#38; &lt;UK&gt;'></Schema>"#.to_owned();
         assert_eq!(
-            fragments_to_schema(&fragments).unwrap(),
-            "<Schema name=\"testname\">\n<SharedDimension name=\"shareddim\"></SharedDimension><SharedDimension name=\"shareddim2\"></SharedDimension><Cube name=\"testcube\"><Dimension name=\"inner\"></Dimension></Cube><Cube name=\"a\"></Cube><Cube name=\"cube2\"><Dimension name=\"inner2\"></Dimension></Cube><Cube name=\"b\"></Cube>\n</Schema>"
+            fragments_to_schema(&[f1]).unwrap(),
+            "<Schema name=\"Sales &quot;EU&quot; &amp; &lt;UK&gt;\">\n</Schema>"
         );
+    }
 
-        let f1 = r#"<Schema name="testname"><Dimension name="shareddim"></Dimension><Cube name="testcube"><Dimension name="inner"></Dimension></Cube><Cube name="a"></Cube></Schema>"#.to_owned();
-        let f2 = r#"<Dimension name="shareddim2"></Dimension><Cube name="cube2"><Dimension name="inner2"></Dimension></Cube><Cube name="b"></Cube>"#.to_owned();
-        let fragments = vec![f1, f2];
+    #[test]
+    fn test_fragments_to_schema_escaped_names() {
+        // the same name, escaped differently
+        let f1 = r#"<Schema name="Sales &quot;EU&quot;"><Cube name="a"></Cube></Schema>"#.to_owned();
+        let f2 = r#"<Schema name='Sales "EU"'><Cube name="b"></Cube></Schema>"#.to_owned();
         assert_eq!(
-            fragments_to_schema(&fragments).unwrap(),
-            "<Schema name=\"testname\">\n<Dimension name=\"shareddim\"></Dimension><Dimension name=\"shareddim2\"></Dimension><Cube name=\"testcube\"><Dimension name=\"inner\"></Dimension></Cube><Cube name=\"a\"></Cube><Cube name=\"cube2\"><Dimension name=\"inner2\"></Dimension></Cube><Cube name=\"b\"></Cube>\n</Schema>"
+            fragments_to_schema(&[f1, f2]).unwrap(),
+            "<Schema name=\"Sales &quot;EU&quot;\">\n<Cube name=\"a\"></Cube>\n<Cube name=\"b\"></Cube>\n</Schema>"
         );
     }
 }
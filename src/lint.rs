@@ -0,0 +1,145 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Lint rules: every kind of problem found while merging has a
+// rule id, and a level saying whether it's let through, warned
+// about or an error. Levels can be set per rule (see
+// `MergeOptions::lints`); otherwise each rule has its default.
+//
+// Findings are all collected before anything fails, so one run
+// reports every problem in the fragments.
+
+use std::fmt;
+use std::str::FromStr;
+
+use error::*;
+
+/// What's done about a finding of a rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    /// Leave it out of the report
+    Allow,
+    /// Report it as a warning
+    Warn,
+    /// Report it as an error, failing the merge
+    Deny,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Level::Allow => "allow",
+            Level::Warn => "warn",
+            Level::Deny => "deny",
+        })
+    }
+}
+
+impl FromStr for Level {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Level> {
+        match s {
+            "allow" => Ok(Level::Allow),
+            "warn" => Ok(Level::Warn),
+            "deny" => Ok(Level::Deny),
+            _ => Err(format!("Unknown lint level {}, expected allow, warn or deny", s).into()),
+        }
+    }
+}
+
+/// A kind of problem, and its level unless set otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rule {
+    pub id: &'static str,
+    pub level: Level,
+    pub description: &'static str,
+}
+
+/// Every rule, in the order they're checked.
+pub const RULES: &[Rule] = &[
+    Rule { id: "unknown-element", level: Level::Warn, description: "an element which isn't Mondrian's was left out" },
+    Rule { id: "not-merged", level: Level::Warn, description: "a Mondrian element which isn't merged was left out" },
+    Rule { id: "stray-text", level: Level::Warn, description: "text outside of any element was left out" },
+    Rule { id: "duplicate-name", level: Level::Warn, description: "a shared dimension or cube is defined more than once" },
+    Rule { id: "duplicate-conflict", level: Level::Deny, description: "a shared dimension or cube is defined more than once, differently" },
+    Rule { id: "identical-copy", level: Level::Warn, description: "a cube or virtual cube is given again byte for byte, and left out, while duplicates are warnings" },
+    Rule { id: "overridden", level: Level::Warn, description: "a later fragment's shared dimension or cube replaces an earlier one, as an overlay" },
+    Rule { id: "schema-attribute-conflict", level: Level::Deny, description: "fragments give an attribute of the Schema tag different values" },
+    Rule { id: "unknown-default-role", level: Level::Deny, description: "the schema's defaultRole isn't one of its roles" },
+    Rule { id: "annotation-conflict", level: Level::Deny, description: "fragments give an annotation of the schema or of a cube different values" },
+    Rule { id: "invalid-patch", level: Level::Deny, description: "a patch names no element, or changes what it can't" },
+    Rule { id: "unknown-patch-target", level: Level::Warn, description: "the element a patch is for isn't in the schema" },
+    Rule { id: "unknown-dimension", level: Level::Warn, description: "a DimensionUsage's or VirtualCubeDimension's shared dimension isn't in the schema" },
+    Rule { id: "unused-dimension", level: Level::Warn, description: "a shared dimension isn't used by any cube" },
+    Rule { id: "unknown-cube", level: Level::Warn, description: "a virtual cube's base cube isn't in the schema" },
+    Rule { id: "unknown-cube-dimension", level: Level::Warn, description: "a virtual cube's dimension isn't in its base cube" },
+    Rule { id: "unknown-measure", level: Level::Warn, description: "a virtual cube's measure isn't in its base cube" },
+    Rule { id: "invalid-aggregator", level: Level::Warn, description: "a measure's aggregator is missing or unknown" },
+    Rule { id: "measure-without-column", level: Level::Warn, description: "a measure has no column or MeasureExpression" },
+    Rule { id: "unknown-key-table", level: Level::Warn, description: "a hierarchy's primaryKeyTable isn't a table it joins" },
+    Rule { id: "missing-primary-key", level: Level::Warn, description: "a hierarchy joined with a foreignKey has no primaryKey" },
+    Rule { id: "cube-without-measures", level: Level::Warn, description: "a cube has no measures" },
+    Rule { id: "duplicate-measure", level: Level::Warn, description: "a cube has two measures or calculated members with the same name" },
+    Rule { id: "level-without-column", level: Level::Warn, description: "a level has no column or KeyExpression" },
+    Rule { id: "invalid-level-attribute", level: Level::Warn, description: "a level's levelType, type or uniqueMembers isn't one Mondrian takes" },
+    Rule { id: "missing-foreign-key", level: Level::Warn, description: "a cube's dimension joins a table, but has no foreignKey" },
+    Rule { id: "invalid-formula", level: Level::Warn, description: "a calculated member's or named set's formula is empty or unbalanced" },
+    Rule { id: "unknown-default-measure", level: Level::Deny, description: "a cube's defaultMeasure isn't one of its measures" },
+    Rule { id: "invalid-name", level: Level::Warn, description: "a name has brackets, whitespace at either end or control characters" },
+    Rule { id: "long-name", level: Level::Warn, description: "a name is longer than 100 characters" },
+    Rule { id: "unknown-grant-target", level: Level::Warn, description: "a role grants access to a cube, dimension or hierarchy which isn't in the schema" },
+    Rule { id: "unknown-role", level: Level::Warn, description: "a union role is made of a role which isn't defined" },
+    Rule { id: "circular-reference", level: Level::Deny, description: "a virtual cube or union role refers back to itself through others" },
+    Rule { id: "unknown-named-set", level: Level::Warn, description: "a formula refers to a named set which isn't in the schema" },
+    Rule { id: "duplicate-named-set", level: Level::Warn, description: "two fragments define a named set with the same name in a cube" },
+    Rule { id: "incomplete-aggregate", level: Level::Warn, description: "an aggregate table has no AggFactCount or no AggMeasure" },
+    Rule { id: "unknown-aggregate-target", level: Level::Warn, description: "an aggregate table's measure or level isn't in its cube" },
+    Rule { id: "unknown-writeback-target", level: Level::Warn, description: "a writeback table's dimension or measure isn't in its cube" },
+    Rule { id: "invalid-attribute-value", level: Level::Warn, description: "a boolean attribute isn't true or false, or a numeric one isn't a whole number" },
+    Rule { id: "deprecated-high-cardinality", level: Level::Warn, description: "a dimension has highCardinality, which Mondrian has deprecated" },
+    Rule { id: "deprecated-member-reader-class", level: Level::Warn, description: "a hierarchy has memberReaderClass, which Mondrian has deprecated" },
+    Rule { id: "deprecated-level-formatter", level: Level::Warn, description: "a level has formatter rather than a MemberFormatter" },
+    Rule { id: "deprecated-cell-formatter", level: Level::Warn, description: "a measure or calculated member has formatter rather than a CellFormatter" },
+    Rule { id: "deprecated-property-formatter", level: Level::Warn, description: "a property has formatter rather than a PropertyFormatter" },
+    Rule { id: "duplicate-hierarchy", level: Level::Warn, description: "a dimension has two hierarchies with the same name" },
+    Rule { id: "hierarchy-relation", level: Level::Warn, description: "a hierarchy has more than one relation, or a shared dimension's has none" },
+    Rule { id: "naming-convention", level: Level::Warn, description: "a name doesn't match the naming convention for its element" },
+    Rule { id: "invalid-structure", level: Level::Allow, description: "an element breaks the Mondrian 3 content model" },
+    Rule { id: "missing-caption", level: Level::Allow, description: "a cube, dimension, hierarchy, level or measure has no caption" },
+    Rule { id: "missing-description", level: Level::Allow, description: "a cube, dimension, hierarchy, level or measure has no description" },
+];
+
+/// The rule with id `id`.
+pub fn rule(id: &str) -> Option<&'static Rule> {
+    RULES.iter().find(|rule| rule.id == id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules() {
+        assert_eq!(rule("duplicate-name").unwrap().level, Level::Warn);
+        assert_eq!(rule("missing-caption").unwrap().level, Level::Allow);
+        assert!(rule("Duplicate-Name").is_none());
+
+        let mut ids: Vec<_> = RULES.iter().map(|rule| rule.id).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), RULES.len());
+    }
+
+    #[test]
+    fn test_level() {
+        assert_eq!("deny".parse::<Level>().unwrap(), Level::Deny);
+        assert_eq!(Level::Warn.to_string(), "warn");
+        assert!("error".parse::<Level>().is_err());
+    }
+}
@@ -0,0 +1,393 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Schema manifests: a toml file (usually `schema.toml`) listing
+// the fragments of a schema, in merge order, along with how to
+// merge them.
+//
+// ```toml
+// name = "Sales"
+// output = "build/schema.xml"
+// xml_declaration = true
+//
+// [[fragments]]
+// path = "schema.xml"
+//
+// [[fragments]]
+// path = "cubes/**/*.xml"
+//
+// [[fragments]]
+// path = "legacy/cube.xml"
+// encoding = "windows-1252"
+// optional = true
+// priority = 10
+// prefix = "legacy_"
+//
+// [lints]
+// cube-without-measures = "allow"
+// unknown-dimension = "deny"
+//
+// [naming]
+// Cube = "[A-Z][A-Za-z0-9 ]+"
+//
+// [exclude]
+// VirtualCube = "Legacy.*"
+//
+// [[virtual_cubes]]
+// name = "Sales and Stock"
+// cubes = ["Sales", "Stock"]
+// measures = ["Units", "Stock Level"]
+// dimensions = ["Store", "Time"]
+// ```
+//
+// Finding the files is left to the caller, since paths and
+// patterns are relative to wherever the manifest lives.
+
+use std::collections::BTreeMap;
+
+use toml;
+
+use error::*;
+use lint::{self, Level};
+use validate;
+use {
+    name_filters, ConflictStrategy, DefaultRolePolicy, DuplicatePolicy, MergeOptions, Metamodel, Namespace, ScanOptions,
+    SchemaAttributePolicy, SortOrder, VirtualCubeRecipe,
+};
+
+/// A parsed schema manifest.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Manifest {
+    /// Name of the schema to build. Only Schema blocks with this
+    /// name are merged, as with `MergeOptions::only_schema`.
+    pub name: Option<String>,
+    /// Name the merged schema this, instead of the name found in
+    /// the fragments, e.g. for a staging copy of the schema
+    pub schema_name: Option<String>,
+    /// Name the merged schema this if no fragment names it
+    pub default_schema_name: Option<String>,
+    /// Merge Schema blocks with different names into one, named
+    /// `schema_name`, which must be given
+    pub combine: bool,
+    /// Where to write the merged schema, relative to the manifest
+    pub output: Option<String>,
+    pub xml_declaration: bool,
+    pub ignore_case: bool,
+    pub normalize_case: bool,
+    pub keep_comments: bool,
+    /// Check the merged schema is well formed before writing it
+    pub verify_output: bool,
+    /// Namespace prefix of Mondrian elements, e.g. `m`
+    pub ns_prefix: Option<String>,
+    /// Namespace uri of Mondrian elements
+    pub ns_uri: Option<String>,
+    /// `error`, `warn` (the default) or `allow` duplicate names
+    pub duplicates: DuplicatePolicy,
+    /// Leave out duplicates identical to the first definition
+    pub dedupe_identical: bool,
+    /// `keep-all` (the default), `error`, `first-wins`,
+    /// `last-wins`, `rename-with-suffix` or `overlay`: how
+    /// elements with the same name are merged
+    pub conflicts: ConflictStrategy,
+    /// Merge cubes with the same name into one
+    pub merge_cubes: bool,
+    /// `auto` (the default), `"3"` or `"4"`: the version of
+    /// Mondrian the fragments are written for
+    pub metamodel: Metamodel,
+    /// `error` (the default), `first`, `last` or `drop` when the
+    /// fragments give different defaultRoles
+    pub default_roles: DefaultRolePolicy,
+    /// `error` (the default), `first`, `last` or `concat` when
+    /// the fragments give other Schema attributes different values
+    pub schema_attributes: SchemaAttributePolicy,
+    /// Annotations set on the merged schema, name to value
+    pub annotations: BTreeMap<String, String>,
+    /// Annotations set on every cube, name to value
+    pub cube_annotations: BTreeMap<String, String>,
+    /// `input` (the default), `name` or `dependency` order of
+    /// merged elements
+    pub sort: SortOrder,
+    /// Check the merged elements against the Mondrian 3 content
+    /// model; short for warning about `invalid-structure`
+    pub validate_structure: bool,
+    /// Warn about user visible elements without a caption
+    pub require_captions: bool,
+    /// Warn about user visible elements without a description
+    pub require_descriptions: bool,
+    /// Levels of lint rules, by rule id. These win over the
+    /// shorthands above.
+    pub lints: BTreeMap<String, Level>,
+    /// Naming conventions, as with `MergeOptions::naming`
+    pub naming: BTreeMap<String, String>,
+    /// Filters on what's merged, as with `MergeOptions::include`
+    pub include: BTreeMap<String, String>,
+    /// Filters on what's left out, as with
+    /// `MergeOptions::exclude`
+    pub exclude: BTreeMap<String, String>,
+    /// Virtual cubes to generate, as with
+    /// `MergeOptions::virtual_cubes`
+    pub virtual_cubes: Vec<VirtualCubeRecipe>,
+    /// The fragments, in the order they're merged
+    pub fragments: Vec<ManifestFragment>,
+}
+
+/// One entry of the fragment list of a manifest.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestFragment {
+    /// Path or glob pattern, relative to the manifest. Matches
+    /// of a pattern are merged sorted by path.
+    pub path: String,
+    /// It's not an error if nothing is found at `path`
+    #[serde(default)]
+    pub optional: bool,
+    /// Decode the fragment with this encoding, instead of
+    /// detecting it
+    #[serde(default)]
+    pub encoding: Option<String>,
+    /// Where the fragment goes in the merge order, highest
+    /// first, instead of any priority it declares itself (see
+    /// `directives`)
+    #[serde(default)]
+    pub priority: Option<i64>,
+    /// Put this before the names of the shared dimensions,
+    /// cubes and virtual cubes the fragment defines, and the
+    /// references to them (see `prefix`)
+    #[serde(default)]
+    pub prefix: Option<String>,
+}
+
+impl Manifest {
+    /// Parse a manifest from toml.
+    pub fn from_toml(s: &str) -> Result<Manifest> {
+        let manifest: Manifest = toml::from_str(s)
+            .map_err(|err| ErrorKind::InvalidManifest(err.to_string()))?;
+
+        let invalid = |message: &str| Err(ErrorKind::InvalidManifest(message.to_owned()).into());
+        if manifest.fragments.is_empty() {
+            return invalid("no fragments listed");
+        }
+        if manifest.ns_prefix.is_some() && manifest.ns_uri.is_some() {
+            return invalid("only one of ns_prefix and ns_uri can be given");
+        }
+        if manifest.normalize_case && !manifest.ignore_case {
+            return invalid("normalize_case needs ignore_case");
+        }
+        if manifest.combine && manifest.schema_name.is_none() {
+            return invalid("combine needs schema_name");
+        }
+        if let Some(rule) = manifest.lints.keys().find(|rule| lint::rule(rule).is_none()) {
+            return invalid(&format!("unknown lint rule {}", rule));
+        }
+        if let Err(err) = validate::naming_conventions(&manifest.naming) {
+            return invalid(&err.to_string());
+        }
+        for filters in &[&manifest.include, &manifest.exclude] {
+            if let Err(err) = name_filters(filters) {
+                return invalid(&err.to_string());
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// The options to merge the manifest's fragments with.
+    pub fn merge_options(&self) -> MergeOptions {
+        let namespace = self.ns_prefix.clone()
+            .map(Namespace::Prefix)
+            .or_else(|| self.ns_uri.clone().map(Namespace::Uri));
+
+        let mut lints = BTreeMap::new();
+        let shorthands = [
+            (self.validate_structure, "invalid-structure"),
+            (self.require_captions, "missing-caption"),
+            (self.require_descriptions, "missing-description"),
+        ];
+        for &(set, rule) in &shorthands {
+            if set {
+                lints.insert(rule.to_owned(), Level::Warn);
+            }
+        }
+        lints.extend(self.lints.iter().map(|(rule, &level)| (rule.clone(), level)));
+
+        MergeOptions {
+            xml_declaration: self.xml_declaration,
+            scan: ScanOptions {
+                ignore_case: self.ignore_case,
+                namespace,
+                keep_comments: self.keep_comments,
+            },
+            normalize_case: self.normalize_case,
+            only_schema: self.name.clone(),
+            schema_name: self.schema_name.clone(),
+            default_schema_name: self.default_schema_name.clone(),
+            combine: self.combine,
+            duplicates: self.duplicates,
+            dedupe_identical: self.dedupe_identical,
+            conflicts: self.conflicts,
+            merge_cubes: self.merge_cubes,
+            metamodel: self.metamodel,
+            default_roles: self.default_roles,
+            schema_attributes: self.schema_attributes,
+            annotations: self.annotations.clone(),
+            cube_annotations: self.cube_annotations.clone(),
+            sort: self.sort,
+            verify_output: self.verify_output,
+            lints,
+            naming: self.naming.clone(),
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+            virtual_cubes: self.virtual_cubes.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_toml() {
+        let manifest = Manifest::from_toml(r#"
+            name = "Sales"
+            schema_name = "Sales_Staging"
+            default_schema_name = "Sales"
+            combine = true
+            output = "build/schema.xml"
+            ignore_case = true
+            ns_uri = "http://mondrian"
+            duplicates = "error"
+            dedupe_identical = true
+            conflicts = "last-wins"
+            merge_cubes = true
+            default_roles = "last"
+            schema_attributes = "concat"
+            metamodel = "4"
+            sort = "name"
+            verify_output = true
+            validate_structure = true
+            require_captions = true
+
+            [lints]
+            missing-caption = "deny"
+            cube-without-measures = "allow"
+
+            [naming]
+            Cube = "[A-Z].*"
+
+            [include]
+            Cube = "Sales.*"
+
+            [exclude]
+            VirtualCube = "Legacy"
+
+            [annotations]
+            environment = "staging"
+
+            [cube_annotations]
+            data_version = "42"
+
+            [[virtual_cubes]]
+            name = "Sales and Stock"
+            cubes = ["Sales", "Stock"]
+            measures = ["Units"]
+
+            [[fragments]]
+            path = "schema.xml"
+
+            [[fragments]]
+            path = "cubes/*.xml"
+            optional = true
+            encoding = "windows-1252"
+            priority = -1
+            prefix = "legacy_"
+        "#).unwrap();
+
+        assert_eq!(manifest.name, Some("Sales".to_owned()));
+        assert_eq!(manifest.output, Some("build/schema.xml".to_owned()));
+        assert_eq!(
+            manifest.fragments,
+            vec![
+                ManifestFragment { path: "schema.xml".to_owned(), optional: false, encoding: None, priority: None, prefix: None },
+                ManifestFragment {
+                    path: "cubes/*.xml".to_owned(),
+                    optional: true,
+                    encoding: Some("windows-1252".to_owned()),
+                    priority: Some(-1),
+                    prefix: Some("legacy_".to_owned()),
+                },
+            ]
+        );
+
+        let options = manifest.merge_options();
+        assert!(options.scan.ignore_case);
+        assert!(!options.xml_declaration);
+        assert_eq!(options.only_schema, Some("Sales".to_owned()));
+        assert_eq!(options.schema_name, Some("Sales_Staging".to_owned()));
+        assert_eq!(options.default_schema_name, Some("Sales".to_owned()));
+        assert!(options.combine);
+        assert_eq!(options.duplicates, DuplicatePolicy::Error);
+        assert!(options.dedupe_identical);
+        assert_eq!(options.conflicts, ConflictStrategy::LastWins);
+        assert!(options.merge_cubes);
+        assert_eq!(options.default_roles, DefaultRolePolicy::Last);
+        assert_eq!(options.schema_attributes, SchemaAttributePolicy::Concat);
+        assert_eq!(options.metamodel, Metamodel::Mondrian4);
+        assert_eq!(options.sort, SortOrder::Name);
+        assert!(options.verify_output);
+        assert_eq!(options.level("invalid-structure"), Level::Warn);
+        assert_eq!(options.level("missing-caption"), Level::Deny);
+        assert_eq!(options.level("missing-description"), Level::Allow);
+        assert_eq!(options.level("cube-without-measures"), Level::Allow);
+        assert_eq!(options.naming.get("Cube").map(String::as_str), Some("[A-Z].*"));
+        assert_eq!(options.include.get("Cube").map(String::as_str), Some("Sales.*"));
+        assert_eq!(options.exclude.get("VirtualCube").map(String::as_str), Some("Legacy"));
+        assert_eq!(options.annotations.get("environment").map(String::as_str), Some("staging"));
+        assert_eq!(options.cube_annotations.get("data_version").map(String::as_str), Some("42"));
+        assert_eq!(
+            options.virtual_cubes,
+            vec![VirtualCubeRecipe {
+                name: "Sales and Stock".to_owned(),
+                cubes: vec!["Sales".to_owned(), "Stock".to_owned()],
+                measures: vec!["Units".to_owned()],
+                dimensions: Vec::new(),
+            }]
+        );
+        match options.scan.namespace {
+            Some(Namespace::Uri(ref uri)) => assert_eq!(uri, "http://mondrian"),
+            ref other => panic!("unexpected namespace {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_from_toml_invalid() {
+        match *Manifest::from_toml("").unwrap_err().kind() {
+            ErrorKind::InvalidManifest(ref message) => assert_eq!(message, "no fragments listed"),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        assert!(Manifest::from_toml("name = 'a'\n[[fragments]]\npth = 'a.xml'").is_err());
+        assert!(Manifest::from_toml("colour = 'red'\n[[fragments]]\npath = 'a.xml'").is_err());
+        assert!(Manifest::from_toml("ns_prefix = 'm'\nns_uri = 'u'\n[[fragments]]\npath = 'a.xml'").is_err());
+        assert!(Manifest::from_toml("normalize_case = true\n[[fragments]]\npath = 'a.xml'").is_err());
+        match *Manifest::from_toml("combine = true\n[[fragments]]\npath = 'a.xml'").unwrap_err().kind() {
+            ErrorKind::InvalidManifest(ref message) => assert_eq!(message, "combine needs schema_name"),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        assert!(Manifest::from_toml("duplicates = 'maybe'\n[[fragments]]\npath = 'a.xml'").is_err());
+        assert!(Manifest::from_toml("[lints]\nstray-text = 'error'\n[[fragments]]\npath = 'a.xml'").is_err());
+        match *Manifest::from_toml("[lints]\nstray_text = 'deny'\n[[fragments]]\npath = 'a.xml'").unwrap_err().kind() {
+            ErrorKind::InvalidManifest(ref message) => assert_eq!(message, "unknown lint rule stray_text"),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+        assert!(Manifest::from_toml("[naming]
+Cube = '[A-Z'
+[[fragments]]
+path = 'a.xml'").is_err());
+        assert!(Manifest::from_toml("[exclude]\nCube = '(('\n[[fragments]]\npath = 'a.xml'").is_err());
+        assert!(Manifest::from_toml("[[virtual_cubes]]\nname = 'v'\ncube = ['a']\n[[fragments]]\npath = 'a.xml'").is_err());
+    }
+}
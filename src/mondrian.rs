@@ -0,0 +1,230 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Knowledge about the Mondrian 3 schema vocabulary.
+
+/// Every element name in the Mondrian 3 schema, in its
+/// canonical casing.
+pub const ELEMENTS: &[&str] = &[
+    "AggExclude",
+    "AggFactCount",
+    "AggForeignKey",
+    "AggIgnoreColumn",
+    "AggLevel",
+    "AggLevelProperty",
+    "AggMeasure",
+    "AggMeasureFactCount",
+    "AggName",
+    "AggPattern",
+    "Annotation",
+    "Annotations",
+    "CalculatedMember",
+    "CalculatedMemberProperty",
+    "CaptionExpression",
+    "CellFormatter",
+    "Closure",
+    "ColumnDef",
+    "ColumnDefs",
+    "Cube",
+    "CubeGrant",
+    "CubeUsage",
+    "CubeUsages",
+    "Dimension",
+    "DimensionGrant",
+    "DimensionUsage",
+    "Formula",
+    "Hierarchy",
+    "Hint",
+    "HierarchyGrant",
+    "InlineTable",
+    "Join",
+    "KeyExpression",
+    "Level",
+    "Measure",
+    "MeasureExpression",
+    "MemberFormatter",
+    "MemberGrant",
+    "MemberReaderParameter",
+    "NameExpression",
+    "NamedSet",
+    "OrdinalExpression",
+    "Parameter",
+    "ParentExpression",
+    "Property",
+    "PropertyFormatter",
+    "Role",
+    "RoleUsage",
+    "Row",
+    "Rows",
+    "SQL",
+    "Schema",
+    "SchemaGrant",
+    "Script",
+    "SharedDimension",
+    "Table",
+    "Union",
+    "UserDefinedFunction",
+    "Value",
+    "View",
+    "VirtualCube",
+    "VirtualCubeDimension",
+    "VirtualCubeMeasure",
+    "WritebackAttribute",
+    "WritebackMeasure",
+    "WritebackTable",
+];
+
+/// What an element may hold, and the attributes it must have.
+pub struct Structure {
+    pub name: &'static str,
+    pub children: &'static [&'static str],
+    pub required: &'static [&'static str],
+}
+
+const RELATIONS: &[&str] = &["Table", "View", "Join", "InlineTable"];
+const EXPRESSION: &[&str] = &["SQL"];
+const AGG_TABLE: &[&str] = &[
+    "AggFactCount", "AggIgnoreColumn", "AggForeignKey", "AggMeasure", "AggLevel", "AggMeasureFactCount", "AggExclude",
+];
+
+/// The content model of the Mondrian 3 schema, as in its XSD:
+/// which elements each element may hold, and which attributes
+/// it needs. Order and counts aren't modelled. Elements which
+/// only hold text have no children.
+///
+/// SharedDimension isn't Mondrian 3, but is accepted for shared
+/// dimensions along with Dimension.
+pub const STRUCTURE: &[Structure] = &[
+    Structure { name: "Schema", required: &["name"], children: &[
+        "Annotations", "Parameter", "Dimension", "SharedDimension", "Cube", "VirtualCube", "NamedSet", "Role",
+        "UserDefinedFunction",
+    ] },
+    Structure { name: "Cube", required: &["name"], children: &[
+        "Annotations", "Table", "View", "InlineTable", "Dimension", "DimensionUsage", "Measure", "CalculatedMember",
+        "NamedSet", "WritebackTable",
+    ] },
+    Structure { name: "VirtualCube", required: &["name"], children: &[
+        "Annotations", "CubeUsages", "VirtualCubeDimension", "VirtualCubeMeasure", "CalculatedMember", "NamedSet",
+    ] },
+    Structure { name: "CubeUsages", required: &[], children: &["CubeUsage"] },
+    Structure { name: "CubeUsage", required: &["cubeName"], children: &[] },
+    Structure { name: "VirtualCubeDimension", required: &["name"], children: &["Annotations"] },
+    Structure { name: "VirtualCubeMeasure", required: &["cubeName", "name"], children: &["Annotations"] },
+    Structure { name: "DimensionUsage", required: &["name", "source"], children: &["Annotations"] },
+    Structure { name: "Dimension", required: &["name"], children: &["Annotations", "Hierarchy"] },
+    Structure { name: "SharedDimension", required: &["name"], children: &["Annotations", "Hierarchy"] },
+    Structure { name: "Hierarchy", required: &[], children: &[
+        "Annotations", "Table", "View", "Join", "InlineTable", "Level", "MemberReaderParameter",
+    ] },
+    Structure { name: "MemberReaderParameter", required: &["name", "value"], children: &[] },
+    Structure { name: "Level", required: &[], children: &[
+        "Annotations", "KeyExpression", "NameExpression", "CaptionExpression", "OrdinalExpression",
+        "ParentExpression", "Closure", "Property", "MemberFormatter",
+    ] },
+    Structure { name: "Closure", required: &["parentColumn", "childColumn"], children: &["Table"] },
+    Structure { name: "Property", required: &["name", "column"], children: &["PropertyFormatter"] },
+    Structure { name: "Measure", required: &["name", "aggregator"], children: &[
+        "Annotations", "MeasureExpression", "CalculatedMemberProperty", "CellFormatter",
+    ] },
+    Structure { name: "CalculatedMember", required: &["name"], children: &[
+        "Annotations", "Formula", "CalculatedMemberProperty", "CellFormatter",
+    ] },
+    Structure { name: "CalculatedMemberProperty", required: &["name"], children: &[] },
+    Structure { name: "NamedSet", required: &["name"], children: &["Annotations", "Formula"] },
+    Structure { name: "Formula", required: &[], children: &[] },
+    Structure { name: "Table", required: &["name"], children: &["SQL", "AggExclude", "AggName", "AggPattern", "Hint"] },
+    Structure { name: "Hint", required: &["type"], children: &[] },
+    Structure { name: "View", required: &["alias"], children: EXPRESSION },
+    Structure { name: "SQL", required: &["dialect"], children: &[] },
+    Structure { name: "Join", required: &[], children: RELATIONS },
+    Structure { name: "InlineTable", required: &[], children: &["ColumnDefs", "Rows"] },
+    Structure { name: "ColumnDefs", required: &[], children: &["ColumnDef"] },
+    Structure { name: "ColumnDef", required: &["name", "type"], children: &[] },
+    Structure { name: "Rows", required: &[], children: &["Row"] },
+    Structure { name: "Row", required: &[], children: &["Value"] },
+    Structure { name: "Value", required: &["column"], children: &[] },
+    Structure { name: "KeyExpression", required: &[], children: EXPRESSION },
+    Structure { name: "NameExpression", required: &[], children: EXPRESSION },
+    Structure { name: "CaptionExpression", required: &[], children: EXPRESSION },
+    Structure { name: "OrdinalExpression", required: &[], children: EXPRESSION },
+    Structure { name: "ParentExpression", required: &[], children: EXPRESSION },
+    Structure { name: "MeasureExpression", required: &[], children: EXPRESSION },
+    Structure { name: "Role", required: &["name"], children: &["Annotations", "SchemaGrant", "Union"] },
+    Structure { name: "SchemaGrant", required: &["access"], children: &["CubeGrant"] },
+    Structure { name: "CubeGrant", required: &["cube", "access"], children: &["DimensionGrant", "HierarchyGrant"] },
+    Structure { name: "DimensionGrant", required: &["dimension", "access"], children: &[] },
+    Structure { name: "HierarchyGrant", required: &["hierarchy", "access"], children: &["MemberGrant"] },
+    Structure { name: "MemberGrant", required: &["member", "access"], children: &[] },
+    Structure { name: "Union", required: &[], children: &["RoleUsage"] },
+    Structure { name: "RoleUsage", required: &["roleName"], children: &[] },
+    Structure { name: "UserDefinedFunction", required: &["name"], children: &["Script"] },
+    Structure { name: "Parameter", required: &["name"], children: &[] },
+    Structure { name: "Annotations", required: &[], children: &["Annotation"] },
+    Structure { name: "Annotation", required: &["name"], children: &[] },
+    Structure { name: "AggName", required: &["name"], children: AGG_TABLE },
+    Structure { name: "AggPattern", required: &["pattern"], children: AGG_TABLE },
+    Structure { name: "AggExclude", required: &[], children: &[] },
+    Structure { name: "AggFactCount", required: &["column"], children: &[] },
+    Structure { name: "AggIgnoreColumn", required: &["column"], children: &[] },
+    Structure { name: "AggForeignKey", required: &["factColumn", "aggColumn"], children: &[] },
+    Structure { name: "AggMeasure", required: &["column", "name"], children: &[] },
+    Structure { name: "AggLevel", required: &["column", "name"], children: &["AggLevelProperty"] },
+    Structure { name: "AggLevelProperty", required: &["name", "column"], children: &[] },
+    Structure { name: "AggMeasureFactCount", required: &["column", "factColumn"], children: &[] },
+    Structure { name: "CellFormatter", required: &[], children: &["Script"] },
+    Structure { name: "MemberFormatter", required: &[], children: &["Script"] },
+    Structure { name: "PropertyFormatter", required: &[], children: &["Script"] },
+    Structure { name: "Script", required: &[], children: &[] },
+    Structure { name: "WritebackTable", required: &["name"], children: &["WritebackAttribute", "WritebackMeasure"] },
+    Structure { name: "WritebackAttribute", required: &["dimension", "column"], children: &[] },
+    Structure { name: "WritebackMeasure", required: &["name", "column"], children: &[] },
+];
+
+/// The content model of an element, matched ignoring ascii
+/// case if `ignore_case`.
+pub fn structure(name: &str, ignore_case: bool) -> Option<&'static Structure> {
+    STRUCTURE.iter().find(|s| if ignore_case { s.name.eq_ignore_ascii_case(name) } else { s.name == name })
+}
+
+/// The canonical casing of a Mondrian element name, matched
+/// ignoring ascii case.
+pub fn canonical_element(name: &str) -> Option<&'static str> {
+    ELEMENTS.iter()
+        .find(|e| e.eq_ignore_ascii_case(name))
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_element() {
+        assert_eq!(canonical_element("Cube"), Some("Cube"));
+        assert_eq!(canonical_element("cube"), Some("Cube"));
+        assert_eq!(canonical_element("DIMENSIONUSAGE"), Some("DimensionUsage"));
+        assert_eq!(canonical_element("sql"), Some("SQL"));
+        assert_eq!(canonical_element("Cubee"), None);
+    }
+
+    #[test]
+    fn test_structure() {
+        // every element is modelled, and only holds elements
+        for name in ELEMENTS {
+            assert!(structure(name, false).is_some(), "{} isn't modelled", name);
+        }
+        for s in STRUCTURE {
+            assert_eq!(canonical_element(s.name), Some(s.name));
+            for child in s.children {
+                assert_eq!(canonical_element(child), Some(*child), "{} holds unknown {}", s.name, child);
+            }
+        }
+
+        assert_eq!(structure("cube", true).unwrap().name, "Cube");
+        assert!(structure("cube", false).is_none());
+    }
+}
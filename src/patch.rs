@@ -0,0 +1,265 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Patches: changes to elements other fragments define, so an
+// environment's overlay can tweak a cube without a copy of it:
+//
+// ```xml
+// <msc:patch cube="Sales">
+//   <set attribute="defaultMeasure" value="Amount"/>
+//   <remove attribute="cache"/>
+// </msc:patch>
+// ```
+//
+// A patch names a cube, virtual cube or shared dimension (with
+// `cube`, `virtualCube` or `dimension`), and its changes are
+// made to that element's start tag once the fragments are
+// merged, patch by patch in the order of the fragments. Names
+// can't be changed, since what refers to the element by its
+// name would then refer to nothing.
+
+use escape;
+use scan::{self, ScanOptions};
+use tokenizer::{self, TokenKind, Tokenizer};
+use tree;
+use validate::Source;
+use {ElementKind, Warning};
+
+pub const PATCH_TAG: &str = "msc:patch";
+
+/// The attributes naming what a patch is for
+const TARGETS: &[(&str, ElementKind, &str)] = &[
+    ("cube", ElementKind::Cube, "cube"),
+    ("virtualCube", ElementKind::VirtualCube, "virtual cube"),
+    ("dimension", ElementKind::SharedDimension, "shared dimension"),
+];
+
+/// One change to the start tag of an element.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// Set `attribute` to `value`, unescaped, adding it if the
+    /// element doesn't have it
+    Set { attribute: String, value: String },
+    /// Take `attribute` away, if the element has it
+    Remove { attribute: String },
+}
+
+/// A patch of one element, read from a fragment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Patch<'a> {
+    /// The kind of element patched
+    pub kind: ElementKind,
+    /// Name of the element patched, unescaped
+    pub name: String,
+    pub changes: Vec<Change>,
+    fragment: usize,
+    input: &'a str,
+    text: &'a str,
+}
+
+impl<'a> Patch<'a> {
+    /// Read `text`, a `<msc:patch>` of fragment `fragment`, and a
+    /// slice of `input`. One which can't be applied is `None`,
+    /// and an `invalid-patch` in `warnings`.
+    pub fn parse(
+        fragment: usize,
+        input: &'a str,
+        text: &'a str,
+        options: &ScanOptions,
+        warnings: &mut Vec<Warning>,
+    ) -> Option<Patch<'a>>
+    {
+        let mut patch = Patch { kind: ElementKind::Cube, name: String::new(), changes: Vec::new(), fragment, input, text };
+        let root = match tree::parse(text).ok().and_then(|roots| roots.into_iter().next()) {
+            Some(root) => root,
+            None => {
+                warnings.push(patch.invalid(0, text.len(), "The patch isn't well formed".to_owned()));
+                return None;
+            },
+        };
+        let targets: Vec<_> = TARGETS.iter().filter_map(|&(attr, kind, _)| Some((kind, root.attr(attr)?))).collect();
+        match targets.as_slice() {
+            [(kind, name)] => {
+                patch.kind = *kind;
+                patch.name = name.clone().into_owned();
+            },
+            _ => {
+                warnings.push(patch.invalid(0, text.len(), "A patch names one cube, virtual cube or shared dimension".to_owned()));
+                return None;
+            },
+        }
+
+        for child in &root.children {
+            let attribute = child.attr("attribute").map(|attribute| attribute.into_owned());
+            let change = match attribute {
+                Some(ref attribute) if attribute == "name" => Err("A patch can't change a name"),
+                Some(attribute) if scan::names_match(child.name, "set", options) => match child.attr("value") {
+                    Some(value) => Ok(Change::Set { attribute, value: value.into_owned() }),
+                    None => Err("`set` needs an attribute and a value"),
+                },
+                Some(attribute) if scan::names_match(child.name, "remove", options) => Ok(Change::Remove { attribute }),
+                None if scan::names_match(child.name, "set", options) => Err("`set` needs an attribute and a value"),
+                None if scan::names_match(child.name, "remove", options) => Err("`remove` needs an attribute"),
+                _ => Err("A patch can only `set` or `remove` attributes"),
+            };
+            match change {
+                Ok(change) => patch.changes.push(change),
+                Err(message) => {
+                    warnings.push(patch.invalid(child.start, child.text.len(), message.to_owned()));
+                    return None;
+                },
+            }
+        }
+        Some(patch)
+    }
+
+    /// `text`, the element patched, whose pieces came from
+    /// `sources`, with the changes made, and where the pieces
+    /// of that came from
+    pub fn apply<'s>(&self, text: &str, sources: &[Source<'s>]) -> (String, Vec<Source<'s>>) {
+        let mut text = text.to_owned();
+        let mut sources = sources.to_vec();
+        for change in &self.changes {
+            let tag = match Tokenizer::new(&text)
+                .filter_map(|token| token.ok())
+                .find(|token| matches!(token.kind, TokenKind::StartTag { .. }))
+            {
+                Some(tag) => tag,
+                None => break,
+            };
+            let (start, end) = (tag.start, tag.start + tag.text.len());
+            let attributes = scan::attributes(tag.text);
+            let found = |attribute: &str| attributes.iter().find(|&&(name, _)| name == attribute).cloned();
+            let edit = match *change {
+                Change::Set { ref attribute, ref value } => match found(attribute) {
+                    Some((_, raw)) => {
+                        let value_start = start + scan::offset_in(tag.text, raw);
+                        Some((value_start, value_start + raw.len(), escape::escape(value).into_owned()))
+                    },
+                    None => {
+                        let close = if tag.text.ends_with("/>") { 2 } else { 1 };
+                        let at = start + tag.text[..tag.text.len() - close].trim_end().len();
+                        Some((at, at, format!(" {}=\"{}\"", attribute, escape::escape(value))))
+                    },
+                },
+                Change::Remove { ref attribute } => found(attribute).map(|(name, raw)| {
+                    let name_start = start + scan::offset_in(tag.text, name);
+                    // past the closing quote
+                    let value_end = start + scan::offset_in(tag.text, raw) + raw.len() + 1;
+                    (text[..name_start].trim_end().len(), value_end.min(end), String::new())
+                }),
+            };
+            if let Some((edit_start, edit_end, replacement)) = edit {
+                sources = moved(&sources, edit_start, edit_end, replacement.len());
+                text.replace_range(edit_start..edit_end, &replacement);
+            }
+        }
+        (text, sources)
+    }
+
+    /// The `unknown-patch-target` warning for a patch of an
+    /// element not in the merged schema
+    pub fn unknown_target(&self) -> Warning {
+        let what = TARGETS.iter().find(|&&(_, kind, _)| kind == self.kind).map_or("element", |&(_, _, what)| what);
+        let message = format!("The patch of {} \"{}\" applies to nothing, as there's no such {}", what, self.name, what);
+        Warning { rule: "unknown-patch-target", ..self.invalid(0, self.text.len(), message) }
+    }
+
+    /// An `invalid-patch` warning about the `len` bytes at
+    /// `pos` in the patch
+    fn invalid(&self, pos: usize, len: usize, message: String) -> Warning {
+        let start = scan::offset_in(self.input, self.text) + pos;
+        let (line, column) = tokenizer::line_col(self.input, start);
+        Warning {
+            fragment: self.fragment,
+            line,
+            column,
+            rule: "invalid-patch",
+            element: self.label(),
+            path: self.label().into_iter().collect(),
+            span: start..start + len,
+            message,
+            first: None,
+        }
+    }
+
+    /// The patch's tag and what it names, like `msc:patch
+    /// cube="Sales"`
+    fn label(&self) -> Option<String> {
+        tree::parse(self.text).ok()?.into_iter().next().map(|root| match root.attributes.first() {
+            Some(&(attr, value)) => format!("{} {}=\"{}\"", root.name, attr, value),
+            None => root.name.to_owned(),
+        })
+    }
+}
+
+/// `sources` of a text in which `start..end` is replaced by
+/// `len` bytes
+fn moved<'s>(sources: &[Source<'s>], start: usize, end: usize, len: usize) -> Vec<Source<'s>> {
+    // what comes after the replacement is where it was before
+    let covering = sources.iter().rev().find(|source| source.start <= end).unwrap_or(&sources[0]);
+    let after = Source { start: start + len, offset: covering.offset + end - covering.start, ..covering.clone() };
+    let mut res: Vec<_> = sources.iter().filter(|source| source.start <= start).cloned().collect();
+    res.push(after);
+    res.extend(sources.iter()
+        .filter(|source| source.start > end)
+        .map(|source| Source { start: source.start + len - (end - start), ..source.clone() }));
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_patch() {
+        let input = "<Schema name=\"s\">\n<msc:patch cube=\"Sales\">\n  <set attribute=\"defaultMeasure\" value=\"Amount &amp; more\"/>\n  \
+                     <set attribute=\"caption\" value=\"Sales\"/>\n  <remove attribute=\"cache\"/>\n  <remove attribute=\"visible\"/>\n\
+                     </msc:patch>\n</Schema>";
+        let text = &input[18..input.len() - 10];
+        let mut warnings = Vec::new();
+        let patch = Patch::parse(1, input, text, &ScanOptions::default(), &mut warnings).unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!((patch.kind, patch.name.as_str()), (ElementKind::Cube, "Sales"));
+        assert_eq!(patch.changes.len(), 4);
+
+        let cube = "<Cube name=\"Sales\" cache='true' defaultMeasure='Units'>\n  <Table name=\"sales\"/>\n</Cube>";
+        let sources = vec![Source { start: 0, fragment: 0, input: cube, offset: 0 }];
+        let (patched, sources) = patch.apply(cube, &sources);
+        assert_eq!(
+            patched,
+            "<Cube name=\"Sales\" defaultMeasure='Amount &amp; more' caption=\"Sales\">\n  <Table name=\"sales\"/>\n</Cube>"
+        );
+        // the table is still found where it was
+        let table = patched.find("<Table").unwrap();
+        let source = sources.iter().rev().find(|source| source.start <= table).unwrap();
+        assert_eq!(source.offset + table - source.start, cube.find("<Table").unwrap());
+
+        let (patched, _) = patch.apply("<Cube name=\"Sales\"/>", &[Source { start: 0, fragment: 0, input: cube, offset: 0 }]);
+        assert_eq!(patched, "<Cube name=\"Sales\" defaultMeasure=\"Amount &amp; more\" caption=\"Sales\"/>");
+
+        let invalid = |text: &str| {
+            let mut warnings = Vec::new();
+            assert!(Patch::parse(0, text, text, &ScanOptions::default(), &mut warnings).is_none());
+            let warning = warnings.remove(0);
+            (warning.rule, warning.column, warning.message)
+        };
+        assert_eq!(
+            invalid("<msc:patch cube=\"a\" dimension=\"b\"/>"),
+            ("invalid-patch", 1, "A patch names one cube, virtual cube or shared dimension".to_owned())
+        );
+        assert_eq!(invalid("<msc:patch cube=\"a\"><set attribute=\"name\" value=\"b\"/></msc:patch>").2, "A patch can't change a name");
+        assert_eq!(invalid("<msc:patch cube=\"a\"><set attribute=\"caption\"/></msc:patch>").1, 21);
+        assert_eq!(invalid("<msc:patch cube=\"a\"><rename/></msc:patch>").2, "A patch can only `set` or `remove` attributes");
+
+        let text = "<msc:patch virtualCube=\"v\"/>";
+        let patch = Patch::parse(0, text, text, &ScanOptions::default(), &mut warnings).unwrap();
+        let warning = patch.unknown_target();
+        assert_eq!(warning.rule, "unknown-patch-target");
+        assert_eq!(warning.message, "The patch of virtual cube \"v\" applies to nothing, as there's no such virtual cube");
+    }
+}
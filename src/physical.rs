@@ -0,0 +1,136 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Merging the `<PhysicalSchema>` blocks of Mondrian 4 fragments
+// into the one a schema can have.
+//
+// Each fragment usually declares the tables its dimensions and
+// cubes use, so the same table turns up in several fragments.
+// Tables, queries and inline tables are told apart by alias, or
+// by name when they have none: one identical to the first with
+// its alias (but for how it's written) is dropped, while one
+// which differs is a `duplicate-conflict`.
+// Links are kept unless identical to one already kept.
+
+use std::collections::HashMap;
+
+use diff;
+use scan::{self, ScanOptions};
+use tokenizer;
+use tree;
+use Warning;
+
+/// Merge `blocks`, each a `<PhysicalSchema>` element as
+/// (fragment, input, text), with `text` a slice of `input`, into
+/// the children of the merged block, in the order first seen.
+pub fn merge_physical_schemas<'a>(blocks: &[(usize, &'a str, &'a str)], options: &ScanOptions) -> (Vec<&'a str>, Vec<Warning>) {
+    let mut res = Vec::new();
+    let mut warnings = Vec::new();
+    // alias to the first definition
+    let mut seen: HashMap<String, &str> = HashMap::new();
+    let mut links = Vec::new();
+    for &(fragment, input, text) in blocks {
+        let block = match tree::parse(text).ok().and_then(|roots| roots.into_iter().next()) {
+            Some(block) => block,
+            None => continue,
+        };
+        let offset = scan::offset_in(input, text);
+        for element in &block.children {
+            let child = &text[element.start..element.start + element.text.len()];
+            if scan::names_match(element.name, "Link", options) {
+                let normalized = diff::normalize(child);
+                if !links.contains(&normalized) {
+                    links.push(normalized);
+                    res.push(child);
+                }
+                continue;
+            }
+            let alias = match element.attr("alias").or_else(|| element.name_attr()) {
+                Some(alias) => alias.into_owned(),
+                None => {
+                    res.push(child);
+                    continue;
+                },
+            };
+            let first = match seen.get(&alias) {
+                Some(&first) => first,
+                None => {
+                    seen.insert(alias, child);
+                    res.push(child);
+                    continue;
+                },
+            };
+            if diff::normalize(first) == diff::normalize(child) {
+                continue;
+            }
+            let start = offset + element.start;
+            let (line, column) = tokenizer::line_col(input, start);
+            let message = format!(
+                "{} \"{}\" of the physical schema differs from its first definition:\n{}",
+                element.name,
+                alias,
+                diff::diff_lines(first, child).trim_end()
+            );
+            warnings.push(Warning {
+                fragment,
+                line,
+                column,
+                rule: "duplicate-conflict",
+                element: Some(format!("{} \"{}\"", element.name, alias)),
+                path: vec![block.name.to_owned(), format!("{} \"{}\"", element.name, alias)],
+                span: start..start + element.text.len(),
+                message,
+                first: None,
+            });
+        }
+    }
+    (res, warnings)
+}
+
+/// The `<PhysicalSchema>` block holding `elements`, one to a
+/// line and indented two spaces
+pub fn physical_schema_block(elements: &[&str]) -> String {
+    let mut res = String::from("<PhysicalSchema>\n");
+    for element in elements {
+        res.push_str("  ");
+        res.push_str(element);
+        res.push('\n');
+    }
+    res.push_str("</PhysicalSchema>");
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_physical_schemas() {
+        let f0 = "<PhysicalSchema>\n  <Table name=\"sales_fact\"/>\n  <Table name=\"store\" keyColumn=\"id\"/>\n  \
+                  <Link target=\"sales_fact\" source=\"store\" foreignKeyColumn=\"store_id\"/>\n</PhysicalSchema>";
+        let f1 = "<PhysicalSchema><Table name=\"store\"  keyColumn=\"id\"/><Table name=\"store\" alias=\"shop\"/>\
+                  <Link target=\"sales_fact\" source=\"store\" foreignKeyColumn=\"store_id\"/>\
+                  <Table name=\"sales_fact\"><Key><Column name=\"id\"/></Key></Table></PhysicalSchema>";
+        let blocks = vec![(0, f0, f0), (1, f1, f1)];
+        let (elements, warnings) = merge_physical_schemas(&blocks, &ScanOptions::default());
+        assert_eq!(
+            physical_schema_block(&elements),
+            "<PhysicalSchema>\n  <Table name=\"sales_fact\"/>\n  <Table name=\"store\" keyColumn=\"id\"/>\n  \
+             <Link target=\"sales_fact\" source=\"store\" foreignKeyColumn=\"store_id\"/>\n  <Table name=\"store\" alias=\"shop\"/>\n\
+             </PhysicalSchema>"
+        );
+
+        let found: Vec<_> = warnings.iter().map(|w| (w.fragment, w.line, w.column, w.rule, w.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![(1, 1, 158, "duplicate-conflict",
+                  "Table \"sales_fact\" of the physical schema differs from its first definition:\n\
+                   - <Table name=\"sales_fact\"/>\n\
+                   + <Table name=\"sales_fact\"><Key><Column name=\"id\"/></Key></Table>")]
+        );
+    }
+}
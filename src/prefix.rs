@@ -0,0 +1,148 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Prefixing the names a fragment defines, so schemas written
+// on their own can be merged without their cubes and
+// dimensions colliding, e.g. with `finance_` on everything
+// from the finance team's.
+//
+// The shared dimensions, cubes and virtual cubes at the top
+// level of the fragment get the prefix, and so do the
+// references to them within the fragment: `source` of
+// dimension usages, `cubeName` of virtual cube parts and
+// `cube` of cube grants. References to what other fragments
+// define are left alone.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::ops::Range;
+
+use escape;
+use scan::{self, ScanOptions};
+use tree::{self, Element};
+use {CUBE_TAG, DIM_TAG, SCHEMA_TAG, SHAREDDIM_TAG, VIRTUALCUBE_TAG};
+
+/// `fragment` with `prefix` put before the names of its shared
+/// dimensions, cubes and virtual cubes, and the references to
+/// them. A fragment which isn't well formed is left as is.
+pub fn prefix_names<'a>(fragment: &'a str, prefix: &str, options: &ScanOptions) -> Cow<'a, str> {
+    let roots = match tree::parse(fragment) {
+        Ok(roots) => roots,
+        Err(_) => return Cow::Borrowed(fragment),
+    };
+    let is = |element: &Element, tags: &[&str]| {
+        tags.iter().any(|tag| scan::names_match(scan::local_name(element.name, options), tag, options))
+    };
+    let top_level: Vec<&Element> = roots.iter()
+        .flat_map(|root| if is(root, &[SCHEMA_TAG]) { root.children.iter().collect() } else { vec![root] })
+        .collect();
+
+    let mut dims = HashSet::new();
+    let mut cubes = HashSet::new();
+    let mut edits = Vec::new();
+    for element in &top_level {
+        let names = if is(element, &[DIM_TAG, SHAREDDIM_TAG]) {
+            &mut dims
+        } else if is(element, &[CUBE_TAG, VIRTUALCUBE_TAG]) {
+            &mut cubes
+        } else {
+            continue;
+        };
+        if let Some(name) = element.name_attr() {
+            names.insert(name.into_owned());
+            edits.extend(edit(fragment, element, "name", prefix));
+        }
+    }
+
+    // the references, wherever they are
+    let refers = |element: &Element, attribute: &str, names: &HashSet<String>| {
+        element.attr(attribute).is_some_and(|name| names.contains(name.as_ref()))
+    };
+    for element in top_level.iter().flat_map(|element| element.descendants()) {
+        if is(element, &["DimensionUsage", DIM_TAG]) && refers(element, "source", &dims) {
+            edits.extend(edit(fragment, element, "source", prefix));
+        } else if is(element, &["CubeUsage", "VirtualCubeDimension", "VirtualCubeMeasure"]) && refers(element, "cubeName", &cubes) {
+            edits.extend(edit(fragment, element, "cubeName", prefix));
+        } else if is(element, &["VirtualCubeDimension"]) && element.attr("cubeName").is_none() && refers(element, "name", &dims) {
+            // a shared dimension, used as is
+            edits.extend(edit(fragment, element, "name", prefix));
+        } else if is(element, &["CubeGrant"]) && refers(element, "cube", &cubes) {
+            edits.extend(edit(fragment, element, "cube", prefix));
+        }
+    }
+    if edits.is_empty() {
+        return Cow::Borrowed(fragment);
+    }
+
+    edits.sort_by_key(|edit| edit.0.start);
+    let mut res = String::with_capacity(fragment.len() + edits.len() * prefix.len());
+    let mut pos = 0;
+    for (range, value) in edits {
+        res.push_str(&fragment[pos..range.start]);
+        res.push_str(&value);
+        pos = range.end;
+    }
+    res.push_str(&fragment[pos..]);
+    Cow::Owned(res)
+}
+
+/// Where the value of `attribute` of `element` is in
+/// `fragment`, and the value with `prefix`, escaped
+fn edit(fragment: &str, element: &Element, attribute: &str, prefix: &str) -> Option<(Range<usize>, String)> {
+    let &(_, raw) = element.attributes.iter().find(|&&(name, _)| name == attribute)?;
+    let start = scan::offset_in(fragment, raw);
+    let value = escape::unescape(raw).unwrap_or(Cow::Borrowed(raw));
+    Some((start..start + raw.len(), escape::escape(&format!("{}{}", prefix, value)).into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Namespace;
+
+    #[test]
+    fn test_prefix_names() {
+        let fragment = "<Schema name=\"Finance\">\n\
+                        <Dimension name=\"Account\"/>\n\
+                        <Cube name=\"Ledger\">\n  \
+                          <DimensionUsage name=\"Account\" source=\"Account\"/>\n  \
+                          <DimensionUsage name=\"Time\" source=\"Time\"/>\n\
+                        </Cube>\n\
+                        <VirtualCube name=\"All &amp; more\">\n  \
+                          <VirtualCubeDimension name=\"Account\"/>\n  \
+                          <VirtualCubeDimension cubeName=\"Sales\" name=\"Store\"/>\n  \
+                          <VirtualCubeMeasure cubeName=\"Ledger\" name=\"[Measures].[Amount]\"/>\n\
+                        </VirtualCube>\n\
+                        <Role name=\"Auditor\"><SchemaGrant access=\"none\"><CubeGrant cube=\"Ledger\" access=\"all\"/></SchemaGrant></Role>\n\
+                        </Schema>";
+        assert_eq!(
+            prefix_names(fragment, "fin_", &ScanOptions::default()),
+            "<Schema name=\"Finance\">\n\
+             <Dimension name=\"fin_Account\"/>\n\
+             <Cube name=\"fin_Ledger\">\n  \
+               <DimensionUsage name=\"Account\" source=\"fin_Account\"/>\n  \
+               <DimensionUsage name=\"Time\" source=\"Time\"/>\n\
+             </Cube>\n\
+             <VirtualCube name=\"fin_All &amp; more\">\n  \
+               <VirtualCubeDimension name=\"fin_Account\"/>\n  \
+               <VirtualCubeDimension cubeName=\"Sales\" name=\"Store\"/>\n  \
+               <VirtualCubeMeasure cubeName=\"fin_Ledger\" name=\"[Measures].[Amount]\"/>\n\
+             </VirtualCube>\n\
+             <Role name=\"Auditor\"><SchemaGrant access=\"none\"><CubeGrant cube=\"fin_Ledger\" access=\"all\"/></SchemaGrant></Role>\n\
+             </Schema>"
+        );
+
+        // nothing to prefix
+        assert_eq!(prefix_names("<Role name=\"r\"/>", "fin_", &ScanOptions::default()), Cow::Borrowed("<Role name=\"r\"/>"));
+
+        let options = ScanOptions { namespace: Some(Namespace::Prefix("m".to_owned())), ..ScanOptions::default() };
+        assert_eq!(
+            prefix_names("<m:Cube name='c'><m:DimensionUsage source='d'/></m:Cube><m:Dimension name='d'/>", "x_", &options),
+            "<m:Cube name='x_c'><m:DimensionUsage source='x_d'/></m:Cube><m:Dimension name='x_d'/>"
+        );
+    }
+}
@@ -0,0 +1,140 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Virtual cubes generated from recipes in a manifest, instead
+// of fragments mirroring the base cubes by hand:
+//
+// ```toml
+// [[virtual_cubes]]
+// name = "Sales and Stock"
+// cubes = ["Sales", "Stock"]
+// measures = ["Units", "Stock Level"]
+// dimensions = ["Store", "Time"]
+// ```
+//
+// Each measure is taken from the first of the cubes which has
+// it, as a measure or a calculated member of `Measures`, and
+// each dimension must be a shared one. A recipe naming
+// anything the merged schema doesn't have is an
+// `InvalidRecipe`, since Mondrian would refuse the virtual cube.
+
+use error::*;
+use escape;
+use scan::{self, ScanOptions};
+use tree;
+
+/// A virtual cube to generate from the cubes of the merged
+/// schema.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VirtualCubeRecipe {
+    pub name: String,
+    /// Names of the base cubes
+    pub cubes: Vec<String>,
+    /// Names of the measures taken from the base cubes
+    #[serde(default)]
+    pub measures: Vec<String>,
+    /// Names of the shared dimensions
+    #[serde(default)]
+    pub dimensions: Vec<String>,
+}
+
+impl VirtualCubeRecipe {
+    /// The `<VirtualCube>` the recipe makes of `cubes`, the cubes
+    /// of the schema as (name, text), and `dimensions`, the
+    /// names of its shared dimensions
+    pub fn generate(&self, cubes: &[(&str, &str)], dimensions: &[&str], options: &ScanOptions) -> Result<String> {
+        let invalid = |message: String| Err(ErrorKind::InvalidRecipe(self.name.clone(), message).into());
+        if self.cubes.is_empty() {
+            return invalid("it has no cubes".to_owned());
+        }
+        let mut bases = Vec::new();
+        for name in &self.cubes {
+            match cubes.iter().find(|&&(cube, _)| cube == name) {
+                Some(&(_, text)) => bases.push((name, tree::parse(text).ok().and_then(|roots| roots.into_iter().next()))),
+                None => return invalid(format!("cube \"{}\" isn't in the schema", name)),
+            }
+        }
+        if let Some(dimension) = self.dimensions.iter().find(|dimension| !dimensions.contains(&dimension.as_str())) {
+            return invalid(format!("shared dimension \"{}\" isn't in the schema", dimension));
+        }
+
+        let mut res = format!("<VirtualCube name=\"{}\">\n  <CubeUsages>\n", escape::escape(&self.name));
+        for name in &self.cubes {
+            res.push_str(&format!("    <CubeUsage cubeName=\"{}\"/>\n", escape::escape(name)));
+        }
+        res.push_str("  </CubeUsages>\n");
+        for dimension in &self.dimensions {
+            res.push_str(&format!("  <VirtualCubeDimension name=\"{}\"/>\n", escape::escape(dimension)));
+        }
+        for measure in &self.measures {
+            let is_measure = |element: &tree::Element| {
+                element.name_attr().is_some_and(|name| name == *measure)
+                    && (scan::names_match(element.name, "Measure", options)
+                        || scan::names_match(element.name, "CalculatedMember", options)
+                            && element.attr("dimension").is_some_and(|dimension| dimension == "Measures"))
+            };
+            let cube = bases.iter()
+                .find(|(_, root)| root.as_ref().is_some_and(|root| root.children.iter().any(&is_measure)))
+                .map(|&(name, _)| name);
+            let cube = match cube {
+                Some(cube) => cube,
+                None => return invalid(format!("measure \"{}\" isn't in any of its cubes", measure)),
+            };
+            res.push_str(&format!(
+                "  <VirtualCubeMeasure cubeName=\"{}\" name=\"[Measures].[{}]\"/>\n",
+                escape::escape(cube),
+                escape::escape(&measure.replace(']', "]]"))
+            ));
+        }
+        res.push_str("</VirtualCube>");
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let cubes = [
+            ("Sales", "<Cube name=\"Sales\"><Measure name=\"Units\" column=\"units\" aggregator=\"sum\"/>\
+                       <CalculatedMember name=\"Margin\" dimension=\"Measures\" formula=\"1\"/></Cube>"),
+            ("Stock & Co", "<Cube name=\"Stock &amp; Co\"><Measure name=\"Units\"/><Measure name=\"Level [max]\"/></Cube>"),
+        ];
+        let recipe = VirtualCubeRecipe {
+            name: "All".to_owned(),
+            cubes: vec!["Sales".to_owned(), "Stock & Co".to_owned()],
+            measures: vec!["Units".to_owned(), "Level [max]".to_owned(), "Margin".to_owned()],
+            dimensions: vec!["Store".to_owned()],
+        };
+        assert_eq!(
+            recipe.generate(&cubes, &["Store", "Time"], &ScanOptions::default()).unwrap(),
+            "<VirtualCube name=\"All\">\n  <CubeUsages>\n    <CubeUsage cubeName=\"Sales\"/>\n    \
+             <CubeUsage cubeName=\"Stock &amp; Co\"/>\n  </CubeUsages>\n  <VirtualCubeDimension name=\"Store\"/>\n  \
+             <VirtualCubeMeasure cubeName=\"Sales\" name=\"[Measures].[Units]\"/>\n  \
+             <VirtualCubeMeasure cubeName=\"Stock &amp; Co\" name=\"[Measures].[Level [max]]]\"/>\n  \
+             <VirtualCubeMeasure cubeName=\"Sales\" name=\"[Measures].[Margin]\"/>\n</VirtualCube>"
+        );
+
+        let invalid = |recipe: VirtualCubeRecipe| match *recipe.generate(&cubes, &["Store"], &ScanOptions::default()).unwrap_err().kind() {
+            ErrorKind::InvalidRecipe(ref name, ref message) => format!("{}: {}", name, message),
+            ref kind => panic!("unexpected error {:?}", kind),
+        };
+        let recipe = |cubes: &[&str], measures: &[&str], dimensions: &[&str]| VirtualCubeRecipe {
+            name: "v".to_owned(),
+            cubes: cubes.iter().map(|cube| cube.to_string()).collect(),
+            measures: measures.iter().map(|measure| measure.to_string()).collect(),
+            dimensions: dimensions.iter().map(|dimension| dimension.to_string()).collect(),
+        };
+        assert_eq!(invalid(recipe(&[], &[], &[])), "v: it has no cubes");
+        assert_eq!(invalid(recipe(&["Returns"], &[], &[])), "v: cube \"Returns\" isn't in the schema");
+        assert_eq!(invalid(recipe(&["Sales"], &[], &["Time"])), "v: shared dimension \"Time\" isn't in the schema");
+        assert_eq!(invalid(recipe(&["Stock & Co"], &["Margin"], &[])), "v: measure \"Margin\" isn't in any of its cubes");
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Validation reports: everything the lint rules found in a
+// merge, errors and warnings alike, as data for other tools to
+// render or filter (see `Merged::report`).
+
+use std::fmt;
+use std::ops::Range;
+
+use diagnostic::Severity;
+use Warning;
+
+/// The findings of a merge, in the order they're in the
+/// fragments.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValidationReport {
+    pub findings: Vec<Finding>,
+}
+
+/// One problem found in the fragments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    /// Error for rules at level `deny`, otherwise warning
+    pub severity: Severity,
+    /// Id of the rule which found it, one of `lint::RULES`
+    pub rule: &'static str,
+    pub message: String,
+    /// The elements from the merged one down to the one it's
+    /// about, like `Cube "Sales"`, `Measure "Units"`. Empty for
+    /// content left out of the schema.
+    pub path: Vec<String>,
+    /// Position of the fragment in the input
+    pub fragment: usize,
+    /// Byte range of what it's about in the fragment
+    pub span: Range<usize>,
+    /// 1-based line and column of the start of `span`
+    pub line: usize,
+    pub column: usize,
+    /// For a duplicate name, the fragment and line it was first
+    /// defined at
+    pub first: Option<(usize, usize)>,
+}
+
+impl Finding {
+    pub fn new(severity: Severity, warning: &Warning) -> Finding {
+        Finding {
+            severity,
+            rule: warning.rule,
+            message: warning.message.clone(),
+            path: warning.path.clone(),
+            fragment: warning.fragment,
+            span: warning.span.clone(),
+            line: warning.line,
+            column: warning.column,
+            first: warning.first,
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}[{}]: Fragment {} line {}, column {}: {}",
+            self.severity, self.rule, self.fragment, self.line, self.column, self.message
+        )?;
+        if let Some((fragment, line)) = self.first {
+            write!(f, ", first in fragment {} at line {}", fragment, line)?;
+        }
+        Ok(())
+    }
+}
+
+impl ValidationReport {
+    /// Findings of rules at level `deny`
+    pub fn errors(&self) -> impl Iterator<Item = &Finding> {
+        self.findings.iter().filter(|finding| finding.severity == Severity::Error)
+    }
+
+    /// Findings of rules at level `warn`
+    pub fn warnings(&self) -> impl Iterator<Item = &Finding> {
+        self.findings.iter().filter(|finding| finding.severity == Severity::Warning)
+    }
+
+    /// Findings of the rule with id `rule`
+    pub fn with_rule<'a>(&'a self, rule: &'a str) -> impl Iterator<Item = &'a Finding> + 'a {
+        self.findings.iter().filter(move |finding| finding.rule == rule)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    /// One finding per line
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for finding in &self.findings {
+            writeln!(f, "{}", finding)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lint::Level;
+    use {merge_fragments, MergeOptions};
+
+    #[test]
+    fn test_report() {
+        let fragments = vec![
+            "<Schema name=\"s\">\n<Cube name=\"a\"><Measure name=\"m\" aggregator=\"sum\" column=\"c\"/></Cube>\n</Schema>".to_owned(),
+            "<Cube name=\"a\">\n  <Measure name=\"m\" aggregator=\"add\" column=\"c\"/>\n</Cube>".to_owned(),
+        ];
+        let mut options = MergeOptions::default();
+        options.lints.insert("invalid-aggregator".to_owned(), Level::Deny);
+        let report = merge_fragments(&fragments, &options).unwrap().report();
+
+        let found: Vec<_> = report.findings.iter().map(|f| (f.severity, f.rule, f.fragment, f.span.clone())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (Severity::Error, "duplicate-conflict", 1, 0..73),
+                (Severity::Warning, "duplicate-name", 1, 0..73),
+                (Severity::Error, "invalid-aggregator", 1, 18..65),
+            ]
+        );
+        assert!(report.has_errors());
+        assert_eq!(report.with_rule("duplicate-name").count(), 1);
+        assert_eq!(
+            report.with_rule("invalid-aggregator").next().unwrap().path,
+            vec!["Cube \"a\"".to_owned(), "Measure \"m\"".to_owned()]
+        );
+        options.lints.insert("duplicate-conflict".to_owned(), Level::Allow);
+        let report = merge_fragments(&fragments, &options).unwrap().report();
+        assert_eq!(
+            report.to_string(),
+            "warning[duplicate-name]: Fragment 1 line 1, column 1: Cube \"a\" is defined more than once, \
+             first in fragment 0 at line 2\n\
+             error[invalid-aggregator]: Fragment 1 line 2, column 3: Measure \"m\" of Cube \"a\" has an unknown \
+             aggregator \"add\", expected one of sum, count, min, max, avg, distinct-count, distinct count, none\n"
+        );
+    }
+}
@@ -0,0 +1,750 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Text scanning helpers used by the fragment extractors.
+//
+// Extraction is still plain text search, but some regions of
+// a schema must never be searched: a CDATA section holding
+// SQL or an MDX expression can contain anything, including
+// text like `<Cube`, and matching inside it would cut the
+// fragment at the wrong place.
+
+use std::borrow::Cow;
+
+use mondrian;
+use tokenizer::{self, SyntaxError, TokenKind, Tokenizer};
+
+const CDATA_OPEN: &str = "<![CDATA[";
+const CDATA_CLOSE: &str = "]]>";
+const XML_DECL_OPEN: &str = "<?xml";
+const PI_CLOSE: &str = "?>";
+const PI_OPEN: &str = "<?";
+const COMMENT_OPEN: &str = "<!--";
+const COMMENT_CLOSE: &str = "-->";
+const DOCTYPE_OPEN: &str = "<!DOCTYPE";
+const BOM: char = '\u{feff}';
+
+/// Options for how element names are matched.
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptions {
+    /// Match element names ignoring ascii case, so that
+    /// `<cube>` is found when looking for `<Cube`.
+    pub ignore_case: bool,
+    /// Namespace of the Mondrian elements, for fragments which
+    /// write them with a prefix, like `<m:Cube>`.
+    pub namespace: Option<Namespace>,
+    /// Extract the comments just before an element along with
+    /// the element, instead of starting exactly at its tag.
+    pub keep_comments: bool,
+}
+
+/// How to recognize namespace-prefixed Mondrian elements.
+#[derive(Debug, Clone)]
+pub enum Namespace {
+    /// Elements are written with this prefix, e.g. `m` for
+    /// `<m:Cube>`.
+    Prefix(String),
+    /// Elements are in the namespace with this uri. The prefix
+    /// is looked up from the `xmlns:` declarations of each
+    /// fragment, so fragments can use different prefixes.
+    Uri(String),
+}
+
+/// Whether element names `a` and `b` are the same, taking
+/// `ignore_case` into account.
+pub fn names_match(a: &str, b: &str, options: &ScanOptions) -> bool {
+    if options.ignore_case {
+        a.eq_ignore_ascii_case(b)
+    } else {
+        a == b
+    }
+}
+
+/// `name` without its namespace prefix, if fragments have one
+pub fn local_name<'a>(name: &'a str, options: &ScanOptions) -> &'a str {
+    match options.namespace {
+        Some(_) => name.rsplit(':').next().unwrap_or(name),
+        None => name,
+    }
+}
+
+/// Find the first occurrence of `pat` in `s` which is not
+/// inside a CDATA section.
+///
+/// An unterminated CDATA section runs to the end of `s`.
+pub fn find(s: &str, pat: &str, options: &ScanOptions) -> Option<usize> {
+    let mut pos = 0;
+    loop {
+        let i = find_pat(&s[pos..], pat, options)?;
+        match s[pos..].find(CDATA_OPEN) {
+            Some(c) if c <= i => {
+                let body = pos + c + CDATA_OPEN.len();
+                pos = body + s[body..].find(CDATA_CLOSE)? + CDATA_CLOSE.len();
+            },
+            _ => return Some(pos + i),
+        }
+    }
+}
+
+fn find_pat(s: &str, pat: &str, options: &ScanOptions) -> Option<usize> {
+    if options.ignore_case {
+        // byte offsets are fine to return, since the patterns
+        // searched for all start with an ascii `<`
+        let pat = pat.as_bytes();
+        s.as_bytes()
+            .windows(pat.len())
+            .position(|w| w.eq_ignore_ascii_case(pat))
+    } else {
+        s.find(pat)
+    }
+}
+
+/// Find the first start tag named `name` which is not inside a
+/// CDATA section or a comment. Returns the whole tag, from `<`
+/// to `>`.
+///
+/// Unlike `find`, this won't match a longer tag name that
+/// starts with `name`.
+pub fn find_start_tag<'a>(s: &'a str, name: &str, options: &ScanOptions) -> Result<Option<&'a str>, SyntaxError> {
+    for token in Tokenizer::new(s) {
+        let token = token?;
+        match token.kind {
+            TokenKind::StartTag { name: tag, .. } if names_match(tag, name, options) => return Ok(Some(token.text)),
+            _ => {},
+        }
+    }
+    Ok(None)
+}
+
+/// Split `s` into one slice per top level `name` element, each
+/// running from the start tag through the matching end tag (or
+/// to the end of `s`, if the element isn't closed).
+///
+/// Text outside of the elements is returned in its own slices,
+/// unless it's only whitespace, so that nothing is lost. If
+/// there are no `name` elements, `s` is returned whole.
+pub fn split_elements<'a>(s: &'a str, name: &str, options: &ScanOptions) -> Result<Vec<&'a str>, SyntaxError> {
+    let mut res = Vec::new();
+    let mut depth = 0usize;
+    // end of the last slice taken, and start of the element
+    // being read
+    let mut pos = 0;
+    let mut current = None;
+
+    for token in Tokenizer::new(s) {
+        let token = token?;
+        match token.kind {
+            TokenKind::StartTag { name: tag, self_closing } => {
+                if depth == 0 && names_match(tag, name, options) {
+                    if !s[pos..token.start].trim().is_empty() {
+                        res.push(&s[pos..token.start]);
+                    }
+                    if self_closing {
+                        res.push(token.text);
+                        pos = token.end();
+                    } else {
+                        current = Some(token.start);
+                    }
+                }
+                if !self_closing {
+                    depth += 1;
+                }
+            },
+            TokenKind::EndTag { .. } => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    if let Some(start) = current.take() {
+                        res.push(&s[start..token.end()]);
+                        pos = token.end();
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+
+    if let Some(start) = current {
+        res.push(&s[start..]);
+        pos = s.len();
+    }
+    let rest = &s[pos..];
+    if res.is_empty() || !rest.trim().is_empty() {
+        res.push(rest);
+    }
+    Ok(res)
+}
+
+/// An element found at the top level of a fragment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Element<'a> {
+    /// The element's tag name, as written
+    pub name: &'a str,
+    /// Byte offset of the element's start tag
+    pub start: usize,
+    /// The element, from its start tag through its end tag
+    pub text: &'a str,
+}
+
+/// Find the top level elements of `s`, in the order written:
+/// those which aren't inside another element, except that a
+/// `container` element (e.g. Schema) is looked into rather
+/// than returned.
+///
+/// An element which is never closed runs to the end of `s`.
+pub fn top_level_elements<'a>(s: &'a str, container: &str, options: &ScanOptions) -> Result<Vec<Element<'a>>, SyntaxError> {
+    let mut res = Vec::new();
+    // names of the open elements, and whether the outermost
+    // one is the container
+    let mut open = Vec::new();
+    let mut in_container = false;
+    // start of the top level element being read
+    let mut current: Option<(&str, usize)> = None;
+
+    for token in Tokenizer::new(s) {
+        let token = token?;
+        let top_level = open.len() == in_container as usize;
+        match token.kind {
+            TokenKind::StartTag { name, self_closing } => {
+                if open.is_empty() && !self_closing && names_match(name, container, options) {
+                    in_container = true;
+                } else if top_level {
+                    if self_closing {
+                        res.push(Element { name, start: token.start, text: token.text });
+                    } else {
+                        current = Some((name, token.start));
+                    }
+                }
+                if !self_closing {
+                    open.push(name);
+                }
+            },
+            TokenKind::EndTag { .. } => {
+                open.pop();
+                if open.is_empty() {
+                    in_container = false;
+                }
+                if open.len() == in_container as usize {
+                    if let Some((name, start)) = current.take() {
+                        res.push(Element { name, start, text: &s[start..token.end()] });
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+
+    if let Some((name, start)) = current {
+        res.push(Element { name, start, text: &s[start..] });
+    }
+    Ok(res)
+}
+
+/// Find the text at the top level of `s`, in the same sense as
+/// `top_level_elements`, returning the offset and trimmed text
+/// of each run which isn't only whitespace. CDATA sections count
+/// as text.
+pub fn top_level_text<'a>(s: &'a str, container: &str, options: &ScanOptions) -> Result<Vec<(usize, &'a str)>, SyntaxError> {
+    let mut res = Vec::new();
+    let mut depth = 0usize;
+    let mut in_container = false;
+
+    for token in Tokenizer::new(s) {
+        let token = token?;
+        match token.kind {
+            TokenKind::StartTag { name, self_closing } => {
+                if depth == 0 && !self_closing && names_match(name, container, options) {
+                    in_container = true;
+                }
+                if !self_closing {
+                    depth += 1;
+                }
+            },
+            TokenKind::EndTag { .. } => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    in_container = false;
+                }
+            },
+            TokenKind::Text | TokenKind::CData if depth == in_container as usize => {
+                let text = token.text.trim();
+                if !text.is_empty() {
+                    let start = token.start + token.text.len() - token.text.trim_start().len();
+                    res.push((start, text));
+                }
+            },
+            _ => {},
+        }
+    }
+    Ok(res)
+}
+
+/// Byte offset of `inner` within `outer`, which it must be a
+/// slice of.
+pub fn offset_in(outer: &str, inner: &str) -> usize {
+    let offset = inner.as_ptr() as usize - outer.as_ptr() as usize;
+    debug_assert!(offset + inner.len() <= outer.len());
+    offset
+}
+
+/// Walk back from `pos` over any comments (and the whitespace
+/// around them) which lead up to it, returning the start of the
+/// first one. Returns `pos` if it isn't preceded by a comment.
+pub fn leading_comments_start(s: &str, pos: usize) -> usize {
+    let mut start = pos;
+    loop {
+        let before = s[..start].trim_end();
+        if !before.ends_with(COMMENT_CLOSE) {
+            return start;
+        }
+        match before.rfind(COMMENT_OPEN) {
+            Some(i) => start = i,
+            None => return start,
+        }
+    }
+}
+
+/// Rewrite the name of every Mondrian element in `s` to its
+/// canonical casing, so `<cube>` becomes `<Cube>`. Anything
+/// which isn't a known element is left alone.
+pub fn normalize_case(s: &str) -> String {
+    rewrite_tag_names(s, |name| mondrian::canonical_element(name).unwrap_or(name))
+}
+
+/// Remove the namespace prefix from the Mondrian elements in
+/// `s`, so `<m:Cube>` becomes `<Cube>` and the rest of the
+/// scanning doesn't need to know about namespaces.
+///
+/// Returns `s` unchanged if there's no namespace configured,
+/// or if `s` doesn't declare the configured namespace uri.
+pub fn strip_namespace<'a>(s: &'a str, options: &ScanOptions) -> Cow<'a, str> {
+    let prefix = match options.namespace {
+        Some(Namespace::Prefix(ref prefix)) => Some(prefix.as_str()),
+        Some(Namespace::Uri(ref uri)) => namespace_prefix(s, uri),
+        None => None,
+    };
+
+    match prefix {
+        Some(prefix) => {
+            let prefix = format!("{}:", prefix);
+            Cow::Owned(rewrite_tag_names(s, |name| name.strip_prefix(prefix.as_str()).unwrap_or(name)))
+        },
+        None => Cow::Borrowed(s),
+    }
+}
+
+/// The prefix bound to the namespace `uri` by the first
+/// matching `xmlns:` declaration in `s`.
+fn namespace_prefix<'a>(s: &'a str, uri: &str) -> Option<&'a str> {
+    const XMLNS: &str = "xmlns:";
+    let mut rest = s;
+    while let Some(i) = find(rest, XMLNS, &ScanOptions::default()) {
+        rest = &rest[i + XMLNS.len()..];
+        let prefix_len = rest
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(rest.len());
+        let value = rest[prefix_len..]
+            .trim_start()
+            .strip_prefix('=')
+            .map(|v| v.trim_start());
+        if let Some(value) = value {
+            if let Some(quote) = value.chars().next().filter(|&c| c == '"' || c == '\'') {
+                if value[1..].split(quote).next() == Some(uri) {
+                    return Some(&rest[..prefix_len]);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Rewrite the name of every start and end tag in `s` with
+/// `f`. The content of CDATA sections, comments, processing
+/// instructions and scripts is left alone.
+fn rewrite_tag_names<F>(s: &str, f: F) -> String
+    where F: for<'b> Fn(&'b str) -> &'b str
+{
+    let mut res = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('<') {
+        res.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        if let Some(len) = markup_len(rest) {
+            res.push_str(&rest[..len]);
+            rest = &rest[len..];
+            continue;
+        }
+
+        let name_start = if rest.starts_with("</") { 2 } else { 1 };
+        let name_len = rest[name_start..]
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .unwrap_or(rest.len() - name_start);
+        let name = &rest[name_start..name_start + name_len];
+        res.push_str(&rest[..name_start]);
+        res.push_str(f(name));
+        let tag = rest;
+        rest = &rest[name_start + name_len..];
+
+        // a script's body is copied as written
+        if name_start == 1 && tokenizer::is_script(name) {
+            if let Some(len) = start_tag_len(tag).filter(|&len| !tag[..len].ends_with("/>")) {
+                let body_start = len - name_start - name_len;
+                let body_len = tokenizer::script_body_len(&rest[body_start..], name);
+                res.push_str(&rest[..body_start + body_len]);
+                rest = &rest[body_start + body_len..];
+            }
+        }
+    }
+    res.push_str(rest);
+    res
+}
+
+/// Length of the CDATA section, comment or processing
+/// instruction at the start of `s`. None if `s` doesn't start
+/// with one of those.
+///
+/// If it's unterminated, it runs to the end of `s`.
+fn markup_len(s: &str) -> Option<usize> {
+    let close = if s.starts_with(CDATA_OPEN) {
+        CDATA_CLOSE
+    } else if s.starts_with(COMMENT_OPEN) {
+        COMMENT_CLOSE
+    } else if s.starts_with(PI_OPEN) {
+        PI_CLOSE
+    } else {
+        return None;
+    };
+
+    let len = s[2..].find(close)
+        .map(|i| i + 2 + close.len())
+        .unwrap_or_else(|| s.len());
+    Some(len)
+}
+
+/// Length of the start tag at the beginning of `s`, up to and
+/// including the closing `>`. A `>` inside a quoted attribute
+/// value doesn't close the tag.
+fn start_tag_len(s: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {},
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '>') => return Some(i + 1),
+            _ => {},
+        }
+    }
+    None
+}
+
+/// Parse the attributes of a start tag (as returned by
+/// `find_start_tag`) into `(name, value)` pairs, in the order
+/// written. Values are returned as written, still escaped.
+///
+/// Parsing stops at the first thing that isn't a well formed
+/// attribute.
+pub fn attributes(tag: &str) -> Vec<(&str, &str)> {
+    let mut res = Vec::new();
+
+    // skip the `<` and the element name
+    let mut rest = tag.trim_start_matches('<');
+    rest = rest.trim_start_matches(|c: char| !c.is_whitespace() && c != '>' && c != '/');
+
+    loop {
+        rest = rest.trim_start();
+        let name_len = rest
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '>' || c == '/')
+            .unwrap_or(rest.len());
+        if name_len == 0 {
+            break;
+        }
+        let name = &rest[..name_len];
+
+        let value = match rest[name_len..].trim_start().strip_prefix('=') {
+            Some(value) => value.trim_start(),
+            None => break,
+        };
+        let quote = match value.chars().next() {
+            Some(q) if q == '"' || q == '\'' => q,
+            _ => break,
+        };
+        let value = &value[1..];
+        match value.find(quote) {
+            Some(end) => {
+                res.push((name, &value[..end]));
+                rest = &value[end + 1..];
+            },
+            None => break,
+        }
+    }
+
+    res
+}
+
+/// Strip the prolog (byte order mark, XML declaration and
+/// DOCTYPE) from the start of a fragment.
+///
+/// Each fragment exported from a tool carries its own prolog,
+/// and they can't be repeated in the middle of the output.
+/// Comments in the prolog are kept.
+pub fn strip_prolog(s: &str) -> &str {
+    let mut rest = s.trim_start_matches(BOM).trim_start();
+    if rest.starts_with(XML_DECL_OPEN) {
+        match rest.find(PI_CLOSE) {
+            Some(i) => rest = rest[i + PI_CLOSE.len()..].trim_start(),
+            None => return rest,
+        }
+    }
+    if rest.starts_with(DOCTYPE_OPEN) {
+        if let Some(i) = tokenizer::doctype_len(rest) {
+            rest = rest[i..].trim_start();
+        }
+    }
+    rest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let opts = ScanOptions::default();
+        assert_eq!(find("<Cube></Cube>", "<Cube", &opts), Some(0));
+        assert_eq!(find("<Cube></Cube>", "<VirtualCube", &opts), None);
+        assert_eq!(find("", "<Cube", &opts), None);
+    }
+
+    #[test]
+    fn test_find_skips_cdata() {
+        let opts = ScanOptions::default();
+        let s = "<SQL><![CDATA[select '<Cube' from t]]></SQL><Cube>";
+        assert_eq!(find(s, "<Cube", &opts), Some(44));
+
+        // only inside cdata
+        let s = "<SQL><![CDATA[<Cube]]></SQL>";
+        assert_eq!(find(s, "<Cube", &opts), None);
+
+        // several sections
+        let s = "<![CDATA[<Cube]]><![CDATA[<Cube]]><Cube";
+        assert_eq!(find(s, "<Cube", &opts), Some(34));
+
+        // unterminated
+        let s = "<![CDATA[<Cube";
+        assert_eq!(find(s, "<Cube", &opts), None);
+
+        // cdata after the match doesn't matter
+        let s = "<Cube><![CDATA[x]]>";
+        assert_eq!(find(s, "<Cube", &opts), Some(0));
+    }
+
+    #[test]
+    fn test_find_ignore_case() {
+        let opts = ScanOptions { ignore_case: true, ..ScanOptions::default() };
+        assert_eq!(find("<cube></cube>", "<Cube", &opts), Some(0));
+        assert_eq!(find("<Dimension/><CUBE>", "<Cube", &opts), Some(12));
+        assert_eq!(find("<![CDATA[<cube]]>", "<Cube", &opts), None);
+        assert_eq!(find_start_tag("<schema name='a'>", "Schema", &opts).unwrap(), Some("<schema name='a'>"));
+
+        let opts = ScanOptions::default();
+        assert_eq!(find("<cube></cube>", "<Cube", &opts), None);
+        assert_eq!(find_start_tag("<schema name='a'>", "Schema", &opts).unwrap(), None);
+    }
+
+    #[test]
+    fn test_top_level_elements() {
+        let opts = ScanOptions::default();
+        let names = |s| {
+            top_level_elements(s, "Schema", &opts).unwrap()
+                .into_iter()
+                .map(|e| (e.name, e.text))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names(r#"<Cube name="a"><Dimension/></Cube> <Dimension name="d"/>"#),
+            vec![("Cube", r#"<Cube name="a"><Dimension/></Cube>"#), ("Dimension", r#"<Dimension name="d"/>"#)]
+        );
+        assert_eq!(
+            names(r#"<Schema name="s"><Cube name="a"><Cube/></Cube><VirtualCube></VirtualCube></Schema><Role/>"#),
+            vec![("Cube", r#"<Cube name="a"><Cube/></Cube>"#), ("VirtualCube", "<VirtualCube></VirtualCube>"), ("Role", "<Role/>")]
+        );
+        assert_eq!(
+            names("<Cube><SQL><![CDATA[</Cube><Dimension>]]></SQL></Cube>"),
+            vec![("Cube", "<Cube><SQL><![CDATA[</Cube><Dimension>]]></SQL></Cube>")]
+        );
+        assert_eq!(names("<Schema name='s'/><Cube/>"), vec![("Schema", "<Schema name='s'/>"), ("Cube", "<Cube/>")]);
+        assert_eq!(names("<Cube><Measure/>"), vec![("Cube", "<Cube><Measure/>")]);
+        assert_eq!(names(""), vec![]);
+
+        let elements = top_level_elements("<Dimension/>\n<Cube></Cube>", "Schema", &opts).unwrap();
+        assert_eq!(elements[1].start, 13);
+
+        assert!(top_level_elements("<Cube name=a>", "Schema", &opts).is_err());
+    }
+
+    #[test]
+    fn test_top_level_text() {
+        let opts = ScanOptions::default();
+        let text = |s| top_level_text(s, "Schema", &opts).unwrap();
+
+        assert_eq!(text("<Cube>inner</Cube>\n  <Dimension/>\n"), vec![]);
+        assert_eq!(text("<Cube/> stray\n<Dimension/>"), vec![(8, "stray")]);
+        assert_eq!(text("<Schema name='s'> a <Cube>b</Cube></Schema> c"), vec![(18, "a"), (44, "c")]);
+        assert_eq!(text("<Schema><![CDATA[x]]></Schema>"), vec![(8, "<![CDATA[x]]>")]);
+        assert_eq!(text("<!-- note --><?pi x?>"), vec![]);
+    }
+
+    #[test]
+    fn test_offset_in() {
+        let s = "<Cube/><Dimension/>";
+        assert_eq!(offset_in(s, &s[7..]), 7);
+        assert_eq!(offset_in(s, s), 0);
+    }
+
+    #[test]
+    fn test_leading_comments_start() {
+        let s = "<Dimension/>\n<!-- owner: a -->\n  <!-- b --><Cube/>";
+        assert_eq!(leading_comments_start(s, 43), 13);
+        assert_eq!(leading_comments_start(s, 12), 12);
+        assert_eq!(leading_comments_start("<Cube/>", 0), 0);
+        assert_eq!(leading_comments_start("x --> <Cube/>", 6), 6);
+    }
+
+    #[test]
+    fn test_normalize_case() {
+        assert_eq!(
+            normalize_case("<cube name='a'><DIMENSION/><measure/></cube>"),
+            "<Cube name='a'><Dimension/><Measure/></Cube>"
+        );
+        assert_eq!(
+            normalize_case("<Cube><SQL><![CDATA[<cube>]]></SQL><!-- <cube> --><Custom/></Cube>"),
+            "<Cube><SQL><![CDATA[<cube>]]></SQL><!-- <cube> --><Custom/></Cube>"
+        );
+        assert_eq!(
+            normalize_case("<cellFormatter><script>return v <cube ? '<measure/>' : v;</script><script/></cellFormatter>"),
+            "<CellFormatter><Script>return v <cube ? '<measure/>' : v;</Script><Script/></CellFormatter>"
+        );
+    }
+
+    #[test]
+    fn test_strip_namespace() {
+        let s = r#"<m:Schema xmlns:m="http://mondrian" name="a"><m:Cube name="c"><Custom/></m:Cube></m:Schema>"#;
+        let stripped = r#"<Schema xmlns:m="http://mondrian" name="a"><Cube name="c"><Custom/></Cube></Schema>"#;
+
+        let opts = ScanOptions::default();
+        assert_eq!(strip_namespace(s, &opts), s);
+
+        let opts = ScanOptions {
+            namespace: Some(Namespace::Prefix("m".to_owned())),
+            ..ScanOptions::default()
+        };
+        assert_eq!(strip_namespace(s, &opts), stripped);
+
+        let opts = ScanOptions {
+            namespace: Some(Namespace::Uri("http://mondrian".to_owned())),
+            ..ScanOptions::default()
+        };
+        assert_eq!(strip_namespace(s, &opts), stripped);
+
+        // prefix comes from each fragment's own declaration
+        let s = r#"<mdr:Cube xmlns:other="http://x" xmlns:mdr="http://mondrian" name="c"></mdr:Cube>"#;
+        assert_eq!(
+            strip_namespace(s, &opts),
+            r#"<Cube xmlns:other="http://x" xmlns:mdr="http://mondrian" name="c"></Cube>"#
+        );
+
+        // uri not declared
+        let s = r#"<m:Cube name="c"></m:Cube>"#;
+        assert_eq!(strip_namespace(s, &opts), s);
+    }
+
+    #[test]
+    fn test_strip_prolog() {
+        assert_eq!(strip_prolog("<Cube/>"), "<Cube/>");
+        assert_eq!(strip_prolog("<?xml version=\"1.0\"?>\n<Cube/>"), "<Cube/>");
+        assert_eq!(strip_prolog("\u{feff}<?xml version=\"1.0\" encoding=\"UTF-8\"?><Cube/>"), "<Cube/>");
+        assert_eq!(
+            strip_prolog("<?xml version=\"1.0\"?>\n<!DOCTYPE Schema SYSTEM \"mondrian.dtd\">\n<Schema name=\"a\"/>"),
+            "<Schema name=\"a\"/>"
+        );
+        assert_eq!(
+            strip_prolog("<!DOCTYPE Schema [\n<!ELEMENT Schema ANY>\n<!ATTLIST Schema name CDATA #REQUIRED>\n]>\n<Schema name=\"a\"/>"),
+            "<Schema name=\"a\"/>"
+        );
+        assert_eq!(
+            strip_prolog("<?xml version=\"1.0\"?>\n<!-- shared dims -->\n<Dimension/>"),
+            "<!-- shared dims -->\n<Dimension/>"
+        );
+    }
+
+    #[test]
+    fn test_find_start_tag() {
+        let opts = ScanOptions::default();
+        let s = r#"<SchemaGrant access="all"/><Schema name="a" description="b>c"><Cube/></Schema>"#;
+        assert_eq!(find_start_tag(s, "Schema", &opts).unwrap(), Some(r#"<Schema name="a" description="b>c">"#));
+        assert_eq!(find_start_tag(s, "Cube", &opts).unwrap(), Some("<Cube/>"));
+        assert_eq!(find_start_tag(s, "Dimension", &opts).unwrap(), None);
+        assert_eq!(find_start_tag("<Schema>", "Schema", &opts).unwrap(), Some("<Schema>"));
+        assert!(find_start_tag("<Schema name=\"a\"", "Schema", &opts).is_err());
+        assert_eq!(find_start_tag("<![CDATA[<Schema name='x'>]]>", "Schema", &opts).unwrap(), None);
+        assert_eq!(find_start_tag("<!-- <Schema name='x'> --><Schema name='y'>", "Schema", &opts).unwrap(), Some("<Schema name='y'>"));
+    }
+
+    #[test]
+    fn test_split_elements() {
+        let opts = ScanOptions::default();
+        assert_eq!(
+            split_elements("<Cube/>", "Schema", &opts).unwrap(),
+            vec!["<Cube/>"]
+        );
+        assert_eq!(
+            split_elements("<Schema name='a'><Cube/></Schema>\n<Schema name='b'/>\n<Schema name='c'></Schema>\n", "Schema", &opts).unwrap(),
+            vec!["<Schema name='a'><Cube/></Schema>", "<Schema name='b'/>", "<Schema name='c'></Schema>"]
+        );
+        assert_eq!(
+            split_elements("<Cube/><Schema name='a'></Schema><Dimension/>", "Schema", &opts).unwrap(),
+            vec!["<Cube/>", "<Schema name='a'></Schema>", "<Dimension/>"]
+        );
+        assert_eq!(
+            split_elements("<Schema name='a'><Cube/>", "Schema", &opts).unwrap(),
+            vec!["<Schema name='a'><Cube/>"]
+        );
+
+        // end tags split over lines still end the element
+        assert_eq!(
+            split_elements("<Schema\n  name='a'><Cube\n/></Schema\n>\n<Schema name='b'></Schema >", "Schema", &opts).unwrap(),
+            vec!["<Schema\n  name='a'><Cube\n/></Schema\n>", "<Schema name='b'></Schema >"]
+        );
+        // only top level elements are split on
+        assert_eq!(
+            split_elements("<Cube><Schema/></Cube>", "Schema", &opts).unwrap(),
+            vec!["<Cube><Schema/></Cube>"]
+        );
+        assert!(split_elements("<Schema name=a>", "Schema", &opts).is_err());
+    }
+
+    #[test]
+    fn test_attributes() {
+        assert_eq!(
+            attributes(r#"<Schema name="a" description="b">"#),
+            vec![("name", "a"), ("description", "b")]
+        );
+        assert_eq!(
+            attributes(r#"<Schema description="d" name="foo">"#),
+            vec![("description", "d"), ("name", "foo")]
+        );
+        assert_eq!(
+            attributes("<Schema\n    name = 'a'\n    measuresCaption=\"M\"/>"),
+            vec![("name", "a"), ("measuresCaption", "M")]
+        );
+        assert_eq!(attributes("<Schema>"), vec![]);
+        assert_eq!(attributes("<Schema/>"), vec![]);
+        assert_eq!(attributes(r#"<Schema name="a" broken>"#), vec![("name", "a")]);
+    }
+}
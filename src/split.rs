@@ -0,0 +1,146 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Splitting a schema back into fragments: the reverse of
+// merging, for bringing an existing schema under moncat.
+//
+// Each shared dimension, cube and virtual cube becomes a part
+// of its own. Everything else stays in the Schema block, which
+// is kept as written apart from what was taken out of it.
+
+use error::*;
+use scan;
+use tree;
+use ElementKind;
+
+/// A schema split into fragments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Split {
+    /// The Schema block, holding whatever isn't a part
+    pub schema: String,
+    /// Shared dimensions, cubes and virtual cubes, in the order
+    /// written
+    pub parts: Vec<Part>,
+}
+
+/// One element split out of a schema.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Part {
+    pub kind: ElementKind,
+    /// The element's name, unescaped, if it has one
+    pub name: Option<String>,
+    /// The element as written, along with any comments just
+    /// before it, unindented by as much as the element was
+    pub text: String,
+}
+
+/// Split a schema into its Schema block and a part for each
+/// shared dimension, cube, virtual cube, top level named set and
+/// role.
+pub fn split_schema(schema: &str) -> Result<Split> {
+    let schema = scan::strip_prolog(schema);
+    let roots = tree::parse_schema(schema, 0)?;
+    let (schema_element, elements) = tree::top_level(&roots);
+
+    let mut parts = Vec::new();
+    // what's left of the Schema block, as (start, end) ranges
+    // of `schema` which are taken out
+    let mut taken = Vec::new();
+    for element in elements {
+        let kind = match ElementKind::from_tag(element.name) {
+            Some(kind) => kind,
+            None => continue,
+        };
+        let start = scan::leading_comments_start(schema, element.start);
+        let end = element.start + element.text.len();
+        parts.push(Part {
+            kind,
+            name: element.name_attr().map(|name| name.into_owned()),
+            text: dedent(&schema[start..end], indentation(schema, element.start)),
+        });
+        taken.push((start, end));
+    }
+
+    let (start, end) = match schema_element {
+        Some(element) => (element.start, element.start + element.text.len()),
+        None => return Err(ErrorKind::NoSchemaName.into()),
+    };
+    let mut rest = String::new();
+    let mut pos = start;
+    for &(taken_start, taken_end) in taken.iter().filter(|&&(s, _)| s >= start && s < end) {
+        rest.push_str(&schema[pos..taken_start]);
+        pos = taken_end;
+    }
+    rest.push_str(&schema[pos..end]);
+
+    Ok(Split { schema: remove_blank_lines(&rest), parts })
+}
+
+/// The whitespace before `pos` on its line
+fn indentation(s: &str, pos: usize) -> &str {
+    let line_start = s[..pos].rfind('\n').map_or(0, |i| i + 1);
+    let before = &s[line_start..pos];
+    &before[..before.len() - before.trim_start().len()]
+}
+
+/// `s` with `indent` taken off the start of every line after
+/// the first, where it's there
+fn dedent(s: &str, indent: &str) -> String {
+    if indent.is_empty() {
+        return s.to_owned();
+    }
+    s.split('\n')
+        .enumerate()
+        .map(|(i, line)| if i > 0 && line.starts_with(indent) { &line[indent.len()..] } else { line })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Drop the lines which only held something taken out
+fn remove_blank_lines(s: &str) -> String {
+    let mut res: String = s.lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    res.push('\n');
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_schema() {
+        let schema = "<?xml version=\"1.0\"?>\n<Schema name=\"Sales\">\n  <!-- time -->\n  <SharedDimension name=\"Time\"/>\n  <Cube name=\"Sales &amp; Ops\">\n    <Measure name=\"m\"/>\n  </Cube>\n  <Role name=\"r\"/>\n  <VirtualCube name=\"v\"/>\n</Schema>\n";
+        let split = split_schema(schema).unwrap();
+        assert_eq!(split.schema, "<Schema name=\"Sales\">\n</Schema>\n");
+        assert_eq!(
+            split.parts,
+            vec![
+                Part {
+                    kind: ElementKind::SharedDimension,
+                    name: Some("Time".to_owned()),
+                    text: "<!-- time -->\n<SharedDimension name=\"Time\"/>".to_owned(),
+                },
+                Part {
+                    kind: ElementKind::Cube,
+                    name: Some("Sales & Ops".to_owned()),
+                    text: "<Cube name=\"Sales &amp; Ops\">\n  <Measure name=\"m\"/>\n</Cube>".to_owned(),
+                },
+                Part { kind: ElementKind::Role, name: Some("r".to_owned()), text: "<Role name=\"r\"/>".to_owned() },
+                Part { kind: ElementKind::VirtualCube, name: Some("v".to_owned()), text: "<VirtualCube name=\"v\"/>".to_owned() },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_schema_errors() {
+        assert!(split_schema("<Cube name=\"a\"/>").is_err());
+        assert!(split_schema("<Schema name=\"a\"><Cube></Schema>").is_err());
+    }
+}
@@ -0,0 +1,456 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// A small xml tokenizer.
+//
+// It only splits a fragment into tags, text and other markup,
+// keeping the byte offset of each token; it doesn't build a
+// tree or resolve entities. That's enough to check that a
+// fragment is well formed, and to report where it isn't.
+//
+// The body of a `<Script>` (of a user defined function or a
+// formatter) is JavaScript or the like, often written without
+// CDATA: it's taken as text up to the `</Script>`, so that a
+// `<` in it is neither a tag nor an error.
+
+use std::fmt;
+
+use escape;
+
+const CDATA_OPEN: &str = "<![CDATA[";
+const CDATA_CLOSE: &str = "]]>";
+const COMMENT_OPEN: &str = "<!--";
+const COMMENT_CLOSE: &str = "-->";
+const PI_OPEN: &str = "<?";
+const PI_CLOSE: &str = "?>";
+const DOCTYPE_OPEN: &str = "<!DOCTYPE";
+const SCRIPT_TAG: &str = "Script";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind<'a> {
+    StartTag { name: &'a str, self_closing: bool },
+    EndTag { name: &'a str },
+    Text,
+    CData,
+    Comment,
+    ProcessingInstruction,
+    Doctype,
+}
+
+/// A token, with its text and byte offset in the source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Token<'a> {
+    pub kind: TokenKind<'a>,
+    pub start: usize,
+    pub text: &'a str,
+}
+
+impl<'a> Token<'a> {
+    /// Byte offset just past the end of the token
+    pub fn end(&self) -> usize {
+        self.start + self.text.len()
+    }
+}
+
+/// A syntax error found at byte offset `pos`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub pos: usize,
+    pub message: String,
+}
+
+impl SyntaxError {
+    fn new<S: Into<String>>(pos: usize, message: S) -> Self {
+        SyntaxError { pos, message: message.into() }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (at byte {})", self.message, self.pos)
+    }
+}
+
+/// Iterator over the tokens of `src`. Iteration ends after
+/// the first syntax error.
+pub struct Tokenizer<'a> {
+    src: &'a str,
+    pos: usize,
+    // name of the Script element whose body is being read
+    script: Option<&'a str>,
+}
+
+impl<'a> Tokenizer<'a> {
+    pub fn new(src: &'a str) -> Self {
+        Tokenizer { src, pos: 0, script: None }
+    }
+
+    fn next_token(&self) -> Result<Token<'a>, SyntaxError> {
+        let start = self.pos;
+        let rest = &self.src[start..];
+
+        let script_text = self.script
+            .filter(|_| !rest.starts_with(CDATA_OPEN))
+            .map(|name| script_body_len(rest, name))
+            .filter(|&len| len > 0);
+        let (kind, len) = if let Some(len) = script_text {
+            (TokenKind::Text, rest.find(CDATA_OPEN).map_or(len, |cdata| cdata.min(len)))
+        } else if !rest.starts_with('<') {
+            (TokenKind::Text, rest.find('<').unwrap_or(rest.len()))
+        } else if rest.starts_with(CDATA_OPEN) {
+            (TokenKind::CData, delimited_len(rest, CDATA_CLOSE, start, "CDATA section")?)
+        } else if rest.starts_with(COMMENT_OPEN) {
+            (TokenKind::Comment, delimited_len(rest, COMMENT_CLOSE, start, "comment")?)
+        } else if rest.starts_with(PI_OPEN) {
+            (TokenKind::ProcessingInstruction, delimited_len(rest, PI_CLOSE, start, "processing instruction")?)
+        } else if rest.starts_with(DOCTYPE_OPEN) {
+            (TokenKind::Doctype, doctype_len(rest).ok_or_else(|| SyntaxError::new(start, "unterminated DOCTYPE"))?)
+        } else if rest.starts_with("<!") {
+            return Err(SyntaxError::new(start, "unknown markup declaration"));
+        } else if rest.starts_with("</") {
+            end_tag(rest, start)?
+        } else {
+            start_tag(rest, start)?
+        };
+
+        Ok(Token { kind, start, text: &rest[..len] })
+    }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Result<Token<'a>, SyntaxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.src.len() {
+            return None;
+        }
+        let res = self.next_token();
+        match res {
+            Ok(ref token) => {
+                self.pos = token.end();
+                match token.kind {
+                    TokenKind::StartTag { name, self_closing: false } if is_script(name) => self.script = Some(name),
+                    TokenKind::EndTag { .. } => self.script = None,
+                    _ => {},
+                }
+            },
+            Err(_) => self.pos = self.src.len(),
+        }
+        Some(res)
+    }
+}
+
+/// Whether `name` (with or without a namespace prefix, in any
+/// case) is a `<Script>` element, whose body is text.
+pub fn is_script(name: &str) -> bool {
+    name.rsplit(':').next().unwrap_or(name).eq_ignore_ascii_case(SCRIPT_TAG)
+}
+
+/// Length of the body of the Script element `name` at the start
+/// of `s`: everything up to its end tag, whatever's in between,
+/// but for CDATA sections, which may hold the end tag. Runs to
+/// the end of `s` if the element isn't closed.
+pub fn script_body_len(s: &str, name: &str) -> usize {
+    let mut pos = 0;
+    while let Some(i) = s[pos..].find('<') {
+        let at = pos + i;
+        let rest = &s[at..];
+        if rest.starts_with(CDATA_OPEN) {
+            match rest.find(CDATA_CLOSE) {
+                Some(end) => pos = at + end + CDATA_CLOSE.len(),
+                None => return s.len(),
+            }
+        } else if rest.starts_with("</") && name_len(&rest[2..]) == name.len()
+            && rest[2..2 + name.len()].eq_ignore_ascii_case(name) {
+            return at;
+        } else {
+            pos = at + 1;
+        }
+    }
+    s.len()
+}
+
+fn delimited_len(s: &str, close: &str, start: usize, what: &str) -> Result<usize, SyntaxError> {
+    s[2..].find(close)
+        .map(|i| i + 2 + close.len())
+        .ok_or_else(|| SyntaxError::new(start, format!("unterminated {}", what)))
+}
+
+/// Length of the DOCTYPE declaration at the start of `s`,
+/// including any internal subset in brackets.
+pub fn doctype_len(s: &str) -> Option<usize> {
+    let mut in_subset = false;
+    let mut quote = None;
+    for (i, c) in s.char_indices() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {},
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '[') => in_subset = true,
+            (None, ']') => in_subset = false,
+            (None, '>') if !in_subset => return Some(i + 1),
+            _ => {},
+        }
+    }
+    None
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_' || c == ':'
+}
+
+fn is_name_char(c: char) -> bool {
+    is_name_start(c) || c.is_numeric() || c == '-' || c == '.'
+}
+
+/// Length of the xml name at the start of `s`, 0 if there's none
+fn name_len(s: &str) -> usize {
+    match s.chars().next() {
+        Some(c) if is_name_start(c) => {
+            s.find(|c: char| !is_name_char(c)).unwrap_or(s.len())
+        },
+        _ => 0,
+    }
+}
+
+fn skip_whitespace(s: &str, i: usize) -> usize {
+    s[i..].find(|c: char| !c.is_whitespace()).map(|j| i + j).unwrap_or(s.len())
+}
+
+fn end_tag(s: &str, start: usize) -> Result<(TokenKind<'_>, usize), SyntaxError> {
+    let len = name_len(&s[2..]);
+    if len == 0 {
+        return Err(SyntaxError::new(start + 2, "expected element name"));
+    }
+    let name = &s[2..2 + len];
+    let i = skip_whitespace(s, 2 + len);
+    if s[i..].starts_with('>') {
+        Ok((TokenKind::EndTag { name }, i + 1))
+    } else {
+        Err(SyntaxError::new(start + i, format!("expected `>` to close </{}", name)))
+    }
+}
+
+fn start_tag(s: &str, start: usize) -> Result<(TokenKind<'_>, usize), SyntaxError> {
+    let len = name_len(&s[1..]);
+    if len == 0 {
+        return Err(SyntaxError::new(start + 1, "expected element name"));
+    }
+    let name = &s[1..1 + len];
+
+    let mut i = 1 + len;
+    loop {
+        let after_ws = skip_whitespace(s, i);
+        let saw_ws = after_ws > i;
+        i = after_ws;
+
+        let rest = &s[i..];
+        if rest.starts_with('>') {
+            return Ok((TokenKind::StartTag { name, self_closing: false }, i + 1));
+        }
+        if rest.starts_with("/>") {
+            return Ok((TokenKind::StartTag { name, self_closing: true }, i + 2));
+        }
+        if rest.is_empty() {
+            return Err(SyntaxError::new(start, format!("unterminated start tag <{}", name)));
+        }
+        if !saw_ws {
+            return Err(SyntaxError::new(start + i, "expected whitespace before attribute"));
+        }
+
+        let attr_len = name_len(rest);
+        if attr_len == 0 {
+            return Err(SyntaxError::new(start + i, "expected attribute name"));
+        }
+        i = skip_whitespace(s, i + attr_len);
+        if !s[i..].starts_with('=') {
+            return Err(SyntaxError::new(start + i, "expected `=` after attribute name"));
+        }
+        i = skip_whitespace(s, i + 1);
+        let quote = match s[i..].chars().next() {
+            Some(q) if q == '"' || q == '\'' => q,
+            _ => return Err(SyntaxError::new(start + i, "expected quoted attribute value")),
+        };
+        let value_len = s[i + 1..].find(quote)
+            .ok_or_else(|| SyntaxError::new(start + i, "unterminated attribute value"))?;
+        let value = &s[i + 1..i + 1 + value_len];
+        if let Some(j) = value.find('<') {
+            return Err(SyntaxError::new(start + i + 1 + j, "`<` in attribute value"));
+        }
+        escape::check_references(value)
+            .map_err(|err| SyntaxError::new(start + i + 1 + err.pos, err.message))?;
+        i += value_len + 2;
+    }
+}
+
+/// Check that `src` is well formed: every token is valid and
+/// every element is closed, in the right order. It doesn't have
+/// to have a single root element.
+///
+/// With `ignore_case`, end tags may differ from the start tag
+/// in ascii case.
+pub fn check_well_formed(src: &str, ignore_case: bool) -> Result<(), SyntaxError> {
+    let mut open: Vec<(&str, usize)> = Vec::new();
+
+    for token in Tokenizer::new(src) {
+        let token = token?;
+        match token.kind {
+            TokenKind::StartTag { name, self_closing: false } => open.push((name, token.start)),
+            // script bodies are taken as written
+            TokenKind::Text if open.last().is_some_and(|&(name, _)| is_script(name)) => {},
+            TokenKind::Text => {
+                escape::check_references(token.text)
+                    .map_err(|err| SyntaxError::new(token.start + err.pos, err.message))?;
+            },
+            TokenKind::EndTag { name } => {
+                match open.pop() {
+                    Some((open_name, _)) => {
+                        let matches = if ignore_case {
+                            open_name.eq_ignore_ascii_case(name)
+                        } else {
+                            open_name == name
+                        };
+                        if !matches {
+                            return Err(SyntaxError::new(
+                                token.start,
+                                format!("expected </{}>, found </{}>", open_name, name)
+                            ));
+                        }
+                    },
+                    None => {
+                        return Err(SyntaxError::new(token.start, format!("unexpected </{}>", name)));
+                    },
+                }
+            },
+            _ => {},
+        }
+    }
+
+    match open.pop() {
+        Some((name, pos)) => Err(SyntaxError::new(pos, format!("<{}> is never closed", name))),
+        None => Ok(()),
+    }
+}
+
+/// The 1-based line and column of byte offset `pos` in `src`.
+/// Columns count chars, not bytes.
+pub fn line_col(src: &str, pos: usize) -> (usize, usize) {
+    let before = &src[..pos];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let col = before[line_start..].chars().count() + 1;
+    (line, col)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(src: &str) -> Vec<TokenKind<'_>> {
+        Tokenizer::new(src).map(|t| t.unwrap().kind).collect()
+    }
+
+    #[test]
+    fn test_tokenizer() {
+        assert_eq!(
+            kinds(r#"<?xml version="1.0"?><Cube name="a>b" visible='true'><!-- c --><SQL><![CDATA[<x>]]></SQL>text<Measure/></Cube >"#),
+            vec![
+                TokenKind::ProcessingInstruction,
+                TokenKind::StartTag { name: "Cube", self_closing: false },
+                TokenKind::Comment,
+                TokenKind::StartTag { name: "SQL", self_closing: false },
+                TokenKind::CData,
+                TokenKind::EndTag { name: "SQL" },
+                TokenKind::Text,
+                TokenKind::StartTag { name: "Measure", self_closing: true },
+                TokenKind::EndTag { name: "Cube" },
+            ]
+        );
+
+        let tokens: Vec<_> = Tokenizer::new("a<b\n  c='d'\n/>").map(|t| t.unwrap()).collect();
+        assert_eq!(tokens[0].start, 0);
+        assert_eq!(tokens[1].start, 1);
+        assert_eq!(tokens[1].text, "<b\n  c='d'\n/>");
+
+        let kinds: Vec<_> = Tokenizer::new("<b\r\n\tc\n=\n'd'\r\n></b\r\n  >").map(|t| t.unwrap().kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::StartTag { name: "b", self_closing: false },
+                TokenKind::EndTag { name: "b" },
+            ]
+        );
+        assert!(check_well_formed("<b\n>\n</b\n>", false).is_ok());
+    }
+
+    #[test]
+    fn test_tokenizer_script() {
+        let src = "<Script>if (a <b && c) { return '</Cube>'; }<![CDATA[</Script>]]>x</Script><b>";
+        let tokens: Vec<_> = Tokenizer::new(src).map(|t| t.unwrap()).collect();
+        let found: Vec<_> = tokens.iter().map(|t| (t.kind, t.text)).collect();
+        assert_eq!(
+            found,
+            vec![
+                (TokenKind::StartTag { name: "Script", self_closing: false }, "<Script>"),
+                (TokenKind::Text, "if (a <b && c) { return '</Cube>'; }"),
+                (TokenKind::CData, "<![CDATA[</Script>]]>"),
+                (TokenKind::Text, "x"),
+                (TokenKind::EndTag { name: "Script" }, "</Script>"),
+                (TokenKind::StartTag { name: "b", self_closing: false }, "<b>"),
+            ]
+        );
+        assert!(check_well_formed("<m:Script>a < b && c</m:Script>", false).is_ok());
+        assert!(check_well_formed("<Script/><Formula>a < b</Formula>", false).is_err());
+    }
+
+    #[test]
+    fn test_tokenizer_errors() {
+        let err = |src| Tokenizer::new(src).find_map(|t| t.err()).unwrap();
+        assert_eq!(err("<Cube name=\"a\"").pos, 0);
+        assert_eq!(err("<Cube name=a>").pos, 11);
+        assert_eq!(err("<Cube name>").pos, 10);
+        assert_eq!(err("<Cube name=\"a\"visible=\"b\">").pos, 14);
+        assert_eq!(err("x < y").pos, 3);
+        assert_eq!(err("<!-- open").pos, 0);
+        assert_eq!(err("<a><![CDATA[ open").pos, 3);
+        assert_eq!(err("</a").pos, 3);
+        assert_eq!(err("<a b=\"<\">").pos, 6);
+        assert_eq!(err("<a b=\"x & y\">").pos, 8);
+    }
+
+    #[test]
+    fn test_check_well_formed() {
+        assert!(check_well_formed("", false).is_ok());
+        assert!(check_well_formed("<Dimension/><Cube><Measure/></Cube>", false).is_ok());
+
+        let err = check_well_formed("<Cube><Measure></Cube>", false).unwrap_err();
+        assert_eq!(err.pos, 15);
+        assert_eq!(err.message, "expected </Measure>, found </Cube>");
+
+        let err = check_well_formed("<Cube></Cube></Cube>", false).unwrap_err();
+        assert_eq!(err.pos, 13);
+
+        let err = check_well_formed("<Cube><Measure/>", false).unwrap_err();
+        assert_eq!(err.pos, 0);
+        assert_eq!(err.message, "<Cube> is never closed");
+
+        assert!(check_well_formed("<cube></CUBE>", false).is_err());
+
+        assert!(check_well_formed("<Formula>a &amp;&lt; b &#65;</Formula>", false).is_ok());
+        let err = check_well_formed("<Formula>a & b</Formula>", false).unwrap_err();
+        assert_eq!(err.pos, 11);
+        assert!(check_well_formed("<cube></CUBE>", true).is_ok());
+    }
+
+    #[test]
+    fn test_line_col() {
+        let src = "<a>\n  <b>\n  é<c>";
+        assert_eq!(line_col(src, 0), (1, 1));
+        assert_eq!(line_col(src, 6), (2, 3));
+        assert_eq!(line_col(src, 14), (3, 4));
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// A minimal element tree, for the tools which need to look
+// inside cubes and dimensions rather than just move them
+// around.
+//
+// Elements borrow from the source and keep their byte offsets,
+// so anything found in them can be pointed at. Text, comments
+// and the like aren't kept as nodes; an element's `body` still
+// has them.
+
+use std::borrow::Cow;
+
+use error::*;
+use escape;
+use scan;
+use tokenizer::{self, SyntaxError, TokenKind, Tokenizer};
+use SCHEMA_TAG;
+
+/// An element, and the elements inside it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Element<'a> {
+    /// The tag name, as written
+    pub name: &'a str,
+    /// Attributes in the order written, values still escaped
+    pub attributes: Vec<(&'a str, &'a str)>,
+    pub children: Vec<Element<'a>>,
+    /// Byte offset of the start tag in the source
+    pub start: usize,
+    /// The element, from its start tag through its end tag
+    pub text: &'a str,
+    /// Everything between the start and end tags
+    pub body: &'a str,
+}
+
+impl<'a> Element<'a> {
+    /// The unescaped value of an attribute. A value which can't
+    /// be unescaped is returned as written.
+    pub fn attr(&self, name: &str) -> Option<Cow<'a, str>> {
+        self.attributes.iter()
+            .find(|&&(attr, _)| attr == name)
+            .map(|&(_, value)| escape::unescape(value).unwrap_or(Cow::Borrowed(value)))
+    }
+
+    /// The element's `name` attribute
+    pub fn name_attr(&self) -> Option<Cow<'a, str>> {
+        self.attr("name")
+    }
+
+    /// The children with tag `name`, in order
+    pub fn children_named<'b>(&'b self, name: &'b str) -> impl Iterator<Item = &'b Element<'a>> + 'b {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    /// Every element inside this one, depth first in the order
+    /// written
+    pub fn descendants(&self) -> Vec<&Element<'a>> {
+        let mut res = Vec::new();
+        for child in &self.children {
+            res.push(child);
+            res.extend(child.descendants());
+        }
+        res
+    }
+}
+
+/// Parse the top level elements of `src`, which should be well
+/// formed (see `tokenizer::check_well_formed`). An element which
+/// is never closed runs to the end of `src`.
+pub fn parse(src: &str) -> ::std::result::Result<Vec<Element<'_>>, SyntaxError> {
+    // the open elements, innermost last, with where their body
+    // starts
+    let mut open: Vec<(Element, usize)> = Vec::new();
+    let mut res = Vec::new();
+
+    for token in Tokenizer::new(src) {
+        let token = token?;
+        let closed = match token.kind {
+            TokenKind::StartTag { name, self_closing } => {
+                let element = Element {
+                    name,
+                    attributes: scan::attributes(token.text),
+                    children: Vec::new(),
+                    start: token.start,
+                    text: token.text,
+                    body: "",
+                };
+                if !self_closing {
+                    open.push((element, token.end()));
+                    continue;
+                }
+                element
+            },
+            TokenKind::EndTag { .. } => match open.pop() {
+                Some((mut element, body_start)) => {
+                    element.text = &src[element.start..token.end()];
+                    element.body = &src[body_start..token.start];
+                    element
+                },
+                None => continue,
+            },
+            _ => continue,
+        };
+        match open.last_mut() {
+            Some(&mut (ref mut parent, _)) => parent.children.push(closed),
+            None => res.push(closed),
+        }
+    }
+
+    // anything left open runs to the end
+    while let Some((mut element, body_start)) = open.pop() {
+        element.text = &src[element.start..];
+        element.body = &src[body_start..];
+        match open.last_mut() {
+            Some(&mut (ref mut parent, _)) => parent.children.push(element),
+            None => res.push(element),
+        }
+    }
+    Ok(res)
+}
+
+/// Check that a whole schema (or fragment) is well formed,
+/// and parse it. Errors are reported as being in fragment
+/// `index`.
+pub fn parse_schema(src: &str, index: usize) -> Result<Vec<Element<'_>>> {
+    let malformed = |err: SyntaxError| {
+        let (line, column) = tokenizer::line_col(src, err.pos);
+        Error::from(ErrorKind::MalformedFragment(index, line, column, err.message))
+    };
+    tokenizer::check_well_formed(src, false).map_err(malformed)?;
+    parse(src).map_err(malformed)
+}
+
+/// The first Schema element of a parsed schema, if any, and the
+/// elements at its top level: those inside a Schema element,
+/// and those outside one.
+pub fn top_level<'b, 'a>(roots: &'b [Element<'a>]) -> (Option<&'b Element<'a>>, Vec<&'b Element<'a>>) {
+    let schema = roots.iter().find(|root| root.name == SCHEMA_TAG);
+    let elements = roots.iter()
+        .flat_map(|root| if root.name == SCHEMA_TAG { root.children.iter().collect() } else { vec![root] })
+        .collect();
+    (schema, elements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let src = "<!-- c --><Cube name=\"Sales &amp; Ops\">\n  <Table name=\"t\"/>\n  <Measure name=\"m\">x</Measure>\n</Cube><Role/>";
+        let elements = parse(src).unwrap();
+        assert_eq!(elements.len(), 2);
+
+        let cube = &elements[0];
+        assert_eq!(cube.name, "Cube");
+        assert_eq!(cube.start, 10);
+        assert_eq!(cube.name_attr().unwrap(), "Sales & Ops");
+        assert_eq!(cube.attributes, vec![("name", "Sales &amp; Ops")]);
+        assert!(cube.text.starts_with("<Cube") && cube.text.ends_with("</Cube>"));
+        assert!(cube.body.starts_with("\n  <Table") && cube.body.ends_with("</Measure>\n"));
+
+        let names: Vec<_> = cube.children.iter().map(|child| child.name).collect();
+        assert_eq!(names, vec!["Table", "Measure"]);
+        assert_eq!(cube.children_named("Measure").next().unwrap().body, "x");
+        assert_eq!(cube.children_named("Table").count(), 1);
+        assert_eq!(cube.children_named("Table").next().unwrap().body, "");
+        assert!(cube.children_named("Dimension").next().is_none());
+
+        assert_eq!(elements[1].name, "Role");
+        assert_eq!(elements[1].text, "<Role/>");
+    }
+
+    #[test]
+    fn test_descendants() {
+        let src = "<Dimension><Hierarchy><Level name=\"a\"/><Level name=\"b\"/></Hierarchy></Dimension>";
+        let dim = &parse(src).unwrap()[0];
+        let names: Vec<_> = dim.descendants().iter().map(|e| e.name).collect();
+        assert_eq!(names, vec!["Hierarchy", "Level", "Level"]);
+    }
+
+    #[test]
+    fn test_top_level() {
+        let roots = parse_schema("<Cube name=\"a\"/><Schema name=\"s\"><Cube name=\"b\"/><Role/></Schema>", 0).unwrap();
+        let (schema, elements) = top_level(&roots);
+        assert_eq!(schema.unwrap().name_attr().unwrap(), "s");
+        let names: Vec<_> = elements.iter().map(|e| e.name).collect();
+        assert_eq!(names, vec!["Cube", "Cube", "Role"]);
+
+        assert!(matches!(
+            *parse_schema("<Cube>\n<Measure></Cube>", 3).unwrap_err().kind(),
+            ErrorKind::MalformedFragment(3, 2, 10, _)
+        ));
+    }
+
+    #[test]
+    fn test_parse_unclosed() {
+        let elements = parse("<Cube><Measure/>").unwrap();
+        assert_eq!(elements[0].text, "<Cube><Measure/>");
+        assert_eq!(elements[0].children[0].name, "Measure");
+    }
+}
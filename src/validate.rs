@@ -0,0 +1,2372 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// Checks of the merged schema as a whole, for the mistakes
+// which come from merging fragments written separately: cubes
+// using shared dimensions which didn't make it in, and the
+// like.
+//
+// Each merged element is parsed on its own, and problems are
+// pointed at the fragment it came from.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use regex::Regex;
+
+use error::*;
+use escape;
+use mondrian;
+use scan::{self, ScanOptions};
+use tokenizer;
+use tree::{self, Element};
+use {ElementKind, Warning};
+
+/// The aggregators Mondrian knows. Mondrian 3 spells distinct
+/// count with a space, Mondrian 4 with a dash.
+const AGGREGATORS: &[&str] = &["sum", "count", "min", "max", "avg", "distinct-count", "distinct count", "none"];
+
+/// The elements users see, which should be captioned
+const CAPTIONED_TAGS: &[&str] = &[
+    "Cube", "VirtualCube", "Dimension", "SharedDimension", "DimensionUsage", "Hierarchy", "Level", "Measure",
+];
+
+/// The elements whose names are MDX identifiers
+const MDX_NAMED_TAGS: &[&str] = &[
+    "Cube", "VirtualCube", "Dimension", "SharedDimension", "DimensionUsage", "VirtualCubeDimension", "Hierarchy",
+    "Level", "Property", "Measure", "CalculatedMember", "NamedSet",
+];
+
+/// Longer names than this are warned about. Mondrian takes them,
+/// but they're unwieldy in MDX and in the tools showing them.
+const MAX_NAME_LENGTH: usize = 100;
+
+/// The elements a hierarchy reads its members from
+pub const RELATION_TAGS: &[&str] = &["Table", "View", "Join", "InlineTable"];
+
+/// The values of a level's `levelType`
+const LEVEL_TYPES: &[&str] = &[
+    "Regular", "TimeYears", "TimeHalfYears", "TimeQuarters", "TimeMonths", "TimeWeeks", "TimeDays",
+    "TimeHours", "TimeMinutes", "TimeSeconds", "TimeUndefined", "Null",
+];
+
+/// The values of a level's `type`
+const LEVEL_DATA_TYPES: &[&str] = &["String", "Numeric", "Integer", "Boolean", "Date", "Time", "Timestamp"];
+
+/// Attributes Mondrian reads as booleans
+const BOOLEAN_ATTRIBUTES: &[&str] = &["highCardinality", "visible", "enabled", "cache"];
+
+/// Attributes Mondrian reads as whole numbers
+const NUMERIC_ATTRIBUTES: &[&str] = &["approxRowCount"];
+
+/// Attributes Mondrian has deprecated: the elements they're on,
+/// the attribute, the rule warning about it and what to do
+/// instead
+const DEPRECATED_ATTRIBUTES: &[(&[&str], &str, &str, &str)] = &[
+    (&["Dimension", "SharedDimension"], "highCardinality", "deprecated-high-cardinality",
+     "it can give wrong results, and Mondrian 4 drops it"),
+    (&["Hierarchy"], "memberReaderClass", "deprecated-member-reader-class",
+     "custom member readers are going away, read members from a table instead"),
+    (&["Level"], "formatter", "deprecated-level-formatter", "use a <MemberFormatter> instead"),
+    (&["Measure", "CalculatedMember"], "formatter", "deprecated-cell-formatter", "use a <CellFormatter> instead"),
+    (&["Property"], "formatter", "deprecated-property-formatter", "use a <PropertyFormatter> instead"),
+];
+
+/// An element of the merged schema, parsed, and the fragment
+/// it came from.
+pub struct Checked<'a> {
+    pub kind: ElementKind,
+    /// Position of the fragment in the input, or of the first
+    /// fragment it was pieced together from
+    pub fragment: usize,
+    /// Where the pieces of the element's text came from, by
+    /// where they start
+    sources: Vec<Source<'a>>,
+    pub element: Element<'a>,
+}
+
+/// A piece of the text of a checked element, copied from a
+/// fragment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source<'a> {
+    /// Offset of the piece in the element's text
+    pub start: usize,
+    /// Position of the fragment in the input
+    pub fragment: usize,
+    /// The whole fragment
+    pub input: &'a str,
+    /// Offset of the piece in the fragment
+    pub offset: usize,
+}
+
+impl<'a> Checked<'a> {
+    /// Parse an element which was merged from `input`. `text`
+    /// must be a slice of `input`, and may start with comments.
+    pub fn new(kind: ElementKind, fragment: usize, input: &'a str, text: &'a str) -> Option<Checked<'a>> {
+        let source = Source { start: 0, fragment, input, offset: scan::offset_in(input, text) };
+        Checked::combined(kind, text, vec![source])
+    }
+
+    /// Parse an element pieced together from `sources`, which
+    /// must be in order, the first starting at 0.
+    pub fn combined(kind: ElementKind, text: &'a str, sources: Vec<Source<'a>>) -> Option<Checked<'a>> {
+        let element = tree::parse(text).ok()?.into_iter().next()?;
+        let fragment = sources.first()?.fragment;
+        Some(Checked { kind, fragment, sources, element })
+    }
+
+    /// The fragment, line and column of `pos` in the element's
+    /// text, and its offset in that fragment
+    fn locate(&self, pos: usize) -> (usize, usize, usize, usize) {
+        locate(&self.sources, pos)
+    }
+
+    /// The element's `name` attribute
+    pub fn name(&self) -> Option<String> {
+        self.element.name_attr().map(|name| name.into_owned())
+    }
+
+    /// A warning about `element`, which is this element or one
+    /// inside it
+    fn warning(&self, element: &Element, rule: &'static str, message: String) -> Warning {
+        let (fragment, line, column, start) = self.locate(element.start);
+        let mut path = Vec::new();
+        path_to(&self.element, element.start, &mut path);
+        Warning {
+            fragment,
+            line,
+            column,
+            rule,
+            element: Some(self.describe()),
+            path,
+            span: start..start + element.text.len(),
+            message,
+            first: None,
+        }
+    }
+
+    /// How the element is referred to in warnings, like
+    /// `Cube "Sales"`
+    fn describe(&self) -> String {
+        let kind = match self.kind {
+            ElementKind::Parameter => "Parameter",
+            ElementKind::SharedDimension => "Shared dimension",
+            ElementKind::Cube => "Cube",
+            ElementKind::VirtualCube => "Virtual cube",
+            ElementKind::Role => "Role",
+            ElementKind::NamedSet => "Named set",
+            ElementKind::UserDefinedFunction => "User defined function",
+        };
+        match self.name() {
+            Some(name) => format!("{} \"{}\"", kind, name),
+            None => kind.to_owned(),
+        }
+    }
+}
+
+/// Push the labels of the elements from `element` down to the
+/// one starting at `start` onto `path`, like `Cube "Sales"`,
+/// `Measure "Units"`. Whether it was found.
+fn path_to(element: &Element, start: usize, path: &mut Vec<String>) -> bool {
+    path.push(match element.name_attr() {
+        Some(name) => format!("{} \"{}\"", element.name, name),
+        None => element.name.to_owned(),
+    });
+    if element.start == start || element.children.iter().any(|child| path_to(child, start, path)) {
+        return true;
+    }
+    path.pop();
+    false
+}
+
+/// The fragment, line and column of `pos` in a text pieced
+/// together from `sources`, and its offset in that fragment
+pub fn locate(sources: &[Source], pos: usize) -> (usize, usize, usize, usize) {
+    let source = sources.iter()
+        .rev()
+        .find(|source| source.start <= pos)
+        .unwrap_or(&sources[0]);
+    let offset = source.offset + pos - source.start;
+    let (line, column) = tokenizer::line_col(source.input, offset);
+    (source.fragment, line, column, offset)
+}
+
+/// Check that the shared dimensions used by each cube are in
+/// the schema
+pub fn check_dimension_usages(elements: &[Checked], options: &ScanOptions) -> Vec<Warning> {
+    let shared_dims: HashSet<String> = elements.iter()
+        .filter(|checked| checked.kind == ElementKind::SharedDimension)
+        .filter_map(|checked| checked.name())
+        .collect();
+
+    let mut res = Vec::new();
+    for cube in elements.iter().filter(|checked| checked.kind == ElementKind::Cube) {
+        for usage in cube.element.children.iter().filter(|child| scan::names_match(child.name, "DimensionUsage", options)) {
+            let source = match usage.attr("source") {
+                Some(source) => source,
+                None => continue,
+            };
+            if !shared_dims.contains(source.as_ref()) {
+                let message = format!("{} uses shared dimension \"{}\", which isn't in the schema", cube.describe(), source);
+                res.push(cube.warning(usage, "unknown-dimension", message));
+            }
+        }
+    }
+    res
+}
+
+/// Check that each shared dimension is used, by a cube's
+/// DimensionUsage or a virtual cube's VirtualCubeDimension
+/// without a cube
+pub fn check_unused_dimensions(elements: &[Checked], options: &ScanOptions) -> Vec<Warning> {
+    let mut used = HashSet::new();
+    for checked in elements.iter().filter(|checked| checked.kind.is_cube()) {
+        for child in &checked.element.children {
+            let source = if scan::names_match(child.name, "DimensionUsage", options) {
+                child.attr("source")
+            } else if scan::names_match(child.name, "VirtualCubeDimension", options) && child.attr("cubeName").is_none() {
+                child.name_attr()
+            } else {
+                None
+            };
+            used.extend(source.map(|source| source.into_owned()));
+        }
+    }
+
+    elements.iter()
+        .filter(|checked| checked.kind == ElementKind::SharedDimension)
+        .filter(|dimension| dimension.name().is_some_and(|name| !used.contains(&name)))
+        .map(|dimension| {
+            let message = format!("{} isn't used by any cube", dimension.describe());
+            dimension.warning(&dimension.element, "unused-dimension", message)
+        })
+        .collect()
+}
+
+/// Check that the cubes each virtual cube takes dimensions
+/// and measures from are in the schema, and that the dimensions
+/// and measures are in those cubes. A dimension without a cube
+/// must be a shared dimension.
+pub fn check_virtual_cubes(elements: &[Checked], options: &ScanOptions) -> Vec<Warning> {
+    let cubes: HashMap<String, &Checked> = elements.iter()
+        .filter(|checked| checked.kind == ElementKind::Cube)
+        .filter_map(|checked| checked.name().map(|name| (name, checked)))
+        .collect();
+    let shared_dims: HashSet<String> = elements.iter()
+        .filter(|checked| checked.kind == ElementKind::SharedDimension)
+        .filter_map(|checked| checked.name())
+        .collect();
+
+    let mut res = Vec::new();
+    for virtual_cube in elements.iter().filter(|checked| checked.kind == ElementKind::VirtualCube) {
+        for child in &virtual_cube.element.children {
+            let is_measure = scan::names_match(child.name, "VirtualCubeMeasure", options);
+            if !is_measure && !scan::names_match(child.name, "VirtualCubeDimension", options) {
+                continue;
+            }
+            let cube_name = match child.attr("cubeName") {
+                Some(cube_name) => cube_name,
+                None => {
+                    match child.name_attr() {
+                        Some(name) if !is_measure && !shared_dims.contains(name.as_ref()) => {
+                            let message = format!(
+                                "{} uses shared dimension \"{}\", which isn't in the schema",
+                                virtual_cube.describe(),
+                                name
+                            );
+                            res.push(virtual_cube.warning(child, "unknown-dimension", message));
+                        },
+                        _ => {},
+                    }
+                    continue;
+                },
+            };
+            let cube = match cubes.get(cube_name.as_ref()) {
+                Some(cube) => cube,
+                None => {
+                    let message = format!("{} uses cube \"{}\", which isn't in the schema", virtual_cube.describe(), cube_name);
+                    res.push(virtual_cube.warning(child, "unknown-cube", message));
+                    continue;
+                },
+            };
+            if !is_measure {
+                let dimension = child.name_attr().unwrap_or_default();
+                let in_cube = cube.element.children.iter()
+                    .filter(|child| {
+                        scan::names_match(child.name, "Dimension", options)
+                            || scan::names_match(child.name, "DimensionUsage", options)
+                    })
+                    .any(|child| child.name_attr().or_else(|| child.attr("source")) == Some(dimension.clone()));
+                if !in_cube {
+                    let message = format!(
+                        "{} uses dimension \"{}\" of cube \"{}\", which isn't in that cube",
+                        virtual_cube.describe(),
+                        dimension,
+                        cube_name
+                    );
+                    res.push(virtual_cube.warning(child, "unknown-cube-dimension", message));
+                }
+                continue;
+            }
+            let measure = match child.attr("name") {
+                Some(ref name) => measure_name(name),
+                None => continue,
+            };
+            if !measure_names(&cube.element, options).contains(&measure) {
+                let message = format!(
+                    "{} uses measure \"{}\" of cube \"{}\", which isn't in that cube",
+                    virtual_cube.describe(),
+                    measure,
+                    cube_name
+                );
+                res.push(virtual_cube.warning(child, "unknown-measure", message));
+            }
+        }
+    }
+    res
+}
+
+/// Check that each measure of a cube has a known aggregator,
+/// and a column or expression to aggregate
+pub fn check_measures(elements: &[Checked], options: &ScanOptions) -> Vec<Warning> {
+    let mut res = Vec::new();
+    for cube in elements.iter().filter(|checked| checked.kind == ElementKind::Cube) {
+        for measure in cube.element.children.iter().filter(|child| scan::names_match(child.name, "Measure", options)) {
+            let name = measure.name_attr().unwrap_or_default();
+            match measure.attr("aggregator") {
+                Some(ref aggregator) if AGGREGATORS.contains(&aggregator.as_ref()) => {},
+                Some(aggregator) => {
+                    let message = format!(
+                        "Measure \"{}\" of {} has an unknown aggregator \"{}\", expected one of {}",
+                        name,
+                        cube.describe(),
+                        aggregator,
+                        AGGREGATORS.join(", ")
+                    );
+                    res.push(cube.warning(measure, "invalid-aggregator", message));
+                },
+                None => {
+                    let message = format!("Measure \"{}\" of {} has no aggregator", name, cube.describe());
+                    res.push(cube.warning(measure, "invalid-aggregator", message));
+                },
+            }
+
+            let has_expression = measure.children.iter()
+                .any(|child| scan::names_match(child.name, "MeasureExpression", options));
+            if measure.attr("column").is_none() && !has_expression {
+                let message = format!(
+                    "Measure \"{}\" of {} has neither a column nor a MeasureExpression",
+                    name,
+                    cube.describe()
+                );
+                res.push(cube.warning(measure, "measure-without-column", message));
+            }
+        }
+    }
+    res
+}
+
+/// Check that each hierarchy's `primaryKeyTable` is one of the
+/// tables it joins, and that hierarchies joined to a fact table
+/// with a `foreignKey` have a `primaryKey` to join on
+pub fn check_hierarchy_keys(elements: &[Checked], options: &ScanOptions) -> Vec<Warning> {
+    let is = |element: &Element, tag: &str| scan::names_match(element.name, tag, options);
+
+    // shared dimensions used with a foreign key
+    let mut joined = HashSet::new();
+    for cube in elements.iter().filter(|checked| checked.kind == ElementKind::Cube) {
+        for usage in cube.element.children.iter().filter(|child| is(child, "DimensionUsage")) {
+            if let (Some(source), Some(_)) = (usage.attr("source"), usage.attr("foreignKey")) {
+                joined.insert(source.into_owned());
+            }
+        }
+    }
+
+    let mut res = Vec::new();
+    for checked in elements {
+        for (dimension, label) in dimensions(checked, options) {
+            // whether it's joined to a cube with a foreign key
+            let is_joined = match checked.kind {
+                ElementKind::SharedDimension => checked.name().is_some_and(|name| joined.contains(&name)),
+                _ => dimension.attr("foreignKey").is_some(),
+            };
+            for hierarchy in dimension.children.iter().filter(|child| is(child, "Hierarchy")) {
+                let name = hierarchy.name_attr().or_else(|| dimension.name_attr()).unwrap_or_default();
+                let has_relation = hierarchy.children.iter()
+                    .any(|child| RELATION_TAGS.iter().any(|tag| is(child, tag)));
+
+                if let Some(table) = hierarchy.attr("primaryKeyTable") {
+                    let joins_table = hierarchy.descendants().into_iter()
+                        .filter(|element| is(element, "Table"))
+                        .any(|element| element.attr("alias").or_else(|| element.name_attr()) == Some(table.clone()));
+                    if !joins_table {
+                        let message = format!(
+                            "Hierarchy \"{}\" of {} has primaryKeyTable \"{}\", which isn't a table it joins",
+                            name,
+                            label,
+                            table
+                        );
+                        res.push(checked.warning(hierarchy, "unknown-key-table", message));
+                    }
+                }
+
+                if is_joined && has_relation && hierarchy.attr("primaryKey").is_none() {
+                    let message = format!(
+                        "Hierarchy \"{}\" of {} has no primaryKey, but is joined to a cube with a foreignKey",
+                        name,
+                        label
+                    );
+                    res.push(checked.warning(hierarchy, "missing-primary-key", message));
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Check that the dimensions of each cube which join a table
+/// (shared or private) have a `foreignKey` to join it on.
+/// Degenerate dimensions, read from the fact table, don't.
+pub fn check_foreign_keys(elements: &[Checked], options: &ScanOptions) -> Vec<Warning> {
+    // shared dimensions, by whether they join a table
+    let shared_dims: HashMap<String, bool> = elements.iter()
+        .filter(|checked| checked.kind == ElementKind::SharedDimension)
+        .filter_map(|checked| checked.name().map(|name| (name, joins_table(&checked.element, options))))
+        .collect();
+
+    let mut res = Vec::new();
+    for cube in elements.iter().filter(|checked| checked.kind == ElementKind::Cube) {
+        for child in &cube.element.children {
+            if child.attr("foreignKey").is_some() {
+                continue;
+            }
+            let message = if scan::names_match(child.name, "DimensionUsage", options) {
+                let source = child.attr("source").unwrap_or_default();
+                if shared_dims.get(source.as_ref()) != Some(&true) {
+                    continue;
+                }
+                format!(
+                    "DimensionUsage \"{}\" of {} has no foreignKey to join shared dimension \"{}\" on",
+                    child.name_attr().unwrap_or_else(|| source.clone()),
+                    cube.describe(),
+                    source
+                )
+            } else if scan::names_match(child.name, "Dimension", options) && joins_table(child, options) {
+                format!(
+                    "Dimension \"{}\" of {} has no foreignKey to join its table on",
+                    child.name_attr().unwrap_or_default(),
+                    cube.describe()
+                )
+            } else {
+                continue;
+            };
+            res.push(cube.warning(child, "missing-foreign-key", message));
+        }
+    }
+    res
+}
+
+/// Check the names of cubes, dimensions, measures and the like
+/// for characters MDX handles badly (brackets, which quote
+/// identifiers, whitespace at either end and control
+/// characters), and for being too long
+pub fn check_names(elements: &[Checked], options: &ScanOptions) -> Vec<Warning> {
+    let mut res = Vec::new();
+    for checked in elements {
+        let inside = checked.element.descendants();
+        for element in Some(&checked.element).into_iter().chain(inside) {
+            if !MDX_NAMED_TAGS.iter().any(|tag| scan::names_match(element.name, tag, options)) {
+                continue;
+            }
+            let name = match element.name_attr() {
+                Some(name) => name,
+                None => continue,
+            };
+            let label = if element.start == checked.element.start {
+                checked.describe()
+            } else {
+                format!("{} \"{}\" in {}", element.name, name, checked.describe())
+            };
+
+            let problem = if name.contains('[') || name.contains(']') {
+                Some("brackets")
+            } else if name.trim() != name {
+                Some("whitespace at either end")
+            } else if name.chars().any(char::is_control) {
+                Some("control characters")
+            } else {
+                None
+            };
+            if let Some(problem) = problem {
+                res.push(checked.warning(element, "invalid-name", format!("The name of {} has {}", label, problem)));
+            }
+
+            let length = name.chars().count();
+            if length > MAX_NAME_LENGTH {
+                let message = format!("The name of {} is {} characters long, more than {}", label, length, MAX_NAME_LENGTH);
+                res.push(checked.warning(element, "long-name", message));
+            }
+        }
+    }
+    res
+}
+
+/// Check the formulas of calculated members and named sets:
+/// that there is one, and that its brackets, parentheses,
+/// braces and quotes are balanced. It's no MDX parser, but a
+/// formula cut short or pasted twice is caught before Mondrian
+/// refuses the whole schema.
+pub fn check_formulas(elements: &[Checked], options: &ScanOptions) -> Vec<Warning> {
+    let is = |element: &Element, tag: &str| scan::names_match(element.name, tag, options);
+
+    let mut res = Vec::new();
+    for checked in elements {
+        let inside = checked.element.descendants();
+        for element in inside.into_iter().filter(|element| is(element, "CalculatedMember") || is(element, "NamedSet")) {
+            let formula = formula(element, options);
+            let problem = if formula.trim().is_empty() {
+                "is empty".to_owned()
+            } else {
+                match formula_problem(&formula) {
+                    Some(problem) => problem,
+                    None => continue,
+                }
+            };
+            let message = format!(
+                "The formula of {} \"{}\" in {} {}",
+                element.name,
+                element.name_attr().unwrap_or_default(),
+                checked.describe(),
+                problem
+            );
+            res.push(checked.warning(element, "invalid-formula", message));
+        }
+    }
+    res
+}
+
+/// Check that no two hierarchies of a dimension have the same
+/// name, counting an unnamed hierarchy as having the
+/// dimension's, the way Mondrian does
+pub fn check_duplicate_hierarchies(elements: &[Checked], options: &ScanOptions) -> Vec<Warning> {
+    let mut res = Vec::new();
+    for checked in elements {
+        for (dimension, label) in dimensions(checked, options) {
+            // name to the line it was first seen on
+            let mut seen: HashMap<String, usize> = HashMap::new();
+            for hierarchy in dimension.children.iter().filter(|child| scan::names_match(child.name, "Hierarchy", options)) {
+                let name = hierarchy.name_attr().or_else(|| dimension.name_attr()).unwrap_or_default().into_owned();
+                let message = format!("Hierarchy \"{}\" of {} is defined more than once", name, label);
+                let warning = checked.warning(hierarchy, "duplicate-hierarchy", message);
+                match seen.get(&name) {
+                    Some(&first_line) => res.push(Warning { first: Some((checked.fragment, first_line)), ..warning }),
+                    None => {
+                        seen.insert(name, warning.line);
+                    },
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Check that each hierarchy has exactly one relation (a
+/// Table, View, Join or InlineTable) to read its members from.
+/// A cube's own dimensions may have none, reading from the fact
+/// table, but shared dimensions have no fact table.
+pub fn check_hierarchy_relations(elements: &[Checked], options: &ScanOptions) -> Vec<Warning> {
+    let mut res = Vec::new();
+    for checked in elements {
+        for (dimension, label) in dimensions(checked, options) {
+            for hierarchy in dimension.children.iter().filter(|child| scan::names_match(child.name, "Hierarchy", options)) {
+                let name = hierarchy.name_attr().or_else(|| dimension.name_attr()).unwrap_or_default();
+                let relations: Vec<_> = hierarchy.children.iter()
+                    .filter(|child| RELATION_TAGS.iter().any(|tag| scan::names_match(child.name, tag, options)))
+                    .map(|child| child.name)
+                    .collect();
+                let message = match relations.len() {
+                    0 if checked.kind == ElementKind::SharedDimension => format!(
+                        "Hierarchy \"{}\" of {} has no Table, View, Join or InlineTable, which only a cube's own dimensions can do without",
+                        name, label
+                    ),
+                    0 | 1 => continue,
+                    _ => format!(
+                        "Hierarchy \"{}\" of {} has {} relations ({}), expected one",
+                        name, label, relations.len(), relations.join(", ")
+                    ),
+                };
+                res.push(checked.warning(hierarchy, "hierarchy-relation", message));
+            }
+        }
+    }
+    res
+}
+
+/// A naming convention: the elements it's for, and the
+/// regular expression their names must match
+#[derive(Debug)]
+pub struct NamingConvention {
+    pub element: String,
+    pub pattern: String,
+    regex: Regex,
+}
+
+/// Compile naming conventions (see `MergeOptions::naming`), so
+/// that each matches names as a whole
+pub fn naming_conventions(naming: &BTreeMap<String, String>) -> Result<Vec<NamingConvention>> {
+    naming.iter()
+        .map(|(element, pattern)| match Regex::new(&format!("^(?:{})$", pattern)) {
+            Ok(regex) => Ok(NamingConvention { element: element.clone(), pattern: pattern.clone(), regex }),
+            Err(err) => Err(ErrorKind::InvalidNamingConvention(element.clone(), err.to_string()).into()),
+        })
+        .collect()
+}
+
+/// Check the names of elements with a naming convention against
+/// it
+pub fn check_naming(elements: &[Checked], naming: &[NamingConvention], options: &ScanOptions) -> Vec<Warning> {
+    let mut res = Vec::new();
+    if naming.is_empty() {
+        return res;
+    }
+    for checked in elements {
+        let mut inside = vec![(&checked.element, checked.describe())];
+        inside.extend(checked.element.descendants().into_iter().map(|element| {
+            let label = format!("{} \"{}\" in {}", element.name, element.name_attr().unwrap_or_default(), checked.describe());
+            (element, label)
+        }));
+        for (element, label) in inside {
+            let name = match element.name_attr() {
+                Some(name) if scan::names_match(element.name, "VirtualCubeMeasure", options) => measure_name(&name),
+                Some(name) => name.into_owned(),
+                None => continue,
+            };
+            for convention in naming {
+                if scan::names_match(element.name, &convention.element, options) && !convention.regex.is_match(&name) {
+                    let message = format!("{} doesn't follow the naming convention {}", label, convention.pattern);
+                    res.push(checked.warning(element, "naming-convention", message));
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Check that each level has a column (or key expression) to
+/// read its members from, and that its `levelType`, `type` and
+/// `uniqueMembers` have values Mondrian takes
+pub fn check_levels(elements: &[Checked], options: &ScanOptions) -> Vec<Warning> {
+    let is = |element: &Element, tag: &str| scan::names_match(element.name, tag, options);
+
+    let mut res = Vec::new();
+    for checked in elements {
+        for (dimension, label) in dimensions(checked, options) {
+            for hierarchy in dimension.children.iter().filter(|child| is(child, "Hierarchy")) {
+                let hierarchy_name = hierarchy.name_attr().or_else(|| dimension.name_attr()).unwrap_or_default();
+                for level in hierarchy.children.iter().filter(|child| is(child, "Level")) {
+                    let level_label = format!(
+                        "Level \"{}\" of hierarchy \"{}\" of {}",
+                        level.name_attr().unwrap_or_default(),
+                        hierarchy_name,
+                        label
+                    );
+
+                    let has_key_expression = level.children.iter().any(|child| is(child, "KeyExpression"));
+                    if level.attr("column").is_none() && !has_key_expression {
+                        let message = format!("{} has neither a column nor a KeyExpression", level_label);
+                        res.push(checked.warning(level, "level-without-column", message));
+                    }
+
+                    let attributes = [
+                        ("levelType", LEVEL_TYPES),
+                        ("type", LEVEL_DATA_TYPES),
+                        ("uniqueMembers", &["true", "false"][..]),
+                    ];
+                    for &(attribute, values) in &attributes {
+                        match level.attr(attribute) {
+                            Some(ref value) if !values.contains(&value.as_ref()) => {
+                                let message = format!(
+                                    "{} has {} \"{}\", expected one of {}",
+                                    level_label,
+                                    attribute,
+                                    value,
+                                    values.join(", ")
+                                );
+                                res.push(checked.warning(level, "invalid-level-attribute", message));
+                            },
+                            _ => {},
+                        }
+                    }
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Check that the attributes Mondrian reads as booleans are
+/// `true` or `false`, and those it reads as numbers are whole
+/// numbers, as anything else fails the schema's parsing
+pub fn check_attribute_values(elements: &[Checked]) -> Vec<Warning> {
+    let mut res = Vec::new();
+    for checked in elements {
+        let mut inside = vec![(&checked.element, checked.describe())];
+        inside.extend(checked.element.descendants().into_iter().map(|element| {
+            let label = format!("{} \"{}\" in {}", element.name, element.name_attr().unwrap_or_default(), checked.describe());
+            (element, label)
+        }));
+        for (element, label) in inside {
+            for &(attribute, _) in &element.attributes {
+                let value = element.attr(attribute).unwrap_or_default();
+                let expected = if BOOLEAN_ATTRIBUTES.contains(&attribute) {
+                    if value == "true" || value == "false" {
+                        continue;
+                    }
+                    "true or false"
+                } else if NUMERIC_ATTRIBUTES.contains(&attribute) {
+                    if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+                        continue;
+                    }
+                    "a whole number"
+                } else {
+                    continue;
+                };
+                let message = format!("{} has {} \"{}\", expected {}", label, attribute, value, expected);
+                res.push(checked.warning(element, "invalid-attribute-value", message));
+            }
+        }
+    }
+    res
+}
+
+/// Check for attributes Mondrian has deprecated. Each has its
+/// own rule, so one which is used knowingly can be allowed.
+pub fn check_deprecated_attributes(elements: &[Checked], options: &ScanOptions) -> Vec<Warning> {
+    let mut res = Vec::new();
+    for checked in elements {
+        let mut inside = vec![(&checked.element, checked.describe())];
+        inside.extend(checked.element.descendants().into_iter().map(|element| {
+            let label = format!("{} \"{}\" in {}", element.name, element.name_attr().unwrap_or_default(), checked.describe());
+            (element, label)
+        }));
+        for (element, label) in inside {
+            for &(tags, attribute, rule, instead) in DEPRECATED_ATTRIBUTES {
+                if element.attr(attribute).is_some() && tags.iter().any(|tag| scan::names_match(element.name, tag, options)) {
+                    let message = format!("{} has {}, which is deprecated: {}", label, attribute, instead);
+                    res.push(checked.warning(element, rule, message));
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Check that each cube has a measure, calculated or not.
+/// Mondrian loads a cube without any, but it's never what was
+/// meant.
+pub fn check_cubes_have_measures(elements: &[Checked], options: &ScanOptions) -> Vec<Warning> {
+    elements.iter()
+        .filter(|checked| checked.kind == ElementKind::Cube)
+        .filter(|cube| {
+            !cube.element.children.iter().any(|child| {
+                scan::names_match(child.name, "Measure", options) || scan::names_match(child.name, "CalculatedMember", options)
+            })
+        })
+        .map(|cube| {
+            let message = format!("{} has no measures", cube.describe());
+            cube.warning(&cube.element, "cube-without-measures", message)
+        })
+        .collect()
+}
+
+/// Check that each cube's and virtual cube's `defaultMeasure`
+/// is one of its measures. A cube merged more than once (with
+/// duplicates allowed) has the measures of all its parts.
+pub fn check_default_measures(elements: &[Checked], options: &ScanOptions) -> Vec<Warning> {
+    let cubes: Vec<_> = elements.iter().filter(|checked| checked.kind.is_cube()).collect();
+    let mut measures: HashMap<String, HashSet<String>> = HashMap::new();
+    for cube in &cubes {
+        if let Some(name) = cube.name() {
+            measures.entry(name).or_default().extend(measure_names(&cube.element, options));
+        }
+    }
+
+    let mut res = Vec::new();
+    for cube in cubes {
+        let default_measure = match cube.element.attr("defaultMeasure") {
+            Some(default_measure) => measure_name(&default_measure),
+            None => continue,
+        };
+        let known = cube.name().and_then(|name| measures.get(&name)).is_some_and(|names| names.contains(&default_measure));
+        if !known {
+            let message = format!("{} has defaultMeasure \"{}\", which isn't one of its measures", cube.describe(), default_measure);
+            res.push(cube.warning(&cube.element, "unknown-default-measure", message));
+        }
+    }
+    res
+}
+
+/// Check that no two measures of a cube or virtual cube have
+/// the same name, counting calculated measures, and that no two
+/// calculated members of another dimension do
+pub fn check_duplicate_measures(elements: &[Checked], options: &ScanOptions) -> Vec<Warning> {
+    let is = |element: &Element, tag: &str| scan::names_match(element.name, tag, options);
+
+    let mut res = Vec::new();
+    for cube in elements.iter().filter(|checked| checked.kind.is_cube()) {
+        // (dimension, name) to where it was first seen
+        let mut seen: HashMap<(String, String), (usize, usize)> = HashMap::new();
+        for child in &cube.element.children {
+            let (dimension, name) = if is(child, "Measure") {
+                ("Measures".to_owned(), child.name_attr().map(|name| name.into_owned()))
+            } else if is(child, "VirtualCubeMeasure") {
+                ("Measures".to_owned(), child.name_attr().map(|name| measure_name(&name)))
+            } else if is(child, "CalculatedMember") {
+                let dimension = child.attr("dimension").unwrap_or_default().into_owned();
+                (dimension, child.name_attr().map(|name| name.into_owned()))
+            } else {
+                continue;
+            };
+            let name = match name {
+                Some(name) => name,
+                None => continue,
+            };
+            let what = if dimension == "Measures" {
+                format!("Measure \"{}\"", name)
+            } else {
+                format!("Calculated member \"{}\" of dimension \"{}\"", name, dimension)
+            };
+            let message = format!("{} of {} is defined more than once", what, cube.describe());
+            let warning = cube.warning(child, "duplicate-measure", message);
+            match seen.get(&(dimension.clone(), name.clone())) {
+                Some(&first) => res.push(Warning { first: Some(first), ..warning }),
+                None => {
+                    seen.insert((dimension, name), (warning.fragment, warning.line));
+                },
+            }
+        }
+    }
+    res
+}
+
+/// Check what roles grant access to: that each CubeGrant's
+/// cube is in the schema, and each DimensionGrant's dimension
+/// and HierarchyGrant's hierarchy is in that cube. Also that
+/// the roles a union role is made of are defined.
+pub fn check_roles(elements: &[Checked], options: &ScanOptions) -> Vec<Warning> {
+    let is = |element: &Element, tag: &str| scan::names_match(element.name, tag, options);
+
+    let cubes: HashMap<String, &Checked> = elements.iter()
+        .filter(|checked| checked.kind.is_cube())
+        .filter_map(|checked| checked.name().map(|name| (name, checked)))
+        .collect();
+    let shared_dims: HashMap<String, &Element> = elements.iter()
+        .filter(|checked| checked.kind == ElementKind::SharedDimension)
+        .filter_map(|checked| checked.name().map(|name| (name, &checked.element)))
+        .collect();
+    let roles: Vec<_> = elements.iter().filter(|checked| checked.kind == ElementKind::Role).collect();
+    let role_names: HashSet<String> = roles.iter().filter_map(|role| role.name()).collect();
+
+    let mut res = Vec::new();
+    for role in roles {
+        for element in role.element.descendants() {
+            if is(element, "RoleUsage") {
+                if let Some(name) = element.attr("roleName") {
+                    if !role_names.contains(name.as_ref()) {
+                        let message = format!("{} is a union with role \"{}\", which isn't defined", role.describe(), name);
+                        res.push(role.warning(element, "unknown-role", message));
+                    }
+                }
+                continue;
+            }
+            if !is(element, "CubeGrant") {
+                continue;
+            }
+            let cube_name = match element.attr("cube") {
+                Some(cube_name) => cube_name,
+                None => continue,
+            };
+            let cube = match cubes.get(cube_name.as_ref()) {
+                Some(cube) => cube,
+                None => {
+                    let message = format!("{} grants access to cube \"{}\", which isn't in the schema", role.describe(), cube_name);
+                    res.push(role.warning(element, "unknown-grant-target", message));
+                    continue;
+                },
+            };
+            let cube_dims = cube_hierarchies(cube, &shared_dims, options);
+
+            for grant in &element.children {
+                let reference = if is(grant, "DimensionGrant") {
+                    grant.attr("dimension")
+                } else if is(grant, "HierarchyGrant") {
+                    grant.attr("hierarchy")
+                } else {
+                    continue;
+                };
+                let segments = match reference {
+                    Some(reference) => mdx_segments(&reference),
+                    None => continue,
+                };
+                // `[Time.Weekly]` is the old way of writing
+                // `[Time].[Weekly]`
+                let (dimension, hierarchy) = match segments.len() {
+                    0 => continue,
+                    1 if !cube_dims.contains_key(&segments[0]) && segments[0].contains('.') => {
+                        let dot = segments[0].find('.').unwrap_or_default();
+                        (segments[0][..dot].to_owned(), Some(segments[0][dot + 1..].to_owned()))
+                    },
+                    1 => (segments[0].clone(), None),
+                    _ => (segments[0].clone(), Some(segments[1].clone())),
+                };
+                if dimension == "Measures" {
+                    continue;
+                }
+                match (cube_dims.get(&dimension), hierarchy) {
+                    (None, _) => {
+                        let message = format!(
+                            "{} grants access to dimension \"{}\" of {}, which isn't in that cube",
+                            role.describe(), dimension, cube.describe()
+                        );
+                        res.push(role.warning(grant, "unknown-grant-target", message));
+                    },
+                    (Some(Some(hierarchies)), Some(hierarchy)) if !hierarchies.contains_key(&hierarchy) => {
+                        let message = format!(
+                            "{} grants access to hierarchy \"{}\" of dimension \"{}\" of {}, which isn't in that dimension",
+                            role.describe(), hierarchy, dimension, cube.describe()
+                        );
+                        res.push(role.warning(grant, "unknown-grant-target", message));
+                    },
+                    _ => {},
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Check that no virtual cube is built on itself through other
+/// virtual cubes, and that no union role is made of itself
+/// through other roles, which Mondrian can't load. Each cycle is
+/// reported once, where it closes.
+pub fn check_cycles(elements: &[Checked], options: &ScanOptions) -> Vec<Warning> {
+    let mut res = cycles(elements, ElementKind::VirtualCube, &["CubeUsage", "VirtualCubeDimension", "VirtualCubeMeasure"], "cubeName", options);
+    res.extend(cycles(elements, ElementKind::Role, &["RoleUsage"], "roleName", options));
+    res
+}
+
+/// The cycles among the `kind` elements, each referring to
+/// others with `attribute` of `tags` elements in it
+fn cycles(elements: &[Checked], kind: ElementKind, tags: &[&str], attribute: &str, options: &ScanOptions) -> Vec<Warning> {
+    let nodes: Vec<_> = elements.iter().filter(|checked| checked.kind == kind).collect();
+    let mut names = HashMap::new();
+    for (i, node) in nodes.iter().enumerate() {
+        if let Some(name) = node.name() {
+            names.entry(name).or_insert(i);
+        }
+    }
+    // the references of each node, to the node referred to
+    let references: Vec<Vec<(usize, &Element)>> = nodes.iter()
+        .map(|node| node.element.descendants().into_iter()
+            .filter(|element| tags.iter().any(|tag| scan::names_match(element.name, tag, options)))
+            .filter_map(|element| Some((*names.get(element.attr(attribute)?.as_ref())?, element)))
+            .collect())
+        .collect();
+
+    // depth first, with a reference back to a node on the path
+    // closing a cycle: (node, reference, the nodes in the cycle)
+    fn visit(i: usize, references: &[Vec<(usize, &Element)>], path: &mut Vec<usize>, done: &mut [bool], found: &mut Vec<(usize, usize, Vec<usize>)>) {
+        if done[i] {
+            return;
+        }
+        path.push(i);
+        for (k, &(to, _)) in references[i].iter().enumerate() {
+            match path.iter().position(|&on_path| on_path == to) {
+                Some(pos) => found.push((i, k, path[pos..].to_vec())),
+                None => visit(to, references, path, done, found),
+            }
+        }
+        path.pop();
+        done[i] = true;
+    }
+    let mut found = Vec::new();
+    let mut done = vec![false; nodes.len()];
+    for i in 0..nodes.len() {
+        visit(i, &references, &mut Vec::new(), &mut done, &mut found);
+    }
+
+    let mut res = Vec::new();
+    let mut reported = HashSet::new();
+    for (i, k, cycle) in found {
+        let mut members = cycle.clone();
+        members.sort();
+        if !reported.insert(members) {
+            continue;
+        }
+        // from where it closes, round to it again
+        let through: Vec<_> = Some(i).into_iter().chain(cycle).map(|j| nodes[j].name().unwrap_or_default()).collect();
+        let message = format!("{} refers back to itself: {}", nodes[i].describe(), through.join(" -> "));
+        res.push(nodes[i].warning(references[i][k].1, "circular-reference", message));
+    }
+    res
+}
+
+/// Check that the formulas of calculated members and named sets
+/// only refer to named sets which are in the schema: their own
+/// cube's or top level ones. A name on its own in brackets, like
+/// `[Top Sellers]`, which isn't a set, dimension, hierarchy,
+/// level or measure is taken for a set which is missing. Also
+/// that no two fragments define a set with the same name in a
+/// cube, or one in a cube with the name of a top level set.
+pub fn check_named_sets(elements: &[Checked], options: &ScanOptions) -> Vec<Warning> {
+    let is = |element: &Element, tag: &str| scan::names_match(element.name, tag, options);
+    let top_level: Vec<_> = elements.iter().filter(|checked| checked.kind == ElementKind::NamedSet).collect();
+
+    let mut res = Vec::new();
+    // name to the fragment and line it was first defined at; top
+    // level sets defined twice are already duplicate names
+    let mut global_sets: HashMap<String, (usize, usize)> = HashMap::new();
+    for set in &top_level {
+        if let Some(name) = set.name() {
+            let (fragment, line, _, _) = set.locate(set.element.start);
+            global_sets.entry(name).or_insert((fragment, line));
+        }
+    }
+
+    // the sets of each cube, with those of cubes merged more
+    // than once together
+    let cubes: Vec<_> = elements.iter().filter(|checked| checked.kind.is_cube()).collect();
+    let mut cube_sets: HashMap<String, HashMap<String, (usize, usize)>> = HashMap::new();
+    for cube in &cubes {
+        let sets = cube_sets.entry(cube.name().unwrap_or_default()).or_default();
+        for set in cube.element.children.iter().filter(|child| is(child, "NamedSet")) {
+            let name = match set.name_attr() {
+                Some(name) => name.into_owned(),
+                None => continue,
+            };
+            let message = format!("Named set \"{}\" of {} is defined more than once", name, cube.describe());
+            let warning = cube.warning(set, "duplicate-named-set", message);
+            match sets.get(&name).or_else(|| global_sets.get(&name)) {
+                Some(&first) if first.0 != warning.fragment => res.push(Warning { first: Some(first), ..warning }),
+                Some(_) => {},
+                None => {
+                    sets.insert(name, (warning.fragment, warning.line));
+                },
+            }
+        }
+    }
+
+    // everything else a name on its own can refer to
+    let mut names: HashSet<String> = HashSet::new();
+    names.insert("Measures".to_owned());
+    for checked in elements {
+        let mut inside = checked.element.descendants();
+        inside.push(&checked.element);
+        for element in inside {
+            if is(element, "NamedSet") {
+                continue;
+            }
+            if let Some(name) = element.name_attr() {
+                if is(element, "VirtualCubeMeasure") {
+                    names.insert(measure_name(&name));
+                    continue;
+                }
+                for hierarchy in element.children.iter().filter(|child| is(child, "Hierarchy")) {
+                    if let Some(hierarchy_name) = hierarchy.name_attr() {
+                        names.insert(format!("{}.{}", name, hierarchy_name));
+                    }
+                }
+                names.insert(name.into_owned());
+            }
+        }
+    }
+
+    let mut check = |checked: &Checked, element: &Element, sets: Option<&HashMap<String, (usize, usize)>>| {
+        for reference in set_references(&formula(element, options)) {
+            let known = names.contains(&reference)
+                || global_sets.contains_key(&reference)
+                || sets.is_some_and(|sets| sets.contains_key(&reference));
+            if !known {
+                let what = if checked.kind == ElementKind::NamedSet {
+                    checked.describe()
+                } else {
+                    format!("{} \"{}\" in {}", element.name, element.name_attr().unwrap_or_default(), checked.describe())
+                };
+                let message = format!(
+                    "The formula of {} refers to [{}], which isn't a named set or anything else in the schema",
+                    what, reference
+                );
+                res.push(checked.warning(element, "unknown-named-set", message));
+            }
+        }
+    };
+    for cube in &cubes {
+        let sets = cube_sets.get(&cube.name().unwrap_or_default());
+        for element in cube.element.descendants().into_iter().filter(|element| is(element, "CalculatedMember") || is(element, "NamedSet")) {
+            check(cube, element, sets);
+        }
+    }
+    for set in &top_level {
+        check(set, &set.element, None);
+    }
+    res
+}
+
+/// Check the aggregate tables declared in each cube's fact
+/// table: that each `<AggName>` and `<AggPattern>` has an
+/// `<AggFactCount>` and at least one `<AggMeasure>`, and that the
+/// measures and levels it aggregates are the cube's.
+pub fn check_aggregates(elements: &[Checked], options: &ScanOptions) -> Vec<Warning> {
+    let is = |element: &Element, tag: &str| scan::names_match(element.name, tag, options);
+    let shared_dims: HashMap<String, &Element> = elements.iter()
+        .filter(|checked| checked.kind == ElementKind::SharedDimension)
+        .filter_map(|checked| checked.name().map(|name| (name, &checked.element)))
+        .collect();
+
+    let mut res = Vec::new();
+    for cube in elements.iter().filter(|checked| checked.kind == ElementKind::Cube) {
+        let aggregates: Vec<_> = cube.element.children.iter()
+            .filter(|child| is(child, "Table"))
+            .flat_map(|table| &table.children)
+            .filter(|child| is(child, "AggName") || is(child, "AggPattern"))
+            .collect();
+        if aggregates.is_empty() {
+            continue;
+        }
+        let measures: HashSet<String> = cube.element.children.iter()
+            .filter(|child| is(child, "Measure"))
+            .filter_map(|measure| measure.name_attr().map(|name| name.into_owned()))
+            .collect();
+        let cube_dims = cube_hierarchies(cube, &shared_dims, options);
+
+        for aggregate in aggregates {
+            let what = if is(aggregate, "AggName") {
+                format!("Aggregate table \"{}\" of {}", aggregate.name_attr().unwrap_or_default(), cube.describe())
+            } else {
+                format!("Aggregate pattern \"{}\" of {}", aggregate.attr("pattern").unwrap_or_default(), cube.describe())
+            };
+            if !aggregate.children.iter().any(|child| is(child, "AggFactCount")) {
+                res.push(cube.warning(aggregate, "incomplete-aggregate", format!("{} has no AggFactCount", what)));
+            }
+            if !aggregate.children.iter().any(|child| is(child, "AggMeasure")) {
+                res.push(cube.warning(aggregate, "incomplete-aggregate", format!("{} has no AggMeasure", what)));
+            }
+
+            for child in &aggregate.children {
+                let reference = match child.name_attr() {
+                    Some(reference) => reference,
+                    None => continue,
+                };
+                if is(child, "AggMeasure") {
+                    let measure = measure_name(&reference);
+                    if !measures.contains(&measure) {
+                        let message = format!("{} aggregates measure \"{}\", which isn't one of the cube's measures", what, measure);
+                        res.push(cube.warning(child, "unknown-aggregate-target", message));
+                    }
+                } else if is(child, "AggLevel") {
+                    let mut segments = mdx_segments(&reference);
+                    // `[Time.Weekly].[Year]` is the old way of
+                    // writing `[Time].[Weekly].[Year]`
+                    if segments.len() == 2 && !cube_dims.contains_key(&segments[0]) {
+                        if let Some(dot) = segments[0].find('.') {
+                            let hierarchy = segments[0][dot + 1..].to_owned();
+                            segments[0].truncate(dot);
+                            segments.insert(1, hierarchy);
+                        }
+                    }
+                    let known = match (segments.len(), cube_dims.get(&segments[0])) {
+                        (_, Some(None)) => true,
+                        (2, Some(Some(hierarchies))) => hierarchies.values().any(|levels| levels.contains(&segments[1])),
+                        (3, Some(Some(hierarchies))) => hierarchies.get(&segments[1]).is_some_and(|levels| levels.contains(&segments[2])),
+                        _ => false,
+                    };
+                    if !known {
+                        let message = format!("{} aggregates level \"{}\", which isn't in the cube", what, reference);
+                        res.push(cube.warning(child, "unknown-aggregate-target", message));
+                    }
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Check the writeback table of each cube: that the dimension
+/// of each `<WritebackAttribute>` and the measure of each
+/// `<WritebackMeasure>` are the cube's.
+pub fn check_writeback(elements: &[Checked], options: &ScanOptions) -> Vec<Warning> {
+    let is = |element: &Element, tag: &str| scan::names_match(element.name, tag, options);
+    let shared_dims: HashMap<String, &Element> = elements.iter()
+        .filter(|checked| checked.kind == ElementKind::SharedDimension)
+        .filter_map(|checked| checked.name().map(|name| (name, &checked.element)))
+        .collect();
+
+    let mut res = Vec::new();
+    for cube in elements.iter().filter(|checked| checked.kind == ElementKind::Cube) {
+        let tables: Vec<_> = cube.element.children.iter()
+            .filter(|child| is(child, "WritebackTable"))
+            .collect();
+        if tables.is_empty() {
+            continue;
+        }
+        // only stored measures can be written back
+        let measures: HashSet<String> = cube.element.children.iter()
+            .filter(|child| is(child, "Measure"))
+            .filter_map(|measure| measure.name_attr().map(|name| name.into_owned()))
+            .collect();
+        let cube_dims = cube_hierarchies(cube, &shared_dims, options);
+
+        for table in tables {
+            let what = format!("Writeback table \"{}\" of {}", table.name_attr().unwrap_or_default(), cube.describe());
+            for child in &table.children {
+                if is(child, "WritebackAttribute") {
+                    let dimension = match child.attr("dimension") {
+                        Some(dimension) => mdx_segments(&dimension).into_iter().next().unwrap_or_default(),
+                        None => continue,
+                    };
+                    if !cube_dims.contains_key(&dimension) {
+                        let message = format!("{} writes back dimension \"{}\", which isn't one of the cube's dimensions", what, dimension);
+                        res.push(cube.warning(child, "unknown-writeback-target", message));
+                    }
+                } else if is(child, "WritebackMeasure") {
+                    let measure = match child.name_attr() {
+                        Some(measure) => measure_name(&measure),
+                        None => continue,
+                    };
+                    if !measures.contains(&measure) {
+                        let message = format!("{} writes back measure \"{}\", which isn't one of the cube's stored measures", what, measure);
+                        res.push(cube.warning(child, "unknown-writeback-target", message));
+                    }
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Check that the elements users see (cubes, dimensions,
+/// hierarchies, levels and measures) have an `attribute`, which
+/// is `caption` or `description`. Warnings are for `rule`.
+pub fn check_captions(elements: &[Checked], options: &ScanOptions, attribute: &str, rule: &'static str) -> Vec<Warning> {
+    let mut res = Vec::new();
+    for checked in elements {
+        let inside = checked.element.descendants();
+        for element in Some(&checked.element).into_iter().chain(inside) {
+            if !CAPTIONED_TAGS.iter().any(|tag| scan::names_match(element.name, tag, options)) {
+                continue;
+            }
+            if element.attr(attribute).is_some_and(|value| !value.trim().is_empty()) {
+                continue;
+            }
+            let label = if element.start == checked.element.start {
+                checked.describe()
+            } else {
+                match element.name_attr() {
+                    Some(name) => format!("{} \"{}\" in {}", element.name, name, checked.describe()),
+                    None => format!("{} in {}", element.name, checked.describe()),
+                }
+            };
+            res.push(checked.warning(element, rule, format!("{} has no {}", label, attribute)));
+        }
+    }
+    res
+}
+
+/// Check each element against the Mondrian 3 content model:
+/// that it's a Mondrian element, holds only what it may, and has
+/// the attributes it needs
+pub fn check_structure(elements: &[Checked], options: &ScanOptions) -> Vec<Warning> {
+    let mut res = Vec::new();
+    for checked in elements {
+        check_element_structure(checked, &checked.element, options, &mut res);
+    }
+    res
+}
+
+fn check_element_structure(checked: &Checked, element: &Element, options: &ScanOptions, res: &mut Vec<Warning>) {
+    let structure = match mondrian::structure(element.name, options.ignore_case) {
+        Some(structure) => structure,
+        None => {
+            let message = format!("Unknown element <{}> in {}", element.name, checked.describe());
+            res.push(checked.warning(element, "invalid-structure", message));
+            return;
+        },
+    };
+
+    for attribute in structure.required {
+        if element.attr(attribute).is_none() {
+            let message = format!(
+                "<{}> in {} is missing the required attribute {}",
+                element.name,
+                checked.describe(),
+                attribute
+            );
+            res.push(checked.warning(element, "invalid-structure", message));
+        }
+    }
+
+    for child in &element.children {
+        let allowed = structure.children.iter().any(|name| scan::names_match(child.name, name, options));
+        if !allowed && mondrian::structure(child.name, options.ignore_case).is_some() {
+            let message = format!("<{}> isn't allowed in <{}>, in {}", child.name, element.name, checked.describe());
+            res.push(checked.warning(child, "invalid-structure", message));
+        }
+        check_element_structure(checked, child, options, res);
+    }
+}
+
+/// The dimensions which are, or are in, a merged element, each
+/// with how to refer to it in warnings. Virtual cubes only
+/// refer to dimensions, so have none.
+fn dimensions<'c, 'a>(checked: &'c Checked<'a>, options: &ScanOptions) -> Vec<(&'c Element<'a>, String)> {
+    match checked.kind {
+        ElementKind::SharedDimension => vec![(&checked.element, checked.describe())],
+        ElementKind::Cube => checked.element.children.iter()
+            .filter(|child| scan::names_match(child.name, "Dimension", options))
+            .map(|dimension| {
+                let label = format!("dimension \"{}\" of {}", dimension.name_attr().unwrap_or_default(), checked.describe());
+                (dimension, label)
+            })
+            .collect(),
+        ElementKind::Parameter
+        | ElementKind::VirtualCube
+        | ElementKind::NamedSet
+        | ElementKind::Role
+        | ElementKind::UserDefinedFunction => Vec::new(),
+    }
+}
+
+/// The formula of a calculated member or named set, from its
+/// `formula` attribute or its `<Formula>`
+fn formula(element: &Element, options: &ScanOptions) -> String {
+    match element.attr("formula") {
+        Some(formula) => formula.into_owned(),
+        None => element.children.iter()
+            .filter(|child| scan::names_match(child.name, "Formula", options))
+            .map(|child| text_content(child.body))
+            .collect(),
+    }
+}
+
+/// The names in an MDX formula which are in brackets on their
+/// own, like `[Top Sellers]` but not `[Store].[USA]`, leaving
+/// out string literals
+fn set_references(formula: &str) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut chars = formula.chars().peekable();
+    // whether the last thing outside brackets was a dot
+    let mut after_dot = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '[' => {
+                let mut name = String::new();
+                while let Some(c) = chars.next() {
+                    if c == ']' {
+                        if chars.peek() == Some(&']') {
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                    name.push(c);
+                }
+                while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                    chars.next();
+                }
+                if !after_dot && chars.peek() != Some(&'.') {
+                    res.push(name);
+                }
+                after_dot = false;
+            },
+            '"' | '\'' => {
+                for end in chars.by_ref() {
+                    if end == c {
+                        break;
+                    }
+                }
+                after_dot = false;
+            },
+            '.' => after_dot = true,
+            // `&` marks a key, as in `[Time].&[1997]`
+            '&' => {},
+            c if c.is_whitespace() => {},
+            _ => after_dot = false,
+        }
+    }
+    res
+}
+
+/// A dimension's hierarchies by name, each with the names of
+/// its levels
+type Hierarchies = HashMap<String, HashSet<String>>;
+
+/// The names of a cube's dimensions, each with its hierarchies,
+/// where they're known. Virtual cubes' dimensions and those of
+/// shared dimensions which aren't in the schema have none.
+fn cube_hierarchies(cube: &Checked, shared_dims: &HashMap<String, &Element>, options: &ScanOptions) -> HashMap<String, Option<Hierarchies>> {
+    let hierarchies = |dimension: &Element, name: &str| -> Hierarchies {
+        dimension.children.iter()
+            .filter(|child| scan::names_match(child.name, "Hierarchy", options))
+            .map(|hierarchy| {
+                let levels = hierarchy.children.iter()
+                    .filter(|child| scan::names_match(child.name, "Level", options))
+                    .filter_map(|level| level.name_attr().map(|name| name.into_owned()))
+                    .collect();
+                (hierarchy.name_attr().map(|name| name.into_owned()).unwrap_or_else(|| name.to_owned()), levels)
+            })
+            .collect()
+    };
+
+    let mut res = HashMap::new();
+    for child in &cube.element.children {
+        let name = match child.name_attr() {
+            Some(name) => name.into_owned(),
+            None => continue,
+        };
+        if scan::names_match(child.name, "Dimension", options) {
+            let dimension_hierarchies = hierarchies(child, &name);
+            res.insert(name, Some(dimension_hierarchies));
+        } else if scan::names_match(child.name, "DimensionUsage", options) {
+            let shared = child.attr("source").and_then(|source| shared_dims.get(source.as_ref()));
+            let dimension_hierarchies = shared.map(|shared| hierarchies(shared, &name));
+            res.insert(name, dimension_hierarchies);
+        } else if scan::names_match(child.name, "VirtualCubeDimension", options) {
+            res.insert(name, None);
+        }
+    }
+    res
+}
+
+/// The parts of an MDX name like `[Time].[Weekly]`, unquoted
+fn mdx_segments(reference: &str) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut chars = reference.trim().chars().peekable();
+    while chars.peek().is_some() {
+        let mut segment = String::new();
+        if chars.peek() == Some(&'[') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                if c == ']' {
+                    if chars.peek() == Some(&']') {
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                segment.push(c);
+            }
+            // up to the dot before the next part
+            for c in chars.by_ref() {
+                if c == '.' {
+                    break;
+                }
+            }
+        } else {
+            for c in chars.by_ref() {
+                if c == '.' {
+                    break;
+                }
+                segment.push(c);
+            }
+        }
+        res.push(segment);
+    }
+    res
+}
+
+/// Whether any hierarchy of a dimension reads its members from
+/// a table, rather than the fact table
+fn joins_table(dimension: &Element, options: &ScanOptions) -> bool {
+    dimension.children.iter()
+        .filter(|child| scan::names_match(child.name, "Hierarchy", options))
+        .any(|hierarchy| {
+            hierarchy.children.iter().any(|child| RELATION_TAGS.iter().any(|tag| scan::names_match(child.name, tag, options)))
+        })
+}
+
+/// The text of an element body, unescaped, with CDATA sections
+/// unwrapped
+pub fn text_content(body: &str) -> String {
+    let mut res = String::new();
+    let mut rest = body;
+    while let Some(start) = rest.find("<![CDATA[") {
+        res.push_str(&escape::unescape(&rest[..start]).unwrap_or(Cow::Borrowed(&rest[..start])));
+        let data = &rest[start + "<![CDATA[".len()..];
+        let end = data.find("]]>").unwrap_or(data.len());
+        res.push_str(&data[..end]);
+        rest = &data[(end + "]]>".len()).min(data.len())..];
+    }
+    res.push_str(&escape::unescape(rest).unwrap_or(Cow::Borrowed(rest)));
+    res
+}
+
+/// What's unbalanced in an MDX formula, if anything. Brackets
+/// quote identifiers (with `]]` for a `]`), and quotes strings
+/// (with a doubled quote for a quote); neither hold any other
+/// brackets. Comments are skipped.
+fn formula_problem(formula: &str) -> Option<String> {
+    let mut open: Vec<char> = Vec::new();
+    let mut chars = formula.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '[' | '"' | '\'' => {
+                let close = if c == '[' { ']' } else { c };
+                let mut closed = false;
+                while let Some(c) = chars.next() {
+                    if c == close {
+                        if chars.peek() == Some(&close) {
+                            chars.next();
+                        } else {
+                            closed = true;
+                            break;
+                        }
+                    }
+                }
+                if !closed {
+                    return Some(format!("has an unclosed {}", c));
+                }
+            },
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        closed = true;
+                        break;
+                    }
+                    last = c;
+                }
+                if !closed {
+                    return Some("has an unclosed /* comment".to_owned());
+                }
+            },
+            '/' | '-' if chars.peek() == Some(&c) => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            },
+            '(' | '{' => open.push(c),
+            ')' | '}' => {
+                let opening = if c == ')' { '(' } else { '{' };
+                match open.pop() {
+                    Some(last) if last == opening => {},
+                    Some(last) => return Some(format!("closes {} with {}", last, c)),
+                    None => return Some(format!("has an unmatched {}", c)),
+                }
+            },
+            ']' => return Some("has an unmatched ]".to_owned()),
+            _ => {},
+        }
+    }
+    open.pop().map(|c| format!("has an unclosed {}", c))
+}
+
+/// The names of a cube's measures, including calculated ones,
+/// or those of a virtual cube
+fn measure_names(cube: &Element, options: &ScanOptions) -> HashSet<String> {
+    cube.children.iter()
+        .filter_map(|child| {
+            let is_measure = scan::names_match(child.name, "Measure", options)
+                || (scan::names_match(child.name, "CalculatedMember", options)
+                    && child.attr("dimension").is_some_and(|dimension| dimension == "Measures"));
+            if is_measure {
+                child.name_attr().map(|name| name.into_owned())
+            } else if scan::names_match(child.name, "VirtualCubeMeasure", options) {
+                child.name_attr().map(|name| measure_name(&name))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// The name of a measure referred to as `[Measures].[Name]`, or
+/// just `Name`
+fn measure_name(reference: &str) -> String {
+    let reference = reference.trim();
+    let name = reference.strip_prefix("[Measures].").unwrap_or(reference);
+    if name.starts_with('[') && name.ends_with(']') && name.len() >= 2 {
+        name[1..name.len() - 1].replace("]]", "]")
+    } else {
+        name.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_dimension_usages() {
+        let f0 = "<Schema name=\"s\">\n<SharedDimension name=\"Time\"/>\n</Schema>";
+        let f1 = "<Cube name=\"Sales\">\n  <DimensionUsage name=\"Date\" source=\"Time\"/>\n  <DimensionUsage name=\"Store\" source=\"Store\"/>\n</Cube>";
+        let elements = vec![
+            Checked::new(ElementKind::SharedDimension, 0, f0, &f0[18..48]).unwrap(),
+            Checked::new(ElementKind::Cube, 1, f1, f1).unwrap(),
+        ];
+        assert_eq!(
+            check_dimension_usages(&elements, &ScanOptions::default()),
+            vec![Warning {
+                fragment: 1,
+                line: 3,
+                column: 3,
+                rule: "unknown-dimension",
+                element: Some("Cube \"Sales\"".to_owned()),
+                path: vec!["Cube \"Sales\"".to_owned(), "DimensionUsage \"Store\"".to_owned()],
+                span: 68..113,
+                message: "Cube \"Sales\" uses shared dimension \"Store\", which isn't in the schema".to_owned(),
+                first: None,
+            }]
+        );
+
+        // tags are matched like the rest of the fragment
+        let f1 = "<Cube name=\"Sales\"><dimensionusage source=\"Store\"/></Cube>";
+        let elements = vec![Checked::new(ElementKind::Cube, 0, f1, f1).unwrap()];
+        assert_eq!(check_dimension_usages(&elements, &ScanOptions::default()), vec![]);
+        let options = ScanOptions { ignore_case: true, ..ScanOptions::default() };
+        assert_eq!(check_dimension_usages(&elements, &options).len(), 1);
+    }
+
+    #[test]
+    fn test_check_unused_dimensions() {
+        let f0 = "<SharedDimension name=\"Time\"/><SharedDimension name=\"Store\"/><SharedDimension name=\"Spare\"/>";
+        let f1 = "<Cube name=\"Sales\"><DimensionUsage name=\"Date\" source=\"Time\"/></Cube>";
+        let f2 = "<VirtualCube name=\"v\"><VirtualCubeDimension name=\"Store\"/><VirtualCubeDimension cubeName=\"Sales\" name=\"Spare\"/></VirtualCube>";
+        let elements = vec![
+            Checked::new(ElementKind::SharedDimension, 0, f0, &f0[..30]).unwrap(),
+            Checked::new(ElementKind::SharedDimension, 0, f0, &f0[30..61]).unwrap(),
+            Checked::new(ElementKind::SharedDimension, 0, f0, &f0[61..]).unwrap(),
+            Checked::new(ElementKind::Cube, 1, f1, f1).unwrap(),
+            Checked::new(ElementKind::VirtualCube, 2, f2, f2).unwrap(),
+        ];
+        let warnings = check_unused_dimensions(&elements, &ScanOptions::default());
+        let found: Vec<_> = warnings.iter().map(|w| (w.column, w.rule, w.message.as_str())).collect();
+        assert_eq!(found, vec![(62, "unused-dimension", "Shared dimension \"Spare\" isn't used by any cube")]);
+    }
+
+    #[test]
+    fn test_check_virtual_cubes() {
+        let f0 = "<Cube name=\"Sales\"><Dimension name=\"Store\"/><Measure name=\"Units\"/><CalculatedMember name=\"Profit\" dimension=\"Measures\"/></Cube>";
+        let f1 = "<VirtualCube name=\"v\">
+  <VirtualCubeDimension cubeName=\"Sales\" name=\"Store\"/>
+  <VirtualCubeDimension cubeName=\"Returns\" name=\"Store\"/>
+  <VirtualCubeDimension name=\"Time\"/>
+  <VirtualCubeMeasure cubeName=\"Sales\" name=\"[Measures].[Units]\"/>
+  <VirtualCubeMeasure cubeName=\"Sales\" name=\"[Measures].[Profit]\"/>
+  <VirtualCubeMeasure cubeName=\"Sales\" name=\"[Measures].[Cost]\"/>
+  <VirtualCubeDimension cubeName=\"Sales\" name=\"Promotion\"/>
+  <VirtualCubeDimension name=\"Product\"/>
+</VirtualCube>";
+        let f2 = "<SharedDimension name=\"Time\"/>";
+        let elements = vec![
+            Checked::new(ElementKind::Cube, 0, f0, f0).unwrap(),
+            Checked::new(ElementKind::VirtualCube, 1, f1, f1).unwrap(),
+            Checked::new(ElementKind::SharedDimension, 2, f2, f2).unwrap(),
+        ];
+        let warnings = check_virtual_cubes(&elements, &ScanOptions::default());
+        let found: Vec<_> = warnings.iter().map(|w| (w.line, w.rule, w.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (3, "unknown-cube", "Virtual cube \"v\" uses cube \"Returns\", which isn't in the schema"),
+                (7, "unknown-measure", "Virtual cube \"v\" uses measure \"Cost\" of cube \"Sales\", which isn't in that cube"),
+                (
+                    8,
+                    "unknown-cube-dimension",
+                    "Virtual cube \"v\" uses dimension \"Promotion\" of cube \"Sales\", which isn't in that cube"
+                ),
+                (9, "unknown-dimension", "Virtual cube \"v\" uses shared dimension \"Product\", which isn't in the schema"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_measures() {
+        let f0 = "<Cube name=\"Sales\">
+  <Measure name=\"Units\" column=\"units\" aggregator=\"sum\"/>
+  <Measure name=\"Stores\" aggregator=\"distinct count\"><MeasureExpression/></Measure>
+  <Measure name=\"Avg\" column=\"price\" aggregator=\"average\"/>
+  <Measure name=\"Cost\" aggregator=\"sum\"/>
+  <Measure name=\"Bare\"/>
+</Cube>";
+        let elements = vec![Checked::new(ElementKind::Cube, 0, f0, f0).unwrap()];
+        let warnings = check_measures(&elements, &ScanOptions::default());
+        let found: Vec<_> = warnings.iter().map(|w| (w.line, w.rule, w.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    4,
+                    "invalid-aggregator",
+                    "Measure \"Avg\" of Cube \"Sales\" has an unknown aggregator \"average\", expected one of \
+                     sum, count, min, max, avg, distinct-count, distinct count, none"
+                ),
+                (5, "measure-without-column", "Measure \"Cost\" of Cube \"Sales\" has neither a column nor a MeasureExpression"),
+                (6, "invalid-aggregator", "Measure \"Bare\" of Cube \"Sales\" has no aggregator"),
+                (6, "measure-without-column", "Measure \"Bare\" of Cube \"Sales\" has neither a column nor a MeasureExpression"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_default_measures() {
+        let f0 = "<Cube name=\"Sales\" defaultMeasure=\"Units\"><Measure name=\"Units\"/></Cube>";
+        let f1 = "<Cube name=\"Returns\" defaultMeasure=\"[Measures].[Profit]\"><Measure name=\"Units\"/></Cube>";
+        let f2 = "<Cube name=\"Returns\"><CalculatedMember name=\"Profit\" dimension=\"Measures\"/></Cube>";
+        let f3 = "<VirtualCube name=\"v\" defaultMeasure=\"Cost\">
+  <VirtualCubeMeasure cubeName=\"Sales\" name=\"[Measures].[Units]\"/>
+</VirtualCube>";
+        let elements = vec![
+            Checked::new(ElementKind::Cube, 0, f0, f0).unwrap(),
+            Checked::new(ElementKind::Cube, 1, f1, f1).unwrap(),
+            Checked::new(ElementKind::Cube, 2, f2, f2).unwrap(),
+            Checked::new(ElementKind::VirtualCube, 3, f3, f3).unwrap(),
+        ];
+        let warnings = check_default_measures(&elements, &ScanOptions::default());
+        let found: Vec<_> = warnings.iter().map(|w| (w.fragment, w.rule, w.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![(3, "unknown-default-measure", "Virtual cube \"v\" has defaultMeasure \"Cost\", which isn't one of its measures")]
+        );
+    }
+
+    #[test]
+    fn test_check_duplicate_measures() {
+        let f0 = "<Cube name=\"Sales\">
+  <Measure name=\"Units\"/>
+  <CalculatedMember name=\"Units\" dimension=\"Measures\"/>
+  <CalculatedMember name=\"Units\" dimension=\"Store\"/>
+  <CalculatedMember name=\"Top\" dimension=\"Store\"/><CalculatedMember name=\"Top\" dimension=\"Store\"/>
+</Cube>";
+        let f1 = "<VirtualCube name=\"v\">
+  <VirtualCubeMeasure cubeName=\"Sales\" name=\"[Measures].[Units]\"/>
+  <VirtualCubeMeasure cubeName=\"Returns\" name=\"[Measures].[Units]\"/>
+</VirtualCube>";
+        let elements = vec![
+            Checked::new(ElementKind::Cube, 0, f0, f0).unwrap(),
+            Checked::new(ElementKind::VirtualCube, 1, f1, f1).unwrap(),
+        ];
+        let warnings = check_duplicate_measures(&elements, &ScanOptions::default());
+        let found: Vec<_> = warnings.iter().map(|w| (w.line, w.column, w.first, w.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (3, 3, Some((0, 2)), "Measure \"Units\" of Cube \"Sales\" is defined more than once"),
+                (
+                    5,
+                    51,
+                    Some((0, 5)),
+                    "Calculated member \"Top\" of dimension \"Store\" of Cube \"Sales\" is defined more than once"
+                ),
+                (3, 3, Some((1, 2)), "Measure \"Units\" of Virtual cube \"v\" is defined more than once"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_foreign_keys() {
+        let f0 = "<SharedDimension name=\"Store\"><Hierarchy><Table name=\"store\"/></Hierarchy></SharedDimension>";
+        let f1 = "<SharedDimension name=\"Flag\"><Hierarchy><Level name=\"f\" column=\"f\"/></Hierarchy></SharedDimension>";
+        let f2 = "<Cube name=\"Sales\">
+  <DimensionUsage name=\"Store\" source=\"Store\" foreignKey=\"store_id\"/>
+  <DimensionUsage name=\"Shop\" source=\"Store\"/>
+  <DimensionUsage name=\"Flag\" source=\"Flag\"/>
+  <Dimension name=\"Promo\"><Hierarchy><Table name=\"promotion\"/></Hierarchy></Dimension>
+  <Dimension name=\"Degenerate\"><Hierarchy><Level name=\"l\" column=\"c\"/></Hierarchy></Dimension>
+</Cube>";
+        let elements = vec![
+            Checked::new(ElementKind::SharedDimension, 0, f0, f0).unwrap(),
+            Checked::new(ElementKind::SharedDimension, 1, f1, f1).unwrap(),
+            Checked::new(ElementKind::Cube, 2, f2, f2).unwrap(),
+        ];
+        let warnings = check_foreign_keys(&elements, &ScanOptions::default());
+        let found: Vec<_> = warnings.iter().map(|w| (w.line, w.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (3, "DimensionUsage \"Shop\" of Cube \"Sales\" has no foreignKey to join shared dimension \"Store\" on"),
+                (5, "Dimension \"Promo\" of Cube \"Sales\" has no foreignKey to join its table on"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_names() {
+        let f0 = format!(
+            "<Cube name=\"Sales \">
+  <Dimension name=\"[Store]\"/>
+  <Measure name=\"Units&#9;\"/>
+  <Measure name=\"Fine name\"/>
+  <Measure name=\"{}\"/>
+  <Table name=\"[dbo].[sales]\"/>
+</Cube>",
+            "x".repeat(101)
+        );
+        let elements = vec![Checked::new(ElementKind::Cube, 0, &f0, &f0).unwrap()];
+        let warnings = check_names(&elements, &ScanOptions::default());
+        let found: Vec<_> = warnings.iter().map(|w| (w.line, w.rule, w.message.clone())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (1, "invalid-name", "The name of Cube \"Sales \" has whitespace at either end".to_owned()),
+                (2, "invalid-name", "The name of Dimension \"[Store]\" in Cube \"Sales \" has brackets".to_owned()),
+                (3, "invalid-name", "The name of Measure \"Units\t\" in Cube \"Sales \" has whitespace at either end".to_owned()),
+                (
+                    5,
+                    "long-name",
+                    format!("The name of Measure \"{}\" in Cube \"Sales \" is 101 characters long, more than 100", "x".repeat(101))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_formula_problem() {
+        assert_eq!(formula_problem("[Measures].[Units] * (1 + [Measures].[Rate])"), None);
+        assert_eq!(formula_problem("{[Time].[2018], [Time].[2019]}"), None);
+        assert_eq!(formula_problem("IIf([A]] (b] > 0, 'it''s (', \"x\") -- (\n/* { */"), None);
+        assert_eq!(formula_problem("([Measures].[Units]"), Some("has an unclosed (".to_owned()));
+        assert_eq!(formula_problem("[Measures].[Units"), Some("has an unclosed [".to_owned()));
+        assert_eq!(formula_problem("{[A])"), Some("closes { with )".to_owned()));
+        assert_eq!(formula_problem("[A]) + 1"), Some("has an unmatched )".to_owned()));
+        assert_eq!(formula_problem("'abc"), Some("has an unclosed '".to_owned()));
+    }
+
+    #[test]
+    fn test_check_formulas() {
+        let f0 = "<Cube name=\"Sales\">
+  <CalculatedMember name=\"Profit\" dimension=\"Measures\" formula=\"[Measures].[Sales] - [Measures].[Cost]\"/>
+  <CalculatedMember name=\"Ratio\" dimension=\"Measures\"><Formula><![CDATA[([Measures].[Sales] / 2]]></Formula></CalculatedMember>
+  <CalculatedMember name=\"Empty\" dimension=\"Measures\" formula=\" \"/>
+  <NamedSet name=\"Top\"><Formula>TopCount([Store].Members, 10, ([Measures].[Sales] &gt; 1))</Formula></NamedSet>
+</Cube>";
+        let elements = vec![Checked::new(ElementKind::Cube, 0, f0, f0).unwrap()];
+        let warnings = check_formulas(&elements, &ScanOptions::default());
+        let found: Vec<_> = warnings.iter().map(|w| (w.line, w.rule, w.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (3, "invalid-formula", "The formula of CalculatedMember \"Ratio\" in Cube \"Sales\" has an unclosed ("),
+                (4, "invalid-formula", "The formula of CalculatedMember \"Empty\" in Cube \"Sales\" is empty"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_levels() {
+        let f0 = "<SharedDimension name=\"Time\">
+  <Hierarchy name=\"Calendar\">
+    <Level name=\"Year\" column=\"year\" levelType=\"TimeYears\" type=\"Numeric\" uniqueMembers=\"true\"/>
+    <Level name=\"Month\" levelType=\"TimeMonth\" uniqueMembers=\"yes\"><KeyExpression/></Level>
+  </Hierarchy>
+</SharedDimension>";
+        let f1 = "<Cube name=\"Sales\">
+  <Dimension name=\"Store\"><Hierarchy><Level name=\"City\" type=\"Text\"/></Hierarchy></Dimension>
+</Cube>";
+        let elements = vec![
+            Checked::new(ElementKind::SharedDimension, 0, f0, f0).unwrap(),
+            Checked::new(ElementKind::Cube, 1, f1, f1).unwrap(),
+        ];
+        let warnings = check_levels(&elements, &ScanOptions::default());
+        let found: Vec<_> = warnings.iter().map(|w| (w.fragment, w.line, w.rule, w.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    0,
+                    4,
+                    "invalid-level-attribute",
+                    "Level \"Month\" of hierarchy \"Calendar\" of Shared dimension \"Time\" has levelType \"TimeMonth\", \
+                     expected one of Regular, TimeYears, TimeHalfYears, TimeQuarters, TimeMonths, TimeWeeks, TimeDays, \
+                     TimeHours, TimeMinutes, TimeSeconds, TimeUndefined, Null"
+                ),
+                (
+                    0,
+                    4,
+                    "invalid-level-attribute",
+                    "Level \"Month\" of hierarchy \"Calendar\" of Shared dimension \"Time\" has uniqueMembers \"yes\", \
+                     expected one of true, false"
+                ),
+                (
+                    1,
+                    2,
+                    "level-without-column",
+                    "Level \"City\" of hierarchy \"Store\" of dimension \"Store\" of Cube \"Sales\" has neither a column nor a KeyExpression"
+                ),
+                (
+                    1,
+                    2,
+                    "invalid-level-attribute",
+                    "Level \"City\" of hierarchy \"Store\" of dimension \"Store\" of Cube \"Sales\" has type \"Text\", \
+                     expected one of String, Numeric, Integer, Boolean, Date, Time, Timestamp"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_hierarchy_keys() {
+        let f0 = "<SharedDimension name=\"Store\">
+  <Hierarchy primaryKey=\"id\" primaryKeyTable=\"store\">
+    <Join leftKey=\"region_id\" rightKey=\"id\"><Table name=\"store\"/><Table name=\"region\"/></Join>
+  </Hierarchy>
+  <Hierarchy name=\"Other\" primaryKeyTable=\"s\"><Table name=\"store\"/></Hierarchy>
+</SharedDimension>";
+        let f1 = "<Cube name=\"Sales\">
+  <DimensionUsage source=\"Store\" foreignKey=\"store_id\"/>
+  <Dimension name=\"Promo\" foreignKey=\"promo_id\">
+    <Hierarchy primaryKeyTable=\"p\"><Table name=\"promotion\" alias=\"p\"/></Hierarchy>
+  </Dimension>
+  <Dimension name=\"Degenerate\"><Hierarchy><Level name=\"l\" column=\"c\"/></Hierarchy></Dimension>
+</Cube>";
+        let elements = vec![
+            Checked::new(ElementKind::SharedDimension, 0, f0, f0).unwrap(),
+            Checked::new(ElementKind::Cube, 1, f1, f1).unwrap(),
+        ];
+        let warnings = check_hierarchy_keys(&elements, &ScanOptions::default());
+        let found: Vec<_> = warnings.iter().map(|w| (w.fragment, w.line, w.rule, w.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    0,
+                    5,
+                    "unknown-key-table",
+                    "Hierarchy \"Other\" of Shared dimension \"Store\" has primaryKeyTable \"s\", which isn't a table it joins"
+                ),
+                (
+                    0,
+                    5,
+                    "missing-primary-key",
+                    "Hierarchy \"Other\" of Shared dimension \"Store\" has no primaryKey, but is joined to a cube with a foreignKey"
+                ),
+                (
+                    1,
+                    4,
+                    "missing-primary-key",
+                    "Hierarchy \"Promo\" of dimension \"Promo\" of Cube \"Sales\" has no primaryKey, but is joined to a cube with a foreignKey"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_cubes_have_measures() {
+        let f0 = "<Cube name=\"a\"><Measure name=\"m\"/></Cube>
+<Cube name=\"b\"><CalculatedMember name=\"c\" dimension=\"Measures\"/></Cube>
+<Cube name=\"c\"><Dimension name=\"d\"/></Cube>
+<VirtualCube name=\"v\"/>";
+        let elements: Vec<_> = tree::parse(f0).unwrap().into_iter()
+            .map(|element| {
+                let kind = ElementKind::from_tag(element.name).unwrap();
+                Checked::new(kind, 0, f0, element.text).unwrap()
+            })
+            .collect();
+        let warnings = check_cubes_have_measures(&elements, &ScanOptions::default());
+        let found: Vec<_> = warnings.iter().map(|w| (w.line, w.rule, w.message.as_str())).collect();
+        assert_eq!(found, vec![(3, "cube-without-measures", "Cube \"c\" has no measures")]);
+    }
+
+    #[test]
+    fn test_check_roles() {
+        let f0 = "<Schema name=\"s\">
+<SharedDimension name=\"Time\"><Hierarchy name=\"Weekly\"/></SharedDimension>
+<Cube name=\"Sales\">
+  <DimensionUsage name=\"Date\" source=\"Time\"/>
+  <Dimension name=\"Store\"><Hierarchy/></Dimension>
+</Cube>
+</Schema>";
+        let f1 = "<Role name=\"Analyst\">
+  <SchemaGrant access=\"none\">
+    <CubeGrant cube=\"Sales\" access=\"all\">
+      <DimensionGrant dimension=\"[Measures]\" access=\"all\"/>
+      <HierarchyGrant hierarchy=\"[Store]\" access=\"all\"/>
+      <HierarchyGrant hierarchy=\"[Date.Weekly]\" access=\"all\"/>
+      <HierarchyGrant hierarchy=\"[Date].[Daily]\" access=\"all\"/>
+      <DimensionGrant dimension=\"[Product]\" access=\"none\"/>
+    </CubeGrant>
+    <CubeGrant cube=\"Returns\" access=\"all\"/>
+  </SchemaGrant>
+</Role>";
+        let f2 = "<Role name=\"Everyone\"><Union><RoleUsage roleName=\"Analyst\"/><RoleUsage roleName=\"Admin\"/></Union></Role>";
+        let elements = vec![
+            Checked::new(ElementKind::SharedDimension, 0, f0, &f0[18..91]).unwrap(),
+            Checked::new(ElementKind::Cube, 0, f0, &f0[92..216]).unwrap(),
+            Checked::new(ElementKind::Role, 1, f1, f1).unwrap(),
+            Checked::new(ElementKind::Role, 2, f2, f2).unwrap(),
+        ];
+        let warnings = check_roles(&elements, &ScanOptions::default());
+        let found: Vec<_> = warnings.iter().map(|w| (w.fragment, w.line, w.rule, w.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (1, 7, "unknown-grant-target",
+                 "Role \"Analyst\" grants access to hierarchy \"Daily\" of dimension \"Date\" of Cube \"Sales\", \
+                  which isn't in that dimension"),
+                (1, 8, "unknown-grant-target",
+                 "Role \"Analyst\" grants access to dimension \"Product\" of Cube \"Sales\", which isn't in that cube"),
+                (1, 10, "unknown-grant-target", "Role \"Analyst\" grants access to cube \"Returns\", which isn't in the schema"),
+                (2, 1, "unknown-role", "Role \"Everyone\" is a union with role \"Admin\", which isn't defined"),
+            ]
+        );
+        assert_eq!(warnings[1].path, vec![
+            "Role \"Analyst\"".to_owned(),
+            "SchemaGrant".to_owned(),
+            "CubeGrant".to_owned(),
+            "DimensionGrant".to_owned(),
+        ]);
+    }
+
+    #[test]
+    fn test_check_cycles() {
+        let f0 = "<VirtualCube name=\"A\"><CubeUsages><CubeUsage cubeName=\"Sales\"/><CubeUsage cubeName=\"B\"/></CubeUsages></VirtualCube>";
+        let f1 = "<VirtualCube name=\"B\">\n<CubeUsages><CubeUsage cubeName=\"A\"/></CubeUsages></VirtualCube>";
+        let f2 = "<Role name=\"R\"><Union><RoleUsage roleName=\"R\"/></Union></Role>";
+        let f3 = "<Role name=\"S\"><Union><RoleUsage roleName=\"R\"/></Union></Role>";
+        let elements = vec![
+            Checked::new(ElementKind::Cube, 0, "<Cube name=\"Sales\"/>", "<Cube name=\"Sales\"/>").unwrap(),
+            Checked::new(ElementKind::VirtualCube, 0, f0, f0).unwrap(),
+            Checked::new(ElementKind::VirtualCube, 1, f1, f1).unwrap(),
+            Checked::new(ElementKind::Role, 2, f2, f2).unwrap(),
+            Checked::new(ElementKind::Role, 3, f3, f3).unwrap(),
+        ];
+        let warnings = check_cycles(&elements, &ScanOptions::default());
+        let found: Vec<_> = warnings.iter().map(|w| (w.fragment, w.line, w.rule, w.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (1, 2, "circular-reference", "Virtual cube \"B\" refers back to itself: B -> A -> B"),
+                (2, 1, "circular-reference", "Role \"R\" refers back to itself: R -> R"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_named_sets() {
+        let f0 = "<Cube name=\"Sales\">
+  <Dimension name=\"Store\"><Hierarchy name=\"Region\"><Level name=\"City\"/></Hierarchy></Dimension>
+  <Measure name=\"Units\"/>
+  <NamedSet name=\"Top\" formula=\"TopCount([City].Members, 5, [Measures].[Units])\"/>
+  <CalculatedMember name=\"Top Units\" dimension=\"Measures\" formula=\"Sum([Top], [Units])\"/>
+  <CalculatedMember name=\"Big Units\" dimension=\"Measures\" formula=\"Sum([Big], [Store.Region].&amp;[x])\"/>
+</Cube>";
+        let f1 = "<Cube name=\"Sales\"><NamedSet name=\"Top\"><Formula>{[Store].[Big]}</Formula></NamedSet></Cube>";
+        let f2 = "<NamedSet name=\"Cities\" formula=\"Filter([Store].Members, [Units] > 0 AND [Cities] &lt;&gt; 'x[y]')\"/>";
+        let f3 = "<NamedSet name=\"Towns\" formula=\"[Villages]\"/>";
+        let elements = vec![
+            Checked::new(ElementKind::Cube, 0, f0, f0).unwrap(),
+            Checked::new(ElementKind::Cube, 1, f1, f1).unwrap(),
+            Checked::new(ElementKind::NamedSet, 2, f2, f2).unwrap(),
+            Checked::new(ElementKind::NamedSet, 3, f3, f3).unwrap(),
+        ];
+        let warnings = check_named_sets(&elements, &ScanOptions::default());
+        let found: Vec<_> = warnings.iter().map(|w| (w.fragment, w.line, w.rule, w.message.as_str(), w.first)).collect();
+        assert_eq!(
+            found,
+            vec![
+                (1, 1, "duplicate-named-set", "Named set \"Top\" of Cube \"Sales\" is defined more than once", Some((0, 4))),
+                (0, 6, "unknown-named-set",
+                 "The formula of CalculatedMember \"Big Units\" in Cube \"Sales\" refers to [Big], which isn't a named set \
+                  or anything else in the schema", None),
+                (3, 1, "unknown-named-set",
+                 "The formula of Named set \"Towns\" refers to [Villages], which isn't a named set or anything else \
+                  in the schema", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_aggregates() {
+        let f0 = "<SharedDimension name=\"Time\"><Hierarchy name=\"Weekly\"><Level name=\"Week\"/></Hierarchy></SharedDimension>";
+        let f1 = "<Cube name=\"Sales\">
+  <Table name=\"sales\">
+    <AggName name=\"agg_1\">
+      <AggFactCount column=\"cnt\"/>
+      <AggMeasure name=\"[Measures].[Units]\" column=\"units\"/>
+      <AggLevel name=\"[Store].[City]\" column=\"city\"/>
+      <AggLevel name=\"[Date.Weekly].[Week]\" column=\"week\"/>
+    </AggName>
+    <AggPattern pattern=\"agg_.*\">
+      <AggMeasure name=\"[Measures].[Cost]\" column=\"cost\"/>
+      <AggLevel name=\"[Store].[Region].[State]\" column=\"state\"/>
+    </AggPattern>
+    <AggName name=\"agg_2\"><AggFactCount column=\"cnt\"/></AggName>
+  </Table>
+  <DimensionUsage name=\"Date\" source=\"Time\"/>
+  <Dimension name=\"Store\"><Hierarchy name=\"Region\"><Level name=\"City\"/></Hierarchy></Dimension>
+  <Measure name=\"Units\"/>
+</Cube>";
+        let elements = vec![
+            Checked::new(ElementKind::SharedDimension, 0, f0, f0).unwrap(),
+            Checked::new(ElementKind::Cube, 1, f1, f1).unwrap(),
+        ];
+        let warnings = check_aggregates(&elements, &ScanOptions::default());
+        let found: Vec<_> = warnings.iter().map(|w| (w.line, w.rule, w.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (9, "incomplete-aggregate", "Aggregate pattern \"agg_.*\" of Cube \"Sales\" has no AggFactCount"),
+                (10, "unknown-aggregate-target",
+                 "Aggregate pattern \"agg_.*\" of Cube \"Sales\" aggregates measure \"Cost\", which isn't one of the cube's measures"),
+                (11, "unknown-aggregate-target",
+                 "Aggregate pattern \"agg_.*\" of Cube \"Sales\" aggregates level \"[Store].[Region].[State]\", which isn't in the cube"),
+                (13, "incomplete-aggregate", "Aggregate table \"agg_2\" of Cube \"Sales\" has no AggMeasure"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_writeback() {
+        let f0 = "<SharedDimension name=\"Time\"><Hierarchy><Level name=\"Year\"/></Hierarchy></SharedDimension>";
+        let f1 = "<Cube name=\"Budget\">
+  <Table name=\"budget\"/>
+  <DimensionUsage name=\"Time\" source=\"Time\"/>
+  <Dimension name=\"Store\"><Hierarchy><Level name=\"City\"/></Hierarchy></Dimension>
+  <Measure name=\"Amount\" column=\"amount\" aggregator=\"sum\"/>
+  <CalculatedMember name=\"Twice\" dimension=\"Measures\" formula=\"[Measures].[Amount] * 2\"/>
+  <WritebackTable name=\"budget_wb\">
+    <WritebackAttribute dimension=\"[Time]\" column=\"time_id\"/>
+    <WritebackAttribute dimension=\"Store\" column=\"store_id\"/>
+    <WritebackAttribute dimension=\"Product\" column=\"product_id\"/>
+    <WritebackMeasure name=\"[Measures].[Amount]\" column=\"amount\"/>
+    <WritebackMeasure name=\"Twice\" column=\"twice\"/>
+  </WritebackTable>
+</Cube>";
+        let elements = vec![
+            Checked::new(ElementKind::SharedDimension, 0, f0, f0).unwrap(),
+            Checked::new(ElementKind::Cube, 1, f1, f1).unwrap(),
+        ];
+        let warnings = check_writeback(&elements, &ScanOptions::default());
+        let found: Vec<_> = warnings.iter().map(|w| (w.line, w.rule, w.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (10, "unknown-writeback-target",
+                 "Writeback table \"budget_wb\" of Cube \"Budget\" writes back dimension \"Product\", which isn't one of the cube's dimensions"),
+                (12, "unknown-writeback-target",
+                 "Writeback table \"budget_wb\" of Cube \"Budget\" writes back measure \"Twice\", which isn't one of the cube's stored measures"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_attribute_values() {
+        let f0 = "<Cube name=\"Sales\" cache=\"yes\" enabled=\"true\">
+  <Dimension name=\"Store\" highCardinality=\"false\">
+    <Hierarchy hasAll=\"true\"><Table name=\"store\" approxRowCount=\"1,000\"/></Hierarchy>
+  </Dimension>
+  <Measure name=\"Units\" visible=\"False\"/>
+</Cube>";
+        let f1 = "<SharedDimension name=\"Time\"><Hierarchy><Table name=\"time\" approxRowCount=\"365\"/></Hierarchy></SharedDimension>";
+        let elements = vec![
+            Checked::new(ElementKind::Cube, 0, f0, f0).unwrap(),
+            Checked::new(ElementKind::SharedDimension, 1, f1, f1).unwrap(),
+        ];
+        let warnings = check_attribute_values(&elements);
+        let found: Vec<_> = warnings.iter().map(|w| (w.line, w.rule, w.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (1, "invalid-attribute-value", "Cube \"Sales\" has cache \"yes\", expected true or false"),
+                (3, "invalid-attribute-value",
+                 "Table \"store\" in Cube \"Sales\" has approxRowCount \"1,000\", expected a whole number"),
+                (5, "invalid-attribute-value", "Measure \"Units\" in Cube \"Sales\" has visible \"False\", expected true or false"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_deprecated_attributes() {
+        let f0 = "<Cube name=\"Sales\">
+  <Dimension name=\"Store\" highCardinality=\"true\">
+    <Hierarchy memberReaderClass=\"com.example.Reader\"><Level name=\"City\" formatter=\"com.example.City\"/></Hierarchy>
+  </Dimension>
+  <Measure name=\"Units\" formatString=\"#\"/>
+</Cube>";
+        let f1 = "<SharedDimension name=\"Time\" highCardinality=\"false\"/>";
+        let elements = vec![
+            Checked::new(ElementKind::Cube, 0, f0, f0).unwrap(),
+            Checked::new(ElementKind::SharedDimension, 1, f1, f1).unwrap(),
+        ];
+        let warnings = check_deprecated_attributes(&elements, &ScanOptions::default());
+        let found: Vec<_> = warnings.iter().map(|w| (w.fragment, w.line, w.rule)).collect();
+        assert_eq!(
+            found,
+            vec![
+                (0, 2, "deprecated-high-cardinality"),
+                (0, 3, "deprecated-member-reader-class"),
+                (0, 3, "deprecated-level-formatter"),
+                (1, 1, "deprecated-high-cardinality"),
+            ]
+        );
+        assert_eq!(
+            warnings[2].message,
+            "Level \"City\" in Cube \"Sales\" has formatter, which is deprecated: use a <MemberFormatter> instead"
+        );
+    }
+
+    #[test]
+    fn test_check_duplicate_hierarchies() {
+        let f0 = "<SharedDimension name=\"Time\">
+  <Hierarchy name=\"Weekly\"/>
+  <Hierarchy/>
+  <Hierarchy name=\"Time\"/>
+</SharedDimension>";
+        let f1 = "<Cube name=\"Sales\">
+  <Dimension name=\"Store\"><Hierarchy name=\"Region\"/><Hierarchy name=\"Region\"/></Dimension>
+  <Dimension name=\"Product\"><Hierarchy name=\"Region\"/></Dimension>
+</Cube>";
+        let elements = vec![
+            Checked::new(ElementKind::SharedDimension, 0, f0, f0).unwrap(),
+            Checked::new(ElementKind::Cube, 1, f1, f1).unwrap(),
+        ];
+        let warnings = check_duplicate_hierarchies(&elements, &ScanOptions::default());
+        let found: Vec<_> = warnings.iter().map(|w| (w.fragment, w.line, w.message.as_str(), w.first)).collect();
+        assert_eq!(
+            found,
+            vec![
+                (0, 4, "Hierarchy \"Time\" of Shared dimension \"Time\" is defined more than once", Some((0, 3))),
+                (1, 2, "Hierarchy \"Region\" of dimension \"Store\" of Cube \"Sales\" is defined more than once", Some((1, 2))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_hierarchy_relations() {
+        let f0 = "<SharedDimension name=\"Time\">
+  <Hierarchy name=\"Weekly\"/>
+  <Hierarchy name=\"Monthly\"><Table name=\"time\"/></Hierarchy>
+</SharedDimension>";
+        let f1 = "<Cube name=\"Sales\">
+  <Dimension name=\"Store\"><Hierarchy><Table name=\"store\"/><Join/></Hierarchy></Dimension>
+  <Dimension name=\"Ticket\"><Hierarchy><Level name=\"Number\" column=\"ticket\"/></Hierarchy></Dimension>
+</Cube>";
+        let elements = vec![
+            Checked::new(ElementKind::SharedDimension, 0, f0, f0).unwrap(),
+            Checked::new(ElementKind::Cube, 1, f1, f1).unwrap(),
+        ];
+        let warnings = check_hierarchy_relations(&elements, &ScanOptions::default());
+        let found: Vec<_> = warnings.iter().map(|w| (w.fragment, w.line, w.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (0, 2, "Hierarchy \"Weekly\" of Shared dimension \"Time\" has no Table, View, Join or InlineTable, \
+                        which only a cube's own dimensions can do without"),
+                (1, 2, "Hierarchy \"Store\" of dimension \"Store\" of Cube \"Sales\" has 2 relations (Table, Join), expected one"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_naming() {
+        let f0 = "<Cube name=\"Sales\">
+  <Dimension name=\"store_region\"/>
+  <Measure name=\"Units\"/>
+</Cube>
+<Cube name=\"sales 2\"/>";
+        let f1 = "<Role name=\"analyst\"/>";
+        let elements = vec![
+            Checked::new(ElementKind::Cube, 0, f0, &f0[..88]).unwrap(),
+            Checked::new(ElementKind::Cube, 0, f0, &f0[89..]).unwrap(),
+            Checked::new(ElementKind::Role, 1, f1, f1).unwrap(),
+        ];
+        let mut naming = BTreeMap::new();
+        naming.insert("Cube".to_owned(), "[A-Z][A-Za-z0-9 ]+".to_owned());
+        naming.insert("Dimension".to_owned(), "[^_]+".to_owned());
+        naming.insert("Role".to_owned(), "[a-z]+".to_owned());
+        let naming = naming_conventions(&naming).unwrap();
+        let warnings = check_naming(&elements, &naming, &ScanOptions::default());
+        let found: Vec<_> = warnings.iter().map(|w| (w.line, w.rule, w.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (2, "naming-convention",
+                 "Dimension \"store_region\" in Cube \"Sales\" doesn't follow the naming convention [^_]+"),
+                (5, "naming-convention", "Cube \"sales 2\" doesn't follow the naming convention [A-Z][A-Za-z0-9 ]+"),
+            ]
+        );
+
+        let mut invalid = BTreeMap::new();
+        invalid.insert("Cube".to_owned(), "[A-Z".to_owned());
+        match *naming_conventions(&invalid).unwrap_err().kind() {
+            ErrorKind::InvalidNamingConvention(ref element, _) => assert_eq!(element, "Cube"),
+            ref kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_check_captions() {
+        let f0 = "<SharedDimension name=\"Time\" caption=\"Time\" description=\"When\">
+  <Hierarchy caption=\"Calendar\">
+    <Level name=\"Year\" caption=\" \"/>
+  </Hierarchy>
+</SharedDimension>";
+        let elements = vec![Checked::new(ElementKind::SharedDimension, 0, f0, f0).unwrap()];
+        let warnings = check_captions(&elements, &ScanOptions::default(), "caption", "missing-caption");
+        let found: Vec<_> = warnings.iter().map(|w| (w.line, w.rule, w.message.as_str())).collect();
+        assert_eq!(found, vec![(3, "missing-caption", "Level \"Year\" in Shared dimension \"Time\" has no caption")]);
+
+        let warnings = check_captions(&elements, &ScanOptions::default(), "description", "missing-description");
+        let found: Vec<_> = warnings.iter().map(|w| w.message.as_str()).collect();
+        assert_eq!(
+            found,
+            vec![
+                "Hierarchy in Shared dimension \"Time\" has no description",
+                "Level \"Year\" in Shared dimension \"Time\" has no description",
+            ]
+        );
+
+        let f0 = "<Cube name=\"Sales\"><Table name=\"t\"/><Measure name=\"m\" caption=\"M\"/></Cube>";
+        let elements = vec![Checked::new(ElementKind::Cube, 0, f0, f0).unwrap()];
+        let warnings = check_captions(&elements, &ScanOptions::default(), "caption", "missing-caption");
+        let found: Vec<_> = warnings.iter().map(|w| w.message.as_str()).collect();
+        assert_eq!(found, vec!["Cube \"Sales\" has no caption"]);
+    }
+
+    #[test]
+    fn test_check_structure() {
+        let f0 = "<Cube name=\"Sales\">
+  <Table name=\"sales\"><SQL>x</SQL></Table>
+  <Measure name=\"Units\" column=\"units\"/>
+  <Mesure name=\"Typo\"><Level/></Mesure>
+  <Dimension name=\"d\"><Level name=\"l\"/></Dimension>
+</Cube>";
+        let elements = vec![Checked::new(ElementKind::Cube, 0, f0, f0).unwrap()];
+        let warnings = check_structure(&elements, &ScanOptions::default());
+        let found: Vec<_> = warnings.iter().map(|w| (w.line, w.column, w.message.as_str())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (2, 23, "<SQL> in Cube \"Sales\" is missing the required attribute dialect"),
+                (3, 3, "<Measure> in Cube \"Sales\" is missing the required attribute aggregator"),
+                (4, 3, "Unknown element <Mesure> in Cube \"Sales\""),
+                (5, 23, "<Level> isn't allowed in <Dimension>, in Cube \"Sales\""),
+            ]
+        );
+        assert!(warnings.iter().all(|w| w.rule == "invalid-structure"));
+
+        let f0 = "<cube name=\"Sales\"><measure name=\"m\" aggregator=\"sum\"/></cube>";
+        let elements = vec![Checked::new(ElementKind::Cube, 0, f0, f0).unwrap()];
+        let options = ScanOptions { ignore_case: true, ..ScanOptions::default() };
+        assert_eq!(check_structure(&elements, &options), vec![]);
+
+        // an inline fact table is allowed
+        let f0 = "<Cube name=\"Sales\"><InlineTable alias=\"f\"><ColumnDefs/><Rows/></InlineTable></Cube>";
+        let elements = vec![Checked::new(ElementKind::Cube, 0, f0, f0).unwrap()];
+        assert_eq!(check_structure(&elements, &ScanOptions::default()), vec![]);
+    }
+
+    #[test]
+    fn test_measure_name() {
+        assert_eq!(measure_name("[Measures].[Unit Sales]"), "Unit Sales");
+        assert_eq!(measure_name("[a]]b]"), "a]b");
+        assert_eq!(measure_name("Units"), "Units");
+    }
+}
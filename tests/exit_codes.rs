@@ -0,0 +1,57 @@
+// Copyright 2018 mondrian-schema-cat Developers
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+// The exit codes of the moncat binary, for each kind of failure.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{self, Command};
+
+/// Write `fragment` to a file of its own, for `test`
+fn fragment(test: &str, fragment: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("moncat-exit-codes-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("{}.xml", test));
+    fs::write(&path, fragment).unwrap();
+    path
+}
+
+/// The exit code of moncat run with `args`
+fn moncat(args: &[&str]) -> i32 {
+    let output = Command::new(env!("CARGO_BIN_EXE_moncat")).args(args).output().unwrap();
+    output.status.code().expect("moncat exits with a code")
+}
+
+#[test]
+fn test_success() {
+    let path = fragment("success", "<Schema name=\"s\"><Dimension name=\"Time\"/></Schema>");
+    assert_eq!(moncat(&["-q", path.to_str().unwrap()]), 0);
+}
+
+#[test]
+fn test_bad_flag() {
+    assert_eq!(moncat(&["--no-such-flag"]), 2);
+}
+
+#[test]
+fn test_missing_file() {
+    let path = env::temp_dir().join("moncat-exit-codes-missing").join("missing.xml");
+    assert_eq!(moncat(&[path.to_str().unwrap()]), 3);
+}
+
+#[test]
+fn test_malformed_fragment() {
+    let path = fragment("malformed", "<Schema name=\"s\"><Cube name=\"c\"></Schema>");
+    assert_eq!(moncat(&[path.to_str().unwrap()]), 4);
+}
+
+#[test]
+fn test_denied_finding() {
+    let path = fragment("denied", "<Schema name=\"s\"><Dimension name=\"Time\"/><Dimension name=\"Time\"/></Schema>");
+    assert_eq!(moncat(&["--deny", "duplicate-name", path.to_str().unwrap()]), 5);
+}